@@ -0,0 +1,333 @@
+//! Checks that clients can run before signing a checkout, and decoders of the
+//! accounts they read
+
+use crate::{
+    engine::constants::DEFAULT_DATA,
+    state::{Discriminator, IsClosed, LedgerAccount, LedgerEntry, MerchantAccount, Serdes},
+    utils::{get_checkout_fees, get_order_account_size, split_charity_amount},
+};
+use solana_program::{program_pack::IsInitialized, pubkey::Pubkey, rent::Rent};
+use std::io::{Error as IoError, ErrorKind};
+use thiserror::Error;
+
+/// Why a checkout would fail on chain, see validate_checkout
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum PreflightError {
+    /// The merchant account is closed or was never initialized
+    #[error("Error: The merchant is not active")]
+    MerchantNotActive,
+    /// The checkout amount is 0
+    #[error("Error: The amount must be more than 0")]
+    ZeroAmount,
+    /// The buyer token account does not hold the checkout mint
+    #[error("Error: Seller And Buyer Mints Not The Same")]
+    MintNotEqual,
+    /// The merchant does not accept the checkout mint
+    #[error("Error: The merchant does not accept this mint")]
+    MintNotAccepted,
+    /// The amount is less than the merchant minimum
+    #[error("Error: The amount is less than the merchant minimum of {min_amount}")]
+    AmountTooSmall { min_amount: u64 },
+    /// The amount is more than the merchant maximum
+    #[error("Error: The amount is more than the merchant maximum of {max_amount}")]
+    AmountTooLarge { max_amount: u64 },
+    /// The token fee and charity share are more than the amount
+    #[error("Error: The fee and charity share are more than the amount")]
+    FeeTooHigh,
+    /// The buyer token account holds less than the amount
+    #[error("Error: The buyer needs {needed} tokens")]
+    InsufficientTokenBalance { needed: u64 },
+    /// The buyer has fewer lamports than the SOL fee and order account rent
+    #[error("Error: The buyer needs {needed} lamports")]
+    InsufficientSol { needed: u64 },
+}
+
+/// What a client knows about the checkout it is about to sign
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckoutParams {
+    /// the buyer, who signs the checkout
+    pub buyer: Pubkey,
+    /// the mint of the payment
+    pub mint: Pubkey,
+    /// the mint of the buyer token account
+    pub buyer_token_mint: Pubkey,
+    /// the amount (in token units) to pay
+    pub amount: u64,
+    pub order_id: String,
+    pub secret: Option<String>,
+    pub data: Option<String>,
+    /// whether the merchant pays the order account rent, see with_merchant_rent
+    pub merchant_pays_rent: bool,
+}
+
+/// Run the checks that an express checkout runs on chain
+///
+/// Meant for wallets, which can then show the buyer why a checkout would fail
+/// before signing it.  The order account rent is that of Rent::default and the
+/// transaction fee is not counted, so a buyer with barely enough SOL can still
+/// fail on chain.  Orders quoted in USD can not be checked without the oracle
+/// price and are checked as if the amount were in token units.
+pub fn validate_checkout(
+    params: &CheckoutParams,
+    merchant: &MerchantAccount,
+    buyer_balance: u64,
+    buyer_sol: u64,
+) -> Result<(), PreflightError> {
+    if merchant.is_closed() || !merchant.is_initialized() {
+        return Err(PreflightError::MerchantNotActive);
+    }
+    if params.amount == 0 {
+        return Err(PreflightError::ZeroAmount);
+    }
+    if params.mint != params.buyer_token_mint {
+        return Err(PreflightError::MintNotEqual);
+    }
+    // an empty list accepts any mint
+    if !merchant.accepted_mints.is_empty()
+        && !merchant.accepted_mints.contains(&params.mint.to_bytes())
+    {
+        return Err(PreflightError::MintNotAccepted);
+    }
+    // zero means there is no limit
+    if merchant.min_amount != 0 && params.amount < merchant.min_amount {
+        return Err(PreflightError::AmountTooSmall {
+            min_amount: merchant.min_amount,
+        });
+    }
+    if merchant.max_amount != 0 && params.amount > merchant.max_amount {
+        return Err(PreflightError::AmountTooLarge {
+            max_amount: merchant.max_amount,
+        });
+    }
+    let (sol_fee, token_fee) = get_checkout_fees(merchant, &params.buyer, params.amount);
+    let seller_amount = match merchant.get_charity() {
+        None => params.amount,
+        Some((_charity, charity_bps)) => split_charity_amount(params.amount, charity_bps).0,
+    };
+    if seller_amount < token_fee {
+        return Err(PreflightError::FeeTooHigh);
+    }
+    if buyer_balance < params.amount {
+        return Err(PreflightError::InsufficientTokenBalance {
+            needed: params.amount,
+        });
+    }
+    let rent = if params.merchant_pays_rent {
+        0
+    } else {
+        let data = match &params.data {
+            None => String::from(DEFAULT_DATA),
+            Some(value) => value.clone(),
+        };
+        Rent::default().minimum_balance(get_order_account_size(
+            &params.order_id,
+            &params.secret,
+            &data,
+        ))
+    };
+    let needed = sol_fee.saturating_add(rent);
+    if buyer_sol < needed {
+        return Err(PreflightError::InsufficientSol { needed });
+    }
+    Ok(())
+}
+
+/// Decode the last n payments of a merchant ledger account, oldest first
+///
+/// The data is that of the account at utils::get_ledger_address, see LedgerAccount.
+pub fn decode_ledger(data: &[u8], n: usize) -> Result<Vec<LedgerEntry>, IoError> {
+    let ledger_account = LedgerAccount::unpack(data)?;
+    if ledger_account.discriminator != Discriminator::Ledger as u8 {
+        return Err(IoError::new(ErrorKind::InvalidData, "Not a ledger account"));
+    }
+    Ok(ledger_account.last_entries(n))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{engine::constants::LEDGER_CAPACITY, state::FeeMode},
+        solana_program_test::*,
+    };
+
+    fn test_merchant() -> MerchantAccount {
+        MerchantAccount {
+            discriminator: Discriminator::Merchant as u8,
+            owner: Pubkey::new_unique().to_bytes(),
+            sponsor: Pubkey::new_unique().to_bytes(),
+            fee: 300,
+            data: String::from("{}"),
+            fee_recipient: Pubkey::new_unique().to_bytes(),
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        }
+    }
+
+    fn test_params(mint: &Pubkey) -> CheckoutParams {
+        CheckoutParams {
+            buyer: Pubkey::new_unique(),
+            mint: *mint,
+            buyer_token_mint: *mint,
+            amount: 1000,
+            order_id: String::from("order 1"),
+            secret: Option::None,
+            data: Option::None,
+            merchant_pays_rent: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_checkout() {
+        let mint = Pubkey::new_unique();
+        let mut merchant = test_merchant();
+        let mut params = test_params(&mint);
+        let rent = Rent::default().minimum_balance(get_order_account_size(
+            &params.order_id,
+            &Option::None,
+            &String::from(DEFAULT_DATA),
+        ));
+        assert_eq!(
+            Ok(()),
+            validate_checkout(&params, &merchant, 1000, 300 + rent)
+        );
+        // the buyer does not pay the rent the merchant pays
+        params.merchant_pays_rent = true;
+        assert_eq!(Ok(()), validate_checkout(&params, &merchant, 1000, 300));
+        params.merchant_pays_rent = false;
+        // the fee is in the payment token
+        merchant.fee_mode = FeeMode::BpsToken;
+        assert_eq!(Ok(()), validate_checkout(&params, &merchant, 1000, rent));
+        merchant.accepted_mints = vec![mint.to_bytes()];
+        assert_eq!(Ok(()), validate_checkout(&params, &merchant, 1000, rent));
+    }
+
+    #[tokio::test]
+    async fn test_validate_checkout_errors() {
+        let mint = Pubkey::new_unique();
+        let params = test_params(&mint);
+        let enough_sol = 1000000000;
+
+        let mut merchant = test_merchant();
+        merchant.discriminator = Discriminator::Closed as u8;
+        assert_eq!(
+            Err(PreflightError::MerchantNotActive),
+            validate_checkout(&params, &merchant, 1000, enough_sol)
+        );
+        merchant.discriminator = Discriminator::Uninitialized as u8;
+        assert_eq!(
+            Err(PreflightError::MerchantNotActive),
+            validate_checkout(&params, &merchant, 1000, enough_sol)
+        );
+
+        let merchant = test_merchant();
+        let mut zero_params = params.clone();
+        zero_params.amount = 0;
+        assert_eq!(
+            Err(PreflightError::ZeroAmount),
+            validate_checkout(&zero_params, &merchant, 1000, enough_sol)
+        );
+
+        let mut other_mint_params = params.clone();
+        other_mint_params.buyer_token_mint = Pubkey::new_unique();
+        assert_eq!(
+            Err(PreflightError::MintNotEqual),
+            validate_checkout(&other_mint_params, &merchant, 1000, enough_sol)
+        );
+
+        let mut merchant = test_merchant();
+        merchant.accepted_mints = vec![Pubkey::new_unique().to_bytes()];
+        assert_eq!(
+            Err(PreflightError::MintNotAccepted),
+            validate_checkout(&params, &merchant, 1000, enough_sol)
+        );
+
+        let mut merchant = test_merchant();
+        merchant.min_amount = 1001;
+        assert_eq!(
+            Err(PreflightError::AmountTooSmall { min_amount: 1001 }),
+            validate_checkout(&params, &merchant, 1000, enough_sol)
+        );
+        merchant.min_amount = 0;
+        merchant.max_amount = 999;
+        assert_eq!(
+            Err(PreflightError::AmountTooLarge { max_amount: 999 }),
+            validate_checkout(&params, &merchant, 1000, enough_sol)
+        );
+
+        // 60% of the amount to charity and 50% in fees
+        let mut merchant = test_merchant();
+        merchant.fee_mode = FeeMode::BpsToken;
+        merchant.fee = 5000;
+        merchant.charity = Pubkey::new_unique().to_bytes();
+        merchant.charity_bps = 6000;
+        assert_eq!(
+            Err(PreflightError::FeeTooHigh),
+            validate_checkout(&params, &merchant, 1000, enough_sol)
+        );
+
+        let merchant = test_merchant();
+        assert_eq!(
+            Err(PreflightError::InsufficientTokenBalance { needed: 1000 }),
+            validate_checkout(&params, &merchant, 999, enough_sol)
+        );
+
+        let rent = Rent::default().minimum_balance(get_order_account_size(
+            &params.order_id,
+            &Option::None,
+            &String::from(DEFAULT_DATA),
+        ));
+        assert_eq!(
+            Err(PreflightError::InsufficientSol { needed: 300 + rent }),
+            validate_checkout(&params, &merchant, 1000, 299 + rent)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_ledger() {
+        let mut data = vec![0; LedgerAccount::LEN];
+        let mut ledger_account = LedgerAccount {
+            discriminator: Discriminator::Ledger as u8,
+            merchant: Pubkey::new_unique().to_bytes(),
+            count: 0,
+            entries: vec![],
+        };
+        for index in 0..LEDGER_CAPACITY as u64 + 2 {
+            ledger_account.append(LedgerEntry {
+                amount: index,
+                timestamp: index as i64,
+                order: Pubkey::new_unique().to_bytes(),
+            });
+        }
+        ledger_account.pack(&mut data);
+        let entries = decode_ledger(&data, 3).unwrap();
+        assert_eq!(
+            vec![
+                LEDGER_CAPACITY as u64 - 1,
+                LEDGER_CAPACITY as u64,
+                LEDGER_CAPACITY as u64 + 1
+            ],
+            entries
+                .iter()
+                .map(|entry| entry.amount)
+                .collect::<Vec<u64>>()
+        );
+        // not a ledger account
+        ledger_account.discriminator = Discriminator::Coupon as u8;
+        ledger_account.pack(&mut data);
+        assert!(decode_ledger(&data, 3).is_err());
+    }
+}
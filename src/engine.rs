@@ -1,9 +1,43 @@
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod cancel_order;
+#[cfg(feature = "json")]
 pub mod cancel_subscription;
+#[cfg(feature = "json")]
+pub mod checkout_subscribe;
+pub mod close_subscription;
 pub mod common;
+pub mod config;
 pub mod constants;
+pub mod coupon;
 pub mod json;
+#[cfg(feature = "json")]
+pub mod keeper_renew;
+pub mod ledger;
+pub mod lock;
+pub mod merchant_cancel;
+pub mod merchant_token;
+pub mod migrate_pda;
+pub mod operator;
+pub mod oracle;
+pub mod order_size;
+#[cfg(feature = "json")]
+pub mod package;
+#[cfg(feature = "json")]
+pub mod pause_subscription;
+pub mod pay;
+pub mod refund;
 pub mod register;
+pub mod reveal_secret;
+#[cfg(feature = "json")]
 pub mod renew;
+pub mod signature;
+pub mod simulate_fees;
+#[cfg(feature = "json")]
 pub mod subscribe;
+pub mod sweep;
+pub mod top_up;
+pub mod update_subscription;
+pub mod verify_order;
+pub mod verify_subscription;
 pub mod withdraw;
-pub mod pay;
\ No newline at end of file
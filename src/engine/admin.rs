@@ -0,0 +1,88 @@
+use crate::{
+    engine::{common::pack_order_account, config::get_program_owner},
+    error::PaymentProcessorError,
+    state::{IsClosed, OrderAccount, OrderStatus, Serdes},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+/// Correct the stored fields of an order
+///
+/// Only the program owner (see config::get_program_owner) can do this, and only
+/// in builds with the admin feature.  Meant for recovering orders that a bug
+/// recorded wrongly, fields that are None are left as they are.
+pub fn process_admin_fix_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    expected_amount: Option<u64>,
+    paid_amount: Option<u64>,
+    refunded_amount: Option<u64>,
+    status: Option<u8>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let order_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // only the program owner can fix orders
+    if *signer_info.key != get_program_owner(program_id, config_info)? {
+        msg!("Error: Only the program owner can fix orders");
+        return Err(PaymentProcessorError::WrongProgramOwner.into());
+    }
+    // ensure order account is owned by this program
+    if *order_info.owner != *program_id {
+        msg!("Error: Wrong owner for order account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the order account
+    let mut order_account = OrderAccount::unpack(&order_info.data.borrow())?;
+    if order_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !order_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if let Some(value) = status {
+        // ensure the status is a status an initialized order can have
+        if value == OrderStatus::Uninitialized as u8 || value > OrderStatus::Cancelled as u8 {
+            msg!("Error: Invalid order status {:?}", value);
+            return Err(ProgramError::InvalidArgument);
+        }
+        order_account.status = value;
+    }
+    if let Some(value) = expected_amount {
+        order_account.expected_amount = value;
+    }
+    if let Some(value) = paid_amount {
+        order_account.paid_amount = value;
+    }
+    if let Some(value) = refunded_amount {
+        order_account.refunded_amount = value;
+    }
+    // refunds can never be more than what was paid
+    if order_account.refunded_amount > order_account.paid_amount {
+        msg!("Error: The refunded amount is more than the paid amount");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Updating order account information...
+    order_account.modified = timestamp;
+    pack_order_account(order_info, &order_account)?;
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use crate::{
+    engine::common::{token_program_check, transfer_sol},
+    engine::constants::PDA_SEED,
+    error::PaymentProcessorError,
+    state::{Discriminator, IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes},
+    utils::get_merchant_token_address,
+};
+use solana_program::program_pack::Pack;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+use spl_token::{self, state::Account as TokenAccount};
+
+/// Cancel Pending Order
+///
+/// Closes an order that was never paid, and its seller token account if it is
+/// empty, so that the rent of both goes back.  The merchant or the payer can do
+/// this.
+pub fn process_cancel_pending_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let order_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let order_token_info = next_account_info(account_info_iter)?;
+    let account_to_receive_sol_refund_info = next_account_info(account_info_iter)?;
+    let pda_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure the token program is the SPL token program
+    token_program_check(token_program_info)?;
+    // ensure merchant and order accounts are owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *order_info.owner != *program_id {
+        msg!("Error: Wrong owner for order account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // check that provided pda is correct
+    let (pda, pda_nonce) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+    if pda_info.key != &pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // get the order account
+    let mut order_account = OrderAccount::unpack(&order_info.data.borrow())?;
+    if order_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !order_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // ensure order belongs to this merchant
+    if merchant_info.key.to_bytes() != order_account.merchant {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the order payment token account is the right one
+    if order_token_info.key.to_bytes() != order_account.token {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // only the merchant or the payer can cancel the order
+    if !merchant_account.can_operate(&signer_info.key.to_bytes())
+        && signer_info.key.to_bytes() != order_account.payer
+    {
+        msg!("Error: Only the merchant or the payer can cancel the order");
+        return Err(PaymentProcessorError::WrongPayer.into());
+    }
+    // locked orders can not be modified
+    if order_account.locked {
+        msg!("Error: Order is locked");
+        return Err(PaymentProcessorError::OrderLocked.into());
+    }
+    // paid orders are refunded instead
+    if order_account.status != OrderStatus::Pending as u8 {
+        msg!("Error: Only pending orders can be cancelled");
+        return Err(PaymentProcessorError::InvalidOrder.into());
+    }
+
+    // the merchant token account is shared by many orders so we leave it open
+    let (merchant_token_address, _bump_seed) = get_merchant_token_address(
+        merchant_info.key,
+        &Pubkey::new_from_array(order_account.mint),
+        program_id,
+    );
+    if *order_token_info.key != merchant_token_address && *order_token_info.owner == spl_token::id()
+    {
+        let order_token_data = TokenAccount::unpack(&order_token_info.data.borrow())?;
+        if order_token_data.amount == 0 {
+            // Close the order token account since it will never be needed again
+            invoke_signed(
+                &spl_token::instruction::close_account(
+                    token_program_info.key,
+                    order_token_info.key,
+                    account_to_receive_sol_refund_info.key,
+                    &pda,
+                    &[&pda],
+                )
+                .unwrap(),
+                &[
+                    token_program_info.clone(),
+                    order_token_info.clone(),
+                    account_to_receive_sol_refund_info.clone(),
+                    pda_info.clone(),
+                ],
+                &[&[&PDA_SEED, &[pda_nonce]]],
+            )?;
+        } else {
+            msg!("Info: Order token account is not empty and was left open");
+        }
+    }
+    // mark order account as closed
+    order_account.discriminator = Discriminator::Closed as u8;
+    // Transfer all the sol from the order account to the sol_destination.
+    transfer_sol(
+        order_info.clone(),
+        account_to_receive_sol_refund_info.clone(),
+        order_info.lamports(),
+    )?;
+    // Updating order account information...
+    order_account.transition(OrderStatus::Cancelled)?;
+    order_account.modified = timestamp;
+    OrderAccount::pack(&order_account, &mut order_info.data.borrow_mut());
+
+    Ok(())
+}
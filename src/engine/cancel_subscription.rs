@@ -1,11 +1,12 @@
 use crate::{
-    engine::common::{subscribe_checks, transfer_sol},
+    engine::common::{subscribe_checks, token_program_check, transfer_sol},
     engine::constants::PDA_SEED,
     error::PaymentProcessorError,
     state::{
-        Discriminator, IsClosed, OrderAccount, OrderStatus, Serdes, SubscriptionAccount,
-        SubscriptionStatus,
+        Discriminator, IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes,
+        SubscriptionAccount, SubscriptionStatus,
     },
+    utils::get_merchant_token_address,
 };
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
@@ -13,11 +14,11 @@ use solana_program::{
     msg,
     program::invoke_signed,
     program_error::ProgramError,
-    program_pack::IsInitialized,
+    program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
     sysvar::{clock::Clock, Sysvar},
 };
-use spl_token::{self};
+use spl_token::{self, state::Account as TokenAccount};
 
 /// Cancel Subscription
 /// currently only works well for subscriptions still in the trial period
@@ -33,6 +34,7 @@ pub fn process_cancel_subscription(program_id: &Pubkey, accounts: &[AccountInfo]
     let account_to_receive_sol_refund_info = next_account_info(account_info_iter)?;
     let pda_info = next_account_info(account_info_iter)?;
     let token_program_info = next_account_info(account_info_iter)?;
+    let package_info = next_account_info(account_info_iter).ok();
 
     let timestamp = Clock::get()?.unix_timestamp;
 
@@ -40,6 +42,8 @@ pub fn process_cancel_subscription(program_id: &Pubkey, accounts: &[AccountInfo]
     if !signer_info.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
     }
+    // ensure the token program is the SPL token program
+    token_program_check(token_program_info)?;
     // ensure subscription account is owned by this program
     if *subscription_info.owner != *program_id {
         msg!("Error: Wrong owner for subscription account");
@@ -78,9 +82,15 @@ pub fn process_cancel_subscription(program_id: &Pubkey, accounts: &[AccountInfo]
         merchant_info,
         order_info,
         subscription_info,
+        package_info,
         &subscription_account.name,
     )?;
 
+    // locked orders can not be modified
+    if order_account.locked {
+        msg!("Error: Order is locked");
+        return Err(PaymentProcessorError::OrderLocked.into());
+    }
     // ensure the order payment token account is the right one
     if order_token_info.key.to_bytes() != order_account.token {
         msg!("Error: Incorrect order token account");
@@ -101,6 +111,15 @@ pub fn process_cancel_subscription(program_id: &Pubkey, accounts: &[AccountInfo]
     if timestamp >= (subscription_account.joined + trial_duration) {
         msg!("Info: Subscription amount not refunded because trial period has ended.");
     } else {
+        // unless the merchant allows otherwise, refunds only go to the payer
+        let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+        if !merchant_account.allow_third_party_refunds {
+            let refund_token_data = TokenAccount::unpack(&refund_token_info.data.borrow())?;
+            if refund_token_data.owner.to_bytes() != order_account.payer {
+                msg!("Error: The refund token account must belong to the payer");
+                return Err(PaymentProcessorError::WrongPayer.into());
+            }
+        }
         // Transferring payment back to the payer...
         invoke_signed(
             &spl_token::instruction::transfer(
@@ -109,7 +128,9 @@ pub fn process_cancel_subscription(program_id: &Pubkey, accounts: &[AccountInfo]
                 refund_token_info.key,
                 &pda,
                 &[&pda],
-                order_account.paid_amount,
+                order_account
+                    .paid_amount
+                    .saturating_sub(order_account.refunded_amount),
             )
             .unwrap(),
             &[
@@ -120,24 +141,38 @@ pub fn process_cancel_subscription(program_id: &Pubkey, accounts: &[AccountInfo]
             ],
             &[&[&PDA_SEED, &[pda_nonce]]],
         )?;
-        // Close the order token account since it will never be needed again
-        invoke_signed(
-            &spl_token::instruction::close_account(
-                token_program_info.key,
-                order_token_info.key,
-                account_to_receive_sol_refund_info.key,
-                &pda,
-                &[&pda],
-            )
-            .unwrap(),
-            &[
-                token_program_info.clone(),
-                order_token_info.clone(),
-                account_to_receive_sol_refund_info.clone(),
-                pda_info.clone(),
-            ],
-            &[&[&PDA_SEED, &[pda_nonce]]],
-        )?;
+        // the merchant token account is shared by many orders so we leave it open
+        let (merchant_token_address, _bump_seed) = get_merchant_token_address(
+            merchant_info.key,
+            &Pubkey::new_from_array(order_account.mint),
+            program_id,
+        );
+        if *order_token_info.key != merchant_token_address {
+            // Close the order token account since it will never be needed again
+            invoke_signed(
+                &spl_token::instruction::close_account(
+                    token_program_info.key,
+                    order_token_info.key,
+                    account_to_receive_sol_refund_info.key,
+                    &pda,
+                    &[&pda],
+                )
+                .unwrap(),
+                &[
+                    token_program_info.clone(),
+                    order_token_info.clone(),
+                    account_to_receive_sol_refund_info.clone(),
+                    pda_info.clone(),
+                ],
+                &[&[&PDA_SEED, &[pda_nonce]]],
+            )?;
+        }
+        order_account.refunded_amount = order_account.paid_amount;
+        // orders from before refunds have no room for the refunded amount, which
+        // their cancelled status already implies
+        if !order_account.fits(order_info.data_len()) {
+            order_account.refunded_amount = 0;
+        }
         // mark order account as closed
         order_account.discriminator = Discriminator::Closed as u8;
         // Transfer all the sol from the order account to the sol_destination.
@@ -147,7 +182,7 @@ pub fn process_cancel_subscription(program_id: &Pubkey, accounts: &[AccountInfo]
             order_info.lamports(),
         )?;
         // Updating order account information...
-        order_account.status = OrderStatus::Cancelled as u8;
+        order_account.transition(OrderStatus::Cancelled)?;
         order_account.modified = timestamp;
         OrderAccount::pack(&order_account, &mut order_info.data.borrow_mut());
         // set period end to right now
@@ -156,6 +191,7 @@ pub fn process_cancel_subscription(program_id: &Pubkey, accounts: &[AccountInfo]
 
     // Updating subscription account information...
     subscription_account.status = SubscriptionStatus::Cancelled as u8;
+    subscription_account.modified = timestamp;
     SubscriptionAccount::pack(
         &subscription_account,
         &mut subscription_info.data.borrow_mut(),
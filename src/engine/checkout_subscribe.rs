@@ -0,0 +1,60 @@
+use crate::engine::{
+    pay::{process_order, OrderOptions},
+    subscribe::process_subscribe,
+};
+use serde_json::json;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+/// the number of ExpressCheckout accounts, before its optional accounts
+pub const CHECKOUT_ACCOUNTS_LEN: usize = 13;
+
+/// Pay for a subscription and subscribe in one instruction
+///
+/// Processes an express checkout for an order that names the subscription, then
+/// subscribes with that order, so that the two can not be split up.
+pub fn process_checkout_and_subscribe(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    order_id: String,
+    secret: String,
+    name: String,
+    maybe_data: Option<String>,
+) -> ProgramResult {
+    if accounts.len() <= CHECKOUT_ACCOUNTS_LEN {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let (checkout_accounts, other_accounts) = accounts.split_at(CHECKOUT_ACCOUNTS_LEN);
+    let subscription_info = &other_accounts[0];
+    // the optional checkout accounts come after the subscription account
+    let optional_accounts = &other_accounts[1..];
+
+    let order_data = json!({ "subscription": subscription_info.key.to_string() }).to_string();
+    process_order(
+        program_id,
+        &[checkout_accounts, optional_accounts].concat(),
+        amount,
+        order_id,
+        secret,
+        Some(order_data),
+        OrderOptions::default(),
+    )?;
+
+    // signer, subscription, merchant, order, system program and rent sysvar
+    let mut subscribe_accounts = vec![
+        checkout_accounts[0].clone(),
+        subscription_info.clone(),
+        checkout_accounts[2].clone(),
+        checkout_accounts[1].clone(),
+        checkout_accounts[10].clone(),
+        checkout_accounts[11].clone(),
+    ];
+    // the package account, if any, is the last account
+    if let Some(package_info) = optional_accounts.last() {
+        subscribe_accounts.push(package_info.clone());
+    }
+    process_subscribe(program_id, &subscribe_accounts, name, maybe_data)
+}
@@ -0,0 +1,85 @@
+use crate::{
+    engine::{common::transfer_sol, constants::SUBSCRIPTION_CLOSE_DELAY},
+    error::PaymentProcessorError,
+    state::{Discriminator, IsClosed, Serdes, SubscriptionAccount, SubscriptionStatus},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+/// Close Subscription
+///
+/// Gives the rent of a subscription back to its owner once the subscription is
+/// cancelled, or has lapsed for at least SUBSCRIPTION_CLOSE_DELAY, and no paid
+/// period remains.
+pub fn process_close_subscription(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let subscription_info = next_account_info(account_info_iter)?;
+    let destination_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure subscription account is owned by this program
+    if *subscription_info.owner != *program_id {
+        msg!("Error: Wrong owner for subscription account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the subscription account
+    let subscription_account = SubscriptionAccount::unpack(&subscription_info.data.borrow())?;
+    if !subscription_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if subscription_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if subscription_account.discriminator != Discriminator::Subscription as u8 {
+        msg!("Error: Invalid subscription account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the signer owns the subscription
+    if signer_info.key.to_bytes() != subscription_account.owner {
+        msg!("Error: One can only close their own subscription");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // paused subscriptions still have time left in their period
+    if subscription_account.status == SubscriptionStatus::Paused as u8 {
+        msg!("Error: Paused subscriptions can not be closed");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+    if timestamp < subscription_account.period_end {
+        msg!("Error: Subscription period has not ended");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+    // a lapsed subscription can still be renewed for a while
+    if !subscription_account.is_cancelled()
+        && timestamp < subscription_account.period_end + SUBSCRIPTION_CLOSE_DELAY
+    {
+        msg!("Error: Subscription must be cancelled or have lapsed long enough");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+
+    // Zeroing the subscription account data...
+    for byte in subscription_info.data.borrow_mut().iter_mut() {
+        *byte = 0;
+    }
+    // Transfer all the sol from the subscription account to the destination.
+    transfer_sol(
+        subscription_info.clone(),
+        destination_info.clone(),
+        subscription_info.lamports(),
+    )?;
+
+    Ok(())
+}
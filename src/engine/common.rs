@@ -1,23 +1,35 @@
+use crate::engine::constants::{ASSOCIATED_TOKEN_PROGRAM_ID, KEEPER_REWARD_IN_LAMPORTS};
+use crate::error::PaymentProcessorError;
+use crate::state::{OrderAccount, Serdes};
+use crate::utils::{get_associated_token_address, token_account_rent};
+#[cfg(feature = "json")]
 use crate::{
-    engine::json::{OrderSubscription, Package, Packages},
-    error::PaymentProcessorError,
-    state::{Discriminator, IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes},
+    engine::json::{parse_packages, OrderSubscription, Package},
+    state::{Discriminator, IsClosed, MerchantAccount, OrderStatus, PackageAccount},
+    utils::get_package_address,
 };
+#[cfg(feature = "json")]
 use serde_json::Error as JSONError;
 use solana_program::program_pack::Pack;
 use solana_program::{
-    account_info::AccountInfo,
+    account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
-    program_pack::IsInitialized,
     pubkey::Pubkey,
-    system_instruction,
-    sysvar::rent::Rent,
+    system_instruction, system_program,
+    sysvar::{self, rent::Rent, Sysvar},
 };
+#[cfg(feature = "json")]
+use solana_program::{program_pack::IsInitialized, pubkey::MAX_SEED_LEN};
+use spl_token::state::{Account as TokenAccount, Mint};
+use std::slice::Iter;
+use std::str::FromStr;
 
 /// ensure the order is for the subscription
+#[cfg(feature = "json")]
 pub fn verify_subscription_order(
     subscription_info: &AccountInfo<'_>,
     order_account: &OrderAccount,
@@ -25,7 +37,10 @@ pub fn verify_subscription_order(
     let order_json_data: Result<OrderSubscription, JSONError> =
         serde_json::from_str(&order_account.data);
     let expected_subscription = match order_json_data {
-        Err(_error) => return Err(PaymentProcessorError::InvalidSubscriptionData.into()),
+        Err(_error) => {
+            msg!("Error: The order data does not name a subscription");
+            return Err(PaymentProcessorError::InvalidOrderData.into());
+        }
         Ok(data) => data.subscription,
     };
     if expected_subscription != subscription_info.key.to_string() {
@@ -35,16 +50,18 @@ pub fn verify_subscription_order(
 }
 
 /// Get subscription package
+#[cfg(feature = "json")]
 pub fn get_subscription_package(
     subscription_package_name: &str,
     merchant_account: &MerchantAccount,
 ) -> Result<Package, ProgramError> {
     // ensure the merchant has a subscription by this name
-    let merchant_json_data: Result<Packages, JSONError> =
-        serde_json::from_str(&merchant_account.data);
-    let packages = match merchant_json_data {
-        Err(_error) => return Err(PaymentProcessorError::InvalidSubscriptionData.into()),
-        Ok(data) => data.packages,
+    let packages = match parse_packages(&merchant_account.data) {
+        Err(_error) => {
+            msg!("Error: The merchant data does not have subscription packages");
+            return Err(PaymentProcessorError::InvalidMerchantData.into());
+        }
+        Ok(value) => value,
     };
     // NB: if the are duplicates, take the first one --> verified in a test
     let package = packages
@@ -56,13 +73,86 @@ pub fn get_subscription_package(
     }
 }
 
+/// Get subscription package from a package account
+///
+/// Package accounts do not have a trial period.
+#[cfg(feature = "json")]
+pub fn get_package_from_account(
+    program_id: &Pubkey,
+    merchant_info: &AccountInfo<'_>,
+    package_info: &AccountInfo<'_>,
+    subscription_package_name: &str,
+) -> Result<Package, ProgramError> {
+    if *package_info.owner != *program_id {
+        msg!("Error: Wrong owner for package account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // the package name is a seed of the package account address
+    if subscription_package_name.len() > MAX_SEED_LEN {
+        return Err(PaymentProcessorError::InvalidSubscriptionPackage.into());
+    }
+    let (package_address, _bump_seed) =
+        get_package_address(merchant_info.key, subscription_package_name, program_id);
+    if *package_info.key != package_address {
+        msg!("Error: Package account is incorrect");
+        return Err(PaymentProcessorError::InvalidSubscriptionPackage.into());
+    }
+    let package_account = PackageAccount::unpack(&package_info.data.borrow())?;
+    if package_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if package_account.discriminator != Discriminator::Package as u8 {
+        msg!("Error: Invalid package account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(Package {
+        name: package_account.name,
+        trial: Option::None,
+        trial_price: Option::None,
+        duration: package_account.duration,
+        price: package_account.price,
+        mint: Pubkey::new_from_array(package_account.mint).to_string(),
+    })
+}
+
+/// Get a merchant's subscription package
+///
+/// Looks the package up in the merchant data or, for merchants that keep their
+/// packages in package accounts, in the package account
+#[cfg(feature = "json")]
+pub fn get_merchant_package(
+    program_id: &Pubkey,
+    merchant_info: &AccountInfo<'_>,
+    merchant_account: &MerchantAccount,
+    package_info: Option<&AccountInfo<'_>>,
+    subscription_package_name: &str,
+) -> Result<Package, ProgramError> {
+    if merchant_account.discriminator != Discriminator::MerchantSubscriptionPackages as u8 {
+        return get_subscription_package(subscription_package_name, merchant_account);
+    }
+    match package_info {
+        None => {
+            msg!("Error: Package account is required for this merchant");
+            Err(ProgramError::NotEnoughAccountKeys)
+        }
+        Some(package_info) => get_package_from_account(
+            program_id,
+            merchant_info,
+            package_info,
+            subscription_package_name,
+        ),
+    }
+}
+
 /// run checks for subscription processing
+#[cfg(feature = "json")]
 pub fn subscribe_checks(
     program_id: &Pubkey,
     signer_info: &AccountInfo<'_>,
     merchant_info: &AccountInfo<'_>,
     order_info: &AccountInfo<'_>,
     subscription_info: &AccountInfo<'_>,
+    package_info: Option<&AccountInfo<'_>>,
     subscription_name: &str,
 ) -> Result<(OrderAccount, Package), ProgramError> {
     // ensure signer can sign
@@ -89,6 +179,7 @@ pub fn subscribe_checks(
     let allowed_merchant_account_types = vec![
         Discriminator::MerchantSubscription as u8,
         Discriminator::MerchantSubscriptionWithTrial as u8,
+        Discriminator::MerchantSubscriptionPackages as u8,
     ];
     if !allowed_merchant_account_types.contains(&merchant_account.discriminator) {
         msg!("Error: Invalid merchant account");
@@ -121,18 +212,175 @@ pub fn subscribe_checks(
         return Err(ProgramError::InvalidAccountData);
     }
     // get the package
-    let package = get_subscription_package(subscription_name, &merchant_account)?;
+    let package = get_merchant_package(
+        program_id,
+        merchant_info,
+        &merchant_account,
+        package_info,
+        subscription_name,
+    )?;
     if package.mint != Pubkey::new_from_array(order_account.mint).to_string() {
         return Err(PaymentProcessorError::WrongMint.into());
     }
     Ok((order_account, package))
 }
 
+/// ensure the token program account is the SPL token program
+///
+/// The token program account is used in CPIs so we must never invoke
+/// whatever program the caller passed in its place
+pub fn token_program_check(token_program_info: &AccountInfo<'_>) -> ProgramResult {
+    if *token_program_info.key != spl_token::id() {
+        msg!("Error: Token program account is not the SPL token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// ensure the mint account is an initialized mint of the SPL token program
+///
+/// Otherwise any account (e.g. a token account derived for the order) could be
+/// passed as the mint, and we would rely on later token program calls to fail
+pub fn mint_check(mint_info: &AccountInfo<'_>) -> Result<Mint, ProgramError> {
+    if *mint_info.owner != spl_token::id() {
+        msg!(
+            "Error: Mint {} must be owned by token program",
+            mint_info.key
+        );
+        return Err(PaymentProcessorError::InvalidMint.into());
+    }
+    match Mint::unpack(&mint_info.data.borrow()) {
+        Ok(mint_data) => Ok(mint_data),
+        Err(_error) => {
+            msg!("Error: Mint {} is not an initialized mint", mint_info.key);
+            Err(PaymentProcessorError::InvalidMint.into())
+        }
+    }
+}
+
+/// ensure the system program account is the system program
+///
+/// Like the token program account, it is passed to CPIs that create accounts and
+/// transfer lamports
+pub fn system_program_check(system_program_info: &AccountInfo<'_>) -> ProgramResult {
+    if *system_program_info.key != system_program::id() {
+        msg!("Error: System program account is not the system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// get the rent from the rent sysvar account if it is the next account, or else
+/// from the Rent::get syscall
+///
+/// Older clients pass the rent sysvar account and newer ones leave it out, the
+/// accounts after it are the same either way.
+pub fn next_rent(account_info_iter: &mut Iter<AccountInfo<'_>>) -> Result<Rent, ProgramError> {
+    match account_info_iter.as_slice().first() {
+        Some(account_info) if *account_info.key == sysvar::rent::id() => {
+            Rent::from_account_info(next_account_info(account_info_iter)?)
+        }
+        _ => Rent::get(),
+    }
+}
+
+/// ensure the account we are about to create does not already exist
+///
+/// Creating an account that already exists fails inside the system program with
+/// an error that does not say which account is the problem
+pub fn account_not_exists_check(account_info: &AccountInfo<'_>) -> ProgramResult {
+    if !account_info.data_is_empty() || *account_info.owner != system_program::id() {
+        msg!("Error: Account {} already exists", account_info.key);
+        return Err(PaymentProcessorError::AccountAlreadyExists.into());
+    }
+    Ok(())
+}
+
+/// Create an account owned by this program
+///
+/// Anyone can send lamports to an address before it is created, which makes
+/// system_instruction::create_account fail, so the account is funded with only
+/// what it is missing for rent exemption and then allocated and assigned.  The
+/// signer seeds are those of the new account, or empty when it signs itself.
+pub fn create_program_account<'a>(
+    program_id: &Pubkey,
+    payer_info: &AccountInfo<'a>,
+    new_account_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    account_size: usize,
+    rent: &Rent,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    let required_lamports = rent
+        .minimum_balance(account_size)
+        .max(1)
+        .saturating_sub(new_account_info.lamports());
+    if required_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer_info.key, new_account_info.key, required_lamports),
+            &[
+                payer_info.clone(),
+                new_account_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+    }
+    // no seeds at all would make the runtime derive an address from nothing,
+    // which fails for many program ids
+    let signers = [signer_seeds];
+    let signers: &[&[&[u8]]] = if signer_seeds.is_empty() {
+        &[]
+    } else {
+        &signers
+    };
+    invoke_signed(
+        &system_instruction::allocate(new_account_info.key, account_size as u64),
+        &[new_account_info.clone(), system_program_info.clone()],
+        signers,
+    )?;
+    invoke_signed(
+        &system_instruction::assign(new_account_info.key, program_id),
+        &[new_account_info.clone(), system_program_info.clone()],
+        signers,
+    )
+}
+
+/// Save the order account
+///
+/// Orders from before refunds, locks and consumption have no room to record them
+/// (see OrderAccount::fits), so they can only be saved while those are unused.
+pub fn pack_order_account(
+    order_info: &AccountInfo<'_>,
+    order_account: &OrderAccount,
+) -> ProgramResult {
+    if !order_account.fits(order_info.data_len()) {
+        msg!("Error: The order account is too old to record refunds, locks or consumption");
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    OrderAccount::pack(order_account, &mut order_info.data.borrow_mut());
+    Ok(())
+}
+
+/// ensure an account the program created holds enough lamports to be rent exempt
+pub fn assert_rent_exempt(
+    account_info: &AccountInfo<'_>,
+    account_size: usize,
+    rent: &Rent,
+) -> ProgramResult {
+    if !rent.is_exempt(account_info.lamports(), account_size) {
+        msg!("Error: Account {} is not rent exempt", account_info.key);
+        return Err(ProgramError::AccountNotRentExempt);
+    }
+    Ok(())
+}
+
 /// Create associated token account
 ///
 /// Creates an associated token account that is owned by a custom program.
 /// This is similar to spl_associated_token_account::create_associated_token_account
-/// which would fail for creating token accounts not owned by the token program
+/// which would fail for creating token accounts not owned by the token program.
+/// A token account that already exists with the right mint and owner (the PDA) is
+/// left as it is, so that retrying after a partial earlier attempt succeeds.
 pub fn create_program_owned_associated_token_account(
     program_id: &Pubkey,
     accounts: &[AccountInfo; 8],
@@ -160,6 +408,20 @@ pub fn create_program_owned_associated_token_account(
         msg!("Error: Associated address does not match seed derivation");
         return Err(ProgramError::InvalidSeeds);
     }
+    // the token account may have been initialized already, which is fine if it is
+    // the token account we were going to create anyway
+    if *new_account_info.owner == spl_token::id() {
+        let token_account = TokenAccount::unpack(&new_account_info.data.borrow())?;
+        if token_account.mint != *mint_info.key || token_account.owner != *pda_info.key {
+            msg!("Error: The token account already exists with another mint or owner");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        msg!(
+            "Info: Token account {} already exists",
+            new_account_info.key
+        );
+        return Ok(());
+    }
     // get signer seeds
     let associated_token_account_signer_seeds: &[&[_]] = &[
         &base_account_info.key.to_bytes(),
@@ -167,9 +429,10 @@ pub fn create_program_owned_associated_token_account(
         &mint_info.key.to_bytes(),
         &[bump_seed],
     ];
+    // only mints of the SPL token program are supported so this is the classic length
+    let account_len = spl_token::state::Account::LEN;
     // Fund the associated seller token account with the minimum balance to be rent exempt
-    let required_lamports = rent
-        .minimum_balance(spl_token::state::Account::LEN)
+    let required_lamports = token_account_rent(rent, account_len)
         .max(1)
         .saturating_sub(new_account_info.lamports());
     if required_lamports > 0 {
@@ -189,7 +452,7 @@ pub fn create_program_owned_associated_token_account(
     }
     // Allocate space for the associated seller token account
     invoke_signed(
-        &system_instruction::allocate(new_account_info.key, spl_token::state::Account::LEN as u64),
+        &system_instruction::allocate(new_account_info.key, account_len as u64),
         &[new_account_info.clone(), system_program_info.clone()],
         &[&associated_token_account_signer_seeds],
     )?;
@@ -215,10 +478,75 @@ pub fn create_program_owned_associated_token_account(
             token_program_info.clone(),
         ],
     )?;
+    // ensure the associated seller token account is rent exempt
+    assert_rent_exempt(new_account_info, spl_token::state::Account::LEN, rent)?;
 
     Ok(())
 }
 
+/// Create a wallet's associated token account
+///
+/// Invokes the associated token account program, which is not a dependency of
+/// this program so its instruction is built here.  The funder pays the rent of
+/// the new token account, which belongs to the wallet.
+pub fn create_associated_token_account(accounts: &[AccountInfo; 8]) -> ProgramResult {
+    let funder_info = &accounts[0];
+    let new_account_info = &accounts[1];
+    let wallet_info = &accounts[2];
+    let mint_info = &accounts[3];
+    let system_program_info = &accounts[4];
+    let token_program_info = &accounts[5];
+    let rent_sysvar_info = &accounts[6];
+    let associated_token_program_info = &accounts[7];
+
+    if *associated_token_program_info.key != Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID).unwrap()
+    {
+        msg!("Error: Wrong associated token account program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // assert that the derived address matches the one supplied
+    if *new_account_info.key != get_associated_token_address(wallet_info.key, mint_info.key) {
+        msg!("Error: Associated address does not match seed derivation");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    invoke(
+        &Instruction {
+            program_id: *associated_token_program_info.key,
+            accounts: vec![
+                AccountMeta::new(*funder_info.key, true),
+                AccountMeta::new(*new_account_info.key, false),
+                AccountMeta::new_readonly(*wallet_info.key, false),
+                AccountMeta::new_readonly(*mint_info.key, false),
+                AccountMeta::new_readonly(*system_program_info.key, false),
+                AccountMeta::new_readonly(*token_program_info.key, false),
+                AccountMeta::new_readonly(*rent_sysvar_info.key, false),
+            ],
+            data: vec![],
+        },
+        accounts,
+    )
+}
+
+/// Ensure that no account is passed in more than one of these positions
+///
+/// Accounts are read by position, so an account passed twice could e.g. receive
+/// a transfer that it also pays for.
+pub fn distinct_accounts_check(accounts: &[&AccountInfo]) -> ProgramResult {
+    for (index, account_info) in accounts.iter().enumerate() {
+        if accounts[..index]
+            .iter()
+            .any(|other_info| other_info.key == account_info.key)
+        {
+            msg!(
+                "Error: Account {} is passed more than once",
+                account_info.key
+            );
+            return Err(PaymentProcessorError::DuplicateAccount.into());
+        }
+    }
+    Ok(())
+}
+
 /// Transfer SOL from one account to another
 /// Used for accounts not owned by the system program
 pub fn transfer_sol(
@@ -235,3 +563,23 @@ pub fn transfer_sol(
     **sol_origin_info.lamports.borrow_mut() = origin_starting_lamports.checked_sub(amount).unwrap();
     Ok(())
 }
+
+/// Pay the keeper reward from an account owned by this program
+///
+/// Only the lamports above the rent exemption of the account are used, so that
+/// the account can persist.  The reward is less (or nothing) when there are not
+/// enough of them.  Returns the reward paid.
+pub fn pay_keeper_reward(
+    account_info: &AccountInfo,
+    keeper_info: &AccountInfo,
+    rent: &Rent,
+) -> Result<u64, ProgramError> {
+    let reward = account_info
+        .lamports()
+        .saturating_sub(rent.minimum_balance(account_info.data_len()))
+        .min(KEEPER_REWARD_IN_LAMPORTS);
+    if reward > 0 {
+        transfer_sol(account_info.clone(), keeper_info.clone(), reward)?;
+    }
+    Ok(reward)
+}
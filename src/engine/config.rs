@@ -0,0 +1,226 @@
+use crate::{
+    engine::common::{
+        assert_rent_exempt, create_program_account, system_program_check, transfer_sol,
+    },
+    engine::constants::{CONFIG_SEED, MAX_REGISTRANTS, PROGRAM_OWNER, REGISTRY_SEED},
+    error::PaymentProcessorError,
+    state::{ConfigAccount, Discriminator, IsClosed, RegistryAccount, Serdes},
+    utils::{get_config_address, get_registry_address},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::{rent::Rent, Sysvar},
+};
+use std::str::FromStr;
+
+/// Get the key that collects the program owner fees
+///
+/// Until the config account is created this is the program owner.
+pub fn get_program_owner(
+    program_id: &Pubkey,
+    config_info: &AccountInfo<'_>,
+) -> Result<Pubkey, ProgramError> {
+    let (config_address, _bump_seed) = get_config_address(program_id);
+    if *config_info.key != config_address {
+        msg!("Error: Config account is incorrect");
+        return Err(PaymentProcessorError::WrongConfig.into());
+    }
+    if config_info.data_is_empty() {
+        return Ok(Pubkey::from_str(PROGRAM_OWNER).unwrap());
+    }
+    if *config_info.owner != *program_id {
+        msg!("Error: Wrong owner for config account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let config_account = ConfigAccount::unpack(&config_info.data.borrow())?;
+    if config_account.discriminator != Discriminator::Config as u8 {
+        return Err(PaymentProcessorError::WrongConfig.into());
+    }
+    Ok(Pubkey::new_from_array(config_account.owner))
+}
+
+/// Update the key that collects the program owner fees
+///
+/// Only the current owner can do this.  The config account is created the
+/// first time this is called, by which time the current owner is the program
+/// owner.
+pub fn process_update_config_owner(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_owner: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // only the current owner can hand over the config
+    let current_owner = get_program_owner(program_id, config_info)?;
+    if *signer_info.key != current_owner {
+        msg!("Error: Only the config owner can update the config");
+        return Err(PaymentProcessorError::WrongProgramOwner.into());
+    }
+
+    if config_info.data_is_empty() {
+        // Creating config account on chain...
+        let (_config_address, bump_seed) = get_config_address(program_id);
+        create_program_account(
+            program_id,
+            signer_info,
+            config_info,
+            system_program_info,
+            ConfigAccount::LEN,
+            rent,
+            &[CONFIG_SEED, &[bump_seed]],
+        )?;
+    }
+
+    // Updating config account information...
+    let config_account = ConfigAccount {
+        discriminator: Discriminator::Config as u8,
+        owner: new_owner.to_bytes(),
+    };
+    config_account.pack(&mut config_info.try_borrow_mut_data()?);
+    // ensure config account is rent exempt
+    assert_rent_exempt(config_info, ConfigAccount::LEN, rent)?;
+
+    Ok(())
+}
+
+/// Ensure the signer can register merchants
+///
+/// Until the registry account is created anyone can.
+pub fn registrant_check(
+    program_id: &Pubkey,
+    registry_info: &AccountInfo<'_>,
+    signer_info: &AccountInfo<'_>,
+) -> ProgramResult {
+    let (registry_address, _bump_seed) = get_registry_address(program_id);
+    if *registry_info.key != registry_address {
+        msg!("Error: Registry account is incorrect");
+        return Err(PaymentProcessorError::WrongConfig.into());
+    }
+    if registry_info.data_is_empty() {
+        return Ok(());
+    }
+    if *registry_info.owner != *program_id {
+        msg!("Error: Wrong owner for registry account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let registry_account = RegistryAccount::unpack(&registry_info.data.borrow())?;
+    // a registry closed earlier in the transaction is as good as gone
+    if registry_account.is_closed() {
+        return Ok(());
+    }
+    if registry_account.discriminator != Discriminator::Registry as u8 {
+        return Err(PaymentProcessorError::WrongConfig.into());
+    }
+    if !registry_account.is_approved(&signer_info.key.to_bytes()) {
+        msg!(
+            "Error: {} is not approved to register merchants",
+            signer_info.key
+        );
+        return Err(PaymentProcessorError::RegistrantNotApproved.into());
+    }
+    Ok(())
+}
+
+/// Update the keys approved to register merchants
+///
+/// Only the config owner can do this.  The registry account is created the first
+/// time this is called, and closed when no keys are left so that anyone can
+/// register merchants again.
+pub fn process_update_registry(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    registrants: Vec<Pubkey>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // only the config owner can approve registrants
+    if *signer_info.key != get_program_owner(program_id, config_info)? {
+        msg!("Error: Only the config owner can update the registry");
+        return Err(PaymentProcessorError::WrongProgramOwner.into());
+    }
+    let (registry_address, bump_seed) = get_registry_address(program_id);
+    if *registry_info.key != registry_address {
+        msg!("Error: Registry account is incorrect");
+        return Err(PaymentProcessorError::WrongConfig.into());
+    }
+    if registrants.len() > MAX_REGISTRANTS {
+        msg!(
+            "Error: The registry can approve at most {:?} keys",
+            MAX_REGISTRANTS
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if registrants.is_empty() {
+        if !registry_info.data_is_empty() {
+            // Closing the registry account...
+            RegistryAccount {
+                discriminator: Discriminator::Closed as u8,
+                registrants: vec![],
+            }
+            .pack(&mut registry_info.try_borrow_mut_data()?);
+            transfer_sol(
+                registry_info.clone(),
+                signer_info.clone(),
+                registry_info.lamports(),
+            )?;
+        }
+        return Ok(());
+    }
+
+    if registry_info.data_is_empty() {
+        // Creating registry account on chain...
+        create_program_account(
+            program_id,
+            signer_info,
+            registry_info,
+            system_program_info,
+            RegistryAccount::LEN,
+            rent,
+            &[REGISTRY_SEED, &[bump_seed]],
+        )?;
+    }
+
+    // Updating registry account information...
+    let registry_account = RegistryAccount {
+        discriminator: Discriminator::Registry as u8,
+        registrants: registrants.iter().map(|key| key.to_bytes()).collect(),
+    };
+    registry_account.pack(&mut registry_info.try_borrow_mut_data()?);
+    // ensure registry account is rent exempt
+    assert_rent_exempt(registry_info, RegistryAccount::LEN, rent)?;
+
+    Ok(())
+}
@@ -10,14 +10,60 @@ pub const PAID: &str = "_paid";
 pub const INITIAL: &str = "_initial";
 /// seed for pgram derived addresses
 pub const PDA_SEED: &[u8] = b"sol_payment_processor";
+/// seed for order accounts derived from the merchant order count
+pub const ORDER_SEED: &[u8] = b"order";
+/// seed for subscription package accounts derived from the merchant and package name
+pub const PACKAGE_SEED: &[u8] = b"package";
+/// seed for coupon accounts derived from the merchant and coupon code
+pub const COUPON_SEED: &[u8] = b"coupon";
+/// seed for the ledger accounts of the recent payments of a merchant
+pub const LEDGER_SEED: &[u8] = b"ledger";
+/// seed for the program config account
+pub const CONFIG_SEED: &[u8] = b"config";
+/// seed for the registry account of the keys approved to register merchants
+pub const REGISTRY_SEED: &[u8] = b"registry";
 /// the program owner
 pub const PROGRAM_OWNER: &str = "mosh782eoKyPca9eotWfepHVSKavjDMBjNkNE3Gge6Z";
 /// minimum transaction fee percentage
 pub const MIN_FEE_IN_LAMPORTS: u64 = 50000;
 /// default transaction fee percentage
 pub const DEFAULT_FEE_IN_LAMPORTS: u64 = 500000;
+/// the maximum number of buyers a merchant can charge a different fee
+pub const MAX_FEE_OVERRIDES: usize = 16;
+/// the maximum number of keys the registry can approve to register merchants
+pub const MAX_REGISTRANTS: usize = 32;
+/// the number of recent payments a merchant ledger keeps
+pub const LEDGER_CAPACITY: usize = 32;
+/// the Pyth oracle program that owns the price accounts used to quote checkouts in USD
+pub const PYTH_PROGRAM_ID: &str = "FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH";
+/// the program that creates the associated token accounts of wallets
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+/// the Ed25519 program that verifies the signatures of merchant signed orders
+pub const ED25519_PROGRAM_ID: &str = "Ed25519SigVerify111111111111111111111111111";
+/// the number of decimals of USD amounts e.g. 1000000 is one dollar
+pub const USD_DECIMALS: u32 = 6;
+/// the maximum number of slots since an oracle price was published for it to be used
+pub const MAX_PRICE_AGE_IN_SLOTS: u64 = 25;
+/// the time (in seconds) after its period ends that a lapsed subscription can be closed
+pub const SUBSCRIPTION_CLOSE_DELAY: i64 = 2592000;
+/// the basis points in a whole payment e.g. the most a charity can receive
+pub const MAX_BPS: u16 = 10000;
+/// the reward (in SOL lamports) paid to keepers for permissionless maintenance
+pub const KEEPER_REWARD_IN_LAMPORTS: u64 = 50000;
 /// sponsor fee percentage
 pub const SPONSOR_FEE: u128 = 3;
+/// the maximum instruction data size, the same as the maximum transaction size
+pub const MAX_INSTRUCTION_DATA_SIZE: usize = 1232;
+/// the maximum length of the client tag sent with a checkout
+pub const MAX_CLIENT_TAG_LEN: usize = 32;
+/// the maximum number of different items in a chain checkout order
+pub const MAX_ORDER_ITEMS: usize = 10;
+/// the maximum length of the id of a chain checkout item
+pub const MAX_ITEM_ID_LEN: usize = 32;
+/// the length of the order id and secret of compact express checkouts
+pub const COMPACT_ID_LEN: usize = 16;
+/// the maximum length of an order id built with utils::OrderId
+pub const MAX_ORDER_ID_LEN: usize = 64;
 /// default data value
 pub const DEFAULT_DATA: &str = "{}";
 // these are purely by trial and error ... TODO: understand these some more
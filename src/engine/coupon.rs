@@ -0,0 +1,211 @@
+use crate::{
+    engine::{
+        common::{
+            account_not_exists_check, assert_rent_exempt, create_program_account,
+            system_program_check,
+        },
+        constants::{COUPON_SEED, MAX_BPS},
+    },
+    error::PaymentProcessorError,
+    state::{CouponAccount, Discriminator, IsClosed, MerchantAccount, Serdes},
+    utils::{get_coupon_account_size, get_coupon_address},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::UnixTimestamp,
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::{Pubkey, MAX_SEED_LEN},
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+
+/// run checks for managing coupons
+fn coupon_checks(
+    program_id: &Pubkey,
+    signer_info: &AccountInfo<'_>,
+    merchant_info: &AccountInfo<'_>,
+) -> Result<MerchantAccount, ProgramError> {
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure merchant account is owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // only the merchant can manage its coupons
+    if !merchant_account.can_operate(&signer_info.key.to_bytes()) {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    Ok(merchant_account)
+}
+
+/// Redeem a coupon of the merchant once
+///
+/// Counts the redemption in the coupon account, failing if the coupon expired or was
+/// redeemed as many times as it can be.  Returns the coupon account.
+pub fn redeem_coupon(
+    program_id: &Pubkey,
+    coupon_info: &AccountInfo<'_>,
+    merchant_info: &AccountInfo<'_>,
+    timestamp: UnixTimestamp,
+) -> Result<CouponAccount, ProgramError> {
+    // ensure coupon account is owned by this program
+    if *coupon_info.owner != *program_id {
+        msg!("Error: Wrong owner for coupon account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the coupon account
+    let mut coupon_account = CouponAccount::unpack(&coupon_info.data.borrow())?;
+    if coupon_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !coupon_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if coupon_account.discriminator != Discriminator::Coupon as u8 {
+        msg!("Error: Invalid coupon account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the coupon belongs to this merchant
+    if merchant_info.key.to_bytes() != coupon_account.merchant {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    if coupon_account.is_expired(timestamp) {
+        msg!("Error: Coupon {} has expired", coupon_account.code);
+        return Err(PaymentProcessorError::CouponExpired.into());
+    }
+    if coupon_account.is_exhausted() {
+        msg!(
+            "Error: Coupon {} was already redeemed {:?} times",
+            coupon_account.code,
+            coupon_account.used_count
+        );
+        return Err(PaymentProcessorError::CouponExhausted.into());
+    }
+
+    // Updating coupon account information...
+    coupon_account.used_count += 1;
+    CouponAccount::pack(&coupon_account, &mut coupon_info.data.borrow_mut());
+    msg!(
+        "Coupon: {} ({:?} of {:?})",
+        coupon_account.code,
+        coupon_account.used_count,
+        coupon_account.max_redemptions
+    );
+
+    Ok(coupon_account)
+}
+
+/// Create Coupon
+///
+/// Creates a coupon account for the merchant, derived from the merchant and the
+/// coupon code (see utils::get_coupon_address).
+pub fn process_create_coupon(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    code: String,
+    bps: u16,
+    max_redemptions: u64,
+    expiry: UnixTimestamp,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let coupon_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    coupon_checks(program_id, signer_info, merchant_info)?;
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // the coupon code is a seed of the coupon account address
+    if code.is_empty() || code.len() > MAX_SEED_LEN {
+        msg!("Error: Coupon code must be 1 to {:?} bytes", MAX_SEED_LEN);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if bps > MAX_BPS {
+        msg!(
+            "Error: The coupon discount can be at most {:?} bps",
+            MAX_BPS
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+    if max_redemptions == 0 {
+        msg!("Error: The coupon must be redeemable at least once");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let (coupon_address, bump_seed) = get_coupon_address(merchant_info.key, &code, program_id);
+    if *coupon_info.key != coupon_address {
+        msg!("Error: Coupon address does not match seed derivation");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // ensure this is not an existing coupon
+    account_not_exists_check(coupon_info)?;
+
+    // Creating coupon account on chain...
+    let account_size = get_coupon_account_size(&code);
+    create_program_account(
+        program_id,
+        signer_info,
+        coupon_info,
+        system_program_info,
+        account_size,
+        rent,
+        &[
+            &merchant_info.key.to_bytes(),
+            COUPON_SEED,
+            code.as_bytes(),
+            &[bump_seed],
+        ],
+    )?;
+
+    // Saving coupon information...
+    let coupon_account = CouponAccount {
+        discriminator: Discriminator::Coupon as u8,
+        merchant: merchant_info.key.to_bytes(),
+        bps,
+        max_redemptions,
+        used_count: 0,
+        expiry,
+        code,
+    };
+    coupon_account.pack(&mut coupon_info.try_borrow_mut_data()?);
+    // ensure coupon account is rent exempt
+    assert_rent_exempt(coupon_info, account_size, rent)?;
+
+    Ok(())
+}
+
+/// Redeem Coupon
+///
+/// Counts one redemption of a coupon that was not used in a checkout e.g. at a
+/// point of sale.  Only the merchant can do this.
+pub fn process_redeem_coupon(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let coupon_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    coupon_checks(program_id, signer_info, merchant_info)?;
+    redeem_coupon(program_id, coupon_info, merchant_info, timestamp)?;
+
+    Ok(())
+}
@@ -1,12 +1,20 @@
+#[cfg(feature = "json")]
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
+#[cfg(feature = "json")]
 #[derive(Serialize, Debug, Deserialize, PartialEq)]
 /// Subscription package
 pub struct Package {
     pub name: String,
     /// duration of the trial period in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub trial: Option<i64>,
+    /// the price paid up front for the trial period, for partial trials
+    /// the subscription then lapses at the end of the trial and is converted to a
+    /// paid period by a keeper (see KeeperRenew)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trial_price: Option<u64>,
     /// duration of the subscription in seconds
     pub duration: i64,
     /// the price in full for this subscription option
@@ -17,18 +25,58 @@ pub struct Package {
     pub mint: String,
 }
 
+#[cfg(feature = "json")]
 #[derive(Serialize, Debug, Deserialize, PartialEq)]
 /// Subscription packages
 pub struct Packages {
     pub packages: Vec<Package>,
 }
 
+#[cfg(feature = "json")]
+#[derive(Debug, Default, PartialEq)]
+/// Builds the merchant data for a subscription merchant
+///
+/// e.g. `PackagesBuilder::new().add(basic).add(annual).build()`
+pub struct PackagesBuilder {
+    packages: Vec<Package>,
+}
+
+#[cfg(feature = "json")]
+impl PackagesBuilder {
+    pub fn new() -> Self {
+        PackagesBuilder::default()
+    }
+
+    /// add a subscription package
+    pub fn add(mut self, package: Package) -> Self {
+        self.packages.push(package);
+        self
+    }
+
+    /// the packages as JSON, ready to be used as merchant data
+    pub fn build(self) -> String {
+        serde_json::to_string(&Packages {
+            packages: self.packages,
+        })
+        .unwrap()
+    }
+}
+
+/// Get the subscription packages from the merchant data
+#[cfg(feature = "json")]
+pub fn parse_packages(data: &str) -> Result<Vec<Package>, serde_json::Error> {
+    let packages: Packages = serde_json::from_str(data)?;
+    Ok(packages.packages)
+}
+
+#[cfg(feature = "json")]
 #[derive(Serialize, Debug, Deserialize, PartialEq)]
 /// Used in order account data field to tie the order to a subscription
 pub struct OrderSubscription {
     pub subscription: String,
 }
 
+#[cfg(feature = "json")]
 #[derive(Serialize, Debug, Deserialize, PartialEq)]
 /// Item
 ///
@@ -39,4 +87,4 @@ pub struct Item {
     pub mint: String,
 }
 
-pub type OrderItems = BTreeMap<String, u64>;
\ No newline at end of file
+pub type OrderItems = BTreeMap<String, u64>;
@@ -0,0 +1,262 @@
+use crate::{
+    engine::{
+        common::{
+            assert_rent_exempt, get_merchant_package, pay_keeper_reward, system_program_check,
+            token_program_check,
+        },
+        constants::PDA_SEED,
+        json::OrderSubscription,
+        pay::create_order_accounts,
+    },
+    error::PaymentProcessorError,
+    state::{
+        Discriminator, IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes,
+        SubscriptionAccount, SubscriptionStatus,
+    },
+    utils::get_order_account_size,
+};
+use solana_program::program_pack::Pack;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+use spl_token::{
+    self,
+    state::{Account as TokenAccount, Mint},
+};
+
+/// Keeper Renew
+///
+/// Renews a lapsed subscription for one period.  Anyone (a keeper) can call
+/// this; the keeper pays the rent for the new order account while the price of
+/// the subscription package is pulled from the buyer token account using the
+/// allowance the subscription owner approved for this program's derived address.
+/// No processing fee is charged.  Merchants reward keepers by funding their
+/// merchant account with lamports above its rent exemption (see pay_keeper_reward).
+pub fn process_keeper_renew(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let subscription_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let order_info = next_account_info(account_info_iter)?;
+    let seller_token_info = next_account_info(account_info_iter)?;
+    let buyer_token_info = next_account_info(account_info_iter)?;
+    let mint_info = next_account_info(account_info_iter)?;
+    let pda_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+    let package_info = next_account_info(account_info_iter).ok();
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure the token program is the SPL token program
+    token_program_check(token_program_info)?;
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // ensure subscription & merchant accounts are owned by this program
+    if *subscription_info.owner != *program_id {
+        msg!("Error: Wrong owner for subscription account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // ensure buyer token account is owned by token program
+    if *buyer_token_info.owner != spl_token::id() {
+        msg!("Error: Buyer token account not owned by Token Program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // check that provided pda is correct
+    let (pda, pda_nonce) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+    if pda_info.key != &pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // get the subscription account
+    let mut subscription_account = SubscriptionAccount::unpack(&subscription_info.data.borrow())?;
+    if !subscription_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if subscription_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if subscription_account.discriminator != Discriminator::Subscription as u8 {
+        msg!("Error: Invalid subscription account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // cancelled and paused subscriptions are not renewed
+    if subscription_account.status != SubscriptionStatus::Initialized as u8 {
+        msg!("Error: Subscription is not active");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+    // guard against renewing before the subscription has lapsed
+    if timestamp < subscription_account.period_end {
+        return Err(PaymentProcessorError::SubscriptionNotLapsed.into());
+    }
+    // get the merchant account
+    if merchant_info.key.to_bytes() != subscription_account.merchant {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    let mut merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // get the package
+    let package = get_merchant_package(
+        program_id,
+        merchant_info,
+        &merchant_account,
+        package_info,
+        &subscription_account.name,
+    )?;
+    if package.mint != mint_info.key.to_string() {
+        return Err(PaymentProcessorError::WrongMint.into());
+    }
+    // ensure the buyer token account belongs to the subscription owner and that
+    // this program may spend the package price from it
+    let buyer_token_data = TokenAccount::unpack(&buyer_token_info.data.borrow())?;
+    if buyer_token_data.owner != Pubkey::new_from_array(subscription_account.owner) {
+        return Err(PaymentProcessorError::WrongPayer.into());
+    }
+    if buyer_token_data.mint != *mint_info.key {
+        return Err(PaymentProcessorError::MintNotEqual.into());
+    }
+    if buyer_token_data.delegate != COption::Some(pda) {
+        msg!("Error: Buyer token account has not been delegated to this program");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if buyer_token_data.delegated_amount < package.price {
+        msg!("Error: Insufficient delegated amount");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    // the order is tied to the subscription just like a regular renewal
+    let order_id = format!("{timestamp}", timestamp = timestamp);
+    let secret = Option::None;
+    let data = match serde_json::to_string(&OrderSubscription {
+        subscription: subscription_info.key.to_string(),
+    }) {
+        Err(_error) => return Err(PaymentProcessorError::InvalidOrderData.into()),
+        Ok(value) => value,
+    };
+    let order_account_size = get_order_account_size(&order_id, &secret, &data);
+    create_order_accounts(
+        program_id,
+        &[
+            signer_info.clone(),
+            order_info.clone(),
+            merchant_info.clone(),
+            seller_token_info.clone(),
+            mint_info.clone(),
+            pda_info.clone(),
+            token_program_info.clone(),
+            system_program_info.clone(),
+            rent_sysvar_info.clone(),
+        ],
+        merchant_account.order_count,
+        order_account_size,
+        rent,
+    )?;
+
+    // Transfer the package price using the delegated allowance...
+    let mint_data = Mint::unpack(&mint_info.data.borrow())?;
+    invoke_signed(
+        &spl_token::instruction::transfer_checked(
+            token_program_info.key,
+            buyer_token_info.key,
+            mint_info.key,
+            seller_token_info.key,
+            &pda,
+            &[&pda],
+            package.price,
+            mint_data.decimals,
+        )
+        .unwrap(),
+        &[
+            token_program_info.clone(),
+            buyer_token_info.clone(),
+            mint_info.clone(),
+            seller_token_info.clone(),
+            pda_info.clone(),
+        ],
+        &[&[&PDA_SEED, &[pda_nonce]]],
+    )?;
+
+    // Saving order information...
+    let mut order = OrderAccount {
+        discriminator: Discriminator::OrderExpressCheckout as u8,
+        status: OrderStatus::Uninitialized as u8,
+        created: timestamp,
+        modified: timestamp,
+        merchant: merchant_info.key.to_bytes(),
+        mint: mint_info.key.to_bytes(),
+        token: seller_token_info.key.to_bytes(),
+        payer: subscription_account.owner,
+        expected_amount: package.price,
+        paid_amount: package.price,
+        order_id,
+        secret,
+        data,
+        refunded_amount: 0,
+        locked: false,
+        // the order pays for this renewal only
+        consumed: true,
+        quoted_amount: 0,
+    };
+    order.transition(OrderStatus::Paid)?;
+    order.pack(&mut order_info.try_borrow_mut_data()?);
+
+    // Updating merchant order count...
+    merchant_account.order_count = merchant_account
+        .order_count
+        .checked_add(1)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    // merchant accounts from before the order count have no room for it, their
+    // orders take keypair accounts rather than ones derived from the count
+    if merchant_account.fits(merchant_info.data_len()) {
+        merchant_account.pack(&mut merchant_info.data.borrow_mut());
+    }
+
+    // Rewarding the keeper from the merchant account...
+    let reward = pay_keeper_reward(merchant_info, signer_info, rent)?;
+    msg!("Info: Keeper rewarded {:?} lamports", reward);
+
+    // partial trials lapse at the end of the trial
+    if subscription_account.period_start == subscription_account.joined
+        && package.trial.is_some()
+        && package.trial_price.is_some()
+    {
+        msg!("Info: Trial converted to a paid subscription");
+    }
+    // the subscription had lapsed so we start a new period
+    subscription_account.period_start = timestamp;
+    subscription_account.period_end = timestamp + package.duration;
+    subscription_account.current_order = order_info.key.to_bytes();
+    subscription_account.modified = timestamp;
+    SubscriptionAccount::pack(
+        &subscription_account,
+        &mut subscription_info.data.borrow_mut(),
+    );
+
+    // ensure order account is rent exempt
+    assert_rent_exempt(order_info, order_account_size, rent)?;
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use crate::{
+    engine::{
+        common::{
+            account_not_exists_check, assert_rent_exempt, create_program_account,
+            system_program_check,
+        },
+        constants::LEDGER_SEED,
+    },
+    error::PaymentProcessorError,
+    state::{Discriminator, IsClosed, LedgerAccount, LedgerEntry, MerchantAccount, Serdes},
+    utils::get_ledger_address,
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+/// Get the ledger account of the merchant, which a checkout appends its payment to
+pub fn ledger_check(
+    program_id: &Pubkey,
+    ledger_info: &AccountInfo<'_>,
+    merchant_info: &AccountInfo<'_>,
+) -> Result<LedgerAccount, ProgramError> {
+    // ensure ledger account is owned by this program
+    if *ledger_info.owner != *program_id {
+        msg!("Error: Wrong owner for ledger account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the ledger account
+    let ledger_account = LedgerAccount::unpack(&ledger_info.data.borrow())?;
+    if ledger_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !ledger_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if ledger_account.discriminator != Discriminator::Ledger as u8 {
+        msg!("Error: Invalid ledger account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the ledger belongs to this merchant
+    if merchant_info.key.to_bytes() != ledger_account.merchant {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    Ok(ledger_account)
+}
+
+/// Append a payment to the merchant ledger, see LedgerAccount::append
+pub fn append_to_ledger(
+    ledger_info: &AccountInfo<'_>,
+    mut ledger_account: LedgerAccount,
+    entry: LedgerEntry,
+) -> ProgramResult {
+    // Updating ledger account information...
+    ledger_account.append(entry);
+    ledger_account.pack(&mut ledger_info.try_borrow_mut_data()?);
+    Ok(())
+}
+
+/// Create Ledger
+///
+/// Creates the ledger account of the merchant (see utils::get_ledger_address),
+/// which checkouts then append their payments to (see instruction::with_ledger).
+pub fn process_create_ledger(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let ledger_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure merchant account is owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // only the merchant can create its ledger
+    if !merchant_account.can_operate(&signer_info.key.to_bytes()) {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    let (ledger_address, bump_seed) = get_ledger_address(merchant_info.key, program_id);
+    if *ledger_info.key != ledger_address {
+        msg!("Error: Ledger address does not match seed derivation");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // ensure this is not an existing ledger
+    account_not_exists_check(ledger_info)?;
+
+    // Creating ledger account on chain...
+    create_program_account(
+        program_id,
+        signer_info,
+        ledger_info,
+        system_program_info,
+        LedgerAccount::LEN,
+        rent,
+        &[&merchant_info.key.to_bytes(), LEDGER_SEED, &[bump_seed]],
+    )?;
+
+    // Saving ledger information...
+    let ledger_account = LedgerAccount {
+        discriminator: Discriminator::Ledger as u8,
+        merchant: merchant_info.key.to_bytes(),
+        count: 0,
+        entries: vec![],
+    };
+    ledger_account.pack(&mut ledger_info.try_borrow_mut_data()?);
+    // ensure ledger account is rent exempt
+    assert_rent_exempt(ledger_info, LedgerAccount::LEN, rent)?;
+
+    Ok(())
+}
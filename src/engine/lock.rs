@@ -0,0 +1,62 @@
+use crate::{
+    engine::{common::pack_order_account, config::get_program_owner},
+    error::PaymentProcessorError,
+    state::{IsClosed, OrderAccount, Serdes},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+/// Lock or unlock an order
+///
+/// Only the program owner (see config::get_program_owner) can do this.  Locked
+/// orders can not be withdrawn, refunded or cancelled until they are unlocked.
+pub fn process_lock_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    locked: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let order_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // only the program owner can lock orders
+    if *signer_info.key != get_program_owner(program_id, config_info)? {
+        msg!("Error: Only the program owner can lock or unlock orders");
+        return Err(PaymentProcessorError::WrongProgramOwner.into());
+    }
+    // ensure order account is owned by this program
+    if *order_info.owner != *program_id {
+        msg!("Error: Wrong owner for order account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the order account
+    let mut order_account = OrderAccount::unpack(&order_info.data.borrow())?;
+    if order_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !order_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    // Updating order account information...
+    order_account.locked = locked;
+    order_account.modified = timestamp;
+    pack_order_account(order_info, &order_account)?;
+
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use crate::{
+    engine::common::{mint_check, token_program_check, transfer_sol},
+    engine::constants::PDA_SEED,
+    error::PaymentProcessorError,
+    state::{
+        Discriminator, IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes,
+        SubscriptionAccount, SubscriptionStatus,
+    },
+    utils::get_merchant_token_address,
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+use spl_token::{self, state::Account as TokenAccount};
+
+/// Merchant Cancel Subscription
+///
+/// Lets the merchant (or its operator) cancel one of its subscribers, ending the
+/// current period right away.  When refunding, whatever is left of the order that
+/// paid for the current period goes back to the payer and the order is closed.
+pub fn process_merchant_cancel_subscription(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    refund: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let subscription_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure merchant and subscription accounts are owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *subscription_info.owner != *program_id {
+        msg!("Error: Wrong owner for subscription account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // only the merchant can cancel its subscribers
+    if !merchant_account.can_operate(&signer_info.key.to_bytes()) {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    // get the subscription account
+    let mut subscription_account = SubscriptionAccount::unpack(&subscription_info.data.borrow())?;
+    if !subscription_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if subscription_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if subscription_account.discriminator != Discriminator::Subscription as u8 {
+        msg!("Error: Invalid subscription account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the subscription belongs to this merchant
+    if merchant_info.key.to_bytes() != subscription_account.merchant {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    if subscription_account.is_cancelled() {
+        msg!("Error: The subscription is already cancelled");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+
+    if refund {
+        let order_info = next_account_info(account_info_iter)?;
+        let order_token_info = next_account_info(account_info_iter)?;
+        let refund_token_info = next_account_info(account_info_iter)?;
+        let account_to_receive_sol_refund_info = next_account_info(account_info_iter)?;
+        let pda_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+
+        // ensure the token program is the SPL token program
+        token_program_check(token_program_info)?;
+        if *order_info.owner != *program_id {
+            msg!("Error: Wrong owner for order account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        // ensure token accounts are owned by token program
+        if *order_token_info.owner != spl_token::id() {
+            msg!("Error: Order token account must be owned by token program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *refund_token_info.owner != spl_token::id() {
+            msg!("Error: Refund token account must be owned by token program");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        // check that provided pda is correct
+        let (pda, pda_nonce) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        if pda_info.key != &pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        // only the order that paid for the current period is refunded
+        if order_info.key.to_bytes() != subscription_account.current_order {
+            return Err(PaymentProcessorError::WrongOrderAccount.into());
+        }
+        // get the order account
+        let mut order_account = OrderAccount::unpack(&order_info.data.borrow())?;
+        if order_account.is_closed() {
+            return Err(PaymentProcessorError::ClosedAccount.into());
+        }
+        if !order_account.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if merchant_info.key.to_bytes() != order_account.merchant {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // ensure the order payment token account is the right one
+        if order_token_info.key.to_bytes() != order_account.token {
+            msg!("Error: Incorrect order token account");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // ensure the mint is the one the order was paid in
+        if mint_info.key.to_bytes() != order_account.mint {
+            return Err(PaymentProcessorError::WrongMint.into());
+        }
+        let mint_data = mint_check(mint_info)?;
+        // locked orders can not be modified
+        if order_account.locked {
+            msg!("Error: Order is locked");
+            return Err(PaymentProcessorError::OrderLocked.into());
+        }
+        // only paid orders that have not been withdrawn can be refunded
+        if order_account.status != OrderStatus::Paid as u8 {
+            msg!("Error: Only paid orders can be refunded");
+            return Err(PaymentProcessorError::InvalidOrder.into());
+        }
+        // the merchant picks the accounts so the refund always goes to the payer
+        let refund_token_data = TokenAccount::unpack(&refund_token_info.data.borrow())?;
+        if refund_token_data.owner.to_bytes() != order_account.payer {
+            msg!("Error: The refund token account must belong to the payer");
+            return Err(PaymentProcessorError::WrongPayer.into());
+        }
+
+        // Transferring what is left of the payment back to the payer...
+        invoke_signed(
+            &spl_token::instruction::transfer_checked(
+                token_program_info.key,
+                order_token_info.key,
+                mint_info.key,
+                refund_token_info.key,
+                &pda,
+                &[&pda],
+                order_account
+                    .paid_amount
+                    .saturating_sub(order_account.refunded_amount),
+                mint_data.decimals,
+            )
+            .unwrap(),
+            &[
+                token_program_info.clone(),
+                order_token_info.clone(),
+                mint_info.clone(),
+                refund_token_info.clone(),
+                pda_info.clone(),
+            ],
+            &[&[&PDA_SEED, &[pda_nonce]]],
+        )?;
+        // the merchant token account is shared by many orders so we leave it open
+        let (merchant_token_address, _bump_seed) = get_merchant_token_address(
+            merchant_info.key,
+            &Pubkey::new_from_array(order_account.mint),
+            program_id,
+        );
+        if *order_token_info.key != merchant_token_address {
+            // Close the order token account since it will never be needed again
+            invoke_signed(
+                &spl_token::instruction::close_account(
+                    token_program_info.key,
+                    order_token_info.key,
+                    account_to_receive_sol_refund_info.key,
+                    &pda,
+                    &[&pda],
+                )
+                .unwrap(),
+                &[
+                    token_program_info.clone(),
+                    order_token_info.clone(),
+                    account_to_receive_sol_refund_info.clone(),
+                    pda_info.clone(),
+                ],
+                &[&[&PDA_SEED, &[pda_nonce]]],
+            )?;
+        }
+        order_account.refunded_amount = order_account.paid_amount;
+        // orders from before refunds have no room for the refunded amount, which
+        // their cancelled status already implies
+        if !order_account.fits(order_info.data_len()) {
+            order_account.refunded_amount = 0;
+        }
+        // mark order account as closed
+        order_account.discriminator = Discriminator::Closed as u8;
+        // Transfer all the sol from the order account to the sol_destination.
+        transfer_sol(
+            order_info.clone(),
+            account_to_receive_sol_refund_info.clone(),
+            order_info.lamports(),
+        )?;
+        // Updating order account information...
+        order_account.transition(OrderStatus::Cancelled)?;
+        order_account.modified = timestamp;
+        OrderAccount::pack(&order_account, &mut order_info.data.borrow_mut());
+    }
+
+    // Updating subscription account information...
+    subscription_account.status = SubscriptionStatus::CancelledByMerchant as u8;
+    subscription_account.period_end = timestamp;
+    subscription_account.modified = timestamp;
+    SubscriptionAccount::pack(
+        &subscription_account,
+        &mut subscription_info.data.borrow_mut(),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use crate::{
+    engine::{
+        common::{
+            create_program_owned_associated_token_account, system_program_check,
+            token_program_check,
+        },
+        constants::PDA_SEED,
+    },
+    error::PaymentProcessorError,
+    state::{IsClosed, MerchantAccount, Serdes},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+/// Create a merchant token account
+///
+/// Creates a durable token account owned by the program for a (merchant, mint)
+/// pair.  All checkouts made to the merchant in this mint can then deposit into
+/// this one account instead of paying rent for a new token account per order.
+pub fn process_create_merchant_token_account(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let merchant_token_info = next_account_info(account_info_iter)?;
+    let mint_info = next_account_info(account_info_iter)?;
+    let pda_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure the token program is the SPL token program
+    token_program_check(token_program_info)?;
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // ensure merchant account is owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // only the merchant can create its token accounts
+    if !merchant_account.can_operate(&signer_info.key.to_bytes()) {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    // check that provided pda is correct
+    let (pda, _pda_nonce) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+    if pda_info.key != &pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    // the merchant account is the base of the derived token account address
+    create_program_owned_associated_token_account(
+        program_id,
+        &[
+            signer_info.clone(),
+            merchant_info.clone(),
+            merchant_token_info.clone(),
+            mint_info.clone(),
+            pda_info.clone(),
+            token_program_info.clone(),
+            system_program_info.clone(),
+            rent_sysvar_info.clone(),
+        ],
+        rent,
+    )?;
+
+    Ok(())
+}
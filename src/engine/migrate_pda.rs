@@ -0,0 +1,140 @@
+use crate::{
+    engine::common::{mint_check, token_program_check},
+    engine::config::get_program_owner,
+    engine::constants::PDA_SEED,
+    error::PaymentProcessorError,
+    utils::get_versioned_pda,
+};
+use solana_program::program_pack::Pack;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use spl_token::{self, state::Account as TokenAccount};
+
+/// Migrate PDA Token Account
+///
+/// Moves all the tokens of a token account owned by the derived address of one seed
+/// version (see utils::get_versioned_pda) to a token account of the same mint owned
+/// by the derived address of another, then closes the old token account.  Only the
+/// program owner (see config::get_program_owner) can do this.
+pub fn process_migrate_pda_token_account(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    from_version: u8,
+    to_version: u8,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+    let source_token_info = next_account_info(account_info_iter)?;
+    let destination_token_info = next_account_info(account_info_iter)?;
+    let pda_info = next_account_info(account_info_iter)?;
+    let account_to_receive_sol_refund_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let mint_info = next_account_info(account_info_iter)?;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // only the program owner can move escrowed tokens
+    if *signer_info.key != get_program_owner(program_id, config_info)? {
+        msg!("Error: Only the program owner can migrate token accounts");
+        return Err(PaymentProcessorError::WrongProgramOwner.into());
+    }
+    // ensure the token program is the SPL token program
+    token_program_check(token_program_info)?;
+    if from_version == to_version {
+        msg!("Error: The token account already belongs to this seed version");
+        return Err(ProgramError::InvalidArgument);
+    }
+    // check that provided pda is correct
+    let (pda, pda_nonce) = get_versioned_pda(program_id, from_version);
+    if pda_info.key != &pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let (new_pda, _new_pda_nonce) = get_versioned_pda(program_id, to_version);
+    // ensure token accounts are owned by token program
+    if *source_token_info.owner != spl_token::id() {
+        msg!("Error: Source token account must be owned by token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *destination_token_info.owner != spl_token::id() {
+        msg!("Error: Destination token account must be owned by token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let source_token_data = TokenAccount::unpack(&source_token_info.data.borrow())?;
+    if source_token_data.owner != pda {
+        msg!("Error: The source token account does not belong to the old derived address");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let destination_token_data = TokenAccount::unpack(&destination_token_info.data.borrow())?;
+    if destination_token_data.owner != new_pda {
+        msg!("Error: The destination token account does not belong to the new derived address");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if destination_token_data.mint != source_token_data.mint {
+        msg!("Error: The token accounts have different mints");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if *mint_info.key != source_token_data.mint {
+        return Err(PaymentProcessorError::WrongMint.into());
+    }
+    let mint_data = mint_check(mint_info)?;
+
+    let version_seed = [from_version];
+    let nonce_seed = [pda_nonce];
+    let signer_seeds: Vec<&[u8]> = if from_version == 0 {
+        vec![PDA_SEED, &nonce_seed]
+    } else {
+        vec![PDA_SEED, &version_seed, &nonce_seed]
+    };
+    // Transferring the tokens to the new token account...
+    invoke_signed(
+        &spl_token::instruction::transfer_checked(
+            token_program_info.key,
+            source_token_info.key,
+            mint_info.key,
+            destination_token_info.key,
+            &pda,
+            &[&pda],
+            source_token_data.amount,
+            mint_data.decimals,
+        )
+        .unwrap(),
+        &[
+            token_program_info.clone(),
+            source_token_info.clone(),
+            mint_info.clone(),
+            destination_token_info.clone(),
+            pda_info.clone(),
+        ],
+        &[&signer_seeds],
+    )?;
+    // Close the old token account since it will never be needed again
+    invoke_signed(
+        &spl_token::instruction::close_account(
+            token_program_info.key,
+            source_token_info.key,
+            account_to_receive_sol_refund_info.key,
+            &pda,
+            &[&pda],
+        )
+        .unwrap(),
+        &[
+            token_program_info.clone(),
+            source_token_info.clone(),
+            account_to_receive_sol_refund_info.clone(),
+            pda_info.clone(),
+        ],
+        &[&signer_seeds],
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use crate::{
+    error::PaymentProcessorError,
+    state::{IsClosed, MerchantAccount, Serdes},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+};
+
+/// Set the operator of a merchant account
+///
+/// Only the merchant owner can do this.  The operator can then withdraw and
+/// manage the merchant without the owner key, and Pubkey::default() removes it.
+pub fn process_set_operator(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    operator: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure merchant account is owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the merchant account
+    let mut merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // only the owner, and not the operator, can change the operator
+    if merchant_account.owner != signer_info.key.to_bytes() {
+        msg!("Error: Only the merchant owner can set the operator");
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+
+    // Updating merchant account information...
+    merchant_account.operator = operator.to_bytes();
+    if !merchant_account.fits(merchant_info.data_len()) {
+        msg!("Error: The merchant account has no room for an operator");
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    MerchantAccount::pack(&merchant_account, &mut merchant_info.data.borrow_mut());
+
+    Ok(())
+}
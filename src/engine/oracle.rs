@@ -0,0 +1,63 @@
+use crate::{
+    engine::constants::{MAX_PRICE_AGE_IN_SLOTS, PYTH_PROGRAM_ID},
+    error::PaymentProcessorError,
+};
+use arrayref::array_ref;
+use solana_program::{account_info::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
+use std::str::FromStr;
+
+/// identifies Pyth accounts
+pub const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+/// the Pyth account type of price accounts
+pub const PYTH_PRICE_ACCOUNT_TYPE: u32 = 3;
+/// the Pyth status of prices that can be used
+pub const PYTH_STATUS_TRADING: u32 = 1;
+// offsets into a Pyth price account, see the Price struct in pyth-client
+pub const MAGIC_OFFSET: usize = 0;
+pub const ACCOUNT_TYPE_OFFSET: usize = 8;
+pub const EXPO_OFFSET: usize = 20;
+pub const PRICE_OFFSET: usize = 208;
+pub const STATUS_OFFSET: usize = 224;
+pub const PUB_SLOT_OFFSET: usize = 232;
+/// the size of the part of a Pyth price account that is read
+pub const PYTH_PRICE_LEN: usize = 240;
+
+/// Get the price of one token in USD from a Pyth price account
+///
+/// The price is price * 10^expo USD, returned as (price, expo).  Only prices
+/// that are trading and were published in the last MAX_PRICE_AGE_IN_SLOTS slots
+/// are returned.
+pub fn get_oracle_price(
+    price_info: &AccountInfo,
+    current_slot: u64,
+) -> Result<(i64, i32), ProgramError> {
+    // ensure the price account belongs to the oracle
+    if *price_info.owner != Pubkey::from_str(PYTH_PROGRAM_ID).unwrap() {
+        msg!("Error: Price account must be owned by the oracle program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let data = price_info.data.borrow();
+    if data.len() < PYTH_PRICE_LEN
+        || u32::from_le_bytes(*array_ref![data, MAGIC_OFFSET, 4]) != PYTH_MAGIC
+        || u32::from_le_bytes(*array_ref![data, ACCOUNT_TYPE_OFFSET, 4]) != PYTH_PRICE_ACCOUNT_TYPE
+    {
+        msg!("Error: Invalid price account");
+        return Err(PaymentProcessorError::InvalidOraclePrice.into());
+    }
+    if u32::from_le_bytes(*array_ref![data, STATUS_OFFSET, 4]) != PYTH_STATUS_TRADING {
+        msg!("Error: The oracle price is not trading");
+        return Err(PaymentProcessorError::InvalidOraclePrice.into());
+    }
+    let pub_slot = u64::from_le_bytes(*array_ref![data, PUB_SLOT_OFFSET, 8]);
+    if current_slot.saturating_sub(pub_slot) > MAX_PRICE_AGE_IN_SLOTS {
+        msg!(
+            "Error: The oracle price was published at slot {:?}",
+            pub_slot
+        );
+        return Err(PaymentProcessorError::InvalidOraclePrice.into());
+    }
+    Ok((
+        i64::from_le_bytes(*array_ref![data, PRICE_OFFSET, 8]),
+        i32::from_le_bytes(*array_ref![data, EXPO_OFFSET, 4]),
+    ))
+}
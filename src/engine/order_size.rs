@@ -0,0 +1,33 @@
+use crate::{
+    engine::constants::DEFAULT_DATA,
+    utils::{get_order_account_size, get_order_secret},
+};
+use solana_program::{
+    entrypoint::ProgramResult,
+    msg,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+/// Compute Order Size
+///
+/// Logs the size and rent of the order account an ExpressCheckout with this order
+/// id, secret and data creates, sized the same way process_order sizes it.
+pub fn process_compute_order_size(
+    order_id: String,
+    secret: String,
+    maybe_data: Option<String>,
+) -> ProgramResult {
+    let data = match maybe_data {
+        None => String::from(DEFAULT_DATA),
+        Some(value) => value,
+    };
+    let order_account_size = get_order_account_size(&order_id, &get_order_secret(secret), &data);
+    let rent = Rent::get()?;
+    msg!("Order account size: {:?}", order_account_size);
+    msg!(
+        "Order account rent: {:?}",
+        rent.minimum_balance(order_account_size)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use crate::{
+    engine::{
+        common::{
+            account_not_exists_check, assert_rent_exempt, create_program_account,
+            system_program_check, transfer_sol,
+        },
+        constants::PACKAGE_SEED,
+    },
+    error::PaymentProcessorError,
+    state::{Discriminator, IsClosed, MerchantAccount, PackageAccount, Serdes},
+    utils::{get_package_account_size, get_package_address},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::{Pubkey, MAX_SEED_LEN},
+    sysvar::{rent::Rent, Sysvar},
+};
+
+/// run checks for adding and removing packages
+fn package_checks(
+    program_id: &Pubkey,
+    signer_info: &AccountInfo<'_>,
+    merchant_info: &AccountInfo<'_>,
+) -> Result<MerchantAccount, ProgramError> {
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure merchant account is owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // only the merchant can manage its packages
+    if !merchant_account.can_operate(&signer_info.key.to_bytes()) {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    Ok(merchant_account)
+}
+
+/// Add Package
+///
+/// Creates a package account for the merchant.  The first package turns a
+/// plain merchant into one whose subscriptions use package accounts.
+pub fn process_add_package(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    name: String,
+    duration: i64,
+    price: u64,
+    mint: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let package_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    let mut merchant_account = package_checks(program_id, signer_info, merchant_info)?;
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // merchants with packages in their data keep using them
+    let allowed_merchant_account_types = vec![
+        Discriminator::Merchant as u8,
+        Discriminator::MerchantSubscriptionPackages as u8,
+    ];
+    if !allowed_merchant_account_types.contains(&merchant_account.discriminator) {
+        msg!("Error: Merchant account cannot have package accounts");
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+    // the package name is a seed of the package account address
+    if name.is_empty() || name.len() > MAX_SEED_LEN {
+        msg!("Error: Package name must be 1 to {:?} bytes", MAX_SEED_LEN);
+        return Err(PaymentProcessorError::InvalidSubscriptionPackage.into());
+    }
+    if duration <= 0 {
+        msg!("Error: Package duration must be positive");
+        return Err(PaymentProcessorError::InvalidSubscriptionPackage.into());
+    }
+    if duration < merchant_account.min_subscription_duration {
+        msg!(
+            "Error: Package duration must be at least {:?} seconds",
+            merchant_account.min_subscription_duration
+        );
+        return Err(PaymentProcessorError::InvalidSubscriptionPackage.into());
+    }
+    let (package_address, bump_seed) = get_package_address(merchant_info.key, &name, program_id);
+    if *package_info.key != package_address {
+        msg!("Error: Package address does not match seed derivation");
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // ensure this is not an existing package
+    account_not_exists_check(package_info)?;
+
+    // Creating package account on chain...
+    let account_size = get_package_account_size(&name);
+    create_program_account(
+        program_id,
+        signer_info,
+        package_info,
+        system_program_info,
+        account_size,
+        rent,
+        &[
+            &merchant_info.key.to_bytes(),
+            PACKAGE_SEED,
+            name.as_bytes(),
+            &[bump_seed],
+        ],
+    )?;
+
+    // Saving package information...
+    let package_account = PackageAccount {
+        discriminator: Discriminator::Package as u8,
+        merchant: merchant_info.key.to_bytes(),
+        mint: mint.to_bytes(),
+        duration,
+        price,
+        name,
+    };
+    package_account.pack(&mut package_info.try_borrow_mut_data()?);
+    // ensure package account is rent exempt
+    assert_rent_exempt(package_info, account_size, rent)?;
+
+    if merchant_account.discriminator != Discriminator::MerchantSubscriptionPackages as u8 {
+        merchant_account.discriminator = Discriminator::MerchantSubscriptionPackages as u8;
+        merchant_account.pack(&mut merchant_info.try_borrow_mut_data()?);
+    }
+
+    Ok(())
+}
+
+/// Remove Package
+///
+/// Closes the package account and refunds its rent to the merchant.  Existing
+/// subscriptions to the package can no longer be renewed.
+pub fn process_remove_package(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let package_info = next_account_info(account_info_iter)?;
+
+    package_checks(program_id, signer_info, merchant_info)?;
+    // ensure package account is owned by this program
+    if *package_info.owner != *program_id {
+        msg!("Error: Wrong owner for package account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the package account
+    let mut package_account = PackageAccount::unpack(&package_info.data.borrow())?;
+    if package_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !package_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if package_account.discriminator != Discriminator::Package as u8 {
+        msg!("Error: Invalid package account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the package belongs to this merchant
+    if merchant_info.key.to_bytes() != package_account.merchant {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+
+    // mark account as closed
+    package_account.discriminator = Discriminator::Closed as u8;
+    PackageAccount::pack(&package_account, &mut package_info.data.borrow_mut());
+    // Transfer all the sol from the package account to the merchant owner.
+    transfer_sol(
+        package_info.clone(),
+        signer_info.clone(),
+        package_info.lamports(),
+    )?;
+
+    Ok(())
+}
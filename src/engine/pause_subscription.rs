@@ -0,0 +1,113 @@
+use crate::error::PaymentProcessorError;
+use crate::state::{Discriminator, IsClosed, Serdes, SubscriptionAccount, SubscriptionStatus};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+/// run checks for pausing and resuming a subscription
+fn pause_checks(
+    program_id: &Pubkey,
+    signer_info: &AccountInfo<'_>,
+    subscription_info: &AccountInfo<'_>,
+) -> Result<SubscriptionAccount, ProgramError> {
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure subscription account is owned by this program
+    if *subscription_info.owner != *program_id {
+        msg!("Error: Wrong owner for subscription account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the subscription account
+    let subscription_account = SubscriptionAccount::unpack(&subscription_info.data.borrow())?;
+    if !subscription_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if subscription_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if subscription_account.discriminator != Discriminator::Subscription as u8 {
+        msg!("Error: Invalid subscription account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the signer owns the subscription
+    if signer_info.key.to_bytes() != subscription_account.owner {
+        msg!("Error: One can only pause or resume their own subscription");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(subscription_account)
+}
+
+/// Pause Subscription
+///
+/// The time remaining in the current period is kept and given back on resume
+pub fn process_pause_subscription(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let subscription_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    let mut subscription_account = pause_checks(program_id, signer_info, subscription_info)?;
+    if subscription_account.status != SubscriptionStatus::Initialized as u8 {
+        msg!("Error: Only an active subscription can be paused");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+    if timestamp >= subscription_account.period_end {
+        msg!("Error: Subscription period has already ended");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+    // resuming needs to know when the subscription was paused
+    if SubscriptionAccount::is_legacy(&subscription_info.data.borrow()) {
+        msg!("Error: The subscription account is too old to record when it was paused");
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+
+    // Updating subscription account information...
+    // NB: modified records when the subscription was paused
+    subscription_account.status = SubscriptionStatus::Paused as u8;
+    subscription_account.modified = timestamp;
+    SubscriptionAccount::pack(
+        &subscription_account,
+        &mut subscription_info.data.borrow_mut(),
+    );
+
+    Ok(())
+}
+
+/// Resume Subscription
+pub fn process_resume_subscription(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let subscription_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    let mut subscription_account = pause_checks(program_id, signer_info, subscription_info)?;
+    if subscription_account.status != SubscriptionStatus::Paused as u8 {
+        msg!("Error: Only a paused subscription can be resumed");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+
+    // the period is pushed back by the time spent paused
+    let remaining = subscription_account.period_end - subscription_account.modified;
+    subscription_account.period_end = timestamp + remaining;
+    // Updating subscription account information...
+    subscription_account.status = SubscriptionStatus::Initialized as u8;
+    subscription_account.modified = timestamp;
+    SubscriptionAccount::pack(
+        &subscription_account,
+        &mut subscription_info.data.borrow_mut(),
+    );
+
+    Ok(())
+}
@@ -1,30 +1,156 @@
+#[cfg(feature = "json")]
+use crate::engine::{
+    constants::{INITIAL, MAX_ITEM_ID_LEN, MAX_ORDER_ITEMS, PAID},
+    json::Item,
+};
+#[cfg(feature = "json")]
+use crate::utils::quote_cart;
 use crate::{
     engine::{
-        common::create_program_owned_associated_token_account,
-        constants::{DEFAULT_DATA, INITIAL, PAID, PROGRAM_OWNER, SPONSOR_FEE},
-        json::{Item, OrderItems},
+        common::{
+            account_not_exists_check, assert_rent_exempt, create_associated_token_account,
+            create_program_account, create_program_owned_associated_token_account,
+            distinct_accounts_check, mint_check, system_program_check, token_program_check,
+        },
+        config::get_program_owner,
+        constants::{DEFAULT_DATA, MAX_CLIENT_TAG_LEN, ORDER_SEED, PDA_SEED, PROGRAM_OWNER},
+        coupon::redeem_coupon,
+        json::OrderItems,
+        ledger::{append_to_ledger, ledger_check},
+        oracle::get_oracle_price,
+        signature::verify_order_signature,
     },
     error::PaymentProcessorError,
-    state::{Discriminator, IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes},
-    utils::{get_amounts, get_order_account_size},
+    state::{
+        Discriminator, FeeMode, IsClosed, LedgerEntry, MerchantAccount, OrderAccount, OrderStatus,
+        Serdes,
+    },
+    utils::{
+        get_checkout_fees, get_fee_transfers, get_merchant_token_address, get_order_account_size,
+        get_order_pubkey_by_index, get_order_secret, get_order_signature_message, get_paid_amount,
+        get_token_amount, has_sponsor, is_printable_secret, split_charity_amount,
+    },
 };
+#[cfg(feature = "json")]
 use serde_json::{json, Error as JSONError, Value};
 use solana_program::program_pack::Pack;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
+    hash::Hash,
     msg,
     program::invoke,
     program_error::ProgramError,
+    program_option::COption,
     program_pack::IsInitialized,
     pubkey::Pubkey,
     system_instruction,
     sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
-use spl_token::{self, state::Account as TokenAccount};
+use spl_token::{
+    self,
+    state::{Account as TokenAccount, Mint},
+};
+#[cfg(feature = "json")]
 use std::collections::BTreeMap;
 use std::str::FromStr;
 
+/// Ensure a token account receiving a share of the payment (e.g. the charity
+/// share) belongs to the recipient and holds the order mint
+pub fn recipient_token_checks(
+    token_info: &AccountInfo<'_>,
+    mint_info: &AccountInfo<'_>,
+    recipient: &Pubkey,
+) -> ProgramResult {
+    if *token_info.owner != spl_token::id() {
+        msg!(
+            "Error: Token account {} must be owned by token program",
+            token_info.key
+        );
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let token_data = TokenAccount::unpack(&token_info.data.borrow())?;
+    if token_data.mint != *mint_info.key {
+        msg!(
+            "Error: Token account {} must hold {}",
+            token_info.key,
+            mint_info.key
+        );
+        return Err(PaymentProcessorError::WrongMint.into());
+    }
+    if token_data.owner != *recipient {
+        msg!(
+            "Error: Token account {} must belong to {}",
+            token_info.key,
+            recipient
+        );
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// Transfer a share of the payment from the buyer token account to the recipient
+/// token account
+fn transfer_payment_share<'a>(
+    token_program_info: &AccountInfo<'a>,
+    buyer_token_info: &AccountInfo<'a>,
+    mint_info: &AccountInfo<'a>,
+    recipient_token_info: &AccountInfo<'a>,
+    signer_info: &AccountInfo<'a>,
+    amount: u64,
+    decimals: u8,
+) -> ProgramResult {
+    if amount == 0 {
+        return Ok(());
+    }
+    invoke(
+        &spl_token::instruction::transfer_checked(
+            token_program_info.key,
+            buyer_token_info.key,
+            mint_info.key,
+            recipient_token_info.key,
+            signer_info.key,
+            &[&signer_info.key],
+            amount,
+            decimals,
+        )
+        .unwrap(),
+        &[
+            buyer_token_info.clone(),
+            mint_info.clone(),
+            recipient_token_info.clone(),
+            signer_info.clone(),
+            token_program_info.clone(),
+        ],
+    )
+}
+
+/// Check a checkout against the order account it already created
+///
+/// Clients may resubmit a checkout e.g. after a network timeout.  If the order
+/// is already paid by the same payer for the same order id and amount there is
+/// nothing left to do, otherwise the checkout conflicts with the existing order.
+pub fn existing_order_check(
+    order_info: &AccountInfo<'_>,
+    merchant_info: &AccountInfo<'_>,
+    signer_info: &AccountInfo<'_>,
+    order_id: &str,
+    expected_amount: u64,
+) -> ProgramResult {
+    let order_account = OrderAccount::unpack(&order_info.data.borrow())?;
+    if order_account.status == OrderStatus::Paid as u8
+        && order_account.merchant == merchant_info.key.to_bytes()
+        && order_account.payer == signer_info.key.to_bytes()
+        && order_account.order_id == order_id
+        && order_account.expected_amount == expected_amount
+    {
+        msg!("Info: Order {} is already paid", order_id);
+        return Ok(());
+    }
+    msg!("Error: The order account exists for a different checkout");
+    Err(PaymentProcessorError::OrderConflict.into())
+}
+
 /// Run checks for order processing
 pub fn order_checks(
     program_id: &Pubkey,
@@ -34,6 +160,8 @@ pub fn order_checks(
     mint_info: &AccountInfo<'_>,
     program_owner_info: &AccountInfo<'_>,
     sponsor_info: &AccountInfo<'_>,
+    config_info: &AccountInfo<'_>,
+    program_owner: &Pubkey,
 ) -> Result<MerchantAccount, ProgramError> {
     // ensure signer can sign
     if !signer_info.is_signer {
@@ -57,13 +185,21 @@ pub fn order_checks(
         msg!("Error: Buyer token account not owned by Token Program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    // ensure the mint is an actual mint before comparing it to the token account
+    mint_check(mint_info)?;
     // Get mint details and verify that they match token account
     let buyer_token_data = TokenAccount::unpack(&buyer_token_info.data.borrow())?;
     if *mint_info.key != buyer_token_data.mint {
         return Err(PaymentProcessorError::MintNotEqual.into());
     }
-    // check that provided program owner is correct
-    if *program_owner_info.key != Pubkey::from_str(PROGRAM_OWNER).unwrap() {
+    // check that the provided fee recipient is correct, merchants that have
+    // not set their own fee recipient pay the key in the program config
+    let mut fee_recipient = Pubkey::new_from_array(merchant_account.fee_recipient);
+    if fee_recipient == *program_owner {
+        fee_recipient = get_program_owner(program_id, config_info)?;
+    }
+    if *program_owner_info.key != fee_recipient {
+        msg!("Error: Fee recipient account is incorrect");
         return Err(PaymentProcessorError::WrongProgramOwner.into());
     }
     // check that the provided sponsor is correct
@@ -85,6 +221,7 @@ pub fn order_checks(
 ///     id: quantity
 /// }
 /// e.g. {"item1", 1, "item2": 33}
+#[cfg(feature = "json")]
 pub fn chain_checkout_checks(
     merchant_account: &MerchantAccount,
     mint: &AccountInfo,
@@ -95,6 +232,18 @@ pub fn chain_checkout_checks(
         msg!("Error: Invalid merchant account");
         return Err(PaymentProcessorError::InvalidMerchantData.into());
     }
+    // the items are stored in the order data so their number and size is limited
+    if order_items.len() > MAX_ORDER_ITEMS {
+        msg!(
+            "Error: An order can have at most {:?} items",
+            MAX_ORDER_ITEMS
+        );
+        return Err(PaymentProcessorError::TooManyItems.into());
+    }
+    if let Some(key) = order_items.keys().find(|key| key.len() > MAX_ITEM_ID_LEN) {
+        msg!("Error: Order item id {:?} is too long", key);
+        return Err(PaymentProcessorError::InvalidOrderData.into());
+    }
 
     let merchant_json_data: Result<BTreeMap<String, Item>, JSONError> =
         serde_json::from_str(&merchant_account.data);
@@ -104,9 +253,9 @@ pub fn chain_checkout_checks(
         Ok(data) => data,
     };
 
-    let mut total_amount: u64 = 0;
+    let mut unit_prices: BTreeMap<String, u64> = BTreeMap::new();
 
-    for (key, quantity) in order_items.iter() {
+    for key in order_items.keys() {
         let registered_item = match registered_items.get(key) {
             None => {
                 msg!("Error: Invalid order item {:?}", key);
@@ -122,9 +271,17 @@ pub fn chain_checkout_checks(
             return Err(PaymentProcessorError::WrongMint.into());
         }
 
-        total_amount = total_amount + (registered_item.price * quantity);
+        unit_prices.insert(key.clone(), registered_item.price);
     }
 
+    let total_amount = match quote_cart(order_items, &unit_prices, merchant_account) {
+        None => {
+            msg!("Error: Order amount overflow");
+            return Err(PaymentProcessorError::InvalidOrderData.into());
+        }
+        Some(quote) => quote.total,
+    };
+
     if total_amount > amount {
         msg!("Error: Insufficient amount, should be {:?}", total_amount);
         return Err(ProgramError::InsufficientFunds);
@@ -133,6 +290,150 @@ pub fn chain_checkout_checks(
     Ok(())
 }
 
+/// Get the order data for a chain checkout
+///
+/// Runs the chain checkout checks and then records the paid items in the
+/// order data, keeping any data supplied by the buyer under INITIAL.
+#[cfg(feature = "json")]
+pub fn get_chain_checkout_data(
+    merchant_account: &MerchantAccount,
+    mint: &AccountInfo,
+    order_items: &OrderItems,
+    amount: u64,
+    data: String,
+) -> Result<String, ProgramError> {
+    chain_checkout_checks(merchant_account, mint, order_items, amount)?;
+    if data == String::from(DEFAULT_DATA) {
+        return Ok(json!({ PAID: order_items }).to_string());
+    }
+    let json_data: Value = match serde_json::from_str(&data) {
+        Err(_error) => return Err(PaymentProcessorError::InvalidOrderData.into()),
+        Ok(data) => data,
+    };
+    Ok(json!({
+        INITIAL: json_data,
+        PAID: order_items
+    })
+    .to_string())
+}
+
+/// Chain checkout is not available without the json feature
+#[cfg(not(feature = "json"))]
+pub fn get_chain_checkout_data(
+    _merchant_account: &MerchantAccount,
+    _mint: &AccountInfo,
+    _order_items: &OrderItems,
+    _amount: u64,
+    _data: String,
+) -> Result<String, ProgramError> {
+    msg!("Error: Chain checkout requires the json feature");
+    Err(PaymentProcessorError::InvalidInstruction.into())
+}
+
+/// Create the order account and the seller token account
+///
+/// The order account is either a new keypair account or it is derived from the
+/// merchant order count, in which case this program signs for it.  No seller
+/// token account is created when paying into the merchant token account.  The
+/// signer (the first account) pays the rent of both.
+pub fn create_order_accounts(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo; 9],
+    order_index: u64,
+    order_account_size: usize,
+    rent: &Rent,
+) -> ProgramResult {
+    let signer_info = &accounts[0];
+    let order_info = &accounts[1];
+    let merchant_info = &accounts[2];
+    let seller_token_info = &accounts[3];
+    let mint_info = &accounts[4];
+    let pda_info = &accounts[5];
+    let token_program_info = &accounts[6];
+    let system_program_info = &accounts[7];
+    let rent_sysvar_info = &accounts[8];
+
+    // ensure the order account is new
+    account_not_exists_check(order_info)?;
+    // ensure the order account is either a new keypair account or the one
+    // derived from the merchant order count
+    let (indexed_order_address, order_bump_seed) =
+        get_order_pubkey_by_index(merchant_info.key, order_index, program_id);
+    if *order_info.key != indexed_order_address && !order_info.is_signer {
+        msg!(
+            "Error: Order account does not match the address for order {:?}",
+            order_index
+        );
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // a keypair order account signs the transaction itself
+    let order_index_bytes = order_index.to_le_bytes();
+    let order_bump_seed = [order_bump_seed];
+    let order_signer_seeds: &[&[u8]] = if *order_info.key == indexed_order_address {
+        &[
+            merchant_info.key.as_ref(),
+            ORDER_SEED,
+            &order_index_bytes,
+            &order_bump_seed,
+        ]
+    } else {
+        &[]
+    };
+    create_program_account(
+        program_id,
+        signer_info,
+        order_info,
+        system_program_info,
+        order_account_size,
+        &Rent::default(),
+        order_signer_seeds,
+    )?;
+
+    let (merchant_token_address, _bump_seed) =
+        get_merchant_token_address(merchant_info.key, mint_info.key, program_id);
+    if *seller_token_info.key == merchant_token_address {
+        // the payment goes to the merchant token account which must already exist
+        if *seller_token_info.owner != spl_token::id() {
+            msg!("Error: Merchant token account has not been created");
+            return Err(ProgramError::UninitializedAccount);
+        }
+    } else {
+        // next we are going to try and create a token account owned by the program
+        // but whose address is derived from the order account
+        // TODO: for subscriptions, should this use the subscription account as the base?
+        create_program_owned_associated_token_account(
+            program_id,
+            &[
+                signer_info.clone(),
+                order_info.clone(),
+                seller_token_info.clone(),
+                mint_info.clone(),
+                pda_info.clone(),
+                token_program_info.clone(),
+                system_program_info.clone(),
+                rent_sysvar_info.clone(),
+            ],
+            rent,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The optional parts of an order payment, see process_order
+#[derive(Default)]
+pub struct OrderOptions {
+    pub checkout_items: Option<OrderItems>,
+    pub client_tag: Option<String>,
+    pub usd_amount: Option<u64>,
+    pub revoke_delegate: bool,
+    pub merchant_signed: bool,
+    pub merchant_pays_rent: bool,
+    pub strict_secret: bool,
+    pub coupon: bool,
+    pub ledger: bool,
+}
+
 /// process an order payment
 pub fn process_order(
     program_id: &Pubkey,
@@ -141,8 +442,21 @@ pub fn process_order(
     order_id: String,
     secret: String,
     maybe_data: Option<String>,
-    checkout_items: Option<OrderItems>,
+    options: OrderOptions,
 ) -> ProgramResult {
+    let OrderOptions {
+        checkout_items,
+        client_tag,
+        usd_amount,
+        revoke_delegate,
+        merchant_signed,
+        merchant_pays_rent,
+        strict_secret,
+        coupon,
+        ledger,
+    } = options;
+    log_client_tag(&client_tag)?;
+
     let account_info_iter = &mut accounts.iter();
 
     let signer_info = next_account_info(account_info_iter)?;
@@ -157,11 +471,14 @@ pub fn process_order(
     let token_program_info = next_account_info(account_info_iter)?;
     let system_program_info = next_account_info(account_info_iter)?;
     let rent_sysvar_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
 
     let rent = &Rent::from_account_info(rent_sysvar_info)?;
     let timestamp = Clock::get()?.unix_timestamp;
+    // parse the program owner once for this checkout
+    let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
 
-    let merchant_account = order_checks(
+    let mut merchant_account = order_checks(
         program_id,
         signer_info,
         merchant_info,
@@ -169,65 +486,234 @@ pub fn process_order(
         mint_info,
         program_owner_info,
         sponsor_info,
+        config_info,
+        &program_owner,
     )?;
+    // ensure the token program is the SPL token program
+    token_program_check(token_program_info)?;
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // check that provided pda is correct, the seller token account is owned by it
+    let (pda, _pda_nonce) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+    if pda_info.key != &pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // the order account and the seller token account are created separately
+    if order_info.key == seller_token_info.key {
+        msg!("Error: The order account cannot be the seller token account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // a resubmitted checkout finds its order account already created
+    if *order_info.owner == *program_id {
+        return existing_order_check(
+            order_info,
+            merchant_info,
+            signer_info,
+            &order_id,
+            usd_amount.unwrap_or(amount),
+        );
+    }
+    // clients that pass secrets on to other tooling can require printable secrets
+    if strict_secret && !is_printable_secret(&secret) {
+        msg!("Error: The secret contains control characters");
+        return Err(PaymentProcessorError::InvalidSecret.into());
+    }
+    // merchants can limit the amounts they accept, zero means there is no limit
+    if merchant_account.min_amount != 0 && amount < merchant_account.min_amount {
+        msg!(
+            "Error: The amount is less than the merchant minimum of {:?}",
+            merchant_account.min_amount
+        );
+        return Err(PaymentProcessorError::AmountTooSmall.into());
+    }
+    if merchant_account.max_amount != 0 && amount > merchant_account.max_amount {
+        msg!(
+            "Error: The amount is more than the merchant maximum of {:?}",
+            merchant_account.max_amount
+        );
+        return Err(PaymentProcessorError::AmountTooLarge.into());
+    }
+    // a delegate can only spend what the buyer allowed it to, catch that here
+    // instead of failing in the token program
+    let buyer_token_data = TokenAccount::unpack(&buyer_token_info.data.borrow())?;
+    if buyer_token_data.owner != *signer_info.key
+        && buyer_token_data.delegate == COption::Some(*signer_info.key)
+        && buyer_token_data.delegated_amount < amount
+    {
+        msg!(
+            "Error: The delegated amount of {:?} does not cover the checkout",
+            buyer_token_data.delegated_amount
+        );
+        return Err(PaymentProcessorError::InsufficientDelegatedAmount.into());
+    }
+    // ensure the merchant accepts this mint, an empty list accepts any mint
+    if !merchant_account.accepted_mints.is_empty()
+        && !merchant_account
+            .accepted_mints
+            .contains(&mint_info.key.to_bytes())
+    {
+        msg!(
+            "Error: Mint {} is not accepted by the merchant",
+            mint_info.key
+        );
+        return Err(PaymentProcessorError::MintNotAccepted.into());
+    }
+    let mint_data = Mint::unpack(&mint_info.data.borrow())?;
+    // orders quoted in USD must pay at least the USD amount at the oracle price
+    let (expected_amount, min_paid_amount) = match usd_amount {
+        None => (amount, 0),
+        Some(value) => {
+            let price_info = next_account_info(account_info_iter)?;
+            let (price, expo) = get_oracle_price(price_info, Clock::get()?.slot)?;
+            let token_amount = get_token_amount(value, price, expo, mint_data.decimals)
+                .ok_or(PaymentProcessorError::InvalidOraclePrice)?;
+            (value, token_amount)
+        }
+    };
+    // merchants with a charity give it a share of every payment
+    let charity_token_info = match merchant_account.get_charity() {
+        None => Option::None,
+        Some((charity, _charity_bps)) => {
+            let charity_token_info = next_account_info(account_info_iter)?;
+            recipient_token_checks(
+                charity_token_info,
+                mint_info,
+                &Pubkey::new_from_array(charity),
+            )?;
+            Some(charity_token_info)
+        }
+    };
+    let (seller_amount, charity_amount) = match charity_token_info {
+        None => (amount, 0),
+        Some(_charity_token_info) => split_charity_amount(amount, merchant_account.charity_bps),
+    };
+    // the fee is paid in SOL or, for FeeMode::BpsToken, out of the payment to the
+    // fee recipient token account.  It is the only fee, small amounts included
+    let (sol_fee, token_fee) = get_checkout_fees(&merchant_account, signer_info.key, amount);
+    let fee_token_info = match merchant_account.fee_mode {
+        FeeMode::FlatSol | FeeMode::BpsSol => Option::None,
+        FeeMode::BpsToken => {
+            let fee_token_info = next_account_info(account_info_iter)?;
+            // a missing fee recipient token account is created below, as the
+            // associated token account of the fee recipient
+            let associated_token_program_info = if fee_token_info.data_is_empty() {
+                Some(next_account_info(account_info_iter)?)
+            } else {
+                recipient_token_checks(fee_token_info, mint_info, program_owner_info.key)?;
+                Option::None
+            };
+            Some((fee_token_info, associated_token_program_info))
+        }
+    };
+    // the accounts that pay or receive a part of the payment must all be different,
+    // a merchant without a sponsor (see has_sponsor) pays no sponsor share
+    let mut paid_infos = vec![
+        order_info,
+        merchant_info,
+        seller_token_info,
+        buyer_token_info,
+        program_owner_info,
+    ];
+    if has_sponsor(&merchant_account, program_owner_info.key) {
+        paid_infos.push(sponsor_info);
+    }
+    paid_infos.extend(charity_token_info);
+    paid_infos.extend(fee_token_info.map(|(fee_token_info, _)| fee_token_info));
+    distinct_accounts_check(&paid_infos)?;
+    // merchant signed orders are verified against the merchant's signature of
+    // the order instead of a secret
+    if merchant_signed {
+        let instructions_info = next_account_info(account_info_iter)?;
+        verify_order_signature(
+            instructions_info,
+            &Pubkey::new_from_array(merchant_account.owner),
+            &get_order_signature_message(&order_id, amount, mint_info.key),
+        )?;
+    }
+    // the merchant can pay the lamports of the checkout (rent and SOL fee) so that
+    // buyers without SOL only need to sign for the token transfer
+    let lamports_payer_info = if merchant_pays_rent {
+        let rent_payer_info = next_account_info(account_info_iter)?;
+        if !rent_payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if !merchant_account.can_operate(&rent_payer_info.key.to_bytes()) {
+            msg!("Error: Only the merchant owner or operator can pay the checkout rent");
+            return Err(PaymentProcessorError::WrongMerchant.into());
+        }
+        rent_payer_info
+    } else {
+        signer_info
+    };
+    // coupons are redeemed with the checkout.  The merchant applies the discount to
+    // the amount it signs for, otherwise any buyer could redeem any coupon
+    if coupon {
+        if !merchant_signed {
+            msg!("Error: Only merchant signed orders can redeem a coupon");
+            return Err(PaymentProcessorError::InvalidOrderSignature.into());
+        }
+        let coupon_info = next_account_info(account_info_iter)?;
+        redeem_coupon(program_id, coupon_info, merchant_info, timestamp)?;
+    }
+    // merchants doing accounting keep their recent payments in a ledger account
+    let ledger_info = if ledger {
+        let ledger_info = next_account_info(account_info_iter)?;
+        let ledger_account = ledger_check(program_id, ledger_info, merchant_info)?;
+        Some((ledger_info, ledger_account))
+    } else {
+        Option::None
+    };
+    let fee_token_info = match fee_token_info {
+        Some((fee_token_info, Some(associated_token_program_info))) => {
+            // Creating the fee recipient token account...
+            create_associated_token_account(&[
+                lamports_payer_info.clone(),
+                fee_token_info.clone(),
+                program_owner_info.clone(),
+                mint_info.clone(),
+                system_program_info.clone(),
+                token_program_info.clone(),
+                rent_sysvar_info.clone(),
+                associated_token_program_info.clone(),
+            ])?;
+            recipient_token_checks(fee_token_info, mint_info, program_owner_info.key)?;
+            Some(fee_token_info)
+        }
+        Some((fee_token_info, Option::None)) => Some(fee_token_info),
+        None => Option::None,
+    };
+    let seller_amount = match seller_amount.checked_sub(token_fee) {
+        None => {
+            msg!("Error: The fee and charity share are more than the amount");
+            return Err(PaymentProcessorError::InvalidMerchantData.into());
+        }
+        Some(value) => value,
+    };
 
     // get data
-    let mut data = match maybe_data {
+    let data = match maybe_data {
         None => String::from(DEFAULT_DATA),
         Some(value) => value,
     };
 
-    let mut order_account_type = Discriminator::OrderExpressCheckout as u8;
-
-    // process chain checkout
-    if checkout_items.is_some() {
-        order_account_type = Discriminator::OrderChainCheckout as u8;
-        let order_items = checkout_items.unwrap();
-        chain_checkout_checks(&merchant_account, &mint_info.clone(), &order_items, amount)?;
-        if data == String::from(DEFAULT_DATA) {
-            data = json!({ PAID: order_items }).to_string();
-        } else {
-            // let possible_json_data: Result<BTreeMap<&str, Value>, JSONError> = serde_json::from_str(&data);
-            // let json_data = match possible_json_data {
-            let json_data: Value = match serde_json::from_str(&data) {
-                Err(_error) => return Err(PaymentProcessorError::InvalidOrderData.into()),
-                Ok(data) => data,
-            };
-            data = json!({
-                INITIAL: json_data,
-                PAID: order_items
-            })
-            .to_string();
-        }
-    }
-
-    // create order account
-    let order_account_size = get_order_account_size(&order_id, &secret, &data);
-    // the order account amount includes the fee in SOL
-    let order_account_amount = Rent::default().minimum_balance(order_account_size);
-    invoke(
-        &system_instruction::create_account(
-            signer_info.key,
-            order_info.key,
-            order_account_amount,
-            order_account_size as u64,
-            program_id,
+    let (order_account_type, data) = match checkout_items {
+        None => (Discriminator::OrderExpressCheckout as u8, data),
+        // process chain checkout
+        Some(order_items) => (
+            Discriminator::OrderChainCheckout as u8,
+            get_chain_checkout_data(&merchant_account, mint_info, &order_items, amount, data)?,
         ),
-        &[
-            signer_info.clone(),
-            order_info.clone(),
-            system_program_info.clone(),
-        ],
-    )?;
+    };
 
-    // next we are going to try and create a token account owned by the program
-    // but whose address is derived from the order account
-    // TODO: for subscriptions, should this use the subscription account as the base?
-    create_program_owned_associated_token_account(
+    // create order account, an empty secret is not stored at all
+    let secret = get_order_secret(secret);
+    let order_account_size = get_order_account_size(&order_id, &secret, &data);
+    create_order_accounts(
         program_id,
         &[
-            signer_info.clone(),
+            lamports_payer_info.clone(),
             order_info.clone(),
+            merchant_info.clone(),
             seller_token_info.clone(),
             mint_info.clone(),
             pda_info.clone(),
@@ -235,64 +721,109 @@ pub fn process_order(
             system_program_info.clone(),
             rent_sysvar_info.clone(),
         ],
+        merchant_account.order_count,
+        order_account_size,
         rent,
     )?;
 
     // Transfer payment amount to associated seller token account...
+    let seller_balance_before = TokenAccount::unpack(&seller_token_info.data.borrow())?.amount;
     invoke(
-        &spl_token::instruction::transfer(
+        &spl_token::instruction::transfer_checked(
             token_program_info.key,
             buyer_token_info.key,
+            mint_info.key,
             seller_token_info.key,
             signer_info.key,
             &[&signer_info.key],
-            amount,
+            seller_amount,
+            mint_data.decimals,
         )
         .unwrap(),
         &[
             buyer_token_info.clone(),
+            mint_info.clone(),
             seller_token_info.clone(),
             signer_info.clone(),
             token_program_info.clone(),
         ],
     )?;
-
-    if Pubkey::new_from_array(merchant_account.sponsor) == Pubkey::from_str(PROGRAM_OWNER).unwrap()
-    {
-        // Transferring processing fee to the program owner...
-        invoke(
-            &system_instruction::transfer(
-                &signer_info.key,
-                program_owner_info.key,
-                merchant_account.fee,
-            ),
-            &[
-                signer_info.clone(),
-                program_owner_info.clone(),
-                system_program_info.clone(),
-            ],
+    if let Some(charity_token_info) = charity_token_info {
+        // Transferring the charity share to the charity token account...
+        transfer_payment_share(
+            token_program_info,
+            buyer_token_info,
+            mint_info,
+            charity_token_info,
+            signer_info,
+            charity_amount,
+            mint_data.decimals,
         )?;
-    } else {
-        // we need to pay both the program owner and the sponsor
-        let (program_owner_fee, sponsor_fee) = get_amounts(merchant_account.fee, SPONSOR_FEE);
-        // Transferring processing fee to the program owner and sponsor...
+    }
+    if let Some(fee_token_info) = fee_token_info {
+        // Transferring the token fee to the fee recipient token account...
+        transfer_payment_share(
+            token_program_info,
+            buyer_token_info,
+            mint_info,
+            fee_token_info,
+            signer_info,
+            token_fee,
+            mint_data.decimals,
+        )?;
+    }
+    if revoke_delegate {
+        // Revoking the buyer token account delegate...
         invoke(
-            &system_instruction::transfer(
-                &signer_info.key,
-                program_owner_info.key,
-                program_owner_fee,
-            ),
+            &spl_token::instruction::revoke(
+                token_program_info.key,
+                buyer_token_info.key,
+                signer_info.key,
+                &[&signer_info.key],
+            )
+            .unwrap(),
             &[
+                buyer_token_info.clone(),
                 signer_info.clone(),
-                program_owner_info.clone(),
-                system_program_info.clone(),
+                token_program_info.clone(),
             ],
         )?;
+    }
+    // record the amount actually received, which the seller token account must hold
+    let seller_balance = TokenAccount::unpack(&seller_token_info.data.borrow())?.amount;
+    let paid_amount = get_paid_amount(seller_amount, seller_balance_before, seller_balance);
+    if paid_amount > seller_balance {
+        msg!("Error: Paid amount is more than the seller token account holds");
+        return Err(PaymentProcessorError::InvalidOrder.into());
+    }
+    // the charity share and token fee count towards what the buyer paid
+    if paid_amount
+        .saturating_add(charity_amount)
+        .saturating_add(token_fee)
+        < min_paid_amount
+    {
+        msg!(
+            "Error: Insufficient amount, should be {:?}",
+            min_paid_amount
+        );
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    // without a sponsor the whole fee goes to the fee recipient in one transfer
+    for (recipient, lamports) in
+        get_fee_transfers(&merchant_account, program_owner_info.key, sol_fee)
+    {
+        let recipient_info = if recipient == *program_owner_info.key {
+            program_owner_info
+        } else {
+            sponsor_info
+        };
+        // Transferring processing fee...
         invoke(
-            &system_instruction::transfer(&signer_info.key, sponsor_info.key, sponsor_fee),
+            &system_instruction::transfer(&lamports_payer_info.key, &recipient, lamports),
             &[
-                signer_info.clone(),
-                sponsor_info.clone(),
+                lamports_payer_info.clone(),
+                recipient_info.clone(),
                 system_program_info.clone(),
             ],
         )?;
@@ -302,29 +833,75 @@ pub fn process_order(
     // TODO: ensure this account is not already initialized
     let mut order_account_data = order_info.try_borrow_mut_data()?;
     // Saving order information...
-    let order = OrderAccount {
+    let mut order = OrderAccount {
         discriminator: order_account_type,
-        status: OrderStatus::Paid as u8,
+        status: OrderStatus::Uninitialized as u8,
         created: timestamp,
         modified: timestamp,
         merchant: merchant_info.key.to_bytes(),
         mint: mint_info.key.to_bytes(),
         token: seller_token_info.key.to_bytes(),
         payer: signer_info.key.to_bytes(),
-        expected_amount: amount,
-        paid_amount: amount,
+        expected_amount,
+        paid_amount,
         order_id,
         secret,
         data,
+        refunded_amount: 0,
+        locked: false,
+        consumed: false,
+        // zero unless quoted in USD
+        quoted_amount: min_paid_amount,
     };
+    order.transition(OrderStatus::Paid)?;
 
     order.pack(&mut order_account_data);
+    // integrations (e.g. rollups) can collect order commitments from the logs
+    msg!("Commitment: {}", Hash::new(&order.commitment()));
 
-    // ensure order account is rent exempt
-    if !rent.is_exempt(order_info.lamports(), order_account_size) {
-        return Err(ProgramError::AccountNotRentExempt);
+    // Updating merchant order count...
+    merchant_account.order_count = merchant_account
+        .order_count
+        .checked_add(1)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    // merchant accounts from before the order count have no room for it, their
+    // orders take keypair accounts rather than ones derived from the count
+    if merchant_account.fits(merchant_info.data_len()) {
+        merchant_account.pack(&mut merchant_info.data.borrow_mut());
+    }
+
+    if let Some((ledger_info, ledger_account)) = ledger_info {
+        append_to_ledger(
+            ledger_info,
+            ledger_account,
+            LedgerEntry {
+                amount: paid_amount,
+                timestamp,
+                order: order_info.key.to_bytes(),
+            },
+        )?;
     }
 
+    // ensure order account is rent exempt
+    assert_rent_exempt(order_info, order_account_size, rent)?;
+
+    Ok(())
+}
+
+/// Log the client tag sent with a checkout
+///
+/// The tag is not stored; it only helps correlate failures with client versions
+pub fn log_client_tag(client_tag: &Option<String>) -> ProgramResult {
+    if let Some(value) = client_tag {
+        if value.len() > MAX_CLIENT_TAG_LEN {
+            msg!(
+                "Error: Client tag is longer than {:?} bytes",
+                MAX_CLIENT_TAG_LEN
+            );
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        msg!("Client: {}", value);
+    }
     Ok(())
 }
 
@@ -335,25 +912,22 @@ pub fn process_express_checkout(
     order_id: String,
     secret: String,
     maybe_data: Option<String>,
+    options: OrderOptions,
 ) -> ProgramResult {
     process_order(
-        program_id,
-        accounts,
-        amount,
-        order_id,
-        secret,
-        maybe_data,
-        Option::None,
+        program_id, accounts, amount, order_id, secret, maybe_data, options,
     )?;
     Ok(())
 }
 
+#[cfg(feature = "json")]
 pub fn process_chain_checkout(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     amount: u64,
     order_items: OrderItems,
     maybe_data: Option<String>,
+    client_tag: Option<String>,
 ) -> ProgramResult {
     process_order(
         program_id,
@@ -362,7 +936,11 @@ pub fn process_chain_checkout(
         format!("{timestamp}", timestamp = Clock::get()?.unix_timestamp),
         "".to_string(),
         maybe_data,
-        Some(order_items),
+        OrderOptions {
+            checkout_items: Some(order_items),
+            client_tag,
+            ..OrderOptions::default()
+        },
     )?;
     Ok(())
 }
@@ -0,0 +1,156 @@
+use crate::{
+    engine::common::{mint_check, pack_order_account, token_program_check},
+    engine::constants::PDA_SEED,
+    error::PaymentProcessorError,
+    state::{IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes},
+};
+use solana_program::program_pack::Pack;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+use spl_token::{self, state::Account as TokenAccount};
+
+/// Refund Order
+///
+/// Sends part or all of a paid order back to the payer.  Refunds add up in the
+/// order's refunded amount which can never be more than the amount paid, and
+/// only the rest of the payment is withdrawn later.
+pub fn process_refund_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let order_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let order_payment_token_info = next_account_info(account_info_iter)?;
+    let refund_token_info = next_account_info(account_info_iter)?;
+    let pda_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let mint_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure the token program is the SPL token program
+    token_program_check(token_program_info)?;
+    // ensure merchant and order accounts are owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *order_info.owner != *program_id {
+        msg!("Error: Wrong owner for order account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // check that provided pda is correct
+    let (pda, pda_nonce) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+    if pda_info.key != &pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // only the merchant can refund its orders
+    if !merchant_account.can_operate(&signer_info.key.to_bytes()) {
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    // get the order account
+    let mut order_account = OrderAccount::unpack(&order_info.data.borrow())?;
+    if order_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !order_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // ensure order belongs to this merchant
+    if merchant_info.key.to_bytes() != order_account.merchant {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the order payment token account is the right one
+    if order_payment_token_info.key.to_bytes() != order_account.token {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the mint is the one the order was paid in
+    if mint_info.key.to_bytes() != order_account.mint {
+        return Err(PaymentProcessorError::WrongMint.into());
+    }
+    let mint_data = mint_check(mint_info)?;
+    // locked orders can not be modified
+    if order_account.locked {
+        msg!("Error: Order is locked");
+        return Err(PaymentProcessorError::OrderLocked.into());
+    }
+    // only paid orders that have not been withdrawn can be refunded
+    if order_account.status != OrderStatus::Paid as u8 {
+        msg!("Error: Only paid orders can be refunded");
+        return Err(PaymentProcessorError::InvalidOrder.into());
+    }
+    // unless the merchant allows otherwise, refunds only go to the payer
+    let refund_token_data = TokenAccount::unpack(&refund_token_info.data.borrow())?;
+    if !merchant_account.allow_third_party_refunds
+        && refund_token_data.owner.to_bytes() != order_account.payer
+    {
+        msg!("Error: The refund token account must belong to the payer");
+        return Err(PaymentProcessorError::WrongPayer.into());
+    }
+    // ensure we never refund more than was paid
+    let refunded_amount = order_account
+        .refunded_amount
+        .checked_add(amount)
+        .ok_or(PaymentProcessorError::RefundExceedsPaid)?;
+    if refunded_amount > order_account.paid_amount {
+        msg!(
+            "Error: Only {:?} of the order can still be refunded",
+            order_account.paid_amount - order_account.refunded_amount
+        );
+        return Err(PaymentProcessorError::RefundExceedsPaid.into());
+    }
+
+    // Transferring the refund to the payer...
+    invoke_signed(
+        &spl_token::instruction::transfer_checked(
+            token_program_info.key,
+            order_payment_token_info.key,
+            mint_info.key,
+            refund_token_info.key,
+            &pda,
+            &[&pda],
+            amount,
+            mint_data.decimals,
+        )
+        .unwrap(),
+        &[
+            token_program_info.clone(),
+            order_payment_token_info.clone(),
+            mint_info.clone(),
+            refund_token_info.clone(),
+            pda_info.clone(),
+        ],
+        &[&[&PDA_SEED, &[pda_nonce]]],
+    )?;
+
+    // Updating order account information...
+    order_account.refunded_amount = refunded_amount;
+    order_account.modified = timestamp;
+    pack_order_account(order_info, &order_account)?;
+
+    Ok(())
+}
@@ -1,11 +1,22 @@
+#[cfg(feature = "json")]
+use crate::engine::{
+    constants::TRIAL,
+    json::{parse_packages, Item, Packages},
+};
 use crate::{
+    engine::common::{
+        account_not_exists_check, assert_rent_exempt, next_rent, system_program_check,
+    },
+    engine::config::registrant_check,
     engine::constants::{
-        DEFAULT_DATA, DEFAULT_FEE_IN_LAMPORTS, MERCHANT, MIN_FEE_IN_LAMPORTS, PROGRAM_OWNER, TRIAL,
+        DEFAULT_DATA, DEFAULT_FEE_IN_LAMPORTS, MAX_BPS, MAX_FEE_OVERRIDES, MERCHANT,
+        MIN_FEE_IN_LAMPORTS, PDA_SEED, PROGRAM_OWNER,
     },
-    engine::json::{Item, Packages},
-    state::{Discriminator, MerchantAccount, Serdes},
+    error::PaymentProcessorError,
+    state::{Discriminator, FeeMode, FeeOverride, MerchantAccount, Serdes},
     utils::get_merchant_account_size,
 };
+#[cfg(feature = "json")]
 use serde_json::Error as JSONError;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
@@ -14,80 +25,289 @@ use solana_program::{
     program::invoke,
     program_error::ProgramError,
     pubkey::Pubkey,
-    system_instruction,
-    sysvar::{rent::Rent, Sysvar},
+    system_instruction, system_program,
+    sysvar::rent::Rent,
 };
+#[cfg(feature = "json")]
 use std::collections::BTreeMap;
 use std::str::FromStr;
 
+/// Get the merchant account type from the merchant data
+#[cfg(feature = "json")]
+pub fn get_merchant_account_type(data: &String) -> u8 {
+    let maybe_subscription_merchant: Result<Packages, JSONError> = serde_json::from_str(data);
+    match maybe_subscription_merchant {
+        Ok(_value) => {
+            if data.contains(TRIAL) {
+                Discriminator::MerchantSubscriptionWithTrial as u8
+            } else {
+                Discriminator::MerchantSubscription as u8
+            }
+        }
+        Err(_error) => {
+            let maybe_chain_checkout: Result<BTreeMap<String, Item>, JSONError> =
+                serde_json::from_str(data);
+            match maybe_chain_checkout {
+                Ok(_value) => Discriminator::MerchantChainCheckout as u8,
+                Err(_error) => Discriminator::Merchant as u8,
+            }
+        }
+    }
+}
+
+/// Ensure the subscription packages in the merchant data last at least the
+/// merchant's minimum subscription duration
+#[cfg(feature = "json")]
+fn packages_duration_check(data: &str, min_subscription_duration: i64) -> ProgramResult {
+    if let Ok(packages) = parse_packages(data) {
+        if packages
+            .iter()
+            .any(|package| package.duration < min_subscription_duration)
+        {
+            msg!(
+                "Error: Subscription packages must last at least {:?} seconds",
+                min_subscription_duration
+            );
+            return Err(PaymentProcessorError::InvalidSubscriptionPackage.into());
+        }
+    }
+    Ok(())
+}
+
+/// run checks for registering into a merchant account that already exists
+///
+/// The account must sign so that no one else can register into it, and it must
+/// have room for the merchant account.  Any room left over is zero padding,
+/// which merchant accounts are unpacked with.
+fn existing_merchant_account_checks(
+    merchant_info: &AccountInfo<'_>,
+    account_size: usize,
+) -> ProgramResult {
+    if merchant_info.data.borrow().iter().any(|byte| *byte != 0) {
+        msg!("Error: Account {} already exists", merchant_info.key);
+        return Err(PaymentProcessorError::AccountAlreadyExists.into());
+    }
+    if !merchant_info.is_signer {
+        msg!("Error: An existing merchant account must sign");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if merchant_info.data_len() < account_size {
+        msg!(
+            "Error: Merchant account size should be at least {:?} bytes",
+            account_size
+        );
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// The merchant settings sent with a RegisterMerchant instruction, see
+/// process_register_merchant
+#[derive(Default)]
+pub struct MerchantSettings {
+    pub fee: Option<u64>,
+    pub data: Option<String>,
+    pub settlement_delay: Option<i64>,
+    pub accepted_mints: Option<Vec<Pubkey>>,
+    pub allow_third_party_refunds: Option<bool>,
+    pub fee_overrides: Option<Vec<(Pubkey, u64)>>,
+    pub min_subscription_duration: Option<i64>,
+    pub charity: Option<(Pubkey, u16)>,
+    pub fee_mode: Option<FeeMode>,
+    pub amount_limits: Option<(u64, u64)>,
+    pub fee_free_band: Option<(u64, u64)>,
+    pub require_full_payment: Option<bool>,
+}
+
 pub fn process_register_merchant(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     seed: Option<String>,
-    maybe_fee: Option<u64>,
-    maybe_data: Option<String>,
+    settings: MerchantSettings,
 ) -> ProgramResult {
+    let MerchantSettings {
+        fee: maybe_fee,
+        data: maybe_data,
+        settlement_delay: maybe_settlement_delay,
+        accepted_mints: maybe_accepted_mints,
+        allow_third_party_refunds: maybe_allow_third_party_refunds,
+        fee_overrides: maybe_fee_overrides,
+        min_subscription_duration: maybe_min_subscription_duration,
+        charity: maybe_charity,
+        fee_mode: maybe_fee_mode,
+        amount_limits: maybe_amount_limits,
+        fee_free_band: maybe_fee_free_band,
+        require_full_payment: maybe_require_full_payment,
+    } = settings;
+
     let account_info_iter = &mut accounts.iter();
 
     let signer_info = next_account_info(account_info_iter)?;
     let merchant_info = next_account_info(account_info_iter)?;
     let system_sysvar_info = next_account_info(account_info_iter)?;
-    let rent_sysvar_info = next_account_info(account_info_iter)?;
+    let registry_info = next_account_info(account_info_iter)?;
+    let rent = &next_rent(account_info_iter)?;
     let possible_sponsor_info = next_account_info(account_info_iter);
-    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+    let possible_fee_recipient_info = next_account_info(account_info_iter);
 
     // ensure signer can sign
     if !signer_info.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
     }
+    // ensure the system program is the system program
+    system_program_check(system_sysvar_info)?;
+    // permissioned deployments only let approved keys register merchants
+    registrant_check(program_id, registry_info, signer_info)?;
+    // the sponsor fee is a plain SOL transfer, so the sponsor must be a wallet and
+    // not this program's derived address or an account owned by a program
+    if let Ok(sponsor_info) = &possible_sponsor_info {
+        let (pda, _pda_nonce) = Pubkey::find_program_address(&[PDA_SEED], program_id);
+        if *sponsor_info.key == pda || *sponsor_info.owner != system_program::id() {
+            msg!("Error: The sponsor must be a wallet owned by the system program");
+            return Err(PaymentProcessorError::WrongSponsor.into());
+        }
+    }
 
     let data = match maybe_data {
         None => String::from(DEFAULT_DATA),
         Some(value) => value,
     };
-    let account_size = get_merchant_account_size(&data);
-
-    // Creating merchant account on chain...
-    invoke(
-        &system_instruction::create_account_with_seed(
-            signer_info.key,
-            merchant_info.key,
-            signer_info.key,
-            match &seed {
-                None => MERCHANT,
-                Some(value) => &value,
-            },
-            Rent::default().minimum_balance(account_size),
-            account_size as u64,
-            program_id,
-        ),
-        &[
-            signer_info.clone(),
-            merchant_info.clone(),
-            signer_info.clone(),
-            system_sysvar_info.clone(),
-        ],
-    )?;
-
-    // get merchant account type
-    let maybe_subscription_merchant: Result<Packages, JSONError> = serde_json::from_str(&data);
-    let merchant_account_type: u8 = match maybe_subscription_merchant {
-        Ok(_value) => {
-            if data.contains(TRIAL) {
-                Discriminator::MerchantSubscriptionWithTrial as u8
-            } else {
-                Discriminator::MerchantSubscription as u8
+    let accepted_mints = match maybe_accepted_mints {
+        None => vec![],
+        Some(value) => value,
+    };
+    let fee_mode = maybe_fee_mode.unwrap_or(FeeMode::FlatSol);
+    let fee = match maybe_fee {
+        // basis point fees have no default or minimum
+        _ if fee_mode != FeeMode::FlatSol => {
+            let result = maybe_fee.unwrap_or(0);
+            if result > MAX_BPS as u64 {
+                msg!("Error: A basis point fee cannot be more than {:?}", MAX_BPS);
+                return Err(PaymentProcessorError::InvalidMerchantData.into());
             }
+            result
         }
-        Err(_error) => {
-            let maybe_chain_checkout: Result<BTreeMap<String, Item>, JSONError> =
-                serde_json::from_str(&data);
-            match maybe_chain_checkout {
-                Ok(_value) => Discriminator::MerchantChainCheckout as u8,
-                Err(_error) => Discriminator::Merchant as u8,
+        None => DEFAULT_FEE_IN_LAMPORTS,
+        Some(value) => {
+            let mut result = value;
+            if result < MIN_FEE_IN_LAMPORTS {
+                msg!(
+                    "Info: setting minimum transaction fee of {:?}",
+                    MIN_FEE_IN_LAMPORTS
+                );
+                result = MIN_FEE_IN_LAMPORTS;
             }
+            result
         }
     };
+    let fee_overrides = match maybe_fee_overrides {
+        None => vec![],
+        Some(value) => value,
+    };
+    if fee_overrides.len() > MAX_FEE_OVERRIDES {
+        msg!(
+            "Error: A merchant can have at most {:?} fee overrides",
+            MAX_FEE_OVERRIDES
+        );
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+    // fee overrides can only reduce or waive the merchant fee
+    if fee_overrides
+        .iter()
+        .any(|(_buyer, override_fee)| *override_fee > fee)
+    {
+        msg!("Error: A fee override cannot be more than the merchant fee");
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+    let account_size = get_merchant_account_size(&data, &accepted_mints, &fee_overrides);
+    let settlement_delay = match maybe_settlement_delay {
+        None => 0,
+        Some(value) => value,
+    };
+    if settlement_delay < 0 {
+        msg!("Error: The settlement delay cannot be negative");
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+    let min_subscription_duration = maybe_min_subscription_duration.unwrap_or(0);
+    if min_subscription_duration < 0 {
+        msg!("Error: The minimum subscription duration cannot be negative");
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+    #[cfg(feature = "json")]
+    packages_duration_check(&data, min_subscription_duration)?;
+    let (charity, charity_bps) = match maybe_charity {
+        None => ([0; 32], 0),
+        Some((charity, charity_bps)) => (charity.to_bytes(), charity_bps),
+    };
+    if charity_bps > MAX_BPS {
+        msg!(
+            "Error: The charity share cannot be more than {:?} basis points",
+            MAX_BPS
+        );
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+    // the token fee and the charity share both come out of the payment
+    if fee_mode == FeeMode::BpsToken && fee + charity_bps as u64 > MAX_BPS as u64 {
+        msg!("Error: The fee and charity share cannot be more than the payment");
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+    // zero means there is no limit
+    let (min_amount, max_amount) = maybe_amount_limits.unwrap_or((0, 0));
+    if min_amount != 0 && max_amount != 0 && min_amount > max_amount {
+        msg!("Error: The minimum amount cannot be more than the maximum amount");
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+    // zero means the band has no lower or upper part, a band that covers every
+    // amount is a zero fee
+    let (fee_free_below, fee_free_above) = maybe_fee_free_band.unwrap_or((0, 0));
+    if fee_free_below != 0 && fee_free_above != 0 && fee_free_below > fee_free_above {
+        msg!("Error: The fee free band cannot cover every amount");
+        return Err(PaymentProcessorError::InvalidMerchantData.into());
+    }
+
+    if *merchant_info.owner == *program_id {
+        // the merchant account was created beforehand so we only initialize it
+        existing_merchant_account_checks(merchant_info, account_size)?;
+    } else {
+        // ensure the merchant account is new
+        account_not_exists_check(merchant_info)?;
+        // ensure the merchant account is the one derived from the seed
+        let seed = match &seed {
+            None => MERCHANT,
+            Some(value) => &value,
+        };
+        let merchant_address = Pubkey::create_with_seed(signer_info.key, seed, program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+        if *merchant_info.key != merchant_address {
+            msg!("Error: Merchant address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        // Creating merchant account on chain...
+        invoke(
+            &system_instruction::create_account_with_seed(
+                signer_info.key,
+                merchant_info.key,
+                signer_info.key,
+                seed,
+                Rent::default().minimum_balance(account_size),
+                account_size as u64,
+                program_id,
+            ),
+            &[
+                signer_info.clone(),
+                merchant_info.clone(),
+                signer_info.clone(),
+                system_sysvar_info.clone(),
+            ],
+        )?;
+    }
+
+    // get merchant account type
+    #[cfg(feature = "json")]
+    let merchant_account_type = get_merchant_account_type(&data);
+    #[cfg(not(feature = "json"))]
+    let merchant_account_type = Discriminator::Merchant as u8;
 
     // get the merchant account data
     // TODO: ensure this account is not already initialized
@@ -100,29 +320,39 @@ pub fn process_register_merchant(
             Ok(sponsor_info) => sponsor_info.key.to_bytes(),
             Err(_error) => Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
         },
-        fee: match maybe_fee {
-            None => DEFAULT_FEE_IN_LAMPORTS,
-            Some(value) => {
-                let mut result = value;
-                if result < MIN_FEE_IN_LAMPORTS {
-                    msg!(
-                        "Info: setting minimum transaction fee of {:?}",
-                        MIN_FEE_IN_LAMPORTS
-                    );
-                    result = MIN_FEE_IN_LAMPORTS;
-                }
-                result
-            }
-        },
+        fee,
         data,
+        fee_recipient: match possible_fee_recipient_info {
+            Ok(fee_recipient_info) => fee_recipient_info.key.to_bytes(),
+            Err(_error) => Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+        },
+        settlement_delay,
+        order_count: 0,
+        accepted_mints: accepted_mints.iter().map(|mint| mint.to_bytes()).collect(),
+        allow_third_party_refunds: maybe_allow_third_party_refunds.unwrap_or(false),
+        fee_overrides: fee_overrides
+            .iter()
+            .map(|(buyer, fee)| FeeOverride {
+                buyer: buyer.to_bytes(),
+                fee: *fee,
+            })
+            .collect(),
+        min_subscription_duration,
+        operator: [0; 32],
+        charity,
+        charity_bps,
+        fee_mode,
+        min_amount,
+        max_amount,
+        fee_free_below,
+        fee_free_above,
+        require_full_payment: maybe_require_full_payment.unwrap_or(false),
     };
 
     merchant.pack(&mut merchant_account_data);
 
-    // ensure merchant account is rent exempt
-    if !rent.is_exempt(merchant_info.lamports(), account_size) {
-        return Err(ProgramError::AccountNotRentExempt);
-    }
+    // ensure merchant account is rent exempt, pre-created accounts can be larger
+    assert_rent_exempt(merchant_info, merchant_info.data_len(), rent)?;
 
     Ok(())
 }
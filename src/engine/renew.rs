@@ -1,4 +1,4 @@
-use crate::engine::common::subscribe_checks;
+use crate::engine::common::{pack_order_account, subscribe_checks};
 use crate::error::PaymentProcessorError;
 use crate::state::{Discriminator, IsClosed, Serdes, SubscriptionAccount, SubscriptionStatus};
 use solana_program::{
@@ -22,6 +22,7 @@ pub fn process_renew_subscription(
     let subscription_info = next_account_info(account_info_iter)?;
     let merchant_info = next_account_info(account_info_iter)?;
     let order_info = next_account_info(account_info_iter)?;
+    let package_info = next_account_info(account_info_iter).ok();
 
     // ensure subscription account is owned by this program
     if *subscription_info.owner != *program_id {
@@ -40,14 +41,25 @@ pub fn process_renew_subscription(
         msg!("Error: Invalid subscription account");
         return Err(ProgramError::InvalidAccountData);
     }
-    let (order_account, package) = subscribe_checks(
+    // subscribers cancelled by the merchant can not renew
+    if subscription_account.status == SubscriptionStatus::CancelledByMerchant as u8 {
+        msg!("Error: The merchant cancelled this subscription");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+    let (mut order_account, package) = subscribe_checks(
         program_id,
         signer_info,
         merchant_info,
         order_info,
         subscription_info,
+        package_info,
         &subscription_account.name,
     )?;
+    // ensure the order has not paid for another subscription period
+    if order_account.consumed {
+        msg!("Error: Order was already used");
+        return Err(PaymentProcessorError::OrderAlreadyUsed.into());
+    }
     // ensure the amount paid is as expected
     let expected_amount = (quantity as u64) * package.price;
     if expected_amount > order_account.paid_amount {
@@ -65,10 +77,15 @@ pub fn process_renew_subscription(
             subscription_account.period_end + (package.duration * quantity);
     }
     subscription_account.status = SubscriptionStatus::Initialized as u8;
+    subscription_account.current_order = order_info.key.to_bytes();
+    subscription_account.modified = timestamp;
     SubscriptionAccount::pack(
         &subscription_account,
         &mut subscription_info.data.borrow_mut(),
     );
+    // mark the order as used
+    order_account.consumed = true;
+    pack_order_account(order_info, &order_account)?;
 
     Ok(())
 }
@@ -0,0 +1,87 @@
+use crate::{
+    error::PaymentProcessorError,
+    state::{IsClosed, MerchantAccount, OrderAccount, Serdes},
+    utils::is_cleared_secret,
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+/// Reveal Secret
+///
+/// Logs the secret of an order and then overwrites it with as many NUL characters,
+/// so that one-time secrets (e.g. redemption codes) can only be revealed once.
+/// The order keeps its size since accounts can not be resized.  The merchant or
+/// the payer can do this.
+///
+/// The secret was meant to be returned as return data, which solana-program 1.7
+/// does not have (set_return_data came later), so it is logged instead and
+/// callers read it from the transaction logs.
+pub fn process_reveal_secret(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let order_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure merchant and order accounts are owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *order_info.owner != *program_id {
+        msg!("Error: Wrong owner for order account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // get the order account
+    let mut order_account = OrderAccount::unpack(&order_info.data.borrow())?;
+    if order_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !order_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    // ensure order belongs to this merchant
+    if merchant_info.key.to_bytes() != order_account.merchant {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // only the merchant or the payer can reveal the secret
+    if !merchant_account.can_operate(&signer_info.key.to_bytes())
+        && signer_info.key.to_bytes() != order_account.payer
+    {
+        msg!("Error: Only the merchant or the payer can reveal the secret");
+        return Err(PaymentProcessorError::WrongPayer.into());
+    }
+    let secret = match &order_account.secret {
+        Some(value) if !is_cleared_secret(value) => value,
+        _ => return Err(PaymentProcessorError::NoSecret.into()),
+    };
+
+    msg!("Secret: {}", secret);
+    // Clearing the secret, the same number of bytes keeps the order size...
+    order_account.secret = Some("\0".repeat(secret.len()));
+    order_account.modified = timestamp;
+    OrderAccount::pack(&order_account, &mut order_info.data.borrow_mut());
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use crate::{engine::constants::ED25519_PROGRAM_ID, error::PaymentProcessorError};
+use arrayref::array_ref;
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::instructions::{load_current_index, load_instruction_at},
+};
+use std::str::FromStr;
+
+/// the number of signatures an order signature instruction verifies
+pub const ED25519_SIGNATURE_COUNT: u8 = 1;
+/// an instruction index meaning "this instruction" in the signature offsets
+pub const ED25519_THIS_INSTRUCTION: u16 = u16::MAX;
+// offsets into the Ed25519 instruction data, see Ed25519SignatureOffsets in the sdk
+pub const SIGNATURE_COUNT_OFFSET: usize = 0;
+pub const SIGNATURE_OFFSETS_START: usize = 2;
+pub const SIGNATURE_OFFSETS_LEN: usize = 14;
+/// where the public key, signature and message start in our order signature instructions
+pub const ED25519_DATA_START: usize = SIGNATURE_OFFSETS_START + SIGNATURE_OFFSETS_LEN;
+pub const ED25519_PUBKEY_LEN: usize = 32;
+pub const ED25519_SIGNATURE_LEN: usize = 64;
+
+/// Read one of the u16 signature offsets of an Ed25519 instruction
+fn get_offset(data: &[u8], index: usize) -> usize {
+    u16::from_le_bytes(*array_ref![data, SIGNATURE_OFFSETS_START + index * 2, 2]) as usize
+}
+
+/// Verify that the order was signed by the given signer
+///
+/// The signature itself is verified by the Ed25519 program, in the instruction
+/// just before the current one.  Here we make sure that instruction exists and
+/// that it verified a signature by the signer of exactly this message.
+pub fn verify_order_signature(
+    instructions_info: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> ProgramResult {
+    // ensure the instructions account is the instructions sysvar
+    if !solana_program::sysvar::instructions::check_id(instructions_info.key) {
+        msg!("Error: Instructions account is not the instructions sysvar");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let instructions_data = instructions_info.data.borrow();
+    let current_index = load_current_index(&instructions_data);
+    if current_index == 0 {
+        msg!("Error: The order signature must be verified before the checkout");
+        return Err(PaymentProcessorError::InvalidOrderSignature.into());
+    }
+    let instruction = load_instruction_at(current_index as usize - 1, &instructions_data)
+        .map_err(|_| PaymentProcessorError::InvalidOrderSignature)?;
+    if instruction.program_id != Pubkey::from_str(ED25519_PROGRAM_ID).unwrap() {
+        msg!("Error: The order signature must be verified by the Ed25519 program");
+        return Err(PaymentProcessorError::InvalidOrderSignature.into());
+    }
+    let data = instruction.data;
+    if data.len() < ED25519_DATA_START || data[SIGNATURE_COUNT_OFFSET] != ED25519_SIGNATURE_COUNT {
+        msg!("Error: Invalid order signature instruction");
+        return Err(PaymentProcessorError::InvalidOrderSignature.into());
+    }
+    // Ed25519SignatureOffsets: signature, public key and message offsets, each
+    // followed by the index of the instruction holding them
+    let public_key_offset = get_offset(&data, 2);
+    let message_offset = get_offset(&data, 4);
+    let message_len = get_offset(&data, 5);
+    // the signed data must come from the signature instruction itself
+    if get_offset(&data, 1) != ED25519_THIS_INSTRUCTION as usize
+        || get_offset(&data, 3) != ED25519_THIS_INSTRUCTION as usize
+        || get_offset(&data, 6) != ED25519_THIS_INSTRUCTION as usize
+    {
+        msg!("Error: The order signature must be self contained");
+        return Err(PaymentProcessorError::InvalidOrderSignature.into());
+    }
+    if data.get(public_key_offset..public_key_offset + ED25519_PUBKEY_LEN)
+        != Some(&signer.to_bytes()[..])
+    {
+        msg!("Error: The order was not signed by {}", signer);
+        return Err(PaymentProcessorError::InvalidOrderSignature.into());
+    }
+    if message_len != message.len()
+        || data.get(message_offset..message_offset + message_len) != Some(message)
+    {
+        msg!("Error: The signed order does not match this order");
+        return Err(PaymentProcessorError::InvalidOrderSignature.into());
+    }
+    Ok(())
+}
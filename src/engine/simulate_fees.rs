@@ -0,0 +1,63 @@
+use crate::{
+    engine::{config::get_program_owner, constants::PROGRAM_OWNER},
+    error::PaymentProcessorError,
+    state::{IsClosed, MerchantAccount, Serdes},
+    utils::{get_fee_shares, quote},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+};
+use std::str::FromStr;
+
+/// Simulate Checkout Fees
+///
+/// Logs how a checkout of the amount to the merchant would be split, using the
+/// same fee code as the checkout itself (see utils::quote and utils::get_fee_shares),
+/// without moving any funds.
+pub fn process_simulate_checkout_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let merchant_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+
+    // ensure merchant account is owned by this program
+    if *merchant_info.owner != *program_id {
+        msg!("Error: Wrong owner for merchant account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the merchant account
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if merchant_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if !merchant_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    // merchants that have not set their own fee recipient pay the key in the
+    // program config, as in a checkout
+    let mut fee_recipient = Pubkey::new_from_array(merchant_account.fee_recipient);
+    if fee_recipient == Pubkey::from_str(PROGRAM_OWNER).unwrap() {
+        fee_recipient = get_program_owner(program_id, config_info)?;
+    }
+
+    let fee_quote = quote(amount, &merchant_account);
+    let (program_owner_fee, sponsor_fee) =
+        get_fee_shares(&merchant_account, &fee_recipient, fee_quote.sol_fee);
+    msg!("Program owner fee (lamports): {:?}", program_owner_fee);
+    msg!("Sponsor fee (lamports): {:?}", sponsor_fee);
+    msg!("Token fee: {:?}", fee_quote.token_fee);
+    msg!("Charity amount: {:?}", fee_quote.charity_amount);
+    msg!("Seller amount: {:?}", fee_quote.seller_amount);
+
+    Ok(())
+}
@@ -1,16 +1,21 @@
-use crate::engine::common::subscribe_checks;
+use crate::engine::common::{
+    account_not_exists_check, assert_rent_exempt, next_rent, pack_order_account, subscribe_checks,
+    system_program_check,
+};
 use crate::engine::constants::DEFAULT_DATA;
 use crate::error::PaymentProcessorError;
-use crate::state::{Discriminator, Serdes, SubscriptionAccount, SubscriptionStatus};
-use crate::utils::get_subscription_account_size;
+use crate::state::{
+    Discriminator, MerchantAccount, Serdes, SubscriptionAccount, SubscriptionStatus,
+};
+use crate::utils::{get_subscription_account_pubkey, get_subscription_account_size};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
+    msg,
     program::{invoke, invoke_signed},
-    program_error::ProgramError,
     pubkey::Pubkey,
     system_instruction,
-    sysvar::{clock::Clock, rent::Rent, Sysvar},
+    sysvar::{clock::Clock, Sysvar},
 };
 
 pub fn process_subscribe(
@@ -26,21 +31,46 @@ pub fn process_subscribe(
     let merchant_info = next_account_info(account_info_iter)?;
     let order_info = next_account_info(account_info_iter)?;
     let system_program_info = next_account_info(account_info_iter)?;
-    let rent_sysvar_info = next_account_info(account_info_iter)?;
+    let rent = &next_rent(account_info_iter)?;
+    let package_info = next_account_info(account_info_iter).ok();
+
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
 
-    let (order_account, package) = subscribe_checks(
+    let (mut order_account, package) = subscribe_checks(
         program_id,
         signer_info,
         merchant_info,
         order_info,
         subscription_info,
+        package_info,
         &name,
     )?;
 
+    // ensure the order has not paid for another subscription period
+    if order_account.consumed {
+        msg!("Error: Order was already used");
+        return Err(PaymentProcessorError::OrderAlreadyUsed.into());
+    }
+    // partial trials only pay the trial price up front
+    let partial_trial = package.trial.is_some() && package.trial_price.is_some();
+    let amount_due = match package.trial_price {
+        Some(value) if partial_trial => value,
+        _ => package.price,
+    };
     // ensure the amount paid is as expected
-    if package.price > order_account.paid_amount {
+    if amount_due > order_account.paid_amount {
         return Err(PaymentProcessorError::NotFullyPaid.into());
     }
+    // ensure the package lasts at least as long as the merchant requires
+    let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
+    if package.duration < merchant_account.min_subscription_duration {
+        msg!(
+            "Error: Subscription packages must last at least {:?} seconds",
+            merchant_account.min_subscription_duration
+        );
+        return Err(PaymentProcessorError::InvalidSubscriptionPackage.into());
+    }
     // get subscription account size
     let data = match maybe_data {
         None => String::from(DEFAULT_DATA),
@@ -50,14 +80,8 @@ pub fn process_subscribe(
     // the address of the subscription account is derived using the program id,
     // the signer address, the merchant address, and the subscription package name
     // thus ensuring a unique address for each signer + merchant + name
-    let (_subscribe_account_address, bump_seed) = Pubkey::find_program_address(
-        &[
-            &signer_info.key.to_bytes(),
-            &merchant_info.key.to_bytes(),
-            &name.as_bytes(),
-        ],
-        program_id,
-    );
+    let (_subscribe_account_address, bump_seed) =
+        get_subscription_account_pubkey(signer_info.key, merchant_info.key, &name, program_id);
     // get signer seeds
     let signer_seeds: &[&[_]] = &[
         &signer_info.key.to_bytes(),
@@ -66,12 +90,14 @@ pub fn process_subscribe(
         &[bump_seed],
     ];
 
+    // ensure this is not an existing subscription
+    account_not_exists_check(subscription_info)?;
     // Fund the subscription account with the minimum balance to be rent exempt
     invoke(
         &system_instruction::transfer(
             &signer_info.key,
             subscription_info.key,
-            Rent::default().minimum_balance(account_size),
+            rent.minimum_balance(account_size),
         ),
         &[
             signer_info.clone(),
@@ -92,7 +118,6 @@ pub fn process_subscribe(
         &[&signer_seeds],
     )?;
 
-    let rent = &Rent::from_account_info(rent_sysvar_info)?;
     let timestamp = Clock::get()?.unix_timestamp;
 
     // get the trial period duration
@@ -100,6 +125,20 @@ pub fn process_subscribe(
         None => 0,
         Some(value) => value,
     };
+    // partial trials lapse at the end of the trial, the first full period
+    // is paid for when a keeper renews the subscription
+    let paid_duration = if partial_trial { 0 } else { package.duration };
+    // very long packages must not wrap the period end around
+    let period_end = match timestamp
+        .checked_add(trial_duration)
+        .and_then(|value| value.checked_add(paid_duration))
+    {
+        None => {
+            msg!("Error: The subscription period end overflows");
+            return Err(PaymentProcessorError::SubscriptionPeriodOverflow.into());
+        }
+        Some(value) => value,
+    };
     // get the subscription account
     // TODO: ensure this account is not already initialized
     let mut subscription_data = subscription_info.try_borrow_mut_data()?;
@@ -112,15 +151,18 @@ pub fn process_subscribe(
         name,
         joined: timestamp,
         period_start: timestamp,
-        period_end: timestamp + trial_duration + package.duration,
+        period_end,
         data,
+        modified: timestamp,
+        current_order: order_info.key.to_bytes(),
     };
     subscription.pack(&mut subscription_data);
+    // mark the order as used
+    order_account.consumed = true;
+    pack_order_account(order_info, &order_account)?;
 
     // ensure subscription account is rent exempt
-    if !rent.is_exempt(subscription_info.lamports(), account_size) {
-        return Err(ProgramError::AccountNotRentExempt);
-    }
+    assert_rent_exempt(subscription_info, account_size, rent)?;
 
     Ok(())
 }
@@ -0,0 +1,72 @@
+use crate::{
+    engine::{common::system_program_check, config::get_program_owner, constants::PDA_SEED},
+    error::PaymentProcessorError,
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+/// Sweep the lamports sent to this program's derived address by mistake
+///
+/// Only the program owner (see config::get_program_owner) can do this.  The PDA
+/// holds no data so everything is swept, but should it ever hold data the rent
+/// for that data is left behind.
+pub fn process_sweep_pda(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let pda_info = next_account_info(account_info_iter)?;
+    let destination_info = next_account_info(account_info_iter)?;
+    let config_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // only the program owner can sweep the PDA
+    if *signer_info.key != get_program_owner(program_id, config_info)? {
+        msg!("Error: Only the program owner can sweep the PDA");
+        return Err(PaymentProcessorError::WrongProgramOwner.into());
+    }
+    // check that provided pda is correct
+    let (pda, pda_nonce) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+    if pda_info.key != &pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let rent_needed = if pda_info.data_is_empty() {
+        0
+    } else {
+        rent.minimum_balance(pda_info.data_len())
+    };
+    let amount = pda_info.lamports().saturating_sub(rent_needed);
+    if amount == 0 {
+        msg!("Info: There is nothing to sweep");
+        return Ok(());
+    }
+    // Transferring the excess lamports to the destination...
+    invoke_signed(
+        &system_instruction::transfer(&pda, destination_info.key, amount),
+        &[
+            pda_info.clone(),
+            destination_info.clone(),
+            system_program_info.clone(),
+        ],
+        &[&[&PDA_SEED, &[pda_nonce]]],
+    )?;
+
+    Ok(())
+}
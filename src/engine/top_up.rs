@@ -0,0 +1,57 @@
+use crate::engine::common::system_program_check;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+/// Top up the rent of an account owned by this program
+///
+/// Anyone (e.g. a keeper) can add the lamports an order, merchant or
+/// subscription account is short of being rent exempt for its current size.
+pub fn process_top_up_rent(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let account_info = next_account_info(account_info_iter)?;
+    let system_program_info = next_account_info(account_info_iter)?;
+    let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure the system program is the system program
+    system_program_check(system_program_info)?;
+    // ensure the account is owned by this program
+    if *account_info.owner != *program_id {
+        msg!("Error: Wrong owner for account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let shortfall = rent
+        .minimum_balance(account_info.data_len())
+        .saturating_sub(account_info.lamports());
+    if shortfall == 0 {
+        msg!("Info: Account {} is already rent exempt", account_info.key);
+        return Ok(());
+    }
+    // Transferring the rent shortfall to the account...
+    invoke(
+        &system_instruction::transfer(signer_info.key, account_info.key, shortfall),
+        &[
+            signer_info.clone(),
+            account_info.clone(),
+            system_program_info.clone(),
+        ],
+    )?;
+
+    Ok(())
+}
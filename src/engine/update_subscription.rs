@@ -0,0 +1,90 @@
+use crate::error::PaymentProcessorError;
+use crate::state::{Discriminator, IsClosed, Serdes, SubscriptionAccount};
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+/// Update Subscription Data
+///
+/// Replaces the data of a subscription that is not cancelled.  Accounts can not
+/// be resized so the new data must fit in the account, shorter data is padded
+/// with trailing spaces (which JSON ignores) to fill it.
+pub fn process_update_subscription_data(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let subscription_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure subscription account is owned by this program
+    if *subscription_info.owner != *program_id {
+        msg!("Error: Wrong owner for subscription account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the subscription account
+    let mut subscription_account = SubscriptionAccount::unpack(&subscription_info.data.borrow())?;
+    if !subscription_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if subscription_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if subscription_account.discriminator != Discriminator::Subscription as u8 {
+        msg!("Error: Invalid subscription account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the signer owns the subscription
+    if signer_info.key.to_bytes() != subscription_account.owner {
+        msg!("Error: One can only update their own subscription");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if subscription_account.is_cancelled() {
+        msg!("Error: A cancelled subscription can not be updated");
+        return Err(PaymentProcessorError::InvalidSubscriptionData.into());
+    }
+
+    // the account is unpacked from all of its data so the new data must fill it,
+    // subscriptions from before modified are written without it (see is_legacy)
+    let extension_len = if SubscriptionAccount::is_legacy(&subscription_info.data.borrow()) {
+        SubscriptionAccount::EXTENSION_LEN
+    } else {
+        0
+    };
+    subscription_account.data = data;
+    subscription_account.modified = timestamp;
+    let encoded_len = subscription_account.try_to_vec()?.len() - extension_len;
+    let account_len = subscription_info.data_len();
+    if encoded_len > account_len {
+        msg!(
+            "Error: The data can be at most {:?} bytes long",
+            account_len.saturating_sub(encoded_len - subscription_account.data.len())
+        );
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    subscription_account
+        .data
+        .push_str(&" ".repeat(account_len - encoded_len));
+    // Updating subscription account information...
+    SubscriptionAccount::pack(
+        &subscription_account,
+        &mut subscription_info.data.borrow_mut(),
+    );
+
+    Ok(())
+}
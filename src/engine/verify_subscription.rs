@@ -0,0 +1,79 @@
+use crate::{
+    error::PaymentProcessorError,
+    state::{Discriminator, IsClosed, Serdes, SubscriptionAccount, SubscriptionStatus},
+};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::IsInitialized,
+    pubkey::Pubkey,
+    sysvar::{clock::Clock, Sysvar},
+};
+
+/// Verify Subscription Access
+///
+/// Succeeds only if the signer owns an active subscription to the merchant's
+/// package, so that other programs can gate content on it with a single CPI.
+/// Cancelled subscriptions stay active until their period ends, paused ones do not.
+pub fn process_verify_subscription_access(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    package_name: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_info = next_account_info(account_info_iter)?;
+    let subscription_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    // ensure signer can sign
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // ensure subscription account is owned by this program
+    if *subscription_info.owner != *program_id {
+        msg!("Error: Wrong owner for subscription account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // get the subscription account
+    let subscription_account = SubscriptionAccount::unpack(&subscription_info.data.borrow())?;
+    if !subscription_account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if subscription_account.is_closed() {
+        return Err(PaymentProcessorError::ClosedAccount.into());
+    }
+    if subscription_account.discriminator != Discriminator::Subscription as u8 {
+        msg!("Error: Invalid subscription account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // ensure the signer owns the subscription
+    if signer_info.key.to_bytes() != subscription_account.owner {
+        msg!("Error: The subscription belongs to someone else");
+        return Err(PaymentProcessorError::WrongSubscriptionOwner.into());
+    }
+    if merchant_info.key.to_bytes() != subscription_account.merchant {
+        msg!("Error: The subscription is to another merchant");
+        return Err(PaymentProcessorError::WrongMerchant.into());
+    }
+    if subscription_account.name != package_name {
+        msg!(
+            "Error: The subscription is to package {:?}",
+            subscription_account.name
+        );
+        return Err(PaymentProcessorError::InvalidSubscriptionPackage.into());
+    }
+    if subscription_account.status == SubscriptionStatus::Paused as u8
+        || timestamp < subscription_account.period_start
+        || timestamp >= subscription_account.period_end
+    {
+        msg!("Error: The subscription is not active");
+        return Err(PaymentProcessorError::SubscriptionNotActive.into());
+    }
+
+    Ok(())
+}
@@ -1,11 +1,14 @@
+#[cfg(feature = "json")]
 use crate::{
-    engine::common::{get_subscription_package, transfer_sol, verify_subscription_order},
+    engine::common::{get_subscription_package, verify_subscription_order},
+    state::SubscriptionAccount,
+};
+use crate::{
+    engine::common::{distinct_accounts_check, token_program_check, transfer_sol},
     engine::constants::PDA_SEED,
     error::PaymentProcessorError,
-    state::{
-        Discriminator, IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes,
-        SubscriptionAccount,
-    },
+    state::{Discriminator, IsClosed, MerchantAccount, OrderAccount, OrderStatus, Serdes},
+    utils::{get_merchant_token_address, is_fully_paid},
 };
 use solana_program::program_pack::Pack;
 use solana_program::{
@@ -18,7 +21,10 @@ use solana_program::{
     pubkey::Pubkey,
     sysvar::{clock::Clock, Sysvar},
 };
-use spl_token::{self, state::Account as TokenAccount};
+use spl_token::{
+    self,
+    state::{Account as TokenAccount, Mint},
+};
 
 pub fn process_withdraw_payment(
     program_id: &Pubkey,
@@ -34,6 +40,7 @@ pub fn process_withdraw_payment(
     let account_to_receive_sol_refund_info = next_account_info(account_info_iter)?;
     let pda_info = next_account_info(account_info_iter)?;
     let token_program_info = next_account_info(account_info_iter)?;
+    let mint_info = next_account_info(account_info_iter)?;
 
     let timestamp = Clock::get()?.unix_timestamp;
 
@@ -41,6 +48,8 @@ pub fn process_withdraw_payment(
     if !signer_info.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
     }
+    // ensure the token program is the SPL token program
+    token_program_check(token_program_info)?;
     // ensure merchant and order accounts are owned by this program
     if *merchant_info.owner != *program_id {
         msg!("Error: Wrong owner for merchant account");
@@ -60,6 +69,18 @@ pub fn process_withdraw_payment(
     if pda_info.key != &pda {
         return Err(ProgramError::InvalidSeeds);
     }
+    // the tokens and the rent of the order must leave the order accounts
+    distinct_accounts_check(&[
+        order_info,
+        merchant_info,
+        order_payment_token_info,
+        merchant_token_info,
+    ])?;
+    distinct_accounts_check(&[
+        order_info,
+        order_payment_token_info,
+        account_to_receive_sol_refund_info,
+    ])?;
     // get the merchant account
     let merchant_account = MerchantAccount::unpack(&merchant_info.data.borrow())?;
     if merchant_account.is_closed() {
@@ -91,11 +112,35 @@ pub fn process_withdraw_payment(
     if order_payment_token_info.key.to_bytes() != order_account.token {
         return Err(ProgramError::InvalidAccountData);
     }
+    // ensure the mint is the one the order was paid in
+    if mint_info.key.to_bytes() != order_account.mint {
+        return Err(PaymentProcessorError::WrongMint.into());
+    }
+    let mint_data = Mint::unpack(&mint_info.data.borrow())?;
+    // locked orders can not be modified
+    if order_account.locked {
+        msg!("Error: Order is locked");
+        return Err(PaymentProcessorError::OrderLocked.into());
+    }
     // ensure order is not already paid out
     if order_account.status != OrderStatus::Paid as u8 {
         return Err(PaymentProcessorError::AlreadyWithdrawn.into());
     }
+    // don't allow withdrawal until the merchant's settlement period has passed
+    if !order_account.is_settled(timestamp, merchant_account.settlement_delay) {
+        return Err(PaymentProcessorError::SettlementPending.into());
+    }
+    // merchants that require full payment never withdraw under-paid orders
+    if merchant_account.require_full_payment && !is_fully_paid(&order_account, &merchant_account) {
+        msg!(
+            "Error: The order was paid {:?} of {:?}",
+            order_account.paid_amount,
+            order_account.expected_amount
+        );
+        return Err(PaymentProcessorError::NotFullyPaid.into());
+    }
     // check if this is for a subscription payment that has a trial period
+    #[cfg(feature = "json")]
     if merchant_account.discriminator == Discriminator::MerchantSubscriptionWithTrial as u8 {
         let subscription_info = next_account_info(account_info_iter)?;
         // ensure subscription account is owned by this program
@@ -124,47 +169,79 @@ pub fn process_withdraw_payment(
             return Err(PaymentProcessorError::CantWithdrawDuringTrial.into());
         }
     }
+    // the trial period can not be read without the json feature, so it is never
+    // taken to be over
+    #[cfg(not(feature = "json"))]
+    if merchant_account.discriminator == Discriminator::MerchantSubscriptionWithTrial as u8 {
+        msg!("Error: Withdrawing subscription payments with a trial requires the json feature");
+        return Err(PaymentProcessorError::CantWithdrawDuringTrial.into());
+    }
+    // whatever was refunded to the payer is not withdrawn
+    let withdraw_amount = order_account
+        .paid_amount
+        .saturating_sub(order_account.refunded_amount);
+    // ensure the order payment token account still holds the amount paid
+    let order_payment_token_data = TokenAccount::unpack(&order_payment_token_info.data.borrow())?;
+    if order_payment_token_data.amount < withdraw_amount {
+        msg!(
+            "Error: Order token account holds {:?} but {:?} was paid",
+            order_payment_token_data.amount,
+            withdraw_amount
+        );
+        return Err(PaymentProcessorError::InsufficientOrderBalance.into());
+    }
     // Transferring payment to the merchant...
     invoke_signed(
-        &spl_token::instruction::transfer(
+        &spl_token::instruction::transfer_checked(
             token_program_info.key,
             order_payment_token_info.key,
+            mint_info.key,
             merchant_token_info.key,
             &pda,
             &[&pda],
-            order_account.paid_amount,
+            withdraw_amount,
+            mint_data.decimals,
         )
         .unwrap(),
         &[
             token_program_info.clone(),
             order_payment_token_info.clone(),
+            mint_info.clone(),
             merchant_token_info.clone(),
             pda_info.clone(),
         ],
         &[&[&PDA_SEED, &[pda_nonce]]],
     )?;
-    // Close the order token account since it will never be needed again
-    invoke_signed(
-        &spl_token::instruction::close_account(
-            token_program_info.key,
-            order_payment_token_info.key,
-            account_to_receive_sol_refund_info.key,
-            &pda,
-            &[&pda],
-        )
-        .unwrap(),
-        &[
-            token_program_info.clone(),
-            order_payment_token_info.clone(),
-            account_to_receive_sol_refund_info.clone(),
-            pda_info.clone(),
-        ],
-        &[&[&PDA_SEED, &[pda_nonce]]],
-    )?;
+    // the merchant token account is shared by many orders so we leave it open
+    let (merchant_token_address, _bump_seed) = get_merchant_token_address(
+        merchant_info.key,
+        &Pubkey::new_from_array(order_account.mint),
+        program_id,
+    );
+    if *order_payment_token_info.key != merchant_token_address {
+        // Close the order token account since it will never be needed again
+        invoke_signed(
+            &spl_token::instruction::close_account(
+                token_program_info.key,
+                order_payment_token_info.key,
+                account_to_receive_sol_refund_info.key,
+                &pda,
+                &[&pda],
+            )
+            .unwrap(),
+            &[
+                token_program_info.clone(),
+                order_payment_token_info.clone(),
+                account_to_receive_sol_refund_info.clone(),
+                pda_info.clone(),
+            ],
+            &[&[&PDA_SEED, &[pda_nonce]]],
+        )?;
+    }
 
     if close_order_account {
-        if merchant_account.owner != signer_info.key.to_bytes() {
-            msg!("Error: Only merchant account owner can close order account");
+        if !merchant_account.can_operate(&signer_info.key.to_bytes()) {
+            msg!("Error: Only merchant account owner or operator can close order account");
             return Err(ProgramError::MissingRequiredSignature);
         }
         // mark account as closed
@@ -178,9 +255,74 @@ pub fn process_withdraw_payment(
     }
 
     // Updating order account information...
-    order_account.status = OrderStatus::Withdrawn as u8;
+    order_account.transition(OrderStatus::Withdrawn)?;
     order_account.modified = timestamp;
     OrderAccount::pack(&order_account, &mut order_info.data.borrow_mut());
 
     Ok(())
 }
+
+/// Withdraw a batch of subscription orders
+///
+/// Each order comes with its order token account and subscription account, so
+/// the trial check is applied per order.  Orders still in their trial period are
+/// skipped (and can be withdrawn later) instead of failing the whole batch.
+#[cfg(feature = "json")]
+pub fn process_withdraw_subscription_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    close_order_accounts: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let signer_info = next_account_info(account_info_iter)?;
+    let merchant_info = next_account_info(account_info_iter)?;
+    let merchant_token_info = next_account_info(account_info_iter)?;
+    let account_to_receive_sol_refund_info = next_account_info(account_info_iter)?;
+    let pda_info = next_account_info(account_info_iter)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    let mint_info = next_account_info(account_info_iter)?;
+    // the rest are (order, order token, subscription) triples
+    let order_infos = account_info_iter.as_slice();
+    if order_infos.is_empty() || order_infos.len() % 3 != 0 {
+        msg!("Error: Expected an order, order token and subscription account per order");
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    let mut withdrawn: u64 = 0;
+    for order_accounts in order_infos.chunks(3) {
+        let result = process_withdraw_payment(
+            program_id,
+            &[
+                signer_info.clone(),
+                order_accounts[0].clone(),
+                merchant_info.clone(),
+                order_accounts[1].clone(),
+                merchant_token_info.clone(),
+                account_to_receive_sol_refund_info.clone(),
+                pda_info.clone(),
+                token_program_info.clone(),
+                mint_info.clone(),
+                order_accounts[2].clone(),
+            ],
+            close_order_accounts,
+        );
+        match result {
+            // nothing was changed for an order still in its trial period
+            Err(error) if error == PaymentProcessorError::CantWithdrawDuringTrial.into() => {
+                msg!(
+                    "Info: Order {} is still in its trial period, skipped",
+                    order_accounts[0].key
+                );
+            }
+            Err(error) => return Err(error),
+            Ok(()) => withdrawn += 1,
+        }
+    }
+    msg!(
+        "Info: Withdrew {:?} of {:?} orders",
+        withdrawn,
+        order_infos.len() / 3
+    );
+
+    Ok(())
+}
@@ -64,6 +64,90 @@ pub enum PaymentProcessorError {
     /// The Provided mint Is Wrong
     #[error("Error: The Provided mint Is Wrong")]
     WrongMint,
+    /// The Subscription Has Not Lapsed
+    #[error("Error: The Subscription Has Not Lapsed")]
+    SubscriptionNotLapsed,
+    /// The Account Already Exists
+    #[error("Error: The Account Already Exists")]
+    AccountAlreadyExists,
+    /// The Payment Is Still Pending Settlement
+    #[error("Error: The Payment Is Still Pending Settlement")]
+    SettlementPending,
+    /// The Config Account Is Wrong
+    #[error("Error: The Config Account Is Wrong")]
+    WrongConfig,
+    /// The Mint Is Not Accepted By The Merchant
+    #[error("Error: The Mint Is Not Accepted By The Merchant")]
+    MintNotAccepted,
+    /// The Order Token Account Holds Less Than The Amount Paid
+    #[error("Error: The Order Token Account Holds Less Than The Amount Paid")]
+    InsufficientOrderBalance,
+    /// The Order Cannot Move To This Status
+    #[error("Error: The Order Cannot Move To This Status")]
+    InvalidOrderTransition,
+    /// The Refund Is More Than The Amount Paid
+    #[error("Error: The Refund Is More Than The Amount Paid")]
+    RefundExceedsPaid,
+    /// The Order Is Locked
+    #[error("Error: The Order Is Locked")]
+    OrderLocked,
+    /// The Oracle Price Is Invalid Or Stale
+    #[error("Error: The Oracle Price Is Invalid Or Stale")]
+    InvalidOraclePrice,
+    /// The Order Was Already Used
+    #[error("Error: The Order Was Already Used")]
+    OrderAlreadyUsed,
+    /// The Order Has Too Many Items
+    #[error("Error: The Order Has Too Many Items")]
+    TooManyItems,
+    /// The Subscription Belongs To Someone Else
+    #[error("Error: The Subscription Belongs To Someone Else")]
+    WrongSubscriptionOwner,
+    /// The Subscription Is Not Active
+    #[error("Error: The Subscription Is Not Active")]
+    SubscriptionNotActive,
+    /// The Subscription Period Is Too Long
+    #[error("Error: The Subscription Period Is Too Long")]
+    SubscriptionPeriodOverflow,
+    /// The Mint Account Is Not A Token Mint
+    #[error("Error: The Mint Account Is Not A Token Mint")]
+    InvalidMint,
+    /// The Order Signature Is Missing Or Invalid
+    #[error("Error: The Order Signature Is Missing Or Invalid")]
+    InvalidOrderSignature,
+    /// The Order Exists For A Different Checkout
+    #[error("Error: The Order Exists For A Different Checkout")]
+    OrderConflict,
+    /// The Secret Contains Control Characters
+    #[error("Error: The Secret Contains Control Characters")]
+    InvalidSecret,
+    /// The Amount Is Less Than The Merchant Minimum
+    #[error("Error: The Amount Is Less Than The Merchant Minimum")]
+    AmountTooSmall,
+    /// The Amount Is More Than The Merchant Maximum
+    #[error("Error: The Amount Is More Than The Merchant Maximum")]
+    AmountTooLarge,
+    /// The Order Id Is Empty, Too Long Or Has Invalid Characters
+    #[error("Error: The Order Id Is Empty, Too Long Or Has Invalid Characters")]
+    InvalidOrderId,
+    /// The Delegated Amount Does Not Cover The Checkout
+    #[error("Error: The Delegated Amount Does Not Cover The Checkout")]
+    InsufficientDelegatedAmount,
+    /// The Same Account Is Passed More Than Once
+    #[error("Error: The Same Account Is Passed More Than Once")]
+    DuplicateAccount,
+    /// The Order Has No Secret Or It Was Already Revealed
+    #[error("Error: The Order Has No Secret Or It Was Already Revealed")]
+    NoSecret,
+    /// The Coupon Was Redeemed As Many Times As It Can Be
+    #[error("Error: The Coupon Was Redeemed As Many Times As It Can Be")]
+    CouponExhausted,
+    /// The Coupon Has Expired
+    #[error("Error: The Coupon Has Expired")]
+    CouponExpired,
+    /// The Signer Is Not Approved To Register Merchants
+    #[error("Error: The Signer Is Not Approved To Register Merchants")]
+    RegistrantNotApproved,
 }
 
 impl From<PaymentProcessorError> for ProgramError {
@@ -1,24 +1,99 @@
+use crate::engine::constants::{
+    ASSOCIATED_TOKEN_PROGRAM_ID, COMPACT_ID_LEN, ED25519_PROGRAM_ID, PDA_SEED, PROGRAM_OWNER,
+};
 use crate::engine::json::OrderItems;
+use crate::engine::signature::{
+    ED25519_DATA_START, ED25519_PUBKEY_LEN, ED25519_SIGNATURE_COUNT, ED25519_SIGNATURE_LEN,
+    ED25519_THIS_INSTRUCTION,
+};
+use crate::state::FeeMode;
+use crate::utils::{
+    get_associated_token_address, get_compact_id_string, get_config_address, get_coupon_address,
+    get_ledger_address, get_order_pubkey_by_index, get_order_signature_message,
+    get_package_address, get_registry_address, get_versioned_pda, OrderId,
+};
 use borsh::{BorshDeserialize, BorshSerialize};
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive as FromPrimitiveTrait;
 use solana_program::{
+    clock::UnixTimestamp,
     instruction::{AccountMeta, Instruction},
     pubkey::Pubkey,
     sysvar,
 };
 use spl_token::{self};
 use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Write};
+use std::str::FromStr;
+
+/// Instruction tags
+///
+/// Each instruction is serialized with its tag as the leading byte.  The tags are
+/// fixed so that adding or reordering instruction variants does not break existing
+/// clients.  New instructions must take the next unused tag and a tag must never
+/// be reused.
+#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive)]
+pub enum InstructionTag {
+    RegisterMerchant = 0,
+    ExpressCheckout = 1,
+    ChainCheckout = 2,
+    Withdraw = 3,
+    Subscribe = 4,
+    RenewSubscription = 5,
+    CancelSubscription = 6,
+    CreateMerchantTokenAccount = 7,
+    PauseSubscription = 8,
+    ResumeSubscription = 9,
+    KeeperRenew = 10,
+    UpdateConfigOwner = 11,
+    AddPackage = 12,
+    RemovePackage = 13,
+    RefundOrder = 14,
+    LockOrder = 15,
+    UnlockOrder = 16,
+    TopUpRent = 17,
+    CloseSubscription = 18,
+    SweepPda = 19,
+    SetOperator = 20,
+    VerifySubscriptionAccess = 21,
+    CheckoutAndSubscribe = 22,
+    /// an ExpressCheckout with a fixed length order id and secret, decoded as an
+    /// ExpressCheckout (see compact_express_checkout)
+    CompactExpressCheckout = 23,
+    AdminFixOrder = 24,
+    WithdrawSubscriptionBatch = 25,
+    CancelPendingOrder = 26,
+    VerifyOrderSettled = 27,
+    MigratePdaTokenAccount = 28,
+    UpdateSubscriptionData = 29,
+    ComputeOrderSize = 30,
+    SimulateCheckoutFees = 31,
+    MerchantCancelSubscription = 32,
+    RevealSecret = 33,
+    CreateCoupon = 34,
+    RedeemCoupon = 35,
+    UpdateRegistry = 36,
+    CreateLedger = 37,
+}
 
-#[derive(Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum PaymentProcessorInstruction {
     /// Register for a merchant account.
     ///
     /// Accounts expected:
     ///
     /// 0. `[signer]` The account of the person initializing the merchant account
-    /// 1. `[writable]` The merchant account.  Owned by this program
+    /// 1. `[writable]` The merchant account.  Owned by this program.  Created from the seed unless
+    ///    it was created beforehand, in which case it must sign, be owned by this program,
+    ///    be exactly the merchant account size (see utils::get_merchant_account_size) and
+    ///    hold no data
     /// 2. `[]` System program
-    /// 3. `[]` The rent sysvar
-    /// 4. `[optional]` The sponsor account
+    /// 3. `[]` The registry account (see utils::get_registry_address).  Once it exists only
+    ///    the keys it approves can register merchants (see UpdateRegistry)
+    /// 4. `[optional]` The rent sysvar.  Read with the Rent::get syscall when left out
+    /// 5. `[optional]` The sponsor account.  Must be a wallet, not owned by a program
+    /// 6. `[optional]` The fee recipient account.  Receives the program owner portion of
+    ///    the fee instead of the program owner.  Requires the sponsor account to be present
     RegisterMerchant {
         /// the seed used when creating the account
         #[allow(dead_code)] // not dead code..
@@ -29,26 +104,94 @@ pub enum PaymentProcessorInstruction {
         /// arbitrary merchant data (maybe as a JSON string)
         #[allow(dead_code)] // not dead code..
         data: Option<String>,
+        /// the number of seconds after an order is created before its payment can be
+        /// withdrawn.  Optional in the instruction data so older clients keep working
+        #[allow(dead_code)] // not dead code..
+        settlement_delay: Option<i64>,
+        /// the mints (currencies) the merchant accepts for payment, any mint when empty
+        #[allow(dead_code)] // not dead code..
+        accepted_mints: Option<Vec<Pubkey>>,
+        /// whether subscription refunds may go to token accounts not owned by the payer.
+        /// Refunds only go to the payer by default
+        #[allow(dead_code)] // not dead code..
+        allow_third_party_refunds: Option<bool>,
+        /// buyers charged a different fee (in SOL lamports), at most the merchant fee
+        #[allow(dead_code)] // not dead code..
+        fee_overrides: Option<Vec<(Pubkey, u64)>>,
+        /// the shortest duration (in seconds) of the merchant's subscription packages
+        #[allow(dead_code)] // not dead code..
+        min_subscription_duration: Option<i64>,
+        /// the charity and its share (in basis points) of every payment.  The seller
+        /// receives the rest
+        #[allow(dead_code)] // not dead code..
+        charity: Option<(Pubkey, u16)>,
+        /// how the fee is charged, a flat fee in SOL lamports by default.  With the
+        /// basis point modes the fee is at most MAX_BPS basis points
+        #[allow(dead_code)] // not dead code..
+        fee_mode: Option<FeeMode>,
+        /// the (minimum, maximum) amount a checkout can be for, zero means no limit
+        #[allow(dead_code)] // not dead code..
+        amount_limits: Option<(u64, u64)>,
+        /// checkouts for less than the first or more than the second amount are free
+        /// of fees, zero means no such amount
+        #[allow(dead_code)] // not dead code..
+        fee_free_band: Option<(u64, u64)>,
+        /// whether orders paid less than the merchant's share of their expected amount
+        /// can not be withdrawn.  They can by default
+        #[allow(dead_code)] // not dead code..
+        require_full_payment: Option<bool>,
     },
     /// Express Checkout
     ///
     /// Meant to be used to process payments initialized by systems that reside off-chain
     /// such as traditional e-commerce software.
     ///
+    /// Besides the Borsh encoding of this variant, an ExpressCheckout can be sent in a compact
+    /// encoding that saves transaction space (see compact_express_checkout).
+    ///
+    /// Checkouts are safe to resubmit: if the order account is already paid by the same
+    /// payer for the same order id and amount nothing happens, if it is not the checkout
+    /// fails with PaymentProcessorError::OrderConflict.
+    ///
     /// Accounts expected:
     ///
     /// 0. `[signer]` The account of the person initializing the transaction
-    /// 1. `[writable]` The order account.  Owned by this program
-    /// 2. `[]` The merchant account.  Owned by this program
-    /// 3. `[writable]` The seller token account - this is where the amount paid will go. Owned by this program
+    /// 1. `[writable]` The order account.  Owned by this program.  Either a new account or derived
+    ///    from the merchant order count (see utils::get_order_pubkey_by_index)
+    /// 2. `[writable]` The merchant account.  Owned by this program
+    /// 3. `[writable]` The seller token account - this is where the amount paid will go. Owned by this program.
+    ///    Can be the merchant token account (see CreateMerchantTokenAccount)
     /// 4. `[writable]` The buyer token account
-    /// 5. `[writable]` The program owner account (where we will send program owner fee)
+    /// 5. `[writable]` The merchant's fee recipient, the program owner account unless the
+    ///    merchant registered another one (where we will send program owner fee)
     /// 6. `[writable]` The sponsor account (where we will send sponsor fee)
     /// 7. `[]` The token mint account - represents the 'currency' being used
     /// 8. `[]` This program's derived address
     /// 9. `[]` The token program
     /// 10. `[]` The System program
-    /// 11. `[]` The rent sysvar
+    /// 11. `[]` The rent sysvar, which the token program needs to initialize the seller
+    ///     token account
+    /// 12. `[]` The program config account (see utils::get_config_address)
+    /// 13. `[]` (optional) The oracle price account of the mint, required when the amount is
+    ///     quoted in USD (see with_usd_price)
+    /// 14. `[writable]` (optional) The charity token account, required when the merchant has a
+    ///     charity (see with_charity_token).  Receives the charity share of the amount, which
+    ///     is not refundable
+    /// 15. `[writable]` (optional) The fee recipient token account, required when the merchant
+    ///     fee mode is FeeMode::BpsToken (see with_fee_token).  Receives the fee out of the amount.
+    ///     When it does not exist yet it is followed by the associated token account program
+    ///     and created as the associated token account of the fee recipient (see
+    ///     with_new_fee_token)
+    /// 16. `[]` (optional) The instructions sysvar, required for merchant signed orders
+    ///     (see with_merchant_signature)
+    /// 17. `[signer, writable]` (optional) The merchant owner or operator, when the merchant
+    ///     pays the rent and SOL fee of the checkout (see with_rent_payer)
+    /// 18. `[writable]` (optional) The coupon account, when a coupon of the merchant is
+    ///     redeemed with the checkout (see with_coupon).  Only merchant signed orders can
+    ///     redeem coupons, the merchant signs for the price after the coupon discount (see
+    ///     utils::get_coupon_price)
+    /// 19. `[writable]` (optional) The merchant ledger account, when the payment is appended
+    ///     to it (see with_ledger)
     ExpressCheckout {
         #[allow(dead_code)] // not dead code..
         amount: u64,
@@ -56,12 +199,45 @@ pub enum PaymentProcessorInstruction {
         #[allow(dead_code)] // not dead code..
         order_id: String,
         // An extra field that can store an encrypted (ot not encrypted) string
-        // that the merchant can use to assert if a transaction is authentic
+        // that the merchant can use to assert if a transaction is authentic.
+        // An empty secret is not stored in the order account, saving its rent
         #[allow(dead_code)] // not dead code..
         secret: String,
         /// arbitrary merchant data (maybe as a JSON string)
         #[allow(dead_code)] // not dead code..
         data: Option<String>,
+        /// identifies the client that sent the checkout e.g. "web-sdk-1.2.0".  It is
+        /// only logged, to help correlate failures with client versions
+        #[allow(dead_code)] // not dead code..
+        client_tag: Option<String>,
+        /// the price in USD (see USD_DECIMALS).  The amount paid must be worth at least
+        /// this much at the oracle price and the order expects this amount
+        #[allow(dead_code)] // not dead code..
+        usd_amount: Option<u64>,
+        /// revoke any delegate of the buyer token account after the payment, so that a
+        /// delegate approved for this checkout does not linger
+        #[allow(dead_code)] // not dead code..
+        revoke_delegate: Option<bool>,
+        /// the merchant signed (order_id, amount, mint) with the merchant owner key and
+        /// the Ed25519 program verifies the signature in the previous instruction (see
+        /// merchant_signature and utils::get_order_signature_message)
+        #[allow(dead_code)] // not dead code..
+        merchant_signed: Option<bool>,
+        /// the merchant pays the rent and SOL fee of the checkout, so that buyers without
+        /// SOL can pay (see with_rent_payer)
+        #[allow(dead_code)] // not dead code..
+        merchant_pays_rent: Option<bool>,
+        /// reject secrets with control characters instead of storing them (see
+        /// with_strict_secret)
+        #[allow(dead_code)] // not dead code..
+        strict_secret: Option<bool>,
+        /// a coupon of the merchant is redeemed with the checkout (see with_coupon),
+        /// which requires a merchant signed order
+        #[allow(dead_code)] // not dead code..
+        coupon: Option<bool>,
+        /// the payment is appended to the merchant ledger (see with_ledger)
+        #[allow(dead_code)] // not dead code..
+        ledger: Option<bool>,
     },
     /// Chain Checkout
     ///
@@ -76,17 +252,26 @@ pub enum PaymentProcessorInstruction {
     /// Accounts expected:
     ///
     /// 0. `[signer]` The account of the person initializing the transaction
-    /// 1. `[writable]` The order account.  Owned by this program
-    /// 2. `[]` The merchant account.  Owned by this program
-    /// 3. `[writable]` The seller token account - this is where the amount paid will go. Owned by this program
+    /// 1. `[writable]` The order account.  Owned by this program.  Either a new account or derived
+    ///    from the merchant order count (see utils::get_order_pubkey_by_index)
+    /// 2. `[writable]` The merchant account.  Owned by this program
+    /// 3. `[writable]` The seller token account - this is where the amount paid will go. Owned by this program.
+    ///    Can be the merchant token account (see CreateMerchantTokenAccount)
     /// 4. `[writable]` The buyer token account
-    /// 5. `[writable]` The program owner account (where we will send program owner fee)
+    /// 5. `[writable]` The merchant's fee recipient, the program owner account unless the
+    ///    merchant registered another one (where we will send program owner fee)
     /// 6. `[writable]` The sponsor account (where we will send sponsor fee)
     /// 7. `[]` The token mint account - represents the 'currency' being used
     /// 8. `[]` This program's derived address
     /// 9. `[]` The token program
     /// 10. `[]` The System program
-    /// 11. `[]` The rent sysvar
+    /// 11. `[]` The rent sysvar, which the token program needs to initialize the seller
+    ///     token account
+    /// 12. `[]` The program config account (see utils::get_config_address)
+    /// 13. `[writable]` (optional) The charity token account, required when the merchant has a
+    ///     charity (see with_charity_token)
+    /// 14. `[writable]` (optional) The fee recipient token account, required when the merchant
+    ///     fee mode is FeeMode::BpsToken (see with_fee_token or with_new_fee_token)
     ChainCheckout {
         #[allow(dead_code)] // not dead code..
         amount: u64,
@@ -96,6 +281,9 @@ pub enum PaymentProcessorInstruction {
         /// arbitrary merchant data (maybe as a JSON string)
         #[allow(dead_code)] // not dead code..
         data: Option<String>,
+        /// identifies the client that sent the checkout (see ExpressCheckout)
+        #[allow(dead_code)] // not dead code..
+        client_tag: Option<String>,
     },
     /// Withdraw funds for a particular order
     ///
@@ -112,6 +300,8 @@ pub enum PaymentProcessorInstruction {
     /// 5. `[writable]` This account receives the refunded SOL after closing order token account
     /// 6. `[]` This program's derived address
     /// 7. `[]` The token program
+    /// 8. `[]` The token mint account - the mint the order was paid in
+    /// 9. `[optional]` The subscription account, for orders made for a subscription with a trial
     Withdraw {
         /// should we close the order account?
         /// can be sent as 0 for false; 1 for true from a dApp
@@ -133,9 +323,12 @@ pub enum PaymentProcessorInstruction {
     /// 0. `[signer]` The account of the person initializing the transaction
     /// 1. `[writable]` The subscription account.  Owned by this program
     /// 2. `[]` The merchant account.  Owned by this program
-    /// 3. `[]` The order account.  Owned by this program
+    /// 3. `[writable]` The order account.  Owned by this program.  An order can only be
+    ///    used for one subscription or renewal
     /// 4. `[]` The System program
-    /// 5. `[]` The rent sysvar
+    /// 5. `[optional]` The rent sysvar.  Read with the Rent::get syscall when left out
+    /// 6. `[optional]` The package account, for merchants that keep their packages in
+    ///    package accounts (see AddPackage)
     Subscribe {
         /// the subscription package name
         #[allow(dead_code)] // not dead code..
@@ -156,7 +349,10 @@ pub enum PaymentProcessorInstruction {
     /// 0. `[signer]` The account of the person initializing the transaction
     /// 1. `[writable]` The subscription account.  Owned by this program
     /// 2. `[]` The merchant account.  Owned by this program
-    /// 3. `[]` The order account.  Owned by this program
+    /// 3. `[writable]` The order account.  Owned by this program.  An order can only be
+    ///    used for one subscription or renewal
+    /// 4. `[optional]` The package account, for merchants that keep their packages in
+    ///    package accounts (see AddPackage)
     RenewSubscription {
         /// the number of periods to renew e.g. if the subscription period is a year
         /// you can choose to renew for 1 year, 2 years, n years, etc
@@ -176,43 +372,1179 @@ pub enum PaymentProcessorInstruction {
     /// 2. `[]` The merchant account.  Owned by this program
     /// 3. `[writable]` The order account.  Owned by this program
     /// 4. `[writable]` The order token account - this is where the amount was paid into. Owned by this program
-    /// 5. `[writable]` The refund token account - this is where the refund will go
+    /// 5. `[writable]` The refund token account - this is where the refund will go.  Must be
+    ///    owned by the payer unless the merchant allows third party refunds
     /// 6. `[writable]` This account receives the refunded SOL after closing order token account
     /// 7. `[]` This program's derived address
     /// 8. `[]` The token program
+    /// 9. `[optional]` The package account, for merchants that keep their packages in
+    ///    package accounts (see AddPackage)
     CancelSubscription,
+    /// Create a merchant token account
+    ///
+    /// Creates a token account owned by this program whose address is derived from
+    /// the merchant account and the mint.  Checkouts can pass this account as the
+    /// seller token account so that all orders paid in this mint share it, instead
+    /// of paying rent for a new token account per order.  Withdrawals then move
+    /// each order's paid amount out and leave the account open.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The owner of the merchant account
+    /// 1. `[]` The merchant account.  Owned by this program
+    /// 2. `[writable]` The merchant token account.  Owned by this program
+    /// 3. `[]` The token mint account - represents the 'currency' being used
+    /// 4. `[]` This program's derived address
+    /// 5. `[]` The token program
+    /// 6. `[]` The System program
+    /// 7. `[]` The rent sysvar
+    CreateMerchantTokenAccount,
+    /// Pause a subscription
+    ///
+    /// Only an active subscription can be paused.  The time remaining in the current
+    /// period is kept and the period end is pushed back by the time spent paused
+    /// once the subscription is resumed.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The owner of the subscription
+    /// 1. `[writable]` The subscription account.  Owned by this program
+    PauseSubscription,
+    /// Resume a paused subscription
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The owner of the subscription
+    /// 1. `[writable]` The subscription account.  Owned by this program
+    ResumeSubscription,
+    /// Renew a lapsed subscription from the subscriber's token balance
+    ///
+    /// Meant to be sent by keepers.  The subscriber first approves this program's
+    /// derived address as the delegate of a token account holding the package mint.
+    /// Once the subscription period has ended anyone can send this instruction to
+    /// pull the package price from that account and start a new period.  The keeper
+    /// pays the rent for the new order account and no processing fee is charged.
+    /// Instead the keeper is rewarded from the lamports the merchant account holds
+    /// above its rent exemption, if any.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The keeper account.  Pays for the new accounts and
+    ///    receives the keeper reward
+    /// 1. `[writable]` The subscription account.  Owned by this program
+    /// 2. `[writable]` The merchant account.  Owned by this program.  Pays the keeper reward
+    /// 3. `[writable]` The order account.  Either derived from the merchant order count or a new keypair account
+    /// 4. `[writable]` The seller token account - this is where the amount paid will go. Owned by this program
+    /// 5. `[writable]` The buyer token account.  Delegated to this program's derived address
+    /// 6. `[]` The token mint account - represents the 'currency' being used
+    /// 7. `[]` This program's derived address
+    /// 8. `[]` The token program
+    /// 9. `[]` The System program
+    /// 10. `[]` The rent sysvar
+    /// 11. `[optional]` The package account, for merchants that keep their packages in
+    ///     package accounts (see AddPackage)
+    KeeperRenew,
+    /// Update the key that collects the program owner fees
+    ///
+    /// Checkouts send the program owner fee to the owner in the config account unless
+    /// the merchant has its own fee recipient.  Until the config account is created the
+    /// owner is the program owner; the first update creates the config account.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current config owner
+    /// 1. `[writable]` The program config account (see utils::get_config_address)
+    /// 2. `[]` The System program
+    /// 3. `[]` The rent sysvar
+    UpdateConfigOwner {
+        /// the key that will collect the program owner fees
+        #[allow(dead_code)] // not dead code..
+        new_owner: Pubkey,
+    },
+    /// Add a subscription package account
+    ///
+    /// An alternative to keeping the subscription packages in the merchant data.  Each
+    /// package is its own account derived from the merchant and the package name (see
+    /// utils::get_package_address).  Only merchants registered without packages in their
+    /// data can add package accounts, and once they do their subscriptions use them.
+    /// Package accounts do not have a trial period.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The owner of the merchant account
+    /// 1. `[writable]` The merchant account.  Owned by this program
+    /// 2. `[writable]` The package account.  Owned by this program
+    /// 3. `[]` The System program
+    /// 4. `[]` The rent sysvar
+    AddPackage {
+        /// the subscription package name, at most 32 bytes
+        #[allow(dead_code)] // not dead code..
+        name: String,
+        /// duration of the subscription in seconds
+        #[allow(dead_code)] // not dead code..
+        duration: i64,
+        /// the price in full for this subscription option
+        #[allow(dead_code)] // not dead code..
+        price: u64,
+        /// the mint (currency) used for this package
+        #[allow(dead_code)] // not dead code..
+        mint: Pubkey,
+    },
+    /// Remove a subscription package account
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The owner of the merchant account.  Receives the package account rent
+    /// 1. `[]` The merchant account.  Owned by this program
+    /// 2. `[writable]` The package account.  Owned by this program
+    RemovePackage,
+    /// Refund Order
+    ///
+    /// Refunds part or all of a paid order that has not been withdrawn.  Refunds add up
+    /// and can never be more than the amount paid.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The owner of the merchant account
+    /// 1. `[writable]` The order account.  Owned by this program
+    /// 2. `[]` The merchant account.  Owned by this program
+    /// 3. `[writable]` The order token account (where the money was put during payment)
+    /// 4. `[writable]` The refund token account.  Must be owned by the payer unless the
+    ///    merchant allows third party refunds
+    /// 5. `[]` This program's derived address
+    /// 6. `[]` The token program
+    /// 7. `[]` The token mint account - the mint the order was paid in
+    RefundOrder {
+        /// the amount to refund
+        #[allow(dead_code)] // not dead code..
+        amount: u64,
+    },
+    /// Lock Order
+    ///
+    /// Holds an order (e.g. for compliance reasons) so that it can not be withdrawn,
+    /// refunded or cancelled until it is unlocked.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current config owner
+    /// 1. `[writable]` The order account.  Owned by this program
+    /// 2. `[]` The program config account (see utils::get_config_address)
+    LockOrder,
+    /// Unlock Order
+    ///
+    /// Releases an order locked by LockOrder.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current config owner
+    /// 1. `[writable]` The order account.  Owned by this program
+    /// 2. `[]` The program config account (see utils::get_config_address)
+    UnlockOrder,
+    /// Top Up Rent
+    ///
+    /// Adds the lamports an account owned by this program is short of being rent exempt
+    /// e.g. after its data grew.  Anyone can do this.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account paying for the top up
+    /// 1. `[writable]` The account to top up.  Owned by this program
+    /// 2. `[]` The System program
+    /// 3. `[]` The rent sysvar
+    TopUpRent,
+    /// Close Subscription
+    ///
+    /// Reclaims the rent of a subscription that is cancelled, or that lapsed at least
+    /// SUBSCRIPTION_CLOSE_DELAY ago, once no paid period remains.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The subscription owner
+    /// 1. `[writable]` The subscription account.  Owned by this program
+    /// 2. `[writable]` The account to receive the rent
+    CloseSubscription,
+    /// Sweep PDA
+    ///
+    /// Sends lamports that were sent to this program's derived address by mistake to
+    /// a destination.  Any rent the PDA needs is left behind.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current config owner
+    /// 1. `[writable]` This program's derived address
+    /// 2. `[writable]` The account to receive the lamports
+    /// 3. `[]` The program config account (see utils::get_config_address)
+    /// 4. `[]` The System program
+    /// 5. `[]` The rent sysvar
+    SweepPda,
+    /// Set Operator
+    ///
+    /// Lets a key other than the merchant owner withdraw and manage the merchant.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The merchant owner
+    /// 1. `[writable]` The merchant account.  Owned by this program
+    SetOperator {
+        /// the operator, Pubkey::default() for no operator
+        #[allow(dead_code)] // not dead code..
+        operator: Pubkey,
+    },
+    /// Verify Subscription Access
+    ///
+    /// Succeeds only if the signer owns an active subscription to the package.  Meant to be
+    /// called (e.g. via CPI) by programs that gate content on subscriptions.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The subscription owner
+    /// 1. `[]` The subscription account.  Owned by this program
+    /// 2. `[]` The merchant account
+    VerifySubscriptionAccess {
+        /// the name of the subscription package
+        #[allow(dead_code)] // not dead code..
+        package_name: String,
+    },
+    /// Checkout And Subscribe
+    ///
+    /// Pays for a subscription and subscribes in one instruction, instead of an
+    /// ExpressCheckout followed by a Subscribe.  The order data names the subscription.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0.-12. The ExpressCheckout accounts
+    /// 13. `[writable]` The subscription account.  Owned by this program
+    /// 14. (optional) The optional ExpressCheckout accounts (see with_charity_token and
+    ///     with_fee_token) followed by the package account, for merchants that keep their
+    ///     packages in package accounts (see AddPackage)
+    CheckoutAndSubscribe {
+        #[allow(dead_code)] // not dead code..
+        amount: u64,
+        /// the external order id (as in issued by the merchant)
+        #[allow(dead_code)] // not dead code..
+        order_id: String,
+        /// see ExpressCheckout
+        #[allow(dead_code)] // not dead code..
+        secret: String,
+        /// the subscription package name
+        #[allow(dead_code)] // not dead code..
+        name: String,
+        /// arbitrary merchant data for the subscription (maybe as a JSON string)
+        #[allow(dead_code)] // not dead code..
+        data: Option<String>,
+    },
+    /// Admin Fix Order
+    ///
+    /// Corrects the stored fields of an order that a bug recorded wrongly.  Only
+    /// processed by builds with the admin feature, fields that are None are left as
+    /// they are.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current config owner
+    /// 1. `[writable]` The order account.  Owned by this program
+    /// 2. `[]` The program config account (see utils::get_config_address)
+    AdminFixOrder {
+        #[allow(dead_code)] // not dead code..
+        expected_amount: Option<u64>,
+        #[allow(dead_code)] // not dead code..
+        paid_amount: Option<u64>,
+        /// at most the paid amount
+        #[allow(dead_code)] // not dead code..
+        refunded_amount: Option<u64>,
+        /// an OrderStatus other than Uninitialized
+        #[allow(dead_code)] // not dead code..
+        status: Option<u8>,
+    },
+    /// Withdraw Subscription Batch
+    ///
+    /// Withdraws many subscription orders of one merchant and mint at once.  Orders
+    /// still within their subscription trial period are skipped instead of failing
+    /// the batch.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person initializing the transaction
+    /// 1. `[]` The merchant account.  Owned by this program
+    /// 2. `[writable]` The merchant token account (where we will withdraw to)
+    /// 3. `[writable]` This account receives the refunded SOL after closing order token accounts
+    /// 4. `[]` This program's derived address
+    /// 5. `[]` The token program
+    /// 6. `[]` The token mint account - the mint the orders were paid in
+    /// 7. `[writable]` The first order account.  Owned by this program
+    /// 8. `[writable]` The first order token account
+    /// 9. `[]` The subscription account the first order paid for
+    /// 10. The order, order token and subscription accounts of each other order
+    WithdrawSubscriptionBatch {
+        /// see Withdraw
+        #[allow(dead_code)] // not dead code..
+        close_order_accounts: bool,
+    },
+    /// Cancel Pending Order
+    ///
+    /// Cancels an order that was never paid, closing the order account and the order
+    /// token account, if it is empty, to reclaim their rent.  Paid orders are refunded
+    /// (see RefundOrder) instead.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The merchant owner or operator, or the order payer
+    /// 1. `[writable]` The order account.  Owned by this program
+    /// 2. `[]` The merchant account.  Owned by this program
+    /// 3. `[writable]` The order token account
+    /// 4. `[writable]` The account to receive the rent
+    /// 5. `[]` This program's derived address
+    /// 6. `[]` The token program
+    CancelPendingOrder,
+    /// Verify Order Settled
+    ///
+    /// Succeeds only if the order is paid and the merchant's settlement delay has passed.
+    /// Meant to be called (e.g. via CPI or simulation) before fulfilling an order.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[]` The order account.  Owned by this program
+    /// 1. `[]` The merchant account.  Owned by this program
+    VerifyOrderSettled,
+    /// Migrate PDA Token Account
+    ///
+    /// Moves all the tokens of a token account owned by this program's derived address
+    /// for one seed version (see utils::get_versioned_pda) to a token account owned by
+    /// the derived address for another, and closes the old token account.  Meant for
+    /// rotating the escrow authority.  Only the program owner can do this.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current config owner
+    /// 1. `[]` The program config account (see utils::get_config_address)
+    /// 2. `[writable]` The token account owned by the old derived address
+    /// 3. `[writable]` The token account owned by the new derived address, of the same mint
+    /// 4. `[]` The old derived address
+    /// 5. `[writable]` This account receives the refunded SOL after closing the old token account
+    /// 6. `[]` The token program
+    /// 7. `[]` The token mint account - the mint of both token accounts
+    MigratePdaTokenAccount {
+        /// the seed version of the derived address that owns the old token account
+        #[allow(dead_code)] // not dead code..
+        from_version: u8,
+        /// the seed version of the derived address that owns the new token account
+        #[allow(dead_code)] // not dead code..
+        to_version: u8,
+    },
+    /// Update Subscription Data
+    ///
+    /// Replaces the data of a subscription that is not cancelled.  The subscription
+    /// account keeps its size, so the new data can be at most as long as the data
+    /// it was created with and shorter data is padded with trailing spaces.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The owner of the subscription
+    /// 1. `[writable]` The subscription account.  Owned by this program
+    UpdateSubscriptionData {
+        /// the new subscription data
+        #[allow(dead_code)] // not dead code..
+        data: String,
+    },
+    /// Compute Order Size
+    ///
+    /// Logs the size (in bytes) and rent (in SOL lamports) of the order account that an
+    /// ExpressCheckout with the same order id, secret and data would create.  Meant
+    /// to be simulated by clients that create order accounts themselves.  The
+    /// pinned solana-program has no return data, so the result is only in the logs,
+    /// see utils::get_order_account_size for computing it off chain.
+    ///
+    /// No accounts expected.
+    ComputeOrderSize {
+        /// the order id
+        #[allow(dead_code)] // not dead code..
+        order_id: String,
+        /// the order secret
+        #[allow(dead_code)] // not dead code..
+        secret: String,
+        /// the order data
+        #[allow(dead_code)] // not dead code..
+        data: Option<String>,
+    },
+    /// Simulate Checkout Fees
+    ///
+    /// Logs how a checkout of the amount to the merchant would be split between the
+    /// program owner, the sponsor, the charity and the seller, for buyers without a
+    /// fee override.  Nothing is transferred.  Meant to be simulated by integrators,
+    /// see utils::quote and utils::get_fee_shares for computing it off chain.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[]` The merchant account.  Owned by this program
+    /// 1. `[]` The program config account (see utils::get_config_address)
+    SimulateCheckoutFees {
+        /// the amount (in token units) of the checkout
+        #[allow(dead_code)] // not dead code..
+        amount: u64,
+    },
+    /// Cancel a subscriber's subscription as the merchant
+    ///
+    /// The merchant (or its operator) cancels a subscription to it, e.g. for a policy
+    /// violation.  The current period ends right away and the subscription is marked
+    /// as cancelled by the merchant, so the subscriber can not renew it.  When
+    /// refunding, whatever is left of the order that paid for the current period
+    /// goes back to the payer, which only works until that order is withdrawn.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The merchant owner or operator
+    /// 1. `[writable]` The subscription account.  Owned by this program
+    /// 2. `[]` The merchant account.  Owned by this program
+    ///
+    /// When refunding:
+    ///
+    /// 3. `[writable]` The order account that paid for the current period.  Owned by this program
+    /// 4. `[writable]` The order token account - this is where the amount was paid into. Owned by this program
+    /// 5. `[writable]` The refund token account - this is where the refund will go.  Must be
+    ///    owned by the payer
+    /// 6. `[writable]` This account receives the refunded SOL after closing order token account
+    /// 7. `[]` This program's derived address
+    /// 8. `[]` The token program
+    /// 9. `[]` The token mint account - the mint the order was paid in
+    MerchantCancelSubscription {
+        /// whether to refund what is left of the current period's payment
+        #[allow(dead_code)] // not dead code..
+        refund: bool,
+    },
+    /// Reveal the secret of an order once
+    ///
+    /// Logs the order secret and then clears it, overwriting it with NUL characters, so
+    /// that one-time secrets such as redemption codes can not be revealed again.  The
+    /// pinned solana-program has no return data, so the secret is only in the logs.
+    /// Fails for orders without a secret or whose secret was already revealed.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The merchant owner or operator, or the payer of the order
+    /// 1. `[writable]` The order account.  Owned by this program
+    /// 2. `[]` The merchant account.  Owned by this program
+    RevealSecret,
+    /// Create a coupon account
+    ///
+    /// Each coupon is its own account derived from the merchant and the coupon code (see
+    /// utils::get_coupon_address).  Coupons are redeemed with checkouts (see with_coupon)
+    /// or with RedeemCoupon, at most max_redemptions times and not after they expire.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The merchant owner or operator.  Pays the coupon account rent
+    /// 1. `[]` The merchant account.  Owned by this program
+    /// 2. `[writable]` The coupon account.  Owned by this program
+    /// 3. `[]` The System program
+    /// 4. `[]` The rent sysvar
+    CreateCoupon {
+        /// the coupon code, at most 32 bytes
+        #[allow(dead_code)] // not dead code..
+        code: String,
+        /// the discount in basis points of the checkout amount
+        #[allow(dead_code)] // not dead code..
+        bps: u16,
+        /// how many times the coupon can be redeemed
+        #[allow(dead_code)] // not dead code..
+        max_redemptions: u64,
+        /// the coupon can not be redeemed after this time, zero means it never expires
+        #[allow(dead_code)] // not dead code..
+        expiry: UnixTimestamp,
+    },
+    /// Redeem a coupon outside of a checkout
+    ///
+    /// Counts one redemption of the coupon, e.g. for a coupon used at a point of sale.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The merchant owner or operator
+    /// 1. `[]` The merchant account.  Owned by this program
+    /// 2. `[writable]` The coupon account.  Owned by this program
+    RedeemCoupon,
+    /// Update the keys approved to register merchants
+    ///
+    /// Registration is open to anyone until the registry account is created, the first
+    /// time this is called.  Without keys the registry account is closed and registration
+    /// is open again.  Only the config owner can do this (see UpdateConfigOwner).
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The config owner.  Pays the registry account rent, and
+    ///    receives it back when the registry account is closed
+    /// 1. `[]` The program config account (see utils::get_config_address)
+    /// 2. `[writable]` The registry account (see utils::get_registry_address)
+    /// 3. `[]` The System program
+    /// 4. `[]` The rent sysvar
+    UpdateRegistry {
+        /// the keys approved to register merchants, at most MAX_REGISTRANTS
+        #[allow(dead_code)] // not dead code..
+        registrants: Vec<Pubkey>,
+    },
+    /// Create the ledger account of a merchant
+    ///
+    /// The ledger keeps the amount, time and order account of the last LEDGER_CAPACITY
+    /// payments of the merchant, so that accounting does not need to scan all of its
+    /// order accounts.  Checkouts append their payment to it (see with_ledger).
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The merchant owner or operator.  Pays the ledger account rent
+    /// 1. `[]` The merchant account.  Owned by this program
+    /// 2. `[writable]` The ledger account (see utils::get_ledger_address)
+    /// 3. `[]` The System program
+    /// 4. `[]` The rent sysvar
+    CreateLedger,
 }
 
-/// Creates an 'RegisterMerchant' instruction.
-pub fn register_merchant(
-    program_id: Pubkey,
-    signer: Pubkey,
-    merchant: Pubkey,
-    seed: Option<String>,
-    fee: Option<u64>,
-    data: Option<String>,
-    sponsor: Option<&Pubkey>,
-) -> Instruction {
-    let mut account_metas = vec![
-        AccountMeta::new(signer, true),
-        AccountMeta::new(merchant, false),
-        AccountMeta::new_readonly(solana_program::system_program::id(), false),
-        AccountMeta::new_readonly(sysvar::rent::id(), false),
-    ];
-
-    if let Some(sponsor) = sponsor {
-        account_metas.push(AccountMeta::new_readonly(*sponsor, false));
-    }
-
+impl PaymentProcessorInstruction {
+    /// Get the instruction tag
+    pub fn tag(&self) -> InstructionTag {
+        match self {
+            PaymentProcessorInstruction::RegisterMerchant { .. } => {
+                InstructionTag::RegisterMerchant
+            }
+            PaymentProcessorInstruction::ExpressCheckout { .. } => InstructionTag::ExpressCheckout,
+            PaymentProcessorInstruction::ChainCheckout { .. } => InstructionTag::ChainCheckout,
+            PaymentProcessorInstruction::Withdraw { .. } => InstructionTag::Withdraw,
+            PaymentProcessorInstruction::Subscribe { .. } => InstructionTag::Subscribe,
+            PaymentProcessorInstruction::RenewSubscription { .. } => {
+                InstructionTag::RenewSubscription
+            }
+            PaymentProcessorInstruction::CancelSubscription => InstructionTag::CancelSubscription,
+            PaymentProcessorInstruction::CreateMerchantTokenAccount => {
+                InstructionTag::CreateMerchantTokenAccount
+            }
+            PaymentProcessorInstruction::PauseSubscription => InstructionTag::PauseSubscription,
+            PaymentProcessorInstruction::ResumeSubscription => InstructionTag::ResumeSubscription,
+            PaymentProcessorInstruction::KeeperRenew => InstructionTag::KeeperRenew,
+            PaymentProcessorInstruction::UpdateConfigOwner { .. } => {
+                InstructionTag::UpdateConfigOwner
+            }
+            PaymentProcessorInstruction::AddPackage { .. } => InstructionTag::AddPackage,
+            PaymentProcessorInstruction::RemovePackage => InstructionTag::RemovePackage,
+            PaymentProcessorInstruction::RefundOrder { .. } => InstructionTag::RefundOrder,
+            PaymentProcessorInstruction::LockOrder => InstructionTag::LockOrder,
+            PaymentProcessorInstruction::UnlockOrder => InstructionTag::UnlockOrder,
+            PaymentProcessorInstruction::TopUpRent => InstructionTag::TopUpRent,
+            PaymentProcessorInstruction::CloseSubscription => InstructionTag::CloseSubscription,
+            PaymentProcessorInstruction::SweepPda => InstructionTag::SweepPda,
+            PaymentProcessorInstruction::SetOperator { .. } => InstructionTag::SetOperator,
+            PaymentProcessorInstruction::VerifySubscriptionAccess { .. } => {
+                InstructionTag::VerifySubscriptionAccess
+            }
+            PaymentProcessorInstruction::CheckoutAndSubscribe { .. } => {
+                InstructionTag::CheckoutAndSubscribe
+            }
+            PaymentProcessorInstruction::AdminFixOrder { .. } => InstructionTag::AdminFixOrder,
+            PaymentProcessorInstruction::WithdrawSubscriptionBatch { .. } => {
+                InstructionTag::WithdrawSubscriptionBatch
+            }
+            PaymentProcessorInstruction::CancelPendingOrder => InstructionTag::CancelPendingOrder,
+            PaymentProcessorInstruction::VerifyOrderSettled => InstructionTag::VerifyOrderSettled,
+            PaymentProcessorInstruction::MigratePdaTokenAccount { .. } => {
+                InstructionTag::MigratePdaTokenAccount
+            }
+            PaymentProcessorInstruction::UpdateSubscriptionData { .. } => {
+                InstructionTag::UpdateSubscriptionData
+            }
+            PaymentProcessorInstruction::ComputeOrderSize { .. } => {
+                InstructionTag::ComputeOrderSize
+            }
+            PaymentProcessorInstruction::SimulateCheckoutFees { .. } => {
+                InstructionTag::SimulateCheckoutFees
+            }
+            PaymentProcessorInstruction::MerchantCancelSubscription { .. } => {
+                InstructionTag::MerchantCancelSubscription
+            }
+            PaymentProcessorInstruction::RevealSecret => InstructionTag::RevealSecret,
+            PaymentProcessorInstruction::CreateCoupon { .. } => InstructionTag::CreateCoupon,
+            PaymentProcessorInstruction::RedeemCoupon => InstructionTag::RedeemCoupon,
+            PaymentProcessorInstruction::UpdateRegistry { .. } => InstructionTag::UpdateRegistry,
+            PaymentProcessorInstruction::CreateLedger => InstructionTag::CreateLedger,
+        }
+    }
+}
+
+impl BorshSerialize for PaymentProcessorInstruction {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        (self.tag() as u8).serialize(writer)?;
+        match self {
+            PaymentProcessorInstruction::RegisterMerchant {
+                seed,
+                fee,
+                data,
+                settlement_delay,
+                accepted_mints,
+                allow_third_party_refunds,
+                fee_overrides,
+                min_subscription_duration,
+                charity,
+                fee_mode,
+                amount_limits,
+                fee_free_band,
+                require_full_payment,
+            } => {
+                seed.serialize(writer)?;
+                fee.serialize(writer)?;
+                data.serialize(writer)?;
+                settlement_delay.serialize(writer)?;
+                accepted_mints.serialize(writer)?;
+                allow_third_party_refunds.serialize(writer)?;
+                fee_overrides.serialize(writer)?;
+                min_subscription_duration.serialize(writer)?;
+                charity.serialize(writer)?;
+                fee_mode.serialize(writer)?;
+                amount_limits.serialize(writer)?;
+                fee_free_band.serialize(writer)?;
+                require_full_payment.serialize(writer)
+            }
+            PaymentProcessorInstruction::ExpressCheckout {
+                amount,
+                order_id,
+                secret,
+                data,
+                client_tag,
+                usd_amount,
+                revoke_delegate,
+                merchant_signed,
+                merchant_pays_rent,
+                strict_secret,
+                coupon,
+                ledger,
+            } => {
+                amount.serialize(writer)?;
+                order_id.serialize(writer)?;
+                secret.serialize(writer)?;
+                data.serialize(writer)?;
+                client_tag.serialize(writer)?;
+                usd_amount.serialize(writer)?;
+                revoke_delegate.serialize(writer)?;
+                merchant_signed.serialize(writer)?;
+                merchant_pays_rent.serialize(writer)?;
+                strict_secret.serialize(writer)?;
+                coupon.serialize(writer)?;
+                ledger.serialize(writer)
+            }
+            PaymentProcessorInstruction::ChainCheckout {
+                amount,
+                order_items,
+                data,
+                client_tag,
+            } => {
+                amount.serialize(writer)?;
+                order_items.serialize(writer)?;
+                data.serialize(writer)?;
+                client_tag.serialize(writer)
+            }
+            PaymentProcessorInstruction::Withdraw {
+                close_order_account,
+            } => close_order_account.serialize(writer),
+            PaymentProcessorInstruction::Subscribe { name, data } => {
+                name.serialize(writer)?;
+                data.serialize(writer)
+            }
+            PaymentProcessorInstruction::RenewSubscription { quantity } => {
+                quantity.serialize(writer)
+            }
+            PaymentProcessorInstruction::UpdateConfigOwner { new_owner } => {
+                new_owner.serialize(writer)
+            }
+            PaymentProcessorInstruction::AddPackage {
+                name,
+                duration,
+                price,
+                mint,
+            } => {
+                name.serialize(writer)?;
+                duration.serialize(writer)?;
+                price.serialize(writer)?;
+                mint.serialize(writer)
+            }
+            PaymentProcessorInstruction::RefundOrder { amount } => amount.serialize(writer),
+            PaymentProcessorInstruction::SetOperator { operator } => operator.serialize(writer),
+            PaymentProcessorInstruction::VerifySubscriptionAccess { package_name } => {
+                package_name.serialize(writer)
+            }
+            PaymentProcessorInstruction::CheckoutAndSubscribe {
+                amount,
+                order_id,
+                secret,
+                name,
+                data,
+            } => {
+                amount.serialize(writer)?;
+                order_id.serialize(writer)?;
+                secret.serialize(writer)?;
+                name.serialize(writer)?;
+                data.serialize(writer)
+            }
+            PaymentProcessorInstruction::AdminFixOrder {
+                expected_amount,
+                paid_amount,
+                refunded_amount,
+                status,
+            } => {
+                expected_amount.serialize(writer)?;
+                paid_amount.serialize(writer)?;
+                refunded_amount.serialize(writer)?;
+                status.serialize(writer)
+            }
+            PaymentProcessorInstruction::WithdrawSubscriptionBatch {
+                close_order_accounts,
+            } => close_order_accounts.serialize(writer),
+            PaymentProcessorInstruction::MigratePdaTokenAccount {
+                from_version,
+                to_version,
+            } => {
+                from_version.serialize(writer)?;
+                to_version.serialize(writer)
+            }
+            PaymentProcessorInstruction::UpdateSubscriptionData { data } => data.serialize(writer),
+            PaymentProcessorInstruction::ComputeOrderSize {
+                order_id,
+                secret,
+                data,
+            } => {
+                order_id.serialize(writer)?;
+                secret.serialize(writer)?;
+                data.serialize(writer)
+            }
+            PaymentProcessorInstruction::SimulateCheckoutFees { amount } => {
+                amount.serialize(writer)
+            }
+            PaymentProcessorInstruction::MerchantCancelSubscription { refund } => {
+                refund.serialize(writer)
+            }
+            PaymentProcessorInstruction::CreateCoupon {
+                code,
+                bps,
+                max_redemptions,
+                expiry,
+            } => {
+                code.serialize(writer)?;
+                bps.serialize(writer)?;
+                max_redemptions.serialize(writer)?;
+                expiry.serialize(writer)
+            }
+            PaymentProcessorInstruction::UpdateRegistry { registrants } => {
+                registrants.serialize(writer)
+            }
+            PaymentProcessorInstruction::CancelSubscription
+            | PaymentProcessorInstruction::CreateMerchantTokenAccount
+            | PaymentProcessorInstruction::PauseSubscription
+            | PaymentProcessorInstruction::ResumeSubscription
+            | PaymentProcessorInstruction::KeeperRenew
+            | PaymentProcessorInstruction::RemovePackage
+            | PaymentProcessorInstruction::LockOrder
+            | PaymentProcessorInstruction::UnlockOrder
+            | PaymentProcessorInstruction::TopUpRent
+            | PaymentProcessorInstruction::CloseSubscription
+            | PaymentProcessorInstruction::SweepPda
+            | PaymentProcessorInstruction::CancelPendingOrder
+            | PaymentProcessorInstruction::VerifyOrderSettled
+            | PaymentProcessorInstruction::RevealSecret
+            | PaymentProcessorInstruction::RedeemCoupon
+            | PaymentProcessorInstruction::CreateLedger => Ok(()),
+        }
+    }
+}
+
+impl BorshDeserialize for PaymentProcessorInstruction {
+    fn deserialize(buf: &mut &[u8]) -> Result<Self, Error> {
+        let tag = InstructionTag::from_u8(u8::deserialize(buf)?)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Unknown instruction tag"))?;
+        Ok(match tag {
+            InstructionTag::RegisterMerchant => PaymentProcessorInstruction::RegisterMerchant {
+                seed: BorshDeserialize::deserialize(buf)?,
+                fee: BorshDeserialize::deserialize(buf)?,
+                data: BorshDeserialize::deserialize(buf)?,
+                // older clients don't send the settlement delay
+                settlement_delay: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                // nor the accepted mints
+                accepted_mints: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                allow_third_party_refunds: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                fee_overrides: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                min_subscription_duration: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                charity: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                fee_mode: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                amount_limits: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                fee_free_band: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                require_full_payment: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+            },
+            InstructionTag::ExpressCheckout => PaymentProcessorInstruction::ExpressCheckout {
+                amount: BorshDeserialize::deserialize(buf)?,
+                order_id: BorshDeserialize::deserialize(buf)?,
+                secret: BorshDeserialize::deserialize(buf)?,
+                data: BorshDeserialize::deserialize(buf)?,
+                // older clients don't send a client tag
+                client_tag: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                // nor a USD amount
+                usd_amount: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                // nor whether to revoke the buyer's delegate
+                revoke_delegate: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                // nor whether the merchant signed the order
+                merchant_signed: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                // nor whether the merchant pays the rent
+                merchant_pays_rent: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                // nor whether the secret must be printable
+                strict_secret: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                // nor whether a coupon is redeemed
+                coupon: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+                // nor whether the payment is appended to the merchant ledger
+                ledger: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+            },
+            InstructionTag::ChainCheckout => PaymentProcessorInstruction::ChainCheckout {
+                amount: BorshDeserialize::deserialize(buf)?,
+                order_items: BorshDeserialize::deserialize(buf)?,
+                data: BorshDeserialize::deserialize(buf)?,
+                // older clients don't send a client tag
+                client_tag: if buf.is_empty() {
+                    Option::None
+                } else {
+                    BorshDeserialize::deserialize(buf)?
+                },
+            },
+            InstructionTag::Withdraw => PaymentProcessorInstruction::Withdraw {
+                close_order_account: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::Subscribe => PaymentProcessorInstruction::Subscribe {
+                name: BorshDeserialize::deserialize(buf)?,
+                data: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::RenewSubscription => PaymentProcessorInstruction::RenewSubscription {
+                quantity: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::CancelSubscription => PaymentProcessorInstruction::CancelSubscription,
+            InstructionTag::CreateMerchantTokenAccount => {
+                PaymentProcessorInstruction::CreateMerchantTokenAccount
+            }
+            InstructionTag::PauseSubscription => PaymentProcessorInstruction::PauseSubscription,
+            InstructionTag::ResumeSubscription => PaymentProcessorInstruction::ResumeSubscription,
+            InstructionTag::KeeperRenew => PaymentProcessorInstruction::KeeperRenew,
+            InstructionTag::UpdateConfigOwner => PaymentProcessorInstruction::UpdateConfigOwner {
+                new_owner: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::AddPackage => PaymentProcessorInstruction::AddPackage {
+                name: BorshDeserialize::deserialize(buf)?,
+                duration: BorshDeserialize::deserialize(buf)?,
+                price: BorshDeserialize::deserialize(buf)?,
+                mint: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::RemovePackage => PaymentProcessorInstruction::RemovePackage,
+            InstructionTag::RefundOrder => PaymentProcessorInstruction::RefundOrder {
+                amount: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::LockOrder => PaymentProcessorInstruction::LockOrder,
+            InstructionTag::UnlockOrder => PaymentProcessorInstruction::UnlockOrder,
+            InstructionTag::TopUpRent => PaymentProcessorInstruction::TopUpRent,
+            InstructionTag::CloseSubscription => PaymentProcessorInstruction::CloseSubscription,
+            InstructionTag::SweepPda => PaymentProcessorInstruction::SweepPda,
+            InstructionTag::SetOperator => PaymentProcessorInstruction::SetOperator {
+                operator: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::VerifySubscriptionAccess => {
+                PaymentProcessorInstruction::VerifySubscriptionAccess {
+                    package_name: BorshDeserialize::deserialize(buf)?,
+                }
+            }
+            InstructionTag::CompactExpressCheckout => {
+                let amount = BorshDeserialize::deserialize(buf)?;
+                let order_id: [u8; COMPACT_ID_LEN] = BorshDeserialize::deserialize(buf)?;
+                let secret: [u8; COMPACT_ID_LEN] = BorshDeserialize::deserialize(buf)?;
+                PaymentProcessorInstruction::ExpressCheckout {
+                    amount,
+                    order_id: get_compact_id_string(&order_id),
+                    secret: get_compact_id_string(&secret),
+                    data: Option::None,
+                    client_tag: Option::None,
+                    usd_amount: Option::None,
+                    revoke_delegate: Option::None,
+                    merchant_signed: Option::None,
+                    merchant_pays_rent: Option::None,
+                    strict_secret: Option::None,
+                    coupon: Option::None,
+                    ledger: Option::None,
+                }
+            }
+            InstructionTag::CheckoutAndSubscribe => {
+                PaymentProcessorInstruction::CheckoutAndSubscribe {
+                    amount: BorshDeserialize::deserialize(buf)?,
+                    order_id: BorshDeserialize::deserialize(buf)?,
+                    secret: BorshDeserialize::deserialize(buf)?,
+                    name: BorshDeserialize::deserialize(buf)?,
+                    data: BorshDeserialize::deserialize(buf)?,
+                }
+            }
+            InstructionTag::AdminFixOrder => PaymentProcessorInstruction::AdminFixOrder {
+                expected_amount: BorshDeserialize::deserialize(buf)?,
+                paid_amount: BorshDeserialize::deserialize(buf)?,
+                refunded_amount: BorshDeserialize::deserialize(buf)?,
+                status: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::WithdrawSubscriptionBatch => {
+                PaymentProcessorInstruction::WithdrawSubscriptionBatch {
+                    close_order_accounts: BorshDeserialize::deserialize(buf)?,
+                }
+            }
+            InstructionTag::CancelPendingOrder => PaymentProcessorInstruction::CancelPendingOrder,
+            InstructionTag::VerifyOrderSettled => PaymentProcessorInstruction::VerifyOrderSettled,
+            InstructionTag::MigratePdaTokenAccount => {
+                PaymentProcessorInstruction::MigratePdaTokenAccount {
+                    from_version: BorshDeserialize::deserialize(buf)?,
+                    to_version: BorshDeserialize::deserialize(buf)?,
+                }
+            }
+            InstructionTag::UpdateSubscriptionData => {
+                PaymentProcessorInstruction::UpdateSubscriptionData {
+                    data: BorshDeserialize::deserialize(buf)?,
+                }
+            }
+            InstructionTag::ComputeOrderSize => PaymentProcessorInstruction::ComputeOrderSize {
+                order_id: BorshDeserialize::deserialize(buf)?,
+                secret: BorshDeserialize::deserialize(buf)?,
+                data: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::SimulateCheckoutFees => {
+                PaymentProcessorInstruction::SimulateCheckoutFees {
+                    amount: BorshDeserialize::deserialize(buf)?,
+                }
+            }
+            InstructionTag::MerchantCancelSubscription => {
+                PaymentProcessorInstruction::MerchantCancelSubscription {
+                    refund: BorshDeserialize::deserialize(buf)?,
+                }
+            }
+            InstructionTag::RevealSecret => PaymentProcessorInstruction::RevealSecret,
+            InstructionTag::CreateCoupon => PaymentProcessorInstruction::CreateCoupon {
+                code: BorshDeserialize::deserialize(buf)?,
+                bps: BorshDeserialize::deserialize(buf)?,
+                max_redemptions: BorshDeserialize::deserialize(buf)?,
+                expiry: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::RedeemCoupon => PaymentProcessorInstruction::RedeemCoupon,
+            InstructionTag::UpdateRegistry => PaymentProcessorInstruction::UpdateRegistry {
+                registrants: BorshDeserialize::deserialize(buf)?,
+            },
+            InstructionTag::CreateLedger => PaymentProcessorInstruction::CreateLedger,
+        })
+    }
+}
+
+/// The settings of a merchant registered with register_merchant, see RegisterMerchant
+///
+/// Every setting left as None keeps its default.
+#[derive(Clone, Debug, Default)]
+pub struct MerchantOptions {
+    pub fee: Option<u64>,
+    pub data: Option<String>,
+    pub sponsor: Option<Pubkey>,
+    pub fee_recipient: Option<Pubkey>,
+    pub settlement_delay: Option<i64>,
+    pub accepted_mints: Option<Vec<Pubkey>>,
+    pub allow_third_party_refunds: Option<bool>,
+    pub fee_overrides: Option<Vec<(Pubkey, u64)>>,
+    pub min_subscription_duration: Option<i64>,
+    pub charity: Option<(Pubkey, u16)>,
+    pub fee_mode: Option<FeeMode>,
+    pub amount_limits: Option<(u64, u64)>,
+    pub fee_free_band: Option<(u64, u64)>,
+    pub require_full_payment: Option<bool>,
+}
+
+/// Creates an 'RegisterMerchant' instruction.
+pub fn register_merchant(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    seed: Option<String>,
+    options: MerchantOptions,
+) -> Instruction {
+    let mut account_metas = vec![
+        AccountMeta::new(signer, true),
+        AccountMeta::new(merchant, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(get_registry_address(&program_id).0, false),
+    ];
+
+    if let Some(sponsor) = options.sponsor {
+        account_metas.push(AccountMeta::new_readonly(sponsor, false));
+    }
+    if let Some(fee_recipient) = options.fee_recipient {
+        if options.sponsor.is_none() {
+            // the program owner as sponsor is the same as no sponsor
+            account_metas.push(AccountMeta::new_readonly(
+                Pubkey::from_str(PROGRAM_OWNER).unwrap(),
+                false,
+            ));
+        }
+        account_metas.push(AccountMeta::new_readonly(fee_recipient, false));
+    }
+
     Instruction {
         program_id,
         accounts: account_metas,
-        data: PaymentProcessorInstruction::RegisterMerchant { seed, fee, data }
-            .try_to_vec()
-            .unwrap(),
+        data: PaymentProcessorInstruction::RegisterMerchant {
+            seed,
+            fee: options.fee,
+            data: options.data,
+            settlement_delay: options.settlement_delay,
+            accepted_mints: options.accepted_mints,
+            allow_third_party_refunds: options.allow_third_party_refunds,
+            fee_overrides: options.fee_overrides,
+            min_subscription_duration: options.min_subscription_duration,
+            charity: options.charity,
+            fee_mode: options.fee_mode,
+            amount_limits: options.amount_limits,
+            fee_free_band: options.fee_free_band,
+            require_full_payment: options.require_full_payment,
+        }
+        .try_to_vec()
+        .unwrap(),
     }
 }
 
+/// Creates a 'RegisterMerchant' instruction for a merchant account that was created
+/// beforehand.  The merchant account must sign the transaction.
+pub fn register_existing_merchant(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    options: MerchantOptions,
+) -> Instruction {
+    let mut instruction = register_merchant(program_id, signer, merchant, Option::None, options);
+    instruction.accounts[1].is_signer = true;
+    instruction
+}
+
+/// Adds the creation of the merchant owner's token account to a 'RegisterMerchant'
+/// instruction (see register_merchant)
+///
+/// Returns the instructions for one transaction that registers the merchant and
+/// creates the associated token account of the merchant owner (the signer) for the
+/// mint, where withdrawals can be sent (see utils::get_associated_token_address).
+/// The signer pays for both accounts.
+pub fn with_owner_token_account(instruction: Instruction, mint: Pubkey) -> Vec<Instruction> {
+    let owner = instruction.accounts[0].pubkey;
+    vec![
+        instruction,
+        Instruction {
+            program_id: Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID).unwrap(),
+            accounts: vec![
+                AccountMeta::new(owner, true),
+                AccountMeta::new(get_associated_token_address(&owner, &mint), false),
+                AccountMeta::new_readonly(owner, false),
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            data: vec![],
+        },
+    ]
+}
+
+/// the accounts of an 'ExpressCheckout' instruction in either encoding
+fn express_checkout_accounts(
+    program_id: &Pubkey,
+    signer: Pubkey,
+    order: Pubkey,
+    merchant: Pubkey,
+    seller_token: Pubkey,
+    buyer_token: Pubkey,
+    mint: Pubkey,
+    program_owner: Pubkey,
+    sponsor: Pubkey,
+    pda: Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(signer, true),
+        AccountMeta::new(order, true),
+        AccountMeta::new(merchant, false),
+        AccountMeta::new(seller_token, false),
+        AccountMeta::new(buyer_token, false),
+        AccountMeta::new(program_owner, false),
+        AccountMeta::new(sponsor, false),
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(get_config_address(program_id).0, false),
+    ]
+}
+
 /// Creates an 'ExpressCheckout' instruction.
 pub fn express_checkout(
     program_id: Pubkey,
@@ -226,37 +1558,130 @@ pub fn express_checkout(
     sponsor: Pubkey,
     pda: Pubkey,
     amount: u64,
-    order_id: String,
+    order_id: OrderId,
     secret: String,
     data: Option<String>,
 ) -> Instruction {
     Instruction {
         program_id,
-        accounts: vec![
-            AccountMeta::new(signer, true),
-            AccountMeta::new(order, true),
-            AccountMeta::new_readonly(merchant, false),
-            AccountMeta::new(seller_token, false),
-            AccountMeta::new(buyer_token, false),
-            AccountMeta::new(program_owner, false),
-            AccountMeta::new(sponsor, false),
-            AccountMeta::new_readonly(mint, false),
-            AccountMeta::new_readonly(pda, false),
-            AccountMeta::new_readonly(spl_token::id(), false),
-            AccountMeta::new_readonly(solana_program::system_program::id(), false),
-            AccountMeta::new_readonly(sysvar::rent::id(), false),
-        ],
+        accounts: express_checkout_accounts(
+            &program_id,
+            signer,
+            order,
+            merchant,
+            seller_token,
+            buyer_token,
+            mint,
+            program_owner,
+            sponsor,
+            pda,
+        ),
         data: PaymentProcessorInstruction::ExpressCheckout {
             amount,
-            order_id,
+            order_id: order_id.into(),
             secret,
             data,
+            client_tag: Option::None,
+            usd_amount: Option::None,
+            revoke_delegate: Option::None,
+            merchant_signed: Option::None,
+            merchant_pays_rent: Option::None,
+            strict_secret: Option::None,
+            coupon: Option::None,
+            ledger: Option::None,
         }
         .try_to_vec()
         .unwrap(),
     }
 }
 
+/// Creates an 'ExpressCheckout' instruction in the compact encoding
+///
+/// The order id and secret are fixed length byte arrays instead of strings, the
+/// order account stores them as lowercase hex (see utils::get_compact_id_string)
+/// and an all zero secret as no secret.  The compact encoding has no data.  The
+/// with_* functions re-encode the instruction in the Borsh encoding.
+pub fn compact_express_checkout(
+    program_id: Pubkey,
+    signer: Pubkey,
+    order: Pubkey,
+    merchant: Pubkey,
+    seller_token: Pubkey,
+    buyer_token: Pubkey,
+    mint: Pubkey,
+    program_owner: Pubkey,
+    sponsor: Pubkey,
+    pda: Pubkey,
+    amount: u64,
+    order_id: [u8; COMPACT_ID_LEN],
+    secret: [u8; COMPACT_ID_LEN],
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: express_checkout_accounts(
+            &program_id,
+            signer,
+            order,
+            merchant,
+            seller_token,
+            buyer_token,
+            mint,
+            program_owner,
+            sponsor,
+            pda,
+        ),
+        data: (
+            InstructionTag::CompactExpressCheckout as u8,
+            amount,
+            order_id,
+            secret,
+        )
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates an 'ExpressCheckout' instruction whose order account is derived from
+/// the merchant order count.  order_index must be the current order count of the
+/// merchant account.
+pub fn express_checkout_by_index(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    seller_token: Pubkey,
+    buyer_token: Pubkey,
+    mint: Pubkey,
+    program_owner: Pubkey,
+    sponsor: Pubkey,
+    pda: Pubkey,
+    order_index: u64,
+    amount: u64,
+    order_id: OrderId,
+    secret: String,
+    data: Option<String>,
+) -> Instruction {
+    let (order, _bump_seed) = get_order_pubkey_by_index(&merchant, order_index, &program_id);
+    let mut instruction = express_checkout(
+        program_id,
+        signer,
+        order,
+        merchant,
+        seller_token,
+        buyer_token,
+        mint,
+        program_owner,
+        sponsor,
+        pda,
+        amount,
+        order_id,
+        secret,
+        data,
+    );
+    // the program signs for the derived order account
+    instruction.accounts[1].is_signer = false;
+    instruction
+}
+
 /// Creates an 'ChainCheckout' instruction.
 pub fn chain_checkout(
     program_id: Pubkey,
@@ -278,7 +1703,7 @@ pub fn chain_checkout(
         accounts: vec![
             AccountMeta::new(signer, true),
             AccountMeta::new(order, true),
-            AccountMeta::new_readonly(merchant, false),
+            AccountMeta::new(merchant, false),
             AccountMeta::new(seller_token, false),
             AccountMeta::new(buyer_token, false),
             AccountMeta::new(program_owner, false),
@@ -288,40 +1713,303 @@ pub fn chain_checkout(
             AccountMeta::new_readonly(spl_token::id(), false),
             AccountMeta::new_readonly(solana_program::system_program::id(), false),
             AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(get_config_address(&program_id).0, false),
         ],
         data: PaymentProcessorInstruction::ChainCheckout {
             amount,
             order_items,
             data,
+            client_tag: Option::None,
         }
         .try_to_vec()
         .unwrap(),
     }
 }
 
-/// Creates an 'Withdraw' instruction.
-pub fn withdraw(
-    program_id: Pubkey,
-    signer: Pubkey,
-    order: Pubkey,
-    merchant: Pubkey,
-    order_payment_token: Pubkey,
-    merchant_token: Pubkey,
-    account_to_receive_sol_refund: Pubkey,
-    pda: Pubkey,
-    subscription: Option<Pubkey>,
-    close_order_account: bool,
-) -> Instruction {
-    let mut account_metas = vec![
-        AccountMeta::new(signer, true),
-        AccountMeta::new(order, false),
-        AccountMeta::new_readonly(merchant, false),
-        AccountMeta::new(order_payment_token, false),
-        AccountMeta::new(merchant_token, false),
-        AccountMeta::new(account_to_receive_sol_refund, false),
-        AccountMeta::new_readonly(pda, false),
-        AccountMeta::new_readonly(spl_token::id(), false),
-    ];
+/// Decode an ExpressCheckout instruction, let update set one of its fields and
+/// encode it back
+///
+/// Returns false, leaving the instruction unchanged, for other instructions.
+fn update_express_checkout(
+    instruction: &mut Instruction,
+    update: impl FnOnce(&mut PaymentProcessorInstruction),
+) -> bool {
+    let mut checkout = match PaymentProcessorInstruction::try_from_slice(&instruction.data) {
+        Ok(checkout @ PaymentProcessorInstruction::ExpressCheckout { .. }) => checkout,
+        _ => return false,
+    };
+    update(&mut checkout);
+    instruction.data = checkout.try_to_vec().unwrap();
+    true
+}
+
+/// Tags a checkout instruction with the client that sent it e.g. "web-sdk-1.2.0"
+///
+/// Other instructions are returned unchanged.
+pub fn with_client_tag(mut instruction: Instruction, client_tag: &str) -> Instruction {
+    let mut checkout = match PaymentProcessorInstruction::try_from_slice(&instruction.data) {
+        Ok(checkout) => checkout,
+        Err(_error) => return instruction,
+    };
+    match &mut checkout {
+        PaymentProcessorInstruction::ExpressCheckout {
+            client_tag: tag, ..
+        }
+        | PaymentProcessorInstruction::ChainCheckout {
+            client_tag: tag, ..
+        } => {
+            *tag = Some(String::from(client_tag));
+        }
+        _ => return instruction,
+    }
+    instruction.data = checkout.try_to_vec().unwrap();
+    instruction
+}
+
+/// Quotes an express checkout instruction in USD, paid at the price in the given
+/// oracle price account
+///
+/// Other instructions are returned unchanged.
+pub fn with_usd_price(mut instruction: Instruction, usd_amount: u64, price: Pubkey) -> Instruction {
+    let quoted = update_express_checkout(&mut instruction, |checkout| {
+        if let PaymentProcessorInstruction::ExpressCheckout {
+            usd_amount: field, ..
+        } = checkout
+        {
+            *field = Some(usd_amount);
+        }
+    });
+    if quoted {
+        instruction
+            .accounts
+            .push(AccountMeta::new_readonly(price, false));
+    }
+    instruction
+}
+
+/// Adds the charity token account to a checkout instruction
+///
+/// Needed when the merchant registered a charity.  Must be applied after
+/// with_usd_price since the charity token account comes after the price account.
+pub fn with_charity_token(mut instruction: Instruction, charity_token: Pubkey) -> Instruction {
+    instruction
+        .accounts
+        .push(AccountMeta::new(charity_token, false));
+    instruction
+}
+
+/// Adds the fee recipient token account to a checkout instruction
+///
+/// Needed when the merchant fee mode is FeeMode::BpsToken.  Must be applied after
+/// with_usd_price and with_charity_token since this account comes last.
+pub fn with_fee_token(mut instruction: Instruction, fee_token: Pubkey) -> Instruction {
+    instruction
+        .accounts
+        .push(AccountMeta::new(fee_token, false));
+    instruction
+}
+
+/// Adds the fee recipient token account to a checkout instruction, to be created
+///
+/// Like with_fee_token for a fee recipient that has no token account for the mint
+/// yet.  The checkout creates the associated token account of the fee recipient,
+/// paid for like the order account.
+pub fn with_new_fee_token(
+    mut instruction: Instruction,
+    fee_recipient: Pubkey,
+    mint: Pubkey,
+) -> Instruction {
+    instruction.accounts.push(AccountMeta::new(
+        get_associated_token_address(&fee_recipient, &mint),
+        false,
+    ));
+    instruction.accounts.push(AccountMeta::new_readonly(
+        Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID).unwrap(),
+        false,
+    ));
+    instruction
+}
+
+/// Make an ExpressCheckout instruction revoke the buyer token account delegate
+///
+/// The buyer (the signer) must own the buyer token account.  Other instructions
+/// are returned unchanged.
+pub fn with_revoke_delegate(mut instruction: Instruction) -> Instruction {
+    update_express_checkout(&mut instruction, |checkout| {
+        if let PaymentProcessorInstruction::ExpressCheckout {
+            revoke_delegate: field,
+            ..
+        } = checkout
+        {
+            *field = Some(true);
+        }
+    });
+    instruction
+}
+
+/// Make an ExpressCheckout instruction a merchant signed order
+///
+/// The transaction must verify the merchant's signature of the order right before
+/// this instruction (see merchant_signature).  Must be applied after with_usd_price,
+/// with_charity_token and with_fee_token (or with_new_fee_token) since the
+/// instructions sysvar comes last.
+/// Other instructions are returned unchanged.
+pub fn with_merchant_signature(mut instruction: Instruction) -> Instruction {
+    let signed = update_express_checkout(&mut instruction, |checkout| {
+        if let PaymentProcessorInstruction::ExpressCheckout {
+            merchant_signed: field,
+            ..
+        } = checkout
+        {
+            *field = Some(true);
+        }
+    });
+    if signed {
+        instruction
+            .accounts
+            .push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    }
+    instruction
+}
+
+/// Creates the Ed25519 program instruction that verifies a merchant's signature of
+/// an order (see with_merchant_signature)
+///
+/// signature is the merchant owner's signature of
+/// utils::get_order_signature_message(order_id, amount, mint).
+pub fn merchant_signature(
+    merchant_owner: Pubkey,
+    signature: [u8; 64],
+    order_id: &OrderId,
+    amount: u64,
+    mint: &Pubkey,
+) -> Instruction {
+    let message = get_order_signature_message(order_id.as_str(), amount, mint);
+    let public_key_offset = ED25519_DATA_START;
+    let signature_offset = public_key_offset + ED25519_PUBKEY_LEN;
+    let message_offset = signature_offset + ED25519_SIGNATURE_LEN;
+    let mut data = vec![ED25519_SIGNATURE_COUNT, 0];
+    for offset in &[
+        signature_offset as u16,
+        ED25519_THIS_INSTRUCTION,
+        public_key_offset as u16,
+        ED25519_THIS_INSTRUCTION,
+        message_offset as u16,
+        message.len() as u16,
+        ED25519_THIS_INSTRUCTION,
+    ] {
+        data.extend_from_slice(&offset.to_le_bytes());
+    }
+    data.extend_from_slice(&merchant_owner.to_bytes());
+    data.extend_from_slice(&signature);
+    data.extend_from_slice(&message);
+    Instruction {
+        program_id: Pubkey::from_str(ED25519_PROGRAM_ID).unwrap(),
+        accounts: vec![],
+        data,
+    }
+}
+
+/// Make the merchant pay the rent and SOL fee of an ExpressCheckout instruction
+///
+/// rent_payer must be the merchant owner or operator, the buyer then only needs to
+/// sign for the token transfer.  Must be applied last since the rent payer is the
+/// last account.  Other instructions are returned unchanged.
+pub fn with_rent_payer(mut instruction: Instruction, rent_payer: Pubkey) -> Instruction {
+    let sponsored = update_express_checkout(&mut instruction, |checkout| {
+        if let PaymentProcessorInstruction::ExpressCheckout {
+            merchant_pays_rent: field,
+            ..
+        } = checkout
+        {
+            *field = Some(true);
+        }
+    });
+    if sponsored {
+        instruction
+            .accounts
+            .push(AccountMeta::new(rent_payer, true));
+    }
+    instruction
+}
+
+/// Make an ExpressCheckout instruction reject secrets with control characters
+///
+/// Without this any secret is stored as it is.  Other instructions are returned
+/// unchanged.
+pub fn with_strict_secret(mut instruction: Instruction) -> Instruction {
+    update_express_checkout(&mut instruction, |checkout| {
+        if let PaymentProcessorInstruction::ExpressCheckout {
+            strict_secret: field,
+            ..
+        } = checkout
+        {
+            *field = Some(true);
+        }
+    });
+    instruction
+}
+
+/// Redeems a coupon of the merchant with an ExpressCheckout instruction
+///
+/// The checkout fails if the coupon expired or was redeemed as many times as it can
+/// be.  The checkout must be a merchant signed order (see with_merchant_signature)
+/// for the price after the coupon discount (see utils::get_coupon_price), so this
+/// goes after with_merchant_signature.  Other instructions are returned unchanged.
+pub fn with_coupon(mut instruction: Instruction, coupon: Pubkey) -> Instruction {
+    let redeemed = update_express_checkout(&mut instruction, |checkout| {
+        if let PaymentProcessorInstruction::ExpressCheckout { coupon: field, .. } = checkout {
+            *field = Some(true);
+        }
+    });
+    if redeemed {
+        instruction.accounts.push(AccountMeta::new(coupon, false));
+    }
+    instruction
+}
+
+/// Appends the payment of an ExpressCheckout instruction to the merchant ledger (see
+/// CreateLedger and utils::get_ledger_address)
+///
+/// The ledger account comes after the coupon account, so this goes after with_coupon.
+/// Other instructions are returned unchanged.
+pub fn with_ledger(mut instruction: Instruction, ledger: Pubkey) -> Instruction {
+    let appended = update_express_checkout(&mut instruction, |checkout| {
+        if let PaymentProcessorInstruction::ExpressCheckout { ledger: field, .. } = checkout {
+            *field = Some(true);
+        }
+    });
+    if appended {
+        instruction.accounts.push(AccountMeta::new(ledger, false));
+    }
+    instruction
+}
+
+/// Creates an 'Withdraw' instruction.
+pub fn withdraw(
+    program_id: Pubkey,
+    signer: Pubkey,
+    order: Pubkey,
+    merchant: Pubkey,
+    order_payment_token: Pubkey,
+    merchant_token: Pubkey,
+    account_to_receive_sol_refund: Pubkey,
+    pda: Pubkey,
+    mint: Pubkey,
+    subscription: Option<Pubkey>,
+    close_order_account: bool,
+) -> Instruction {
+    let mut account_metas = vec![
+        AccountMeta::new(signer, true),
+        AccountMeta::new(order, false),
+        AccountMeta::new_readonly(merchant, false),
+        AccountMeta::new(order_payment_token, false),
+        AccountMeta::new(merchant_token, false),
+        AccountMeta::new(account_to_receive_sol_refund, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(mint, false),
+    ];
 
     if let Some(subscription) = subscription {
         account_metas.push(AccountMeta::new_readonly(subscription, false));
@@ -338,6 +2026,73 @@ pub fn withdraw(
     }
 }
 
+/// creates a 'WithdrawSubscriptionBatch' instruction
+///
+/// orders are (order, order token, subscription) triples.
+pub fn withdraw_subscription_batch(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    merchant_token: Pubkey,
+    account_to_receive_sol_refund: Pubkey,
+    pda: Pubkey,
+    mint: Pubkey,
+    orders: Vec<(Pubkey, Pubkey, Pubkey)>,
+    close_order_accounts: bool,
+) -> Instruction {
+    let mut account_metas = vec![
+        AccountMeta::new(signer, true),
+        AccountMeta::new_readonly(merchant, false),
+        AccountMeta::new(merchant_token, false),
+        AccountMeta::new(account_to_receive_sol_refund, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(mint, false),
+    ];
+    for (order, order_payment_token, subscription) in orders {
+        account_metas.push(AccountMeta::new(order, false));
+        account_metas.push(AccountMeta::new(order_payment_token, false));
+        account_metas.push(AccountMeta::new_readonly(subscription, false));
+    }
+
+    Instruction {
+        program_id,
+        accounts: account_metas,
+        data: PaymentProcessorInstruction::WithdrawSubscriptionBatch {
+            close_order_accounts,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// creates a 'CancelPendingOrder' instruction
+pub fn cancel_pending_order(
+    program_id: Pubkey,
+    signer: Pubkey,
+    order: Pubkey,
+    merchant: Pubkey,
+    order_payment_token: Pubkey,
+    account_to_receive_sol_refund: Pubkey,
+    pda: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new(order, false),
+            AccountMeta::new_readonly(merchant, false),
+            AccountMeta::new(order_payment_token, false),
+            AccountMeta::new(account_to_receive_sol_refund, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: PaymentProcessorInstruction::CancelPendingOrder
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
 /// creates a 'Subscribe' instruction
 pub fn subscribe(
     program_id: Pubkey,
@@ -345,19 +2100,25 @@ pub fn subscribe(
     subscription: Pubkey,
     merchant: Pubkey,
     order: Pubkey,
+    package: Option<Pubkey>,
     name: String,
     data: Option<String>,
 ) -> Instruction {
+    let mut account_metas = vec![
+        AccountMeta::new(signer, true),
+        AccountMeta::new(subscription, false),
+        AccountMeta::new_readonly(merchant, false),
+        AccountMeta::new(order, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+
+    if let Some(package) = package {
+        account_metas.push(AccountMeta::new_readonly(package, false));
+    }
+
     Instruction {
         program_id,
-        accounts: vec![
-            AccountMeta::new(signer, true),
-            AccountMeta::new(subscription, false),
-            AccountMeta::new_readonly(merchant, false),
-            AccountMeta::new_readonly(order, false),
-            AccountMeta::new_readonly(solana_program::system_program::id(), false),
-            AccountMeta::new_readonly(sysvar::rent::id(), false),
-        ],
+        accounts: account_metas,
         data: PaymentProcessorInstruction::Subscribe { name, data }
             .try_to_vec()
             .unwrap(),
@@ -371,16 +2132,23 @@ pub fn renew_subscription(
     subscription: Pubkey,
     merchant: Pubkey,
     order: Pubkey,
+    package: Option<Pubkey>,
     quantity: i64,
 ) -> Instruction {
+    let mut account_metas = vec![
+        AccountMeta::new(signer, true),
+        AccountMeta::new(subscription, false),
+        AccountMeta::new_readonly(merchant, false),
+        AccountMeta::new(order, false),
+    ];
+
+    if let Some(package) = package {
+        account_metas.push(AccountMeta::new_readonly(package, false));
+    }
+
     Instruction {
         program_id,
-        accounts: vec![
-            AccountMeta::new(signer, true),
-            AccountMeta::new(subscription, false),
-            AccountMeta::new_readonly(merchant, false),
-            AccountMeta::new_readonly(order, false),
-        ],
+        accounts: account_metas,
         data: PaymentProcessorInstruction::RenewSubscription { quantity }
             .try_to_vec()
             .unwrap(),
@@ -398,236 +2166,7230 @@ pub fn cancel_subscription(
     refund_token: Pubkey,
     account_to_receive_sol_refund: Pubkey,
     pda: Pubkey,
+    package: Option<Pubkey>,
+) -> Instruction {
+    let mut account_metas = vec![
+        AccountMeta::new(signer, true),
+        AccountMeta::new(subscription, false),
+        AccountMeta::new_readonly(merchant, false),
+        AccountMeta::new(order, false),
+        AccountMeta::new(order_token, false),
+        AccountMeta::new(refund_token, false),
+        AccountMeta::new(account_to_receive_sol_refund, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+
+    if let Some(package) = package {
+        account_metas.push(AccountMeta::new_readonly(package, false));
+    }
+
+    Instruction {
+        program_id,
+        accounts: account_metas,
+        data: PaymentProcessorInstruction::CancelSubscription
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'CreateMerchantTokenAccount' instruction
+pub fn create_merchant_token_account(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    merchant_token: Pubkey,
+    mint: Pubkey,
+    pda: Pubkey,
 ) -> Instruction {
     Instruction {
         program_id,
         accounts: vec![
             AccountMeta::new(signer, true),
-            AccountMeta::new(subscription, false),
             AccountMeta::new_readonly(merchant, false),
-            AccountMeta::new(order, false),
-            AccountMeta::new(order_token, false),
-            AccountMeta::new(refund_token, false),
-            AccountMeta::new(account_to_receive_sol_refund, false),
+            AccountMeta::new(merchant_token, false),
+            AccountMeta::new_readonly(mint, false),
             AccountMeta::new_readonly(pda, false),
             AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
         ],
-        data: PaymentProcessorInstruction::CancelSubscription
+        data: PaymentProcessorInstruction::CreateMerchantTokenAccount
             .try_to_vec()
             .unwrap(),
     }
 }
 
-#[cfg(test)]
-mod test {
-    use {
-        super::*,
-        crate::engine::constants::{
-            DEFAULT_FEE_IN_LAMPORTS, INITIAL, MERCHANT, MIN_FEE_IN_LAMPORTS, PAID, PDA_SEED,
-            PROGRAM_OWNER, SPONSOR_FEE,
-        },
-        crate::error::PaymentProcessorError,
-        crate::instruction::PaymentProcessorInstruction,
-        crate::state::{
-            MerchantAccount, OrderAccount, OrderStatus, Serdes, SubscriptionAccount,
-            SubscriptionStatus,
-        },
-        crate::utils::{get_amounts, get_order_account_size},
-        assert_matches::*,
-        serde_json::{json, Value},
-        solana_program::{
-            hash::Hash,
-            program_pack::{IsInitialized, Pack},
-            rent::Rent,
-            system_instruction,
-        },
-        solana_program_test::*,
-        solana_sdk::{
-            instruction::InstructionError,
-            signature::{Keypair, Signer},
-            transaction::{Transaction, TransactionError},
-            transport::TransportError,
-        },
-        spl_token::{
-            instruction::{initialize_account, initialize_mint, mint_to},
-            state::{Account as TokenAccount, Mint},
-        },
-        std::str::FromStr,
-    };
+/// creates a 'PauseSubscription' instruction
+pub fn pause_subscription(program_id: Pubkey, signer: Pubkey, subscription: Pubkey) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(subscription, false),
+        ],
+        data: PaymentProcessorInstruction::PauseSubscription
+            .try_to_vec()
+            .unwrap(),
+    }
+}
 
-    type MerchantResult = (Pubkey, Pubkey, BanksClient, Keypair, Hash);
+/// creates a 'ResumeSubscription' instruction
+pub fn resume_subscription(
+    program_id: Pubkey,
+    signer: Pubkey,
+    subscription: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(subscription, false),
+        ],
+        data: PaymentProcessorInstruction::ResumeSubscription
+            .try_to_vec()
+            .unwrap(),
+    }
+}
 
-    fn create_mint_transaction(
-        payer: &Keypair,
-        mint: &Keypair,
-        mint_authority: &Keypair,
-        recent_blockhash: Hash,
-    ) -> Transaction {
-        let instructions = [
-            system_instruction::create_account(
+/// creates a 'KeeperRenew' instruction whose order account is derived from the
+/// merchant order count.  order_index must be the current order count of the
+/// merchant account.
+pub fn keeper_renew(
+    program_id: Pubkey,
+    signer: Pubkey,
+    subscription: Pubkey,
+    merchant: Pubkey,
+    seller_token: Pubkey,
+    buyer_token: Pubkey,
+    mint: Pubkey,
+    pda: Pubkey,
+    package: Option<Pubkey>,
+    order_index: u64,
+) -> Instruction {
+    let (order, _bump_seed) = get_order_pubkey_by_index(&merchant, order_index, &program_id);
+    let mut account_metas = vec![
+        AccountMeta::new(signer, true),
+        AccountMeta::new(subscription, false),
+        AccountMeta::new(merchant, false),
+        AccountMeta::new(order, false),
+        AccountMeta::new(seller_token, false),
+        AccountMeta::new(buyer_token, false),
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+
+    if let Some(package) = package {
+        account_metas.push(AccountMeta::new_readonly(package, false));
+    }
+
+    Instruction {
+        program_id,
+        accounts: account_metas,
+        data: PaymentProcessorInstruction::KeeperRenew
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates an 'UpdateConfigOwner' instruction
+pub fn update_config_owner(program_id: Pubkey, signer: Pubkey, new_owner: Pubkey) -> Instruction {
+    let (config, _bump_seed) = get_config_address(&program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(config, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: PaymentProcessorInstruction::UpdateConfigOwner { new_owner }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates an 'AddPackage' instruction
+pub fn add_package(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    name: String,
+    duration: i64,
+    price: u64,
+    mint: Pubkey,
+) -> Instruction {
+    let (package, _bump_seed) = get_package_address(&merchant, &name, &program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(merchant, false),
+            AccountMeta::new(package, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: PaymentProcessorInstruction::AddPackage {
+            name,
+            duration,
+            price,
+            mint,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// creates a 'RemovePackage' instruction
+pub fn remove_package(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    name: &str,
+) -> Instruction {
+    let (package, _bump_seed) = get_package_address(&merchant, name, &program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new_readonly(merchant, false),
+            AccountMeta::new(package, false),
+        ],
+        data: PaymentProcessorInstruction::RemovePackage
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'RefundOrder' instruction
+pub fn refund_order(
+    program_id: Pubkey,
+    signer: Pubkey,
+    order: Pubkey,
+    merchant: Pubkey,
+    order_payment_token: Pubkey,
+    refund_token: Pubkey,
+    pda: Pubkey,
+    mint: Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(order, false),
+            AccountMeta::new_readonly(merchant, false),
+            AccountMeta::new(order_payment_token, false),
+            AccountMeta::new(refund_token, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(mint, false),
+        ],
+        data: PaymentProcessorInstruction::RefundOrder { amount }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'LockOrder' instruction
+pub fn lock_order(program_id: Pubkey, signer: Pubkey, order: Pubkey) -> Instruction {
+    let (config, _bump_seed) = get_config_address(&program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(order, false),
+            AccountMeta::new_readonly(config, false),
+        ],
+        data: PaymentProcessorInstruction::LockOrder.try_to_vec().unwrap(),
+    }
+}
+
+/// creates an 'UnlockOrder' instruction
+pub fn unlock_order(program_id: Pubkey, signer: Pubkey, order: Pubkey) -> Instruction {
+    let (config, _bump_seed) = get_config_address(&program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(order, false),
+            AccountMeta::new_readonly(config, false),
+        ],
+        data: PaymentProcessorInstruction::UnlockOrder
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'TopUpRent' instruction
+pub fn top_up_rent(program_id: Pubkey, signer: Pubkey, account: Pubkey) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(account, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: PaymentProcessorInstruction::TopUpRent.try_to_vec().unwrap(),
+    }
+}
+
+/// creates a 'CloseSubscription' instruction
+pub fn close_subscription(
+    program_id: Pubkey,
+    signer: Pubkey,
+    subscription: Pubkey,
+    destination: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new(subscription, false),
+            AccountMeta::new(destination, false),
+        ],
+        data: PaymentProcessorInstruction::CloseSubscription
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'SweepPda' instruction
+pub fn sweep_pda(program_id: Pubkey, signer: Pubkey, destination: Pubkey) -> Instruction {
+    let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+    let (config, _bump_seed) = get_config_address(&program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new(pda, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: PaymentProcessorInstruction::SweepPda.try_to_vec().unwrap(),
+    }
+}
+
+/// creates a 'SetOperator' instruction
+pub fn set_operator(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    operator: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new(merchant, false),
+        ],
+        data: PaymentProcessorInstruction::SetOperator { operator }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'VerifySubscriptionAccess' instruction
+pub fn verify_subscription_access(
+    program_id: Pubkey,
+    signer: Pubkey,
+    subscription: Pubkey,
+    merchant: Pubkey,
+    package_name: String,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new_readonly(subscription, false),
+            AccountMeta::new_readonly(merchant, false),
+        ],
+        data: PaymentProcessorInstruction::VerifySubscriptionAccess { package_name }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'VerifyOrderSettled' instruction
+pub fn verify_order_settled(program_id: Pubkey, order: Pubkey, merchant: Pubkey) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(order, false),
+            AccountMeta::new_readonly(merchant, false),
+        ],
+        data: PaymentProcessorInstruction::VerifyOrderSettled
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'MigratePdaTokenAccount' instruction
+pub fn migrate_pda_token_account(
+    program_id: Pubkey,
+    signer: Pubkey,
+    source_token: Pubkey,
+    destination_token: Pubkey,
+    account_to_receive_sol_refund: Pubkey,
+    mint: Pubkey,
+    from_version: u8,
+    to_version: u8,
+) -> Instruction {
+    let (config, _bump_seed) = get_config_address(&program_id);
+    let (pda, _bump_seed) = get_versioned_pda(&program_id, from_version);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(source_token, false),
+            AccountMeta::new(destination_token, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(account_to_receive_sol_refund, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(mint, false),
+        ],
+        data: PaymentProcessorInstruction::MigratePdaTokenAccount {
+            from_version,
+            to_version,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// creates an 'UpdateSubscriptionData' instruction
+pub fn update_subscription_data(
+    program_id: Pubkey,
+    signer: Pubkey,
+    subscription: Pubkey,
+    data: String,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(subscription, false),
+        ],
+        data: PaymentProcessorInstruction::UpdateSubscriptionData { data }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'ComputeOrderSize' instruction
+pub fn compute_order_size(
+    program_id: Pubkey,
+    order_id: String,
+    secret: String,
+    data: Option<String>,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![],
+        data: PaymentProcessorInstruction::ComputeOrderSize {
+            order_id,
+            secret,
+            data,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// creates a 'SimulateCheckoutFees' instruction
+pub fn simulate_checkout_fees(program_id: Pubkey, merchant: Pubkey, amount: u64) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(merchant, false),
+            AccountMeta::new_readonly(get_config_address(&program_id).0, false),
+        ],
+        data: PaymentProcessorInstruction::SimulateCheckoutFees { amount }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'MerchantCancelSubscription' instruction
+///
+/// refund holds the order, order token, refund token, SOL refund and derived
+/// address accounts when what is left of the current period's payment is refunded
+pub fn merchant_cancel_subscription(
+    program_id: Pubkey,
+    signer: Pubkey,
+    subscription: Pubkey,
+    merchant: Pubkey,
+    refund: Option<(Pubkey, Pubkey, Pubkey, Pubkey, Pubkey, Pubkey)>,
+) -> Instruction {
+    let mut account_metas = vec![
+        AccountMeta::new_readonly(signer, true),
+        AccountMeta::new(subscription, false),
+        AccountMeta::new_readonly(merchant, false),
+    ];
+
+    if let Some((order, order_token, refund_token, account_to_receive_sol_refund, pda, mint)) =
+        refund
+    {
+        account_metas.push(AccountMeta::new(order, false));
+        account_metas.push(AccountMeta::new(order_token, false));
+        account_metas.push(AccountMeta::new(refund_token, false));
+        account_metas.push(AccountMeta::new(account_to_receive_sol_refund, false));
+        account_metas.push(AccountMeta::new_readonly(pda, false));
+        account_metas.push(AccountMeta::new_readonly(spl_token::id(), false));
+        account_metas.push(AccountMeta::new_readonly(mint, false));
+    }
+
+    Instruction {
+        program_id,
+        accounts: account_metas,
+        data: PaymentProcessorInstruction::MerchantCancelSubscription {
+            refund: refund.is_some(),
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// creates a 'RevealSecret' instruction
+pub fn reveal_secret(
+    program_id: Pubkey,
+    signer: Pubkey,
+    order: Pubkey,
+    merchant: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new(order, false),
+            AccountMeta::new_readonly(merchant, false),
+        ],
+        data: PaymentProcessorInstruction::RevealSecret
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'CreateCoupon' instruction
+pub fn create_coupon(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    code: String,
+    bps: u16,
+    max_redemptions: u64,
+    expiry: UnixTimestamp,
+) -> Instruction {
+    let (coupon, _bump_seed) = get_coupon_address(&merchant, &code, &program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new_readonly(merchant, false),
+            AccountMeta::new(coupon, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: PaymentProcessorInstruction::CreateCoupon {
+            code,
+            bps,
+            max_redemptions,
+            expiry,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// creates a 'RedeemCoupon' instruction
+pub fn redeem_coupon(
+    program_id: Pubkey,
+    signer: Pubkey,
+    merchant: Pubkey,
+    code: &str,
+) -> Instruction {
+    let (coupon, _bump_seed) = get_coupon_address(&merchant, code, &program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new_readonly(merchant, false),
+            AccountMeta::new(coupon, false),
+        ],
+        data: PaymentProcessorInstruction::RedeemCoupon
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates an 'UpdateRegistry' instruction
+pub fn update_registry(
+    program_id: Pubkey,
+    signer: Pubkey,
+    registrants: Vec<Pubkey>,
+) -> Instruction {
+    let (config, _bump_seed) = get_config_address(&program_id);
+    let (registry, _bump_seed) = get_registry_address(&program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(registry, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: PaymentProcessorInstruction::UpdateRegistry { registrants }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'CreateLedger' instruction
+pub fn create_ledger(program_id: Pubkey, signer: Pubkey, merchant: Pubkey) -> Instruction {
+    let (ledger, _bump_seed) = get_ledger_address(&merchant, &program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new_readonly(merchant, false),
+            AccountMeta::new(ledger, false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: PaymentProcessorInstruction::CreateLedger
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// creates a 'CheckoutAndSubscribe' instruction
+///
+/// The package account comes last, so any optional checkout accounts (see
+/// with_charity_token) must be inserted before it.
+pub fn checkout_and_subscribe(
+    program_id: Pubkey,
+    signer: Pubkey,
+    order: Pubkey,
+    merchant: Pubkey,
+    seller_token: Pubkey,
+    buyer_token: Pubkey,
+    mint: Pubkey,
+    program_owner: Pubkey,
+    sponsor: Pubkey,
+    pda: Pubkey,
+    subscription: Pubkey,
+    package: Option<Pubkey>,
+    amount: u64,
+    order_id: OrderId,
+    secret: String,
+    name: String,
+    data: Option<String>,
+) -> Instruction {
+    let mut instruction = express_checkout(
+        program_id,
+        signer,
+        order,
+        merchant,
+        seller_token,
+        buyer_token,
+        mint,
+        program_owner,
+        sponsor,
+        pda,
+        amount,
+        order_id.clone(),
+        secret.clone(),
+        Option::None,
+    );
+    instruction
+        .accounts
+        .push(AccountMeta::new(subscription, false));
+    if let Some(package) = package {
+        instruction
+            .accounts
+            .push(AccountMeta::new_readonly(package, false));
+    }
+    instruction.data = PaymentProcessorInstruction::CheckoutAndSubscribe {
+        amount,
+        order_id: order_id.into(),
+        secret,
+        name,
+        data,
+    }
+    .try_to_vec()
+    .unwrap();
+    instruction
+}
+
+/// creates an 'AdminFixOrder' instruction
+pub fn admin_fix_order(
+    program_id: Pubkey,
+    signer: Pubkey,
+    order: Pubkey,
+    expected_amount: Option<u64>,
+    paid_amount: Option<u64>,
+    refunded_amount: Option<u64>,
+    status: Option<u8>,
+) -> Instruction {
+    let (config, _bump_seed) = get_config_address(&program_id);
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(order, false),
+            AccountMeta::new_readonly(config, false),
+        ],
+        data: PaymentProcessorInstruction::AdminFixOrder {
+            expected_amount,
+            paid_amount,
+            refunded_amount,
+            status,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::client::decode_ledger,
+        crate::engine::constants::{
+            DEFAULT_FEE_IN_LAMPORTS, INITIAL, KEEPER_REWARD_IN_LAMPORTS, MAX_CLIENT_TAG_LEN,
+            MAX_INSTRUCTION_DATA_SIZE, MAX_ITEM_ID_LEN, MAX_ORDER_ITEMS, MERCHANT,
+            MIN_FEE_IN_LAMPORTS, PAID, PDA_SEED, PROGRAM_OWNER, PYTH_PROGRAM_ID, SPONSOR_FEE,
+        },
+        crate::engine::oracle::{
+            ACCOUNT_TYPE_OFFSET, EXPO_OFFSET, MAGIC_OFFSET, PRICE_OFFSET, PUB_SLOT_OFFSET,
+            PYTH_MAGIC, PYTH_PRICE_ACCOUNT_TYPE, PYTH_PRICE_LEN, PYTH_STATUS_TRADING,
+            STATUS_OFFSET,
+        },
+        crate::error::PaymentProcessorError,
+        crate::instruction::PaymentProcessorInstruction,
+        crate::state::{
+            ConfigAccount, CouponAccount, Discriminator, LedgerAccount, MerchantAccount,
+            OrderAccount, OrderStatus, PackageAccount, RegistryAccount, Serdes,
+            SubscriptionAccount, SubscriptionStatus,
+        },
+        crate::utils::{
+            get_amounts, get_associated_token_address, get_coupon_price, get_fee_shares,
+            get_merchant_account_size, get_merchant_token_address, get_order_account_size,
+            get_order_secret, get_subscription_account_pubkey, quote, quote_cart,
+        },
+        assert_matches::*,
+        serde_json::{json, Value},
+        solana_program::{
+            account_info::AccountInfo,
+            entrypoint::ProgramResult,
+            hash::Hash,
+            program_error::ProgramError,
+            program_option::COption,
+            program_pack::{IsInitialized, Pack},
+            rent::Rent,
+            system_instruction,
+        },
+        solana_program_test::*,
+        solana_sdk::{
+            instruction::InstructionError,
+            signature::{Keypair, Signature, Signer},
+            transaction::{Transaction, TransactionError},
+            transport::TransportError,
+        },
+        spl_token::{
+            instruction::{
+                approve, initialize_account, initialize_mint, mint_to, set_authority, AuthorityType,
+            },
+            state::{Account as TokenAccount, AccountState, Mint},
+        },
+        std::str::FromStr,
+    };
+
+    #[cfg(feature = "json")]
+    use crate::engine::json::{parse_packages, Package, PackagesBuilder};
+
+    type MerchantResult = (Pubkey, Pubkey, BanksClient, Keypair, Hash);
+
+    fn create_mint_transaction(
+        payer: &Keypair,
+        mint: &Keypair,
+        mint_authority: &Keypair,
+        recent_blockhash: Hash,
+    ) -> Transaction {
+        create_mint_transaction_with_decimals(payer, mint, mint_authority, recent_blockhash, 0)
+    }
+
+    fn create_mint_transaction_with_decimals(
+        payer: &Keypair,
+        mint: &Keypair,
+        mint_authority: &Keypair,
+        recent_blockhash: Hash,
+        decimals: u8,
+    ) -> Transaction {
+        let instructions = [
+            system_instruction::create_account(
+                &payer.pubkey(),
+                &mint.pubkey(),
+                Rent::default().minimum_balance(Mint::LEN),
+                Mint::LEN as u64,
+                &spl_token::id(),
+            ),
+            initialize_mint(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &mint_authority.pubkey(),
+                None,
+                decimals,
+            )
+            .unwrap(),
+        ];
+        let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+        transaction.partial_sign(&[payer, mint], recent_blockhash);
+        transaction
+    }
+
+    fn create_token_account_transaction(
+        payer: &Keypair,
+        mint: &Keypair,
+        recent_blockhash: Hash,
+        token_account: &Keypair,
+        token_account_owner: &Pubkey,
+        amount: u64,
+    ) -> Transaction {
+        let instructions = [
+            system_instruction::create_account(
+                &payer.pubkey(),
+                &token_account.pubkey(),
+                Rent::default().minimum_balance(TokenAccount::LEN),
+                TokenAccount::LEN as u64,
+                &spl_token::id(),
+            ),
+            initialize_account(
+                &spl_token::id(),
+                &token_account.pubkey(),
+                &mint.pubkey(),
+                token_account_owner,
+            )
+            .unwrap(),
+            mint_to(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &token_account.pubkey(),
+                token_account_owner,
+                &[&payer.pubkey()],
+                amount,
+            )
+            .unwrap(),
+        ];
+        let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+        transaction.partial_sign(&[payer, token_account], recent_blockhash);
+        transaction
+    }
+
+    async fn create_merchant_account(
+        seed: Option<String>,
+        fee: Option<u64>,
+        sponsor: Option<&Pubkey>,
+        data: Option<String>,
+    ) -> MerchantResult {
+        create_merchant_account_with_fee_recipient(seed, fee, sponsor, data, Option::None).await
+    }
+
+    async fn create_merchant_account_with_fee_recipient(
+        seed: Option<String>,
+        fee: Option<u64>,
+        sponsor: Option<&Pubkey>,
+        data: Option<String>,
+        fee_recipient: Option<&Pubkey>,
+    ) -> MerchantResult {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+
+        register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                seed,
+                fee,
+                sponsor: sponsor.copied(),
+                data,
+                fee_recipient: fee_recipient.copied(),
+                ..TestMerchant::default()
+            },
+        )
+        .await
+    }
+
+    /// the merchant registered by register_test_merchant, every setting left as
+    /// None keeps its default and the seed defaults to MERCHANT
+    #[derive(Default)]
+    struct TestMerchant {
+        seed: Option<String>,
+        fee: Option<u64>,
+        sponsor: Option<Pubkey>,
+        data: Option<String>,
+        fee_recipient: Option<Pubkey>,
+        settlement_delay: Option<i64>,
+        accepted_mints: Option<Vec<Pubkey>>,
+        allow_third_party_refunds: Option<bool>,
+        fee_overrides: Option<Vec<(Pubkey, u64)>>,
+        min_subscription_duration: Option<i64>,
+        charity: Option<(Pubkey, u16)>,
+        fee_mode: Option<FeeMode>,
+        amount_limits: Option<(u64, u64)>,
+        fee_free_band: Option<(u64, u64)>,
+        require_full_payment: Option<bool>,
+    }
+
+    /// register a merchant using an already started program test
+    ///
+    /// the merchant field of the program test tuple is ignored
+    async fn register_test_merchant(
+        program_test: MerchantResult,
+        test_merchant: TestMerchant,
+    ) -> MerchantResult {
+        let TestMerchant {
+            seed,
+            fee,
+            sponsor,
+            data,
+            fee_recipient,
+            settlement_delay,
+            accepted_mints,
+            allow_third_party_refunds,
+            fee_overrides,
+            min_subscription_duration,
+            charity,
+            fee_mode,
+            amount_limits,
+            fee_free_band,
+            require_full_payment,
+        } = test_merchant;
+        let (program_id, _merchant, mut banks_client, payer, recent_blockhash) = program_test;
+
+        let real_seed = match &seed {
+            None => MERCHANT,
+            Some(value) => &value,
+        };
+
+        // first we create a public key for the merchant account
+        let merchant_acc_pubkey =
+            Pubkey::create_with_seed(&payer.pubkey(), real_seed, &program_id).unwrap();
+
+        // then call register merchant ix
+        let mut transaction = Transaction::new_with_payer(
+            &[register_merchant(
+                program_id,
+                payer.pubkey(),
+                merchant_acc_pubkey,
+                Some(real_seed.to_string()),
+                MerchantOptions {
+                    fee,
+                    data,
+                    sponsor,
+                    fee_recipient,
+                    settlement_delay,
+                    accepted_mints,
+                    allow_third_party_refunds,
+                    fee_overrides,
+                    min_subscription_duration,
+                    charity,
+                    fee_mode,
+                    amount_limits,
+                    fee_free_band,
+                    require_full_payment,
+                },
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+        return (
+            program_id,
+            merchant_acc_pubkey,
+            banks_client,
+            payer,
+            recent_blockhash,
+        );
+    }
+
+    async fn prepare_order(
+        program_id: &Pubkey,
+        merchant: &Pubkey,
+        mint: &Pubkey,
+        banks_client: &mut BanksClient,
+    ) -> (Keypair, Pubkey, Pubkey, MerchantAccount) {
+        let order_acc_keypair = Keypair::new();
+
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+
+        let (seller_token, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order_acc_keypair.pubkey().to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint.to_bytes(),
+            ],
+            program_id,
+        );
+
+        let merchant_data = get_account_data::<MerchantAccount>(banks_client, *merchant).await;
+
+        (order_acc_keypair, seller_token, pda, merchant_data)
+    }
+
+    async fn create_token_account(
+        amount: u64,
+        mint_keypair: &Keypair,
+        merchant_result: &mut MerchantResult,
+    ) -> Keypair {
+        // next create token account for test
+        let buyer_token_keypair = Keypair::new();
+
+        // create and initialize mint
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_mint_transaction(
+                    &merchant_result.3,
+                    &mint_keypair,
+                    &merchant_result.3,
+                    merchant_result.4
+                ))
+                .await,
+            Ok(())
+        );
+        // create and initialize buyer token account
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &merchant_result.3,
+                    &mint_keypair,
+                    merchant_result.4,
+                    &buyer_token_keypair,
+                    &merchant_result.3.pubkey(),
+                    amount + 2000000,
+                ))
+                .await,
+            Ok(())
+        );
+
+        buyer_token_keypair
+    }
+
+    async fn create_order_express_checkout(
+        amount: u64,
+        order_id: &String,
+        secret: &String,
+        data: Option<String>,
+        merchant_result: &mut MerchantResult,
+        mint_keypair: &Keypair,
+    ) -> (Pubkey, Pubkey) {
+        let buyer_token_keypair = create_token_account(amount, mint_keypair, merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+
+        // call express checkout ix
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new(order_id).unwrap(),
+                (&secret).to_string(),
+                data,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_matches!(
+            &mut merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+
+        (order_acc_keypair.pubkey(), seller_token)
+    }
+
+    async fn create_chain_checkout_transaction(
+        amount: u64,
+        order_items: &OrderItems,
+        data: Option<String>,
+        merchant_result: &mut MerchantResult,
+        mint_keypair: &Keypair,
+    ) -> Result<(Pubkey, Pubkey), TransportError> {
+        let buyer_token_keypair = create_token_account(amount, mint_keypair, merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let order_items = order_items.clone();
+
+        // call chain checkout ix
+        let mut transaction = Transaction::new_with_payer(
+            &[chain_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                order_items,
+                data,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        let _result = merchant_result.2.process_transaction(transaction).await?;
+        Ok((order_acc_keypair.pubkey(), seller_token))
+    }
+
+    async fn create_order_chain_checkout(
+        amount: u64,
+        order_items: &OrderItems,
+        data: Option<String>,
+        merchant_result: &mut MerchantResult,
+        mint_keypair: &Keypair,
+    ) -> (Pubkey, Pubkey) {
+        let transaction = create_chain_checkout_transaction(
+            amount,
+            order_items,
+            data,
+            merchant_result,
+            mint_keypair,
+        )
+        .await;
+
+        assert!(transaction.is_ok());
+        transaction.unwrap()
+    }
+
+    async fn run_merchant_tests(result: MerchantResult) -> MerchantAccount {
+        let program_id = result.0;
+        let merchant = result.1;
+        let mut banks_client = result.2;
+        let payer = result.3;
+        // test contents of merchant account
+        let merchant_account = banks_client.get_account(merchant).await;
+        let merchant_account = match merchant_account {
+            Ok(data) => match data {
+                None => panic!("Oo"),
+                Some(value) => value,
+            },
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        assert_eq!(merchant_account.owner, program_id);
+        let merchant_data = MerchantAccount::unpack(&merchant_account.data);
+        let merchant_data = match merchant_data {
+            Ok(data) => data,
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        assert_eq!(true, merchant_data.is_initialized());
+        assert_eq!(payer.pubkey(), Pubkey::new_from_array(merchant_data.owner));
+
+        merchant_data
+    }
+
+    #[tokio::test]
+    async fn test_instruction_tags() {
+        let instructions = vec![
+            (
+                0,
+                PaymentProcessorInstruction::RegisterMerchant {
+                    seed: Some(String::from("mosh")),
+                    fee: Some(50000),
+                    data: Option::None,
+                    settlement_delay: Some(86400),
+                    accepted_mints: Some(vec![Pubkey::new_unique()]),
+                    allow_third_party_refunds: Some(true),
+                    fee_overrides: Some(vec![(Pubkey::new_unique(), 1000)]),
+                    min_subscription_duration: Some(3600),
+                    charity: Some((Pubkey::new_unique(), 250)),
+                    fee_mode: Some(FeeMode::BpsToken),
+                    amount_limits: Some((100, 1000000)),
+                    fee_free_band: Some((1000, 0)),
+                    require_full_payment: Some(true),
+                },
+            ),
+            (
+                1,
+                PaymentProcessorInstruction::ExpressCheckout {
+                    amount: 1337,
+                    order_id: String::from("123"),
+                    secret: String::from("hunter2"),
+                    data: Some(String::from("{}")),
+                    client_tag: Some(String::from("web-sdk-1.2.0")),
+                    usd_amount: Some(5000000),
+                    revoke_delegate: Some(true),
+                    merchant_signed: Some(true),
+                    merchant_pays_rent: Some(true),
+                    strict_secret: Some(true),
+                    coupon: Some(true),
+                    ledger: Some(true),
+                },
+            ),
+            (
+                2,
+                PaymentProcessorInstruction::ChainCheckout {
+                    amount: 1337,
+                    order_items: BTreeMap::new(),
+                    data: Option::None,
+                    client_tag: Option::None,
+                },
+            ),
+            (
+                3,
+                PaymentProcessorInstruction::Withdraw {
+                    close_order_account: true,
+                },
+            ),
+            (
+                4,
+                PaymentProcessorInstruction::Subscribe {
+                    name: String::from("basic"),
+                    data: Option::None,
+                },
+            ),
+            (
+                5,
+                PaymentProcessorInstruction::RenewSubscription { quantity: 2 },
+            ),
+            (6, PaymentProcessorInstruction::CancelSubscription),
+            (7, PaymentProcessorInstruction::CreateMerchantTokenAccount),
+            (8, PaymentProcessorInstruction::PauseSubscription),
+            (9, PaymentProcessorInstruction::ResumeSubscription),
+            (10, PaymentProcessorInstruction::KeeperRenew),
+            (
+                11,
+                PaymentProcessorInstruction::UpdateConfigOwner {
+                    new_owner: Pubkey::new_unique(),
+                },
+            ),
+            (
+                12,
+                PaymentProcessorInstruction::AddPackage {
+                    name: String::from("basic"),
+                    duration: 604800,
+                    price: 1000,
+                    mint: Pubkey::new_unique(),
+                },
+            ),
+            (13, PaymentProcessorInstruction::RemovePackage),
+            (
+                14,
+                PaymentProcessorInstruction::RefundOrder { amount: 1000 },
+            ),
+            (15, PaymentProcessorInstruction::LockOrder),
+            (16, PaymentProcessorInstruction::UnlockOrder),
+            (17, PaymentProcessorInstruction::TopUpRent),
+            (18, PaymentProcessorInstruction::CloseSubscription),
+            (19, PaymentProcessorInstruction::SweepPda),
+            (
+                20,
+                PaymentProcessorInstruction::SetOperator {
+                    operator: Pubkey::new_unique(),
+                },
+            ),
+            (
+                21,
+                PaymentProcessorInstruction::VerifySubscriptionAccess {
+                    package_name: String::from("basic"),
+                },
+            ),
+            (
+                22,
+                PaymentProcessorInstruction::CheckoutAndSubscribe {
+                    amount: 1000000,
+                    order_id: String::from("123"),
+                    secret: String::from(""),
+                    name: String::from("basic"),
+                    data: Some(String::from("{}")),
+                },
+            ),
+            (
+                24,
+                PaymentProcessorInstruction::AdminFixOrder {
+                    expected_amount: Some(1000000),
+                    paid_amount: Option::None,
+                    refunded_amount: Some(0),
+                    status: Some(OrderStatus::Paid as u8),
+                },
+            ),
+            (
+                25,
+                PaymentProcessorInstruction::WithdrawSubscriptionBatch {
+                    close_order_accounts: false,
+                },
+            ),
+            (26, PaymentProcessorInstruction::CancelPendingOrder),
+            (27, PaymentProcessorInstruction::VerifyOrderSettled),
+            (
+                28,
+                PaymentProcessorInstruction::MigratePdaTokenAccount {
+                    from_version: 0,
+                    to_version: 1,
+                },
+            ),
+            (
+                29,
+                PaymentProcessorInstruction::UpdateSubscriptionData {
+                    data: String::from(r#"{"size":"L"}"#),
+                },
+            ),
+            (
+                30,
+                PaymentProcessorInstruction::ComputeOrderSize {
+                    order_id: String::from("1337"),
+                    secret: String::from("hunter2"),
+                    data: Option::None,
+                },
+            ),
+            (
+                31,
+                PaymentProcessorInstruction::SimulateCheckoutFees { amount: 1000 },
+            ),
+            (
+                32,
+                PaymentProcessorInstruction::MerchantCancelSubscription { refund: true },
+            ),
+            (33, PaymentProcessorInstruction::RevealSecret),
+            (
+                34,
+                PaymentProcessorInstruction::CreateCoupon {
+                    code: String::from("SAVE10"),
+                    bps: 1000,
+                    max_redemptions: 100,
+                    expiry: 1700000000,
+                },
+            ),
+            (35, PaymentProcessorInstruction::RedeemCoupon),
+            (
+                36,
+                PaymentProcessorInstruction::UpdateRegistry {
+                    registrants: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                },
+            ),
+            (37, PaymentProcessorInstruction::CreateLedger),
+        ];
+        for (tag, instruction) in instructions {
+            let data = instruction.try_to_vec().unwrap();
+            assert_eq!(tag, data[0]);
+            assert_eq!(tag, instruction.tag() as u8);
+            // and back again
+            assert_eq!(
+                instruction,
+                PaymentProcessorInstruction::try_from_slice(&data).unwrap()
+            );
+        }
+        // unknown tags are rejected
+        assert!(PaymentProcessorInstruction::try_from_slice(&[255]).is_err());
+        // a RegisterMerchant instruction without a settlement delay is still accepted
+        assert_eq!(
+            PaymentProcessorInstruction::RegisterMerchant {
+                seed: Option::None,
+                fee: Option::None,
+                data: Option::None,
+                settlement_delay: Option::None,
+                accepted_mints: Option::None,
+                allow_third_party_refunds: Option::None,
+                fee_overrides: Option::None,
+                min_subscription_duration: Option::None,
+                charity: Option::None,
+                fee_mode: Option::None,
+                amount_limits: Option::None,
+                fee_free_band: Option::None,
+                require_full_payment: Option::None,
+            },
+            PaymentProcessorInstruction::try_from_slice(&[0, 0, 0, 0]).unwrap()
+        );
+        // so is a checkout instruction without a client tag
+        assert_eq!(
+            PaymentProcessorInstruction::ExpressCheckout {
+                amount: 0,
+                order_id: String::from(""),
+                secret: String::from(""),
+                data: Option::None,
+                client_tag: Option::None,
+                usd_amount: Option::None,
+                revoke_delegate: Option::None,
+                merchant_signed: Option::None,
+                merchant_pays_rent: Option::None,
+                strict_secret: Option::None,
+                coupon: Option::None,
+                ledger: Option::None,
+            },
+            PaymentProcessorInstruction::try_from_slice(&[
+                1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+            ])
+            .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_instruction_data() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        // a RegisterMerchant instruction with a huge data string
+        let instruction = PaymentProcessorInstruction::RegisterMerchant {
+            seed: Option::None,
+            fee: Option::None,
+            data: Some("x".repeat(MAX_INSTRUCTION_DATA_SIZE)),
+            settlement_delay: Option::None,
+            accepted_mints: Option::None,
+            allow_third_party_refunds: Option::None,
+            fee_overrides: Option::None,
+            min_subscription_duration: Option::None,
+            charity: Option::None,
+            fee_mode: Option::None,
+            amount_limits: Option::None,
+            fee_free_band: Option::None,
+            require_full_payment: Option::None,
+        };
+        let data = instruction.try_to_vec().unwrap();
+        assert!(data.len() > MAX_INSTRUCTION_DATA_SIZE);
+        assert_eq!(
+            Err(ProgramError::InvalidInstructionData),
+            PaymentProcessorInstruction::process(&program_id, &[], &data)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_merchant() {
+        let result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let merchant_data = run_merchant_tests(result).await;
+        assert_eq!(DEFAULT_FEE_IN_LAMPORTS, merchant_data.fee);
+        assert_eq!(String::from("{}"), merchant_data.data);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Problem")]
+    /// assert that account data is not unpacked as the wrong account type
+    async fn test_get_account_data_wrong_type() {
+        let mut result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        get_account_data::<ConfigAccount>(&mut result.2, result.1).await;
+    }
+
+    #[tokio::test]
+    async fn test_register_merchant_with_seed() {
+        let result = create_merchant_account(
+            Some(String::from("mosh")),
+            Option::None,
+            Option::None,
+            Option::None,
+        )
+        .await;
+        let merchant = result.1;
+        let payer = result.3;
+        let program_id = result.0;
+        assert_eq!(
+            merchant,
+            Pubkey::create_with_seed(&payer.pubkey(), "mosh", &program_id).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    /// assert that the minimum fee is used when custom fee too low
+    async fn test_register_merchant_fee_default() {
+        let result =
+            create_merchant_account(Option::None, Some(10), Option::None, Option::None).await;
+        let merchant_data = run_merchant_tests(result).await;
+        assert_eq!(MIN_FEE_IN_LAMPORTS, merchant_data.fee);
+    }
+
+    #[tokio::test]
+    async fn test_register_merchant_with_all_stuff() {
+        let seed = String::from("mosh");
+        let sponsor_pk = Pubkey::new_unique();
+        let data = String::from(
+            r#"{"code":200,"success":true,"payload":{"features":["awesome","easyAPI","lowLearningCurve"]}}"#,
+        );
+        let datas = data.clone();
+        let result =
+            create_merchant_account(Some(seed), Some(90000), Some(&sponsor_pk), Some(data)).await;
+        let merchant_data = run_merchant_tests(result).await;
+        assert_eq!(datas, merchant_data.data);
+        assert_eq!(90000, merchant_data.fee);
+        assert_eq!(sponsor_pk, Pubkey::new_from_array(merchant_data.sponsor));
+        // just for sanity verify that you can get some of the JSON values
+        let json_value: Value = serde_json::from_str(&merchant_data.data).unwrap();
+        assert_eq!(200, json_value["code"]);
+        assert_eq!(true, json_value["success"]);
+    }
+
+    #[tokio::test]
+    /// test that merchants register with or without the rent sysvar account
+    async fn test_register_merchant_rent_sysvar() {
+        for with_rent_sysvar in &[false, true] {
+            let program_id =
+                Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+            let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
+                "sol_payment_processor",
+                program_id,
+                processor!(PaymentProcessorInstruction::process),
+            )
+            .start()
+            .await;
+            let merchant = Pubkey::create_with_seed(&payer.pubkey(), "mosh", &program_id).unwrap();
+            let sponsor_pk = Pubkey::new_unique();
+            let mut instruction = register_merchant(
+                program_id,
+                payer.pubkey(),
+                merchant,
+                Some(String::from("mosh")),
+                MerchantOptions {
+                    sponsor: Some(sponsor_pk),
+                    ..MerchantOptions::default()
+                },
+            );
+            assert!(instruction
+                .accounts
+                .iter()
+                .all(|account| account.pubkey != sysvar::rent::id()));
+            if *with_rent_sysvar {
+                // as sent by older clients
+                instruction
+                    .accounts
+                    .insert(4, AccountMeta::new_readonly(sysvar::rent::id(), false));
+            }
+            let mut transaction =
+                Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+            transaction.sign(&[&payer], recent_blockhash);
+            assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+            let merchant_data =
+                get_account_data::<MerchantAccount>(&mut banks_client, merchant).await;
+            assert_eq!(sponsor_pk, Pubkey::new_from_array(merchant_data.sponsor));
+        }
+    }
+
+    #[tokio::test]
+    /// test that a merchant registers and gets a token account in one transaction
+    async fn test_register_merchant_with_owner_token_account() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let mint_keypair = Keypair::new();
+        assert_matches!(
+            banks_client
+                .process_transaction(create_mint_transaction(
+                    &payer,
+                    &mint_keypair,
+                    &payer,
+                    recent_blockhash
+                ))
+                .await,
+            Ok(())
+        );
+        let merchant = Pubkey::create_with_seed(&payer.pubkey(), "mosh", &program_id).unwrap();
+        let instructions = with_owner_token_account(
+            register_merchant(
+                program_id,
+                payer.pubkey(),
+                merchant,
+                Some(String::from("mosh")),
+                MerchantOptions::default(),
+            ),
+            mint_keypair.pubkey(),
+        );
+        let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+        let merchant_data = get_account_data::<MerchantAccount>(&mut banks_client, merchant).await;
+        assert_eq!(payer.pubkey().to_bytes(), merchant_data.owner);
+        let owner_token = get_associated_token_address(&payer.pubkey(), &mint_keypair.pubkey());
+        let owner_token_account = banks_client
+            .get_account(owner_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(spl_token::id(), owner_token_account.owner);
+        let owner_token_data = get_token_account_data(&mut banks_client, owner_token).await;
+        assert_eq!(payer.pubkey(), owner_token_data.owner);
+        assert_eq!(mint_keypair.pubkey(), owner_token_data.mint);
+    }
+
+    #[tokio::test]
+    /// test that only a wallet can be the sponsor of a merchant
+    async fn test_register_merchant_sponsor_checks() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        for (sponsor_pk, valid) in &[
+            (pda, false),
+            // owned by the sysvar program
+            (sysvar::rent::id(), false),
+            (Pubkey::new_unique(), true),
+        ] {
+            let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
+                "sol_payment_processor",
+                program_id,
+                processor!(PaymentProcessorInstruction::process),
+            )
+            .start()
+            .await;
+            let merchant = Pubkey::create_with_seed(&payer.pubkey(), "mosh", &program_id).unwrap();
+            let mut transaction = Transaction::new_with_payer(
+                &[register_merchant(
+                    program_id,
+                    payer.pubkey(),
+                    merchant,
+                    Some(String::from("mosh")),
+                    MerchantOptions {
+                        sponsor: Some(*sponsor_pk),
+                        ..MerchantOptions::default()
+                    },
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer], recent_blockhash);
+            let result = banks_client.process_transaction(transaction).await;
+            if *valid {
+                assert_matches!(result, Ok(()));
+                let merchant_data =
+                    get_account_data::<MerchantAccount>(&mut banks_client, merchant).await;
+                assert_eq!(*sponsor_pk, Pubkey::new_from_array(merchant_data.sponsor));
+            } else {
+                assert_eq!(
+                    result.unwrap_err().unwrap(),
+                    TransactionError::InstructionError(
+                        0,
+                        InstructionError::Custom(PaymentProcessorError::WrongSponsor as u32)
+                    )
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_merchant_wrong_seed() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        // the merchant account is derived from a different seed
+        let merchant = Pubkey::create_with_seed(&payer.pubkey(), "other", &program_id).unwrap();
+        let mut transaction = Transaction::new_with_payer(
+            &[register_merchant(
+                program_id,
+                payer.pubkey(),
+                merchant,
+                Some(String::from("mosh")),
+                MerchantOptions::default(),
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidSeeds)
+        );
+    }
+
+    /// replace the system program account of an instruction with some other account
+    fn with_bogus_system_program(mut instruction: Instruction) -> Instruction {
+        for account_meta in instruction.accounts.iter_mut() {
+            if account_meta.pubkey == solana_program::system_program::id() {
+                account_meta.pubkey = Pubkey::new_unique();
+            }
+        }
+        instruction
+    }
+
+    #[tokio::test]
+    async fn test_register_merchant_bogus_system_program() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let merchant = Pubkey::create_with_seed(&payer.pubkey(), MERCHANT, &program_id).unwrap();
+        let mut transaction = Transaction::new_with_payer(
+            &[with_bogus_system_program(register_merchant(
+                program_id,
+                payer.pubkey(),
+                merchant,
+                Option::None,
+                MerchantOptions::default(),
+            ))],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_express_checkout_bogus_system_program() {
+        let amount: u64 = 2000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut transaction = Transaction::new_with_payer(
+            &[with_bogus_system_program(express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new("bogus").unwrap(),
+                String::from(""),
+                Option::None,
+            ))],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_bogus_system_program() {
+        let mint_keypair = Keypair::new();
+        let name = "bogus";
+        let packages = format!(
+            r#"{{"packages":[{{"name":"{name}","price":1000,"duration":604800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string(),
+            name = name
+        );
+        let mut merchant_result = create_merchant_account(
+            Some(String::from("subscription test")),
+            Option::None,
+            Option::None,
+            Some(packages),
+        )
+        .await;
+        let (subscription, _bump_seed) = get_subscription_account_pubkey(
+            &merchant_result.3.pubkey(),
+            &merchant_result.1,
+            name,
+            &merchant_result.0,
+        );
+        let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
+            1000,
+            &String::from(name),
+            &String::from(""),
+            Some(format!(r#"{{"subscription": "{}"}}"#, subscription)),
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let mut transaction = Transaction::new_with_payer(
+            &[with_bogus_system_program(subscribe(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                subscription,
+                merchant_result.1,
+                order_acc_pubkey,
+                Option::None,
+                String::from(name),
+                Option::None,
+            ))],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+        );
+    }
+
+    async fn run_min_subscription_duration_test(
+        min_subscription_duration: i64,
+    ) -> Result<(), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let merchant = Pubkey::create_with_seed(&payer.pubkey(), MERCHANT, &program_id).unwrap();
+        // a package that lasts 12 minutes
+        let packages = format!(
+            r#"{{"packages":[{{"name":"basic","price":100,"duration":720,"mint":"{mint}"}}]}}"#,
+            mint = Pubkey::new_unique().to_string()
+        );
+        let mut transaction = Transaction::new_with_payer(
+            &[register_merchant(
+                program_id,
+                payer.pubkey(),
+                merchant,
+                Option::None,
+                MerchantOptions {
+                    data: Some(packages),
+                    min_subscription_duration: Some(min_subscription_duration),
+                    ..MerchantOptions::default()
+                },
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        let result = banks_client.process_transaction(transaction).await;
+        if result.is_ok() {
+            let merchant_data =
+                get_account_data::<MerchantAccount>(&mut banks_client, merchant).await;
+            assert_eq!(
+                min_subscription_duration,
+                merchant_data.min_subscription_duration
+            );
+        }
+        result
+    }
+
+    #[tokio::test]
+    /// test that merchants cannot register packages shorter than their minimum duration
+    async fn test_register_merchant_min_subscription_duration() {
+        assert_matches!(run_min_subscription_duration_test(600).await, Ok(()));
+        assert_eq!(
+            run_min_subscription_duration_test(3600)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InvalidSubscriptionPackage as u32)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_merchant_twice() {
+        let seed = String::from("twice");
+        let mut merchant_result =
+            create_merchant_account(Some(seed.clone()), Option::None, Option::None, Option::None)
+                .await;
+        let payer = &merchant_result.3;
+        // register again using the same seed
+        let mut transaction = Transaction::new_with_payer(
+            &[register_merchant(
+                merchant_result.0,
+                payer.pubkey(),
+                merchant_result.1,
+                Some(seed),
+                MerchantOptions {
+                    fee: Some(90000),
+                    ..MerchantOptions::default()
+                },
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::AccountAlreadyExists as u32)
+            )
+        );
+    }
+
+    async fn run_register_existing_merchant_test(
+        account_size: usize,
+        lamports: u64,
+    ) -> (Result<(), TransportError>, MerchantResult) {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let mut merchant_result = (
+            program_id,
+            Pubkey::default(),
+            banks_client,
+            payer,
+            recent_blockhash,
+        );
+        let merchant_keypair = Keypair::new();
+        merchant_result.1 = merchant_keypair.pubkey();
+        let payer = &merchant_result.3;
+        // pre-create the merchant account then register into it
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                system_instruction::create_account(
+                    &payer.pubkey(),
+                    &merchant_keypair.pubkey(),
+                    lamports,
+                    account_size as u64,
+                    &program_id,
+                ),
+                register_existing_merchant(
+                    program_id,
+                    payer.pubkey(),
+                    merchant_keypair.pubkey(),
+                    MerchantOptions {
+                        fee: Some(90000),
+                        ..MerchantOptions::default()
+                    },
+                ),
+            ],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer, &merchant_keypair], recent_blockhash);
+        let result = merchant_result.2.process_transaction(transaction).await;
+        (result, merchant_result)
+    }
+
+    #[tokio::test]
+    async fn test_register_existing_merchant() {
+        let account_size = get_merchant_account_size(&String::from("{}"), &[], &[]);
+        let (result, mut merchant_result) = run_register_existing_merchant_test(
+            account_size,
+            Rent::default().minimum_balance(account_size),
+        )
+        .await;
+        assert_matches!(result, Ok(()));
+        let merchant = merchant_result.1;
+        let merchant_account = merchant_result
+            .2
+            .get_account(merchant)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(Rent::default().is_exempt(merchant_account.lamports, account_size));
+        let merchant_data = run_merchant_tests(merchant_result).await;
+        assert_eq!(Discriminator::Merchant as u8, merchant_data.discriminator);
+        assert_eq!(90000, merchant_data.fee);
+        assert_eq!(String::from("{}"), merchant_data.data);
+    }
+
+    #[tokio::test]
+    async fn test_register_existing_merchant_larger_account() {
+        let account_size = get_merchant_account_size(&String::from("{}"), &[], &[]) + 100;
+        let (result, merchant_result) = run_register_existing_merchant_test(
+            account_size,
+            Rent::default().minimum_balance(account_size),
+        )
+        .await;
+        assert_matches!(result, Ok(()));
+        // the room left over is read as padding
+        let merchant_data = run_merchant_tests(merchant_result).await;
+        assert_eq!(90000, merchant_data.fee);
+    }
+
+    #[tokio::test]
+    async fn test_register_existing_merchant_wrong_size() {
+        let account_size = get_merchant_account_size(&String::from("{}"), &[], &[]) - 1;
+        let (result, _merchant_result) = run_register_existing_merchant_test(
+            account_size,
+            Rent::default().minimum_balance(account_size),
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().unwrap(),
+            TransactionError::InstructionError(1, InstructionError::InvalidAccountData)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_existing_merchant_not_rent_exempt() {
+        let account_size = get_merchant_account_size(&String::from("{}"), &[], &[]);
+        let (result, _merchant_result) = run_register_existing_merchant_test(
+            account_size,
+            Rent::default().minimum_balance(account_size) - 1,
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().unwrap(),
+            TransactionError::InstructionError(1, InstructionError::AccountNotRentExempt)
+        );
+    }
+
+    async fn run_common_checkout_tests(
+        amount: u64,
+        merchant_result: &mut MerchantResult,
+        order_acc_pubkey: &Pubkey,
+        seller_account_pubkey: &Pubkey,
+        mint_keypair: &Keypair,
+    ) -> OrderAccount {
+        // program_id => merchant_result.0;
+        // merchant_account_pubkey => merchant_result.1;
+        // banks_client => merchant_result.2;
+        // payer => merchant_result.3;
+
+        let order_account = merchant_result.2.get_account(*order_acc_pubkey).await;
+        let order_account = match order_account {
+            Ok(data) => match data {
+                None => panic!("Oo"),
+                Some(value) => value,
+            },
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        assert_eq!(order_account.owner, merchant_result.0,);
+
+        let order_data = OrderAccount::unpack(&order_account.data);
+        let order_data = match order_data {
+            Ok(data) => data,
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        assert_eq!(true, order_data.is_initialized());
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+        assert_eq!(merchant_result.1.to_bytes(), order_data.merchant);
+        assert_eq!(mint_keypair.pubkey().to_bytes(), order_data.mint);
+        assert_eq!(seller_account_pubkey.to_bytes(), order_data.token);
+        assert_eq!(merchant_result.3.pubkey().to_bytes(), order_data.payer);
+        assert_eq!(amount, order_data.expected_amount);
+        assert_eq!(amount, order_data.paid_amount);
+        assert_eq!(
+            order_account.lamports,
+            Rent::default().minimum_balance(get_order_account_size(
+                &order_data.order_id,
+                &order_data.secret,
+                &order_data.data,
+            ))
+        );
+
+        // test contents of seller token account
+        let seller_token_account = merchant_result.2.get_account(*seller_account_pubkey).await;
+        let seller_token_account = match seller_token_account {
+            Ok(data) => match data {
+                None => panic!("Oo"),
+                Some(value) => value,
+            },
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        let seller_account_data = spl_token::state::Account::unpack(&seller_token_account.data);
+        let seller_account_data = match seller_account_data {
+            Ok(data) => data,
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &merchant_result.0);
+        assert_eq!(amount, seller_account_data.amount);
+        assert_eq!(pda, seller_account_data.owner);
+        assert_eq!(mint_keypair.pubkey(), seller_account_data.mint);
+        // the recorded payment is never more than the seller token account holds
+        assert!(order_data.paid_amount <= seller_account_data.amount);
+
+        // test that sponsor was saved okay
+        let merchant_data =
+            get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant_result.1).await;
+
+        let program_owner_key = Pubkey::new_from_array(merchant_data.fee_recipient);
+        let sponsor = Pubkey::new_from_array(merchant_data.sponsor);
+
+        let program_owner_account = merchant_result.2.get_account(program_owner_key).await;
+        let program_owner_account = match program_owner_account {
+            Ok(data) => match data {
+                None => panic!("Oo"),
+                Some(value) => value,
+            },
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+
+        if sponsor == Pubkey::from_str(PROGRAM_OWNER).unwrap() {
+            // test contents of program owner account
+            assert_eq!(merchant_data.fee, program_owner_account.lamports);
+        } else {
+            // test contents of program owner account and sponsor account
+            let (program_owner_fee, sponsor_fee) = get_amounts(merchant_data.fee, SPONSOR_FEE);
+            let sponsor_account = merchant_result.2.get_account(sponsor).await;
+            let sponsor_account = match sponsor_account {
+                Ok(data) => match data {
+                    None => panic!("Oo"),
+                    Some(value) => value,
+                },
+                Err(error) => panic!("Problem: {:?}", error),
+            };
+            assert_eq!(program_owner_fee, program_owner_account.lamports);
+            assert_eq!(sponsor_fee, sponsor_account.lamports);
+        }
+
+        order_data
+    }
+
+    async fn run_checkout_tests(
+        amount: u64,
+        order_id: String,
+        secret: String,
+        data: Option<String>,
+        merchant_result: &mut MerchantResult,
+        order_acc_pubkey: &Pubkey,
+        seller_account_pubkey: &Pubkey,
+        mint_keypair: &Keypair,
+    ) {
+        let order_data = run_common_checkout_tests(
+            amount,
+            merchant_result,
+            order_acc_pubkey,
+            seller_account_pubkey,
+            mint_keypair,
+        )
+        .await;
+
+        let data_string = match data {
+            None => String::from("{}"),
+            Some(value) => value,
+        };
+        assert_eq!(order_id, order_data.order_id);
+        assert_eq!(get_order_secret(secret), order_data.secret);
+        assert_eq!(data_string, order_data.data);
+    }
+
+    async fn run_chain_checkout_tests(
+        amount: u64,
+        order_items: &OrderItems,
+        data: Option<String>,
+        merchant_result: &mut MerchantResult,
+        order_acc_pubkey: &Pubkey,
+        seller_account_pubkey: &Pubkey,
+        mint_keypair: &Keypair,
+    ) {
+        // test contents of order account
+        let order_data = run_common_checkout_tests(
+            amount,
+            merchant_result,
+            order_acc_pubkey,
+            seller_account_pubkey,
+            mint_keypair,
+        )
+        .await;
+        match data {
+            None => {
+                assert_eq!(json!({ PAID: order_items }).to_string(), order_data.data);
+            }
+            Some(value) => {
+                let json_data: Value = match serde_json::from_str(&value) {
+                    Err(error) => panic!("Problem: {:?}", error),
+                    Ok(data) => data,
+                };
+                assert_eq!(
+                    json!({ INITIAL: json_data, PAID: order_items }).to_string(),
+                    order_data.data
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_checkout() {
+        let mint_keypair = Keypair::new();
+        let amount: u64 = 2000000000;
+
+        let mut order_items: OrderItems = BTreeMap::new();
+        order_items.insert("1".to_string(), 1);
+        order_items.insert("3".to_string(), 1);
+
+        let merchant_data = format!(
+            r#"{{
+            "1": {{"price": 2000000, "mint": "{mint_key}"}},
+            "2": {{"price": 3000000, "mint": "{mint_key}"}},
+            "3": {{"price": 4000000, "mint": "{mint_key}"}},
+            "4": {{"price": 4000000, "mint": "{mint_key}"}},
+            "5": {{"price": 4000000, "mint": "{mint_key}"}}
+        }}"#,
+            mint_key = mint_keypair.pubkey()
+        );
+
+        let mut merchant_result = create_merchant_account(
+            Some("chain".to_string()),
+            Option::None,
+            Option::None,
+            Some(merchant_data),
+        )
+        .await;
+        let (order_acc_pubkey, seller_account_pubkey) = create_order_chain_checkout(
+            amount,
+            &order_items,
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+
+        run_chain_checkout_tests(
+            amount,
+            &order_items,
+            Option::None,
+            &mut merchant_result,
+            &order_acc_pubkey,
+            &seller_account_pubkey,
+            &mint_keypair,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_chain_checkout_with_data() {
+        let mint_keypair = Keypair::new();
+        let amount: u64 = 2000000000;
+
+        let mut order_items: OrderItems = BTreeMap::new();
+        order_items.insert("1".to_string(), 1);
+
+        let merchant_data = format!(
+            r#"{{
+            "1": {{"price": 2000000, "mint": "{mint_key}"}},
+            "2": {{"price": 3000000, "mint": "{mint_key}"}}
+        }}"#,
+            mint_key = mint_keypair.pubkey()
+        );
+
+        let mut merchant_result = create_merchant_account(
+            Some("chain2".to_string()),
+            Option::None,
+            Option::None,
+            Some(merchant_data),
+        )
+        .await;
+        let (order_acc_pubkey, seller_account_pubkey) = create_order_chain_checkout(
+            amount,
+            &order_items,
+            Some(String::from(r#"{"foo": "bar"}"#)),
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+
+        run_chain_checkout_tests(
+            amount,
+            &order_items,
+            Some(String::from(r#"{"foo": "bar"}"#)),
+            &mut merchant_result,
+            &order_acc_pubkey,
+            &seller_account_pubkey,
+            &mint_keypair,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_chain_checkout_quote() {
+        let mint_keypair = Keypair::new();
+
+        let mut order_items: OrderItems = BTreeMap::new();
+        order_items.insert("1".to_string(), 2);
+        order_items.insert("3".to_string(), 3);
+
+        let mut unit_prices: BTreeMap<String, u64> = BTreeMap::new();
+        unit_prices.insert("1".to_string(), 2000000);
+        unit_prices.insert("2".to_string(), 3000000);
+        unit_prices.insert("3".to_string(), 4000000);
+
+        let merchant_data = format!(
+            r#"{{
+            "1": {{"price": 2000000, "mint": "{mint_key}"}},
+            "2": {{"price": 3000000, "mint": "{mint_key}"}},
+            "3": {{"price": 4000000, "mint": "{mint_key}"}}
+        }}"#,
+            mint_key = mint_keypair.pubkey()
+        );
+
+        let mut merchant_result = create_merchant_account(
+            Some("quote".to_string()),
+            Some(50000),
+            Option::None,
+            Some(merchant_data),
+        )
+        .await;
+        let merchant_data =
+            get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant_result.1).await;
+        let quote = quote_cart(&order_items, &unit_prices, &merchant_data).unwrap();
+        assert_eq!(16000000, quote.total);
+        assert_eq!(50000, quote.fee.sol_fee);
+
+        // paying less than the quote fails
+        assert_eq!(
+            create_chain_checkout_transaction(
+                quote.total - 1,
+                &order_items,
+                Option::None,
+                &mut merchant_result,
+                &mint_keypair,
+            )
+            .await
+            .unwrap_err()
+            .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InsufficientFunds)
+        );
+
+        // paying the quote succeeds and charges the quoted fee
+        let (order_acc_pubkey, seller_account_pubkey) = create_order_chain_checkout(
+            quote.total,
+            &order_items,
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        run_chain_checkout_tests(
+            quote.total,
+            &order_items,
+            Option::None,
+            &mut merchant_result,
+            &order_acc_pubkey,
+            &seller_account_pubkey,
+            &mint_keypair,
+        )
+        .await;
+    }
+
+    async fn run_order_items_limit_test(
+        item_count: usize,
+        key_len: usize,
+    ) -> Result<(Pubkey, Pubkey), TransportError> {
+        let mint_keypair = Keypair::new();
+        let mut order_items: OrderItems = BTreeMap::new();
+        let mut registered_items: Vec<String> = vec![];
+        for index in 0..item_count {
+            let key = format!(
+                "{}{}",
+                (b'a' + index as u8) as char,
+                "x".repeat(key_len - 1)
+            );
+            registered_items.push(format!(
+                r#""{}": {{"price": 1, "mint": "{}"}}"#,
+                key,
+                mint_keypair.pubkey()
+            ));
+            order_items.insert(key, 1);
+        }
+        let mut merchant_result = create_merchant_account(
+            Some("limits".to_string()),
+            Option::None,
+            Option::None,
+            Some(format!("{{{}}}", registered_items.join(","))),
+        )
+        .await;
+        create_chain_checkout_transaction(
+            item_count as u64,
+            &order_items,
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_chain_checkout_order_items_limits() {
+        assert!(run_order_items_limit_test(MAX_ORDER_ITEMS, 1).await.is_ok());
+        assert_eq!(
+            run_order_items_limit_test(MAX_ORDER_ITEMS + 1, 1)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::TooManyItems as u32)
+            )
+        );
+        assert!(run_order_items_limit_test(1, MAX_ITEM_ID_LEN).await.is_ok());
+        assert_eq!(
+            run_order_items_limit_test(1, MAX_ITEM_ID_LEN + 1)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InvalidOrderData as u32)
+            )
+        );
+    }
+
+    async fn chain_checkout_failing_test_helper(
+        order_item_id: u8,
+        paid_amount: u64,
+        input_mint: &Keypair,
+        registered_item_id: u8,
+        expected_amount: u64,
+        registered_mint: &Keypair,
+        expected_error: InstructionError,
+    ) -> bool {
+        let mut order_items: OrderItems = BTreeMap::new();
+        order_items.insert(format!("{}", order_item_id), 1);
+
+        let mut merchant_data = String::from("5");
+
+        if registered_item_id != 0 {
+            merchant_data = format!(
+                r#"{{"{registered_item_id}": {{"price": {expected_amount}, "mint": "{mint_key}"}}}}"#,
+                registered_item_id = registered_item_id,
+                expected_amount = expected_amount,
+                mint_key = registered_mint.pubkey()
+            );
+        }
+
+        let mut merchant_result = create_merchant_account(
+            Some("test".to_string()),
+            Option::None,
+            Option::None,
+            Some(merchant_data),
+        )
+        .await;
+
+        match create_chain_checkout_transaction(
+            paid_amount,
+            &order_items,
+            Option::None,
+            &mut merchant_result,
+            &input_mint,
+        )
+        .await
+        {
+            Err(error) => {
+                assert_eq!(
+                    error.unwrap(),
+                    TransactionError::InstructionError(0, expected_error)
+                );
+            }
+            Ok(_value) => panic!("Oo... we expect an error"),
+        };
+
+        true
+    }
+
+    #[tokio::test]
+    async fn test_chain_checkout_failure() {
+        let mint_a = Keypair::new();
+        let mint_b = Keypair::new();
+
+        // insufficient funds
+        assert!(
+            chain_checkout_failing_test_helper(
+                1,       // id of item being ordered
+                20,      // amount to pay
+                &mint_a, // mint being used for payment
+                1,       // registered item id
+                30,      // expected amount
+                &mint_a, // expected mint
+                InstructionError::InsufficientFunds
+            )
+            .await
+        );
+
+        // wrong item id in order
+        assert!(
+            chain_checkout_failing_test_helper(
+                7,       // id of item being ordered
+                20,      // amount to pay
+                &mint_a, // mint being used for payment
+                1,       // registered item id
+                30,      // expected amount
+                &mint_a, // expected mint
+                InstructionError::Custom(PaymentProcessorError::InvalidOrderData as u32)
+            )
+            .await
+        );
+
+        // wrong mint in order
+        assert!(
+            chain_checkout_failing_test_helper(
+                1,       // id of item being ordered
+                20,      // amount to pay
+                &mint_a, // mint being used for payment
+                1,       // registered item id
+                20,      // expected amount
+                &mint_b, // expected mint
+                InstructionError::Custom(PaymentProcessorError::WrongMint as u32)
+            )
+            .await
+        );
+
+        // invalid merchant data
+        assert!(
+            chain_checkout_failing_test_helper(
+                1,       // id of item being ordered
+                20,      // amount to pay
+                &mint_a, // mint being used for payment
+                0,       // registered item id
+                20,      // expected amount
+                &mint_a, // expected mint
+                InstructionError::Custom(PaymentProcessorError::InvalidMerchantData as u32)
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_express_checkout() {
+        let amount: u64 = 2000000000;
+        let order_id = String::from("1337");
+        let secret = String::from("hunter2");
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, seller_account_pubkey) = create_order_express_checkout(
+            amount,
+            &order_id,
+            &secret,
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+
+        run_checkout_tests(
+            amount,
+            order_id,
+            secret,
+            Option::None,
+            &mut merchant_result,
+            &order_acc_pubkey,
+            &seller_account_pubkey,
+            &mint_keypair,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    /// test that an order paid without a secret does not store one
+    async fn test_express_checkout_without_secret() {
+        let amount: u64 = 2000000000;
+        let mut order_sizes = vec![];
+        for (order_id, secret) in vec![("1", "hunter2"), ("2", "")] {
+            let mut merchant_result =
+                create_merchant_account(Option::None, Option::None, Option::None, Option::None)
+                    .await;
+            let mint_keypair = Keypair::new();
+            let (order_acc_pubkey, seller_account_pubkey) = create_order_express_checkout(
+                amount,
+                &String::from(order_id),
+                &String::from(secret),
+                Option::None,
+                &mut merchant_result,
+                &mint_keypair,
+            )
+            .await;
+            run_checkout_tests(
+                amount,
+                String::from(order_id),
+                String::from(secret),
+                Option::None,
+                &mut merchant_result,
+                &order_acc_pubkey,
+                &seller_account_pubkey,
+                &mint_keypair,
+            )
+            .await;
+            let order_account = merchant_result
+                .2
+                .get_account(order_acc_pubkey)
+                .await
+                .unwrap()
+                .unwrap();
+            order_sizes.push(order_account.data.len());
+        }
+        // the secret string and its length prefix are left out
+        assert_eq!(order_sizes[0] - order_sizes[1], "hunter2".len() + 4);
+    }
+
+    #[tokio::test]
+    /// test that the computed order size is the size of the order account checkout creates
+    async fn test_compute_order_size() {
+        let amount: u64 = 2000000000;
+        let order_id = String::from("1337");
+        let secret = String::from("hunter2");
+        let data = String::from(r#"{"a": "b"}"#);
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
+            amount,
+            &order_id,
+            &secret,
+            Option::Some(data.clone()),
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let order_account = merchant_result
+            .2
+            .get_account(order_acc_pubkey)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            order_account.data.len(),
+            get_order_account_size(&order_id, &get_order_secret(secret.clone()), &data)
+        );
+
+        let mut transaction = Transaction::new_with_payer(
+            &[compute_order_size(
+                merchant_result.0,
+                order_id,
+                secret,
+                Option::Some(data),
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    /// test that simulated checkout fees are what a checkout actually pays
+    async fn test_simulate_checkout_fees() {
+        let amount: u64 = 2000000000;
+        let sponsor_pk = Pubkey::new_unique();
+        for sponsor in &[Option::None, Some(&sponsor_pk)] {
+            let mut merchant_result =
+                create_merchant_account(Option::None, Option::None, *sponsor, Option::None).await;
+            let merchant_data =
+                get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant_result.1)
+                    .await;
+            let mut transaction = Transaction::new_with_payer(
+                &[simulate_checkout_fees(
+                    merchant_result.0,
+                    merchant_result.1,
+                    amount,
+                )],
+                Some(&merchant_result.3.pubkey()),
+            );
+            transaction.sign(&[&merchant_result.3], merchant_result.4);
+            assert_matches!(
+                merchant_result.2.process_transaction(transaction).await,
+                Ok(())
+            );
+            let fee_quote = quote(amount, &merchant_data);
+            let (program_owner_fee, sponsor_fee) = get_fee_shares(
+                &merchant_data,
+                &Pubkey::new_from_array(merchant_data.fee_recipient),
+                fee_quote.sol_fee,
+            );
+            assert_eq!(DEFAULT_FEE_IN_LAMPORTS, program_owner_fee + sponsor_fee);
+
+            // without a sponsor the program owner is the sponsor and gets the whole fee
+            let sponsor_key = Pubkey::new_from_array(merchant_data.sponsor);
+            let expected_fee = match sponsor {
+                None => program_owner_fee,
+                Some(_sponsor) => sponsor_fee,
+            };
+            let sponsor_before = match merchant_result.2.get_account(sponsor_key).await {
+                Ok(Some(account)) => account.lamports,
+                _ => 0,
+            };
+            let mint_keypair = Keypair::new();
+            create_order_express_checkout(
+                amount,
+                &String::from("simulated"),
+                &String::from(""),
+                Option::None,
+                &mut merchant_result,
+                &mint_keypair,
+            )
+            .await;
+            let sponsor_after = match merchant_result.2.get_account(sponsor_key).await {
+                Ok(Some(account)) => account.lamports,
+                _ => 0,
+            };
+            assert_eq!(expected_fee, sponsor_after - sponsor_before);
+        }
+    }
+
+    #[tokio::test]
+    /// test that a merchant sponsored by the config owner pays it the whole fee in one
+    /// transfer, as for merchants without a sponsor
+    async fn test_express_checkout_config_owner_sponsor() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (config, _bump_seed) = get_config_address(&program_id);
+        let config_owner = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        program_test.add_account(
+            config,
+            solana_sdk::account::Account {
+                lamports: Rent::default().minimum_balance(ConfigAccount::LEN),
+                data: ConfigAccount {
+                    discriminator: Discriminator::Config as u8,
+                    owner: config_owner.to_bytes(),
+                }
+                .try_to_vec()
+                .unwrap(),
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        program_test.add_account(
+            config_owner,
+            solana_sdk::account::Account {
+                lamports: 1000000000,
+                data: vec![],
+                owner: solana_program::system_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        let (banks_client, payer, recent_blockhash) = program_test.start().await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                sponsor: Some(config_owner),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let amount: u64 = 1000000;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, _merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let config_owner_before = merchant_result
+            .2
+            .get_account(config_owner)
+            .await
+            .unwrap()
+            .unwrap()
+            .lamports;
+        // the config owner is both the fee recipient and the sponsor
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                program_id,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                config_owner,
+                config_owner,
+                pda,
+                amount,
+                OrderId::try_new("sponsored").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let config_owner_after = merchant_result
+            .2
+            .get_account(config_owner)
+            .await
+            .unwrap()
+            .unwrap()
+            .lamports;
+        assert_eq!(
+            DEFAULT_FEE_IN_LAMPORTS,
+            config_owner_after - config_owner_before
+        );
+    }
+
+    #[tokio::test]
+    /// test checkout with all merchant options
+    async fn test_express_checkout_with_all_options() {
+        let sponsor_pk = Pubkey::new_unique();
+        let amount: u64 = 2000000000;
+        let order_id = String::from("123-SQT-MX");
+        let secret = String::from("supersecret");
+        let mut merchant_result = create_merchant_account(
+            Some(String::from("Oo")),
+            Some(123456),
+            Some(&sponsor_pk),
+            Some(String::from(r#"{"foo": "bar"}"#)),
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, seller_account_pubkey) = create_order_express_checkout(
+            amount,
+            &order_id,
+            &secret,
+            Some(String::from(r#"{"a": "b"}"#)),
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        run_checkout_tests(
+            amount,
+            order_id,
+            secret,
+            Some(String::from(r#"{"a": "b"}"#)),
+            &mut merchant_result,
+            &order_acc_pubkey,
+            &seller_account_pubkey,
+            &mint_keypair,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_express_checkout_with_fee_recipient() {
+        let fee_recipient = Pubkey::new_unique();
+        let amount: u64 = 2000000;
+        let order_id = String::from("fee-recipient");
+        let secret = String::from("");
+        let mut merchant_result = create_merchant_account_with_fee_recipient(
+            Option::None,
+            Option::None,
+            Option::None,
+            Option::None,
+            Some(&fee_recipient),
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, seller_account_pubkey) = create_order_express_checkout(
+            amount,
+            &order_id,
+            &secret,
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        // the fee recipient was saved and got the fee
+        run_checkout_tests(
+            amount,
+            order_id,
+            secret,
+            Option::None,
+            &mut merchant_result,
+            &order_acc_pubkey,
+            &seller_account_pubkey,
+            &mint_keypair,
+        )
+        .await;
+        let fee_recipient_account = match merchant_result.2.get_account(fee_recipient).await {
+            Ok(data) => match data {
+                None => panic!("Oo"),
+                Some(value) => value,
+            },
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        assert_eq!(DEFAULT_FEE_IN_LAMPORTS, fee_recipient_account.lamports);
+
+        // the program owner can no longer be used to receive this merchant's fees
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        assert_eq!(fee_recipient.to_bytes(), merchant_data.fee_recipient);
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::from_str(PROGRAM_OWNER).unwrap(),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new("program-owner").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongProgramOwner as u32)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_config_owner() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (config, _bump_seed) = get_config_address(&program_id);
+        let config_owner = Keypair::new();
+        let new_owner = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        // start with a config account that belongs to a key we control
+        program_test.add_account(
+            config,
+            solana_sdk::account::Account {
+                lamports: Rent::default().minimum_balance(ConfigAccount::LEN),
+                data: ConfigAccount {
+                    discriminator: Discriminator::Config as u8,
+                    owner: config_owner.pubkey().to_bytes(),
+                }
+                .try_to_vec()
+                .unwrap(),
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        let (banks_client, payer, recent_blockhash) = program_test.start().await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant::default(),
+        )
+        .await;
+        let payer = &merchant_result.3;
+
+        // only the config owner can update the config
+        let mut transaction = Transaction::new_with_payer(
+            &[update_config_owner(program_id, payer.pubkey(), new_owner)],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], recent_blockhash);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongProgramOwner as u32)
+            )
+        );
+
+        let mut transaction = Transaction::new_with_payer(
+            &[update_config_owner(
+                program_id,
+                config_owner.pubkey(),
+                new_owner,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer, &config_owner], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let config_data = get_account_data::<ConfigAccount>(&mut merchant_result.2, config).await;
+        assert_eq!(new_owner.to_bytes(), config_data.owner);
+
+        // checkouts now send the program owner fee to the new owner
+        let amount: u64 = 1000000;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let payer = &merchant_result.3;
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                program_id,
+                payer.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                new_owner,
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new("new-owner").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer, &order_acc_keypair], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let new_owner_account = match merchant_result.2.get_account(new_owner).await {
+            Ok(data) => match data {
+                None => panic!("Oo"),
+                Some(value) => value,
+            },
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        assert_eq!(merchant_data.fee, new_owner_account.lamports);
+    }
+
+    #[tokio::test]
+    /// test that once the registry exists only the keys it approves can register merchants
+    async fn test_register_merchant_registry() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (config, _bump_seed) = get_config_address(&program_id);
+        let (registry, _bump_seed) = get_registry_address(&program_id);
+        let config_owner = Keypair::new();
+        let approved = Keypair::new();
+        let unapproved = Keypair::new();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        program_test.add_account(
+            config,
+            solana_sdk::account::Account {
+                lamports: Rent::default().minimum_balance(ConfigAccount::LEN),
+                data: ConfigAccount {
+                    discriminator: Discriminator::Config as u8,
+                    owner: config_owner.pubkey().to_bytes(),
+                }
+                .try_to_vec()
+                .unwrap(),
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        // the config owner pays the registry rent and the registrants their merchant accounts
+        for key in &[
+            config_owner.pubkey(),
+            approved.pubkey(),
+            unapproved.pubkey(),
+        ] {
+            program_test.add_account(
+                *key,
+                solana_sdk::account::Account {
+                    lamports: 1000000000,
+                    data: vec![],
+                    owner: solana_program::system_program::id(),
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+        }
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+        let register_transaction = |signer: &Keypair, seed: &str| {
+            let merchant = Pubkey::create_with_seed(&signer.pubkey(), seed, &program_id).unwrap();
+            let mut transaction = Transaction::new_with_payer(
+                &[register_merchant(
+                    program_id,
+                    signer.pubkey(),
+                    merchant,
+                    Some(String::from(seed)),
+                    MerchantOptions::default(),
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer, signer], recent_blockhash);
+            transaction
+        };
+        let registry_transaction = |registrants: Vec<Pubkey>| {
+            let mut transaction = Transaction::new_with_payer(
+                &[update_registry(
+                    program_id,
+                    config_owner.pubkey(),
+                    registrants,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer, &config_owner], recent_blockhash);
+            transaction
+        };
+
+        // without a registry anyone can register
+        assert_matches!(
+            banks_client
+                .process_transaction(register_transaction(&unapproved, "open"))
+                .await,
+            Ok(())
+        );
+        // only the config owner can create the registry
+        let mut transaction = Transaction::new_with_payer(
+            &[update_registry(
+                program_id,
+                payer.pubkey(),
+                vec![payer.pubkey()],
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongProgramOwner as u32)
+            )
+        );
+        assert_matches!(
+            banks_client
+                .process_transaction(registry_transaction(vec![approved.pubkey()]))
+                .await,
+            Ok(())
+        );
+        let registry_data = get_account_data::<RegistryAccount>(&mut banks_client, registry).await;
+        assert_eq!(
+            vec![approved.pubkey().to_bytes()],
+            registry_data.registrants
+        );
+
+        // now only approved keys can register
+        assert_eq!(
+            banks_client
+                .process_transaction(register_transaction(&unapproved, "mosh"))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::RegistrantNotApproved as u32)
+            )
+        );
+        assert_matches!(
+            banks_client
+                .process_transaction(register_transaction(&approved, "mosh"))
+                .await,
+            Ok(())
+        );
+
+        // without keys the registry is closed and anyone can register again
+        assert_matches!(
+            banks_client
+                .process_transaction(registry_transaction(vec![]))
+                .await,
+            Ok(())
+        );
+        assert_eq!(0, banks_client.get_balance(registry).await.unwrap());
+        assert_matches!(
+            banks_client
+                .process_transaction(register_transaction(&unapproved, "reopened"))
+                .await,
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    /// test that a merchant's orders can be enumerated by index
+    async fn test_orders_by_index() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount * 3, &mint_keypair, &mut merchant_result).await;
+        let program_id = merchant_result.0;
+        let merchant = merchant_result.1;
+        let payer = &merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+
+        for index in 0..3 {
+            let (order, _bump_seed) = get_order_pubkey_by_index(&merchant, index, &program_id);
+            let (seller_token, _bump_seed) = Pubkey::find_program_address(
+                &[
+                    &order.to_bytes(),
+                    &spl_token::id().to_bytes(),
+                    &mint_keypair.pubkey().to_bytes(),
+                ],
+                &program_id,
+            );
+            let mut transaction = Transaction::new_with_payer(
+                &[express_checkout_by_index(
+                    program_id,
+                    payer.pubkey(),
+                    merchant,
+                    seller_token,
+                    buyer_token_keypair.pubkey(),
+                    mint_keypair.pubkey(),
+                    program_owner,
+                    program_owner,
+                    pda,
+                    index,
+                    amount,
+                    OrderId::try_new(&format!("order-{}", index)).unwrap(),
+                    String::from(""),
+                    Option::None,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[payer], recent_blockhash);
+            assert_matches!(
+                merchant_result.2.process_transaction(transaction).await,
+                Ok(())
+            );
+        }
+
+        // the merchant order count has been incremented for each order
+        let merchant_data =
+            get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant).await;
+        assert_eq!(3, merchant_data.order_count);
+
+        // enumerate the orders
+        for index in 0..merchant_data.order_count {
+            let (order, _bump_seed) = get_order_pubkey_by_index(&merchant, index, &program_id);
+            let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+            assert_eq!(format!("order-{}", index), order_data.order_id);
+            assert_eq!(merchant.to_bytes(), order_data.merchant);
+            assert_eq!(amount, order_data.paid_amount);
+        }
+    }
+
+    #[tokio::test]
+    /// test that one buyer can pay two merchants with the same order id
+    async fn test_orders_by_index_same_order_id() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount * 2, &mint_keypair, &mut merchant_result).await;
+        let (program_id, merchant, banks_client, payer, recent_blockhash) = merchant_result;
+        let other_merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                seed: Some(String::from("other")),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let (_, other_merchant, mut banks_client, payer, recent_blockhash) = other_merchant_result;
+        assert_ne!(merchant, other_merchant);
+        let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+
+        let mut orders = vec![];
+        for merchant in &[merchant, other_merchant] {
+            // the first order of each merchant
+            let (order, _bump_seed) = get_order_pubkey_by_index(merchant, 0, &program_id);
+            let (seller_token, _bump_seed) = Pubkey::find_program_address(
+                &[
+                    &order.to_bytes(),
+                    &spl_token::id().to_bytes(),
+                    &mint_keypair.pubkey().to_bytes(),
+                ],
+                &program_id,
+            );
+            let mut transaction = Transaction::new_with_payer(
+                &[express_checkout_by_index(
+                    program_id,
+                    payer.pubkey(),
+                    *merchant,
+                    seller_token,
+                    buyer_token_keypair.pubkey(),
+                    mint_keypair.pubkey(),
+                    program_owner,
+                    program_owner,
+                    pda,
+                    0,
+                    amount,
+                    OrderId::try_new("1337").unwrap(),
+                    String::from(""),
+                    Option::None,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer], recent_blockhash);
+            assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+            orders.push(order);
+        }
+
+        // both orders were created, each at its own address
+        assert_ne!(orders[0], orders[1]);
+        for (order, merchant) in orders.iter().zip(&[merchant, other_merchant]) {
+            let order_data = get_account_data::<OrderAccount>(&mut banks_client, *order).await;
+            assert_eq!(String::from("1337"), order_data.order_id);
+            assert_eq!(merchant.to_bytes(), order_data.merchant);
+            assert_eq!(payer.pubkey().to_bytes(), order_data.payer);
+            assert_eq!(amount, order_data.paid_amount);
+        }
+    }
+
+    #[tokio::test]
+    /// test that sending lamports to the next order address does not block the
+    /// checkout that creates it
+    async fn test_orders_by_index_prefunded() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (program_id, merchant, mut banks_client, payer, recent_blockhash) = merchant_result;
+        let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let (order, _bump_seed) = get_order_pubkey_by_index(&merchant, 0, &program_id);
+        let (seller_token, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint_keypair.pubkey().to_bytes(),
+            ],
+            &program_id,
+        );
+
+        let mut transaction = Transaction::new_with_payer(
+            &[system_instruction::transfer(&payer.pubkey(), &order, 1)],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout_by_index(
+                program_id,
+                payer.pubkey(),
+                merchant,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                program_owner,
+                program_owner,
+                pda,
+                0,
+                amount,
+                OrderId::try_new("1337").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+        let order_account = banks_client.get_account(order).await.unwrap().unwrap();
+        assert_eq!(program_id, order_account.owner);
+        assert_eq!(
+            Rent::default().minimum_balance(order_account.data.len()),
+            order_account.lamports
+        );
+        let order_data = get_account_data::<OrderAccount>(&mut banks_client, order).await;
+        assert_eq!(amount, order_data.paid_amount);
+    }
+
+    #[tokio::test]
+    /// test that a checkout creates a pre-funded keypair order account, which
+    /// signs for itself instead of with seeds
+    async fn test_express_checkout_keypair_order_prefunded() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (program_id, merchant, mut banks_client, payer, recent_blockhash) = merchant_result;
+        let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let (order_keypair, seller_token, pda, _merchant_data) = prepare_order(
+            &program_id,
+            &merchant,
+            &mint_keypair.pubkey(),
+            &mut banks_client,
+        )
+        .await;
+        let order = order_keypair.pubkey();
+
+        let mut transaction = Transaction::new_with_payer(
+            &[system_instruction::transfer(&payer.pubkey(), &order, 1)],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                program_id,
+                payer.pubkey(),
+                order,
+                merchant,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                program_owner,
+                program_owner,
+                pda,
+                amount,
+                OrderId::try_new("1337").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer, &order_keypair], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+        let order_account = banks_client.get_account(order).await.unwrap().unwrap();
+        assert_eq!(program_id, order_account.owner);
+        assert_eq!(
+            Rent::default().minimum_balance(order_account.data.len()),
+            order_account.lamports
+        );
+        let order_data = get_account_data::<OrderAccount>(&mut banks_client, order).await;
+        assert_eq!(amount, order_data.paid_amount);
+    }
+
+    #[tokio::test]
+    /// test that a checkout with an order account that is neither a signer nor
+    /// derived from the merchant order count fails
+    async fn test_express_checkout_wrong_order_index() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let program_id = merchant_result.0;
+        let merchant = merchant_result.1;
+        let payer = &merchant_result.3;
+        let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        // the merchant order count is 0 so index 1 is the wrong order account
+        let (order, _bump_seed) = get_order_pubkey_by_index(&merchant, 1, &program_id);
+        let (seller_token, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint_keypair.pubkey().to_bytes(),
+            ],
+            &program_id,
+        );
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout_by_index(
+                program_id,
+                payer.pubkey(),
+                merchant,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                program_owner,
+                program_owner,
+                pda,
+                1,
+                amount,
+                OrderId::try_new("1337").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidSeeds)
+        );
+    }
+
+    async fn run_client_tag_test(client_tag: Option<&str>) -> Result<(), TransportError> {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut instruction = express_checkout(
+            merchant_result.0,
+            merchant_result.3.pubkey(),
+            order_acc_keypair.pubkey(),
+            merchant_result.1,
+            seller_token,
+            buyer_token_keypair.pubkey(),
+            mint_keypair.pubkey(),
+            Pubkey::from_str(PROGRAM_OWNER).unwrap(),
+            Pubkey::new_from_array(merchant_data.sponsor),
+            pda,
+            amount,
+            OrderId::try_new("1337").unwrap(),
+            String::from(""),
+            Option::None,
+        );
+        if let Some(client_tag) = client_tag {
+            instruction = with_client_tag(instruction, client_tag);
+        }
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    /// test that checkouts work with and without a client tag but not with one too long
+    async fn test_express_checkout_client_tag() {
+        assert_matches!(run_client_tag_test(Option::None).await, Ok(()));
+        assert_matches!(run_client_tag_test(Some("web-sdk-1.2.0")).await, Ok(()));
+        assert_eq!(
+            run_client_tag_test(Some(&"x".repeat(MAX_CLIENT_TAG_LEN + 1)))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidInstructionData)
+        );
+    }
+
+    async fn run_usd_price_test(amount: u64) -> Result<(), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let price = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        // one token is worth $20 i.e. 2000000000 * 10^-8
+        let mut price_data = vec![0; PYTH_PRICE_LEN];
+        price_data[MAGIC_OFFSET..MAGIC_OFFSET + 4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+        price_data[ACCOUNT_TYPE_OFFSET..ACCOUNT_TYPE_OFFSET + 4]
+            .copy_from_slice(&PYTH_PRICE_ACCOUNT_TYPE.to_le_bytes());
+        price_data[EXPO_OFFSET..EXPO_OFFSET + 4].copy_from_slice(&(-8i32).to_le_bytes());
+        price_data[PRICE_OFFSET..PRICE_OFFSET + 8].copy_from_slice(&2000000000i64.to_le_bytes());
+        price_data[STATUS_OFFSET..STATUS_OFFSET + 4]
+            .copy_from_slice(&PYTH_STATUS_TRADING.to_le_bytes());
+        price_data[PUB_SLOT_OFFSET..PUB_SLOT_OFFSET + 8].copy_from_slice(&0u64.to_le_bytes());
+        program_test.add_account(
+            price,
+            solana_sdk::account::Account {
+                lamports: Rent::default().minimum_balance(PYTH_PRICE_LEN),
+                data: price_data,
+                owner: Pubkey::from_str(PYTH_PROGRAM_ID).unwrap(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        let (banks_client, payer, recent_blockhash) = program_test.start().await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant::default(),
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        // the order is quoted at $100
+        let usd_amount: u64 = 100000000;
+        let instruction = with_usd_price(
+            express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new("usd").unwrap(),
+                String::from(""),
+                Option::None,
+            ),
+            usd_amount,
+            price,
+        );
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        let result = merchant_result.2.process_transaction(transaction).await;
+        if result.is_ok() {
+            let order_data = get_account_data::<OrderAccount>(
+                &mut merchant_result.2,
+                order_acc_keypair.pubkey(),
+            )
+            .await;
+            assert_eq!(usd_amount, order_data.expected_amount);
+            // $100 is 5 tokens at $20 each
+            assert_eq!(5, order_data.quoted_amount);
+            assert_eq!(amount, order_data.paid_amount);
+        }
+        result
+    }
+
+    #[tokio::test]
+    /// test that checkouts quoted in USD pay at least the USD amount at the oracle price
+    async fn test_express_checkout_usd_price() {
+        // $100 is 5 tokens at $20 each
+        assert_matches!(run_usd_price_test(5).await, Ok(()));
+        assert_eq!(
+            run_usd_price_test(4).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InsufficientFunds)
+        );
+    }
+
+    /// checkout from a buyer token account with a delegate and return the account after
+    async fn run_revoke_delegate_test(revoke_delegate: bool) -> TokenAccount {
+        let amount: u64 = 2000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount * 2, &mint_keypair, &mut merchant_result).await;
+        let payer = &merchant_result.3;
+        // the buyer approved a delegate for this checkout
+        let mut transaction = Transaction::new_with_payer(
+            &[approve(
+                &spl_token::id(),
+                &buyer_token_keypair.pubkey(),
+                &Pubkey::new_unique(),
                 &payer.pubkey(),
-                &mint.pubkey(),
-                Rent::default().minimum_balance(Mint::LEN),
-                Mint::LEN as u64,
+                &[&payer.pubkey()],
+                amount,
+            )
+            .unwrap()],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut instruction = express_checkout(
+            merchant_result.0,
+            merchant_result.3.pubkey(),
+            order_acc_keypair.pubkey(),
+            merchant_result.1,
+            seller_token,
+            buyer_token_keypair.pubkey(),
+            mint_keypair.pubkey(),
+            Pubkey::new_from_array(merchant_data.fee_recipient),
+            Pubkey::new_from_array(merchant_data.sponsor),
+            pda,
+            amount,
+            OrderId::try_new("revoke").unwrap(),
+            String::from(""),
+            Option::None,
+        );
+        if revoke_delegate {
+            instruction = with_revoke_delegate(instruction);
+        }
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        get_token_account_data(&mut merchant_result.2, buyer_token_keypair.pubkey()).await
+    }
+
+    #[tokio::test]
+    /// test that the buyer token account delegate is only revoked when asked for
+    async fn test_express_checkout_revoke_delegate() {
+        let buyer_token_data = run_revoke_delegate_test(true).await;
+        assert_eq!(COption::None, buyer_token_data.delegate);
+        assert_eq!(0, buyer_token_data.delegated_amount);
+        let buyer_token_data = run_revoke_delegate_test(false).await;
+        assert!(buyer_token_data.delegate.is_some());
+        assert_eq!(2000000, buyer_token_data.delegated_amount);
+    }
+
+    /// checkout with a delegate of the buyer token account as the signer
+    async fn run_delegate_allowance_test(allowance: u64) -> Result<(), TransportError> {
+        let amount: u64 = 2000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount * 2, &mint_keypair, &mut merchant_result).await;
+        let payer = &merchant_result.3;
+        // the buyer approved a delegate, which pays for the order account itself
+        let delegate = Keypair::new();
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                approve(
+                    &spl_token::id(),
+                    &buyer_token_keypair.pubkey(),
+                    &delegate.pubkey(),
+                    &payer.pubkey(),
+                    &[&payer.pubkey()],
+                    allowance,
+                )
+                .unwrap(),
+                system_instruction::transfer(&payer.pubkey(), &delegate.pubkey(), 1000000000),
+            ],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                merchant_result.0,
+                delegate.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new("delegate").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(
+            &[&merchant_result.3, &delegate, &order_acc_keypair],
+            merchant_result.4,
+        );
+        let result = merchant_result.2.process_transaction(transaction).await;
+        if result.is_ok() {
+            let buyer_token_data =
+                get_token_account_data(&mut merchant_result.2, buyer_token_keypair.pubkey()).await;
+            assert_eq!(amount, buyer_token_data.amount);
+            assert_eq!(allowance - amount, buyer_token_data.delegated_amount);
+        }
+        result
+    }
+
+    #[tokio::test]
+    /// test that a delegate can only check out within the amount it was approved for
+    async fn test_express_checkout_delegate_allowance() {
+        assert_matches!(run_delegate_allowance_test(3000000).await, Ok(()));
+        assert_eq!(
+            run_delegate_allowance_test(1000000)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InsufficientDelegatedAmount as u32)
+            )
+        );
+    }
+
+    async fn run_accepted_mints_test(mint_is_accepted: bool) -> Result<(), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let amount: u64 = 1000000;
+        let mint_keypair = Keypair::new();
+        let accepted_mint = if mint_is_accepted {
+            mint_keypair.pubkey()
+        } else {
+            Pubkey::new_unique()
+        };
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                accepted_mints: Some(vec![Pubkey::new_unique(), accepted_mint]),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        assert_eq!(2, merchant_data.accepted_mints.len());
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new("accepted").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    /// test that a merchant with accepted mints only takes payments in those mints
+    async fn test_express_checkout_accepted_mints() {
+        assert_matches!(run_accepted_mints_test(true).await, Ok(()));
+        assert_eq!(
+            run_accepted_mints_test(false).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::MintNotAccepted as u32)
+            )
+        );
+    }
+
+    /// returns the (seller, charity) token balances and the order paid amount after a
+    /// checkout to a merchant giving 2.5% to charity
+    async fn run_charity_test(
+        charity_mint_is_order_mint: bool,
+    ) -> Result<(u64, u64, u64), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let amount: u64 = 1000000;
+        // the charity only needs its own token account, which may as well be the payer's
+        let charity = payer.pubkey();
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                charity: Some((charity, 250)),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let mint = mint_keypair.pubkey();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let charity_mint_keypair = if charity_mint_is_order_mint {
+            mint_keypair
+        } else {
+            let other_mint_keypair = Keypair::new();
+            create_token_account(amount, &other_mint_keypair, &mut merchant_result).await;
+            other_mint_keypair
+        };
+        // create the charity token account
+        let charity_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &merchant_result.3,
+                    &charity_mint_keypair,
+                    merchant_result.4,
+                    &charity_token_keypair,
+                    &charity,
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint,
+            &mut merchant_result.2,
+        )
+        .await;
+        assert_eq!(charity.to_bytes(), merchant_data.charity);
+        assert_eq!(250, merchant_data.charity_bps);
+        let mut transaction = Transaction::new_with_payer(
+            &[with_charity_token(
+                express_checkout(
+                    merchant_result.0,
+                    merchant_result.3.pubkey(),
+                    order_acc_keypair.pubkey(),
+                    merchant_result.1,
+                    seller_token,
+                    buyer_token_keypair.pubkey(),
+                    mint,
+                    Pubkey::new_from_array(merchant_data.fee_recipient),
+                    Pubkey::new_from_array(merchant_data.sponsor),
+                    pda,
+                    amount,
+                    OrderId::try_new("charity").unwrap(),
+                    String::from(""),
+                    Option::None,
+                ),
+                charity_token_keypair.pubkey(),
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await?;
+        let order_data =
+            get_account_data::<OrderAccount>(&mut merchant_result.2, order_acc_keypair.pubkey())
+                .await;
+        Ok((
+            get_token_account_data(&mut merchant_result.2, seller_token)
+                .await
+                .amount,
+            get_token_account_data(&mut merchant_result.2, charity_token_keypair.pubkey())
+                .await
+                .amount,
+            order_data.paid_amount,
+        ))
+    }
+
+    #[tokio::test]
+    /// test that a merchant's charity receives its share of the payment and the seller the rest
+    async fn test_express_checkout_charity() {
+        assert_eq!(
+            (975000, 25000, 975000),
+            run_charity_test(true).await.unwrap()
+        );
+        // the charity token account must hold the order mint
+        assert_eq!(
+            run_charity_test(false).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongMint as u32)
+            )
+        );
+    }
+
+    /// returns the fee the program owner received for an express checkout
+    async fn run_fee_override_test(buyer_is_vip: bool) -> u64 {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        // the payer is also the buyer
+        let vip = if buyer_is_vip {
+            payer.pubkey()
+        } else {
+            Pubkey::new_unique()
+        };
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                fee: Some(DEFAULT_FEE_IN_LAMPORTS),
+                fee_overrides: Some(vec![(vip, MIN_FEE_IN_LAMPORTS)]),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let program_owner_before = match merchant_result.2.get_account(program_owner).await {
+            Ok(Some(account)) => account.lamports,
+            _ => 0,
+        };
+        create_order_express_checkout(
+            1000000,
+            &String::from("vip"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &Keypair::new(),
+        )
+        .await;
+        let program_owner_after = merchant_result
+            .2
+            .get_account(program_owner)
+            .await
+            .unwrap()
+            .unwrap()
+            .lamports;
+        program_owner_after - program_owner_before
+    }
+
+    #[tokio::test]
+    /// test that buyers with a fee override pay that fee instead of the merchant fee
+    async fn test_express_checkout_fee_override() {
+        assert_eq!(MIN_FEE_IN_LAMPORTS, run_fee_override_test(true).await);
+        assert_eq!(DEFAULT_FEE_IN_LAMPORTS, run_fee_override_test(false).await);
+    }
+
+    /// returns the lamports the fee recipient received, the token fee and the seller amount
+    async fn run_fee_mode_test(fee_mode: FeeMode, fee: u64, amount: u64) -> (u64, u64, u64) {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        // token fees need a fee recipient token account, which may as well be the payer's
+        let fee_recipient = if fee_mode == FeeMode::BpsToken {
+            payer.pubkey()
+        } else {
+            Pubkey::new_unique()
+        };
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                fee: Some(fee),
+                fee_recipient: Some(fee_recipient),
+                fee_mode: Some(fee_mode),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let mint = mint_keypair.pubkey();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        // create the fee recipient token account
+        let fee_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &merchant_result.3,
+                    &mint_keypair,
+                    merchant_result.4,
+                    &fee_token_keypair,
+                    &fee_recipient,
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint,
+            &mut merchant_result.2,
+        )
+        .await;
+        assert_eq!(fee_mode, merchant_data.fee_mode);
+        let fee_recipient_before = match merchant_result.2.get_account(fee_recipient).await {
+            Ok(Some(account)) => account.lamports,
+            _ => 0,
+        };
+        let mut instruction = express_checkout(
+            merchant_result.0,
+            merchant_result.3.pubkey(),
+            order_acc_keypair.pubkey(),
+            merchant_result.1,
+            seller_token,
+            buyer_token_keypair.pubkey(),
+            mint,
+            fee_recipient,
+            Pubkey::new_from_array(merchant_data.sponsor),
+            pda,
+            amount,
+            OrderId::try_new("fee mode").unwrap(),
+            String::from(""),
+            Option::None,
+        );
+        if fee_mode == FeeMode::BpsToken {
+            instruction = with_fee_token(instruction, fee_token_keypair.pubkey());
+        }
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        // the fee recipient also pays the transaction fee when it is the payer, and
+        // never receives anything when the fee rounds down to 0
+        let fee_recipient_lamports = if fee_mode == FeeMode::BpsToken {
+            0
+        } else {
+            let fee_recipient_after = match merchant_result.2.get_account(fee_recipient).await {
+                Ok(Some(account)) => account.lamports,
+                _ => 0,
+            };
+            fee_recipient_after - fee_recipient_before
+        };
+        (
+            fee_recipient_lamports,
+            get_token_account_data(&mut merchant_result.2, fee_token_keypair.pubkey())
+                .await
+                .amount,
+            get_token_account_data(&mut merchant_result.2, seller_token)
+                .await
+                .amount,
+        )
+    }
+
+    #[tokio::test]
+    /// test that the merchant fee is charged according to the merchant fee mode
+    async fn test_express_checkout_fee_modes() {
+        // a flat fee in lamports
+        assert_eq!(
+            (DEFAULT_FEE_IN_LAMPORTS, 0, 1000000),
+            run_fee_mode_test(FeeMode::FlatSol, DEFAULT_FEE_IN_LAMPORTS, 1000000).await
+        );
+        // 3% of the amount in lamports
+        assert_eq!(
+            (30000, 0, 1000000),
+            run_fee_mode_test(FeeMode::BpsSol, 300, 1000000).await
+        );
+        // 3% of the amount in the payment token, out of what the seller receives
+        assert_eq!(
+            (0, 30000, 970000),
+            run_fee_mode_test(FeeMode::BpsToken, 300, 1000000).await
+        );
+    }
+
+    #[tokio::test]
+    /// test that amounts below 100 pay the merchant fee like any other, and nothing more
+    async fn test_express_checkout_small_amount_fees() {
+        for amount in [1, 99, 100].iter() {
+            // the flat fee does not depend on the amount
+            assert_eq!(
+                (DEFAULT_FEE_IN_LAMPORTS, 0, *amount),
+                run_fee_mode_test(FeeMode::FlatSol, DEFAULT_FEE_IN_LAMPORTS, *amount).await
+            );
+            // 0.3% of these amounts rounds down to nothing
+            assert_eq!(
+                (0, 0, *amount),
+                run_fee_mode_test(FeeMode::BpsSol, 30, *amount).await
+            );
+            assert_eq!(
+                (0, 0, *amount),
+                run_fee_mode_test(FeeMode::BpsToken, 30, *amount).await
+            );
+        }
+        // and is charged once it rounds down to 1
+        assert_eq!(
+            (1, 0, 334),
+            run_fee_mode_test(FeeMode::BpsSol, 30, 334).await
+        );
+        assert_eq!(
+            (0, 1, 333),
+            run_fee_mode_test(FeeMode::BpsToken, 30, 334).await
+        );
+    }
+
+    #[tokio::test]
+    /// test that a missing fee recipient token account is created to receive the token fee
+    async fn test_express_checkout_new_fee_token() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let amount: u64 = 1000000;
+        let fee_recipient = Pubkey::new_unique();
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                fee: Some(300),
+                fee_recipient: Some(fee_recipient),
+                fee_mode: Some(FeeMode::BpsToken),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let mint = mint_keypair.pubkey();
+        let fee_token = get_associated_token_address(&fee_recipient, &mint);
+        let buyer_token_keypair =
+            create_token_account(amount * 2, &mint_keypair, &mut merchant_result).await;
+        for order_id in &["first", "second"] {
+            let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+                &merchant_result.0,
+                &merchant_result.1,
+                &mint,
+                &mut merchant_result.2,
+            )
+            .await;
+            let instruction = express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint,
+                fee_recipient,
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new(order_id).unwrap(),
+                String::from(""),
+                Option::None,
+            );
+            // the first checkout creates the token account, the second one uses it
+            let instruction = if *order_id == "first" {
+                with_new_fee_token(instruction, fee_recipient, mint)
+            } else {
+                with_fee_token(instruction, fee_token)
+            };
+            let mut transaction =
+                Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+            transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+            assert_matches!(
+                merchant_result.2.process_transaction(transaction).await,
+                Ok(())
+            );
+        }
+        // 3% of each payment went to the fee recipient token account
+        let fee_token_data = get_token_account_data(&mut merchant_result.2, fee_token).await;
+        assert_eq!(fee_recipient, fee_token_data.owner);
+        assert_eq!(mint, fee_token_data.mint);
+        assert_eq!(60000, fee_token_data.amount);
+    }
+
+    #[tokio::test]
+    /// test that a checkout using the wrong token program fails
+    async fn test_express_checkout_wrong_token_program() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut instruction = express_checkout(
+            merchant_result.0,
+            merchant_result.3.pubkey(),
+            order_acc_keypair.pubkey(),
+            merchant_result.1,
+            seller_token,
+            buyer_token_keypair.pubkey(),
+            mint_keypair.pubkey(),
+            Pubkey::from_str(PROGRAM_OWNER).unwrap(),
+            Pubkey::new_from_array(merchant_data.sponsor),
+            pda,
+            amount,
+            OrderId::try_new("1337").unwrap(),
+            String::from(""),
+            Option::None,
+        );
+        // replace the token program
+        instruction.accounts[9] = AccountMeta::new_readonly(Pubkey::new_unique(), false);
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+        );
+    }
+
+    #[tokio::test]
+    /// test that a checkout passing an account that is not a mint as the mint fails
+    async fn test_express_checkout_invalid_mint() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        // neither the order's own token account nor the buyer token account is a mint
+        for not_a_mint in vec![seller_token, buyer_token_keypair.pubkey()] {
+            let mut transaction = Transaction::new_with_payer(
+                &[express_checkout(
+                    merchant_result.0,
+                    merchant_result.3.pubkey(),
+                    order_acc_keypair.pubkey(),
+                    merchant_result.1,
+                    seller_token,
+                    buyer_token_keypair.pubkey(),
+                    not_a_mint,
+                    Pubkey::from_str(PROGRAM_OWNER).unwrap(),
+                    Pubkey::new_from_array(merchant_data.sponsor),
+                    pda,
+                    amount,
+                    OrderId::try_new("1337").unwrap(),
+                    String::from(""),
+                    Option::None,
+                )],
+                Some(&merchant_result.3.pubkey()),
+            );
+            transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+            assert_eq!(
+                merchant_result
+                    .2
+                    .process_transaction(transaction)
+                    .await
+                    .unwrap_err()
+                    .unwrap(),
+                TransactionError::InstructionError(
+                    0,
+                    InstructionError::Custom(PaymentProcessorError::InvalidMint as u32)
+                )
+            );
+        }
+    }
+
+    /// stands in for the Ed25519 program, which solana-program-test does not run, by
+    /// verifying the signature of a merchant_signature instruction
+    fn process_ed25519_instruction(
+        _program_id: &Pubkey,
+        _accounts: &[AccountInfo],
+        data: &[u8],
+    ) -> ProgramResult {
+        let signature_offset = ED25519_DATA_START + ED25519_PUBKEY_LEN;
+        let message_offset = signature_offset + ED25519_SIGNATURE_LEN;
+        let signature = Signature::new(&data[signature_offset..message_offset]);
+        if !signature.verify(
+            &data[ED25519_DATA_START..signature_offset],
+            &data[message_offset..],
+        ) {
+            return Err(ProgramError::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    async fn run_merchant_signature_test(
+        signed_amount: u64,
+        tamper_signature: bool,
+    ) -> Result<Option<String>, TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        program_test.add_program(
+            "ed25519_program",
+            Pubkey::from_str(ED25519_PROGRAM_ID).unwrap(),
+            processor!(process_ed25519_instruction),
+        );
+        let (banks_client, payer, recent_blockhash) = program_test.start().await;
+        let amount: u64 = 1000000;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant::default(),
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let mint = mint_keypair.pubkey();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint,
+            &mut merchant_result.2,
+        )
+        .await;
+        // the merchant owner signs the order off-chain
+        let order_id = String::from("signed");
+        let mut signature = [0; 64];
+        signature.copy_from_slice(
+            merchant_result
+                .3
+                .sign_message(&get_order_signature_message(
+                    &order_id,
+                    signed_amount,
+                    &mint,
+                ))
+                .as_ref(),
+        );
+        if tamper_signature {
+            signature[0] ^= 1;
+        }
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                merchant_signature(
+                    merchant_result.3.pubkey(),
+                    signature,
+                    &OrderId::try_new(&order_id).unwrap(),
+                    signed_amount,
+                    &mint,
+                ),
+                with_merchant_signature(express_checkout(
+                    merchant_result.0,
+                    merchant_result.3.pubkey(),
+                    order_acc_keypair.pubkey(),
+                    merchant_result.1,
+                    seller_token,
+                    buyer_token_keypair.pubkey(),
+                    mint,
+                    Pubkey::new_from_array(merchant_data.fee_recipient),
+                    Pubkey::new_from_array(merchant_data.sponsor),
+                    pda,
+                    amount,
+                    OrderId::try_new(&order_id).unwrap(),
+                    String::from(""),
+                    Option::None,
+                )),
+            ],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await?;
+        let order_data =
+            get_account_data::<OrderAccount>(&mut merchant_result.2, order_acc_keypair.pubkey())
+                .await;
+        Ok(order_data.secret)
+    }
+
+    #[tokio::test]
+    /// test that merchant signed orders need the merchant's signature of the order
+    async fn test_express_checkout_merchant_signature() {
+        // signed orders need no secret
+        assert_eq!(
+            Option::None,
+            run_merchant_signature_test(1000000, false).await.unwrap()
+        );
+        // the buyer can not pay less than the merchant signed for
+        assert_eq!(
+            run_merchant_signature_test(2000000, false)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                1,
+                InstructionError::Custom(PaymentProcessorError::InvalidOrderSignature as u32)
+            )
+        );
+        // nor forge the signature
+        assert_eq!(
+            run_merchant_signature_test(1000000, true)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidArgument)
+        );
+    }
+
+    #[tokio::test]
+    /// test that the compact encoding decodes to the same ExpressCheckout in fewer bytes
+    async fn test_compact_express_checkout_encoding() {
+        let mut order_id = [0; COMPACT_ID_LEN];
+        order_id[0] = 0x12;
+        let order_id_string = String::from("12000000000000000000000000000000");
+        let keys: Vec<Pubkey> = (0..10).map(|_| Pubkey::new_unique()).collect();
+        let compact = compact_express_checkout(
+            keys[0],
+            keys[1],
+            keys[2],
+            keys[3],
+            keys[4],
+            keys[5],
+            keys[6],
+            keys[7],
+            keys[8],
+            keys[9],
+            1337,
+            order_id,
+            [0; COMPACT_ID_LEN],
+        );
+        let standard = express_checkout(
+            keys[0],
+            keys[1],
+            keys[2],
+            keys[3],
+            keys[4],
+            keys[5],
+            keys[6],
+            keys[7],
+            keys[8],
+            keys[9],
+            1337,
+            OrderId::try_new(&order_id_string).unwrap(),
+            String::from(""),
+            Option::None,
+        );
+        assert_eq!(standard.accounts, compact.accounts);
+        assert_eq!(
+            InstructionTag::CompactExpressCheckout as u8,
+            compact.data[0]
+        );
+        assert!(compact.data.len() < standard.data.len());
+        let decoded = PaymentProcessorInstruction::try_from_slice(&compact.data).unwrap();
+        assert_eq!(
+            PaymentProcessorInstruction::try_from_slice(&standard.data).unwrap(),
+            decoded
+        );
+        assert_eq!(InstructionTag::ExpressCheckout, decoded.tag());
+        // the with_* functions switch to the standard encoding
+        assert_eq!(
+            InstructionTag::ExpressCheckout as u8,
+            with_revoke_delegate(compact).data[0]
+        );
+    }
+
+    async fn run_compact_express_checkout_test(compact: bool) -> OrderAccount {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let order_id = [0xab; COMPACT_ID_LEN];
+        let mut secret = [0; COMPACT_ID_LEN];
+        secret[COMPACT_ID_LEN - 1] = 7;
+        let instruction = if compact {
+            compact_express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                order_id,
+                secret,
+            )
+        } else {
+            express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new(&get_compact_id_string(&order_id)).unwrap(),
+                get_compact_id_string(&secret),
+                Option::None,
+            )
+        };
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        get_account_data::<OrderAccount>(&mut merchant_result.2, order_acc_keypair.pubkey()).await
+    }
+
+    #[tokio::test]
+    /// test that a compact express checkout produces the same order as the standard one
+    async fn test_compact_express_checkout() {
+        let compact_order = run_compact_express_checkout_test(true).await;
+        let standard_order = run_compact_express_checkout_test(false).await;
+        assert_eq!("ab".repeat(COMPACT_ID_LEN), compact_order.order_id);
+        assert_eq!(
+            Some(String::from("00000000000000000000000000000007")),
+            compact_order.secret
+        );
+        for order in &[&compact_order, &standard_order] {
+            assert_eq!(OrderStatus::Paid as u8, order.status);
+            assert_eq!(1000000, order.paid_amount);
+        }
+        assert_eq!(standard_order.order_id, compact_order.order_id);
+        assert_eq!(standard_order.secret, compact_order.secret);
+        assert_eq!(
+            standard_order.expected_amount,
+            compact_order.expected_amount
+        );
+        assert_eq!(standard_order.data, compact_order.data);
+        assert_eq!(standard_order.payer, compact_order.payer);
+    }
+
+    /// checkout with the given secret, requiring a printable secret if strict
+    async fn run_strict_secret_test(secret: &str, strict: bool) -> Result<(), TransportError> {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut instruction = express_checkout(
+            merchant_result.0,
+            merchant_result.3.pubkey(),
+            order_acc_keypair.pubkey(),
+            merchant_result.1,
+            seller_token,
+            buyer_token_keypair.pubkey(),
+            mint_keypair.pubkey(),
+            Pubkey::new_from_array(merchant_data.fee_recipient),
+            Pubkey::new_from_array(merchant_data.sponsor),
+            pda,
+            amount,
+            OrderId::try_new("1337").unwrap(),
+            String::from(secret),
+            Option::None,
+        );
+        if strict {
+            instruction = with_strict_secret(instruction);
+        }
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        let result = merchant_result.2.process_transaction(transaction).await;
+        if result.is_ok() {
+            let order_data = get_account_data::<OrderAccount>(
+                &mut merchant_result.2,
+                order_acc_keypair.pubkey(),
+            )
+            .await;
+            assert_eq!(Some(String::from(secret)), order_data.secret);
+        }
+        result
+    }
+
+    #[tokio::test]
+    /// test that strict checkouts reject secrets with control characters
+    async fn test_express_checkout_strict_secret() {
+        assert_eq!(
+            run_strict_secret_test("hunter2\n", true)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InvalidSecret as u32)
+            )
+        );
+        assert_matches!(run_strict_secret_test("hunter2", true).await, Ok(()));
+        // without strict secrets any secret is stored as it is
+        assert_matches!(run_strict_secret_test("hunter2\n", false).await, Ok(()));
+    }
+
+    async fn run_amount_limits_test(amount: u64) -> Result<(), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                amount_limits: Some((1000, 5000)),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        assert_eq!(1000, merchant_data.min_amount);
+        assert_eq!(5000, merchant_data.max_amount);
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new("1337").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    /// test that checkouts are for amounts within the merchant limits
+    async fn test_express_checkout_amount_limits() {
+        assert_eq!(
+            run_amount_limits_test(999).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::AmountTooSmall as u32)
+            )
+        );
+        assert_eq!(
+            run_amount_limits_test(5001).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::AmountTooLarge as u32)
+            )
+        );
+        assert_matches!(run_amount_limits_test(1000).await, Ok(()));
+        assert_matches!(run_amount_limits_test(5000).await, Ok(()));
+    }
+
+    /// checkout the amount and return the fee (in SOL lamports) the program owner got
+    async fn run_fee_free_band_test(amount: u64) -> u64 {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                fee_free_band: Some((1000, 5000)),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let merchant_data =
+            get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant_result.1).await;
+        assert_eq!(1000, merchant_data.fee_free_below);
+        assert_eq!(5000, merchant_data.fee_free_above);
+        let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let program_owner_before = match merchant_result.2.get_account(program_owner).await {
+            Ok(Some(account)) => account.lamports,
+            _ => 0,
+        };
+        create_order_express_checkout(
+            amount,
+            &String::from("1337"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &Keypair::new(),
+        )
+        .await;
+        let program_owner_after = match merchant_result.2.get_account(program_owner).await {
+            Ok(Some(account)) => account.lamports,
+            _ => 0,
+        };
+        program_owner_after - program_owner_before
+    }
+
+    #[tokio::test]
+    /// test that checkouts in the merchant's fee free band pay no fee
+    async fn test_express_checkout_fee_free_band() {
+        assert_eq!(0, run_fee_free_band_test(999).await);
+        assert_eq!(0, run_fee_free_band_test(5001).await);
+        assert_eq!(DEFAULT_FEE_IN_LAMPORTS, run_fee_free_band_test(1000).await);
+        assert_eq!(DEFAULT_FEE_IN_LAMPORTS, run_fee_free_band_test(5000).await);
+    }
+
+    #[tokio::test]
+    /// test that checkouts only create seller token accounts owned by the program PDA
+    async fn test_express_checkout_wrong_pda() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, _pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                Pubkey::new_unique(),
+                amount,
+                OrderId::try_new("1337").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidSeeds)
+        );
+        assert!(merchant_result
+            .2
+            .get_account(seller_token)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    /// test that the order account cannot also be the seller token account
+    async fn test_express_checkout_order_is_seller_token() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, _seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut transaction = Transaction::new_with_payer(
+            &[express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                order_acc_keypair.pubkey(),
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new("1337").unwrap(),
+                String::from(""),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+        );
+        assert!(merchant_result
+            .2
+            .get_account(order_acc_keypair.pubkey())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    /// test that a checkout paying an account that also pays or is paid fails
+    async fn test_express_checkout_duplicate_accounts() {
+        let amount: u64 = 1000000;
+        let shared_pk = Pubkey::new_unique();
+        // the buyer token account as the seller token account, then a merchant
+        // whose sponsor is also its fee recipient
+        for same_tokens in &[true, false] {
+            let mut merchant_result = if *same_tokens {
+                create_merchant_account(Option::None, Option::None, Option::None, Option::None)
+                    .await
+            } else {
+                create_merchant_account_with_fee_recipient(
+                    Option::None,
+                    Option::None,
+                    Some(&shared_pk),
+                    Option::None,
+                    Some(&shared_pk),
+                )
+                .await
+            };
+            let mint_keypair = Keypair::new();
+            let buyer_token_keypair =
+                create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+            let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+                &merchant_result.0,
+                &merchant_result.1,
+                &mint_keypair.pubkey(),
+                &mut merchant_result.2,
+            )
+            .await;
+            let mut transaction = Transaction::new_with_payer(
+                &[express_checkout(
+                    merchant_result.0,
+                    merchant_result.3.pubkey(),
+                    order_acc_keypair.pubkey(),
+                    merchant_result.1,
+                    if *same_tokens {
+                        buyer_token_keypair.pubkey()
+                    } else {
+                        seller_token
+                    },
+                    buyer_token_keypair.pubkey(),
+                    mint_keypair.pubkey(),
+                    Pubkey::new_from_array(merchant_data.fee_recipient),
+                    Pubkey::new_from_array(merchant_data.sponsor),
+                    pda,
+                    amount,
+                    OrderId::try_new("1337").unwrap(),
+                    String::from(""),
+                    Option::None,
+                )],
+                Some(&merchant_result.3.pubkey()),
+            );
+            transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+            assert_eq!(
+                merchant_result
+                    .2
+                    .process_transaction(transaction)
+                    .await
+                    .unwrap_err()
+                    .unwrap(),
+                TransactionError::InstructionError(
+                    0,
+                    InstructionError::Custom(PaymentProcessorError::DuplicateAccount as u32)
+                )
+            );
+            let buyer_token_data =
+                get_token_account_data(&mut merchant_result.2, buyer_token_keypair.pubkey()).await;
+            assert_eq!(amount + 2000000, buyer_token_data.amount);
+        }
+    }
+
+    #[tokio::test]
+    /// test that resubmitting a checkout does not charge the buyer twice
+    async fn test_express_checkout_resubmitted() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        // enough for a second payment, should the retry charge the buyer again
+        let buyer_token_keypair =
+            create_token_account(amount * 2, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let (program_id, merchant, mut banks_client, payer, recent_blockhash) = merchant_result;
+        // the client tag tells the attempts apart, identical transactions are only processed once
+        let checkout_transaction = |amount: u64, client_tag: &str| {
+            let mut transaction = Transaction::new_with_payer(
+                &[with_client_tag(
+                    express_checkout(
+                        program_id,
+                        payer.pubkey(),
+                        order_acc_keypair.pubkey(),
+                        merchant,
+                        seller_token,
+                        buyer_token_keypair.pubkey(),
+                        mint_keypair.pubkey(),
+                        Pubkey::new_from_array(merchant_data.fee_recipient),
+                        Pubkey::new_from_array(merchant_data.sponsor),
+                        pda,
+                        amount,
+                        OrderId::try_new("1337").unwrap(),
+                        String::from(""),
+                        Option::None,
+                    ),
+                    client_tag,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer, &order_acc_keypair], recent_blockhash);
+            transaction
+        };
+        assert_matches!(
+            banks_client
+                .process_transaction(checkout_transaction(amount, "first"))
+                .await,
+            Ok(())
+        );
+        // the same checkout again is a no-op
+        assert_matches!(
+            banks_client
+                .process_transaction(checkout_transaction(amount, "retry"))
+                .await,
+            Ok(())
+        );
+        assert_eq!(
+            amount,
+            get_token_account_data(&mut banks_client, buyer_token_keypair.pubkey())
+                .await
+                .amount
+        );
+        assert_eq!(
+            amount,
+            get_token_account_data(&mut banks_client, seller_token)
+                .await
+                .amount
+        );
+        let order_data =
+            get_account_data::<OrderAccount>(&mut banks_client, order_acc_keypair.pubkey()).await;
+        assert_eq!(amount, order_data.paid_amount);
+        // a different checkout for the same order account conflicts with it
+        assert_eq!(
+            banks_client
+                .process_transaction(checkout_transaction(amount + 1, "retry"))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::OrderConflict as u32)
+            )
+        );
+    }
+
+    #[tokio::test]
+    /// test that checkout uses a seller token account that already exists, as long as it
+    /// is the one checkout would have created
+    async fn test_express_checkout_existing_seller_token() {
+        let amount: u64 = 1000000;
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let mint_keypair = Keypair::new();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        // the seller token accounts left behind by earlier attempts, one of them owned by
+        // someone other than the PDA
+        let order_keypair = Keypair::new();
+        let other_order_keypair = Keypair::new();
+        let mut seller_tokens = vec![];
+        for (order, owner) in &[
+            (order_keypair.pubkey(), pda),
+            (other_order_keypair.pubkey(), Pubkey::new_unique()),
+        ] {
+            let (seller_token, _bump_seed) = Pubkey::find_program_address(
+                &[
+                    &order.to_bytes(),
+                    &spl_token::id().to_bytes(),
+                    &mint_keypair.pubkey().to_bytes(),
+                ],
+                &program_id,
+            );
+            let mut data = vec![0; TokenAccount::LEN];
+            TokenAccount::pack(
+                TokenAccount {
+                    mint: mint_keypair.pubkey(),
+                    owner: *owner,
+                    amount: 0,
+                    delegate: COption::None,
+                    state: AccountState::Initialized,
+                    is_native: COption::None,
+                    delegated_amount: 0,
+                    close_authority: COption::None,
+                },
+                &mut data,
+            )
+            .unwrap();
+            program_test.add_account(
+                seller_token,
+                solana_sdk::account::Account {
+                    lamports: Rent::default().minimum_balance(TokenAccount::LEN),
+                    data,
+                    owner: spl_token::id(),
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+            seller_tokens.push(seller_token);
+        }
+        let (banks_client, payer, recent_blockhash) = program_test.start().await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant::default(),
+        )
+        .await;
+        let buyer_token_keypair =
+            create_token_account(amount * 2, &mint_keypair, &mut merchant_result).await;
+        let merchant_data =
+            get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant_result.1).await;
+        let (program_id, merchant, mut banks_client, payer, recent_blockhash) = merchant_result;
+        let checkout_transaction = |order_keypair: &Keypair, seller_token: Pubkey| {
+            let mut transaction = Transaction::new_with_payer(
+                &[express_checkout(
+                    program_id,
+                    payer.pubkey(),
+                    order_keypair.pubkey(),
+                    merchant,
+                    seller_token,
+                    buyer_token_keypair.pubkey(),
+                    mint_keypair.pubkey(),
+                    Pubkey::new_from_array(merchant_data.fee_recipient),
+                    Pubkey::new_from_array(merchant_data.sponsor),
+                    pda,
+                    amount,
+                    OrderId::try_new("1337").unwrap(),
+                    String::from(""),
+                    Option::None,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer, order_keypair], recent_blockhash);
+            transaction
+        };
+        // a token account with another owner is not used
+        assert_eq!(
+            banks_client
+                .process_transaction(checkout_transaction(&other_order_keypair, seller_tokens[1]))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+        );
+        assert_matches!(
+            banks_client
+                .process_transaction(checkout_transaction(&order_keypair, seller_tokens[0]))
+                .await,
+            Ok(())
+        );
+        let order_data =
+            get_account_data::<OrderAccount>(&mut banks_client, order_keypair.pubkey()).await;
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+        assert_eq!(seller_tokens[0].to_bytes(), order_data.token);
+        assert_eq!(
+            amount,
+            get_token_account_data(&mut banks_client, seller_tokens[0])
+                .await
+                .amount
+        );
+    }
+
+    #[tokio::test]
+    /// test that the merchant can pay the checkout rent for a buyer without SOL
+    async fn test_express_checkout_rent_payer() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amount, &mint_keypair, &mut merchant_result).await;
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let (program_id, merchant, mut banks_client, payer, recent_blockhash) = merchant_result;
+        // hand the buyer token account to a buyer who has no SOL at all
+        let buyer = Keypair::new();
+        let mut transaction = Transaction::new_with_payer(
+            &[set_authority(
                 &spl_token::id(),
+                &buyer_token_keypair.pubkey(),
+                Some(&buyer.pubkey()),
+                AuthorityType::AccountOwner,
+                &payer.pubkey(),
+                &[&payer.pubkey()],
+            )
+            .unwrap()],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+        let checkout_transaction = |rent_payer: &Keypair| {
+            let mut transaction = Transaction::new_with_payer(
+                &[with_rent_payer(
+                    express_checkout(
+                        program_id,
+                        buyer.pubkey(),
+                        order_acc_keypair.pubkey(),
+                        merchant,
+                        seller_token,
+                        buyer_token_keypair.pubkey(),
+                        mint_keypair.pubkey(),
+                        Pubkey::new_from_array(merchant_data.fee_recipient),
+                        Pubkey::new_from_array(merchant_data.sponsor),
+                        pda,
+                        amount,
+                        OrderId::try_new("1337").unwrap(),
+                        String::from(""),
+                        Option::None,
+                    ),
+                    rent_payer.pubkey(),
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(
+                &[&payer, &buyer, &order_acc_keypair, rent_payer],
+                recent_blockhash,
+            );
+            transaction
+        };
+        // only the merchant owner or operator can pay the rent
+        assert_eq!(
+            banks_client
+                .process_transaction(checkout_transaction(&Keypair::new()))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongMerchant as u32)
+            )
+        );
+        assert_matches!(
+            banks_client
+                .process_transaction(checkout_transaction(&payer))
+                .await,
+            Ok(())
+        );
+        let order_data =
+            get_account_data::<OrderAccount>(&mut banks_client, order_acc_keypair.pubkey()).await;
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+        assert_eq!(buyer.pubkey().to_bytes(), order_data.payer);
+        assert_eq!(amount, order_data.paid_amount);
+        assert_eq!(
+            0,
+            get_token_account_data(&mut banks_client, buyer_token_keypair.pubkey())
+                .await
+                .amount
+        );
+        // the buyer still has no SOL
+        assert_eq!(0, banks_client.get_balance(buyer.pubkey()).await.unwrap());
+    }
+
+    #[tokio::test]
+    /// test that a withdrawal using the wrong token program fails
+    async fn test_withdraw_wrong_token_program() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, seller_token) = create_order_express_checkout(
+            amount,
+            &String::from("1337"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let merchant_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &merchant_result.3,
+                    &mint_keypair,
+                    merchant_result.4,
+                    &merchant_token_keypair,
+                    &merchant_result.3.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &merchant_result.0);
+        let mut instruction = withdraw(
+            merchant_result.0,
+            merchant_result.3.pubkey(),
+            order_acc_pubkey,
+            merchant_result.1,
+            seller_token,
+            merchant_token_keypair.pubkey(),
+            Pubkey::from_str(PROGRAM_OWNER).unwrap(),
+            pda,
+            mint_keypair.pubkey(),
+            Option::None,
+            false,
+        );
+        // replace the token program
+        instruction.accounts[7] = AccountMeta::new_readonly(Pubkey::new_unique(), false);
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+        );
+    }
+
+    #[tokio::test]
+    /// test that a withdrawal cannot pay the order accounts to themselves
+    async fn test_withdraw_duplicate_accounts() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, seller_token) = create_order_express_checkout(
+            amount,
+            &String::from("aliased"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let merchant_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &merchant_result.3,
+                    &mint_keypair,
+                    merchant_result.4,
+                    &merchant_token_keypair,
+                    &merchant_result.3.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &merchant_result.0);
+        // the order token account as the merchant token account, then as the
+        // account receiving the order rent
+        for (merchant_token, account_to_receive_sol_refund) in &[
+            (seller_token, merchant_result.3.pubkey()),
+            (merchant_token_keypair.pubkey(), seller_token),
+        ] {
+            let mut transaction = Transaction::new_with_payer(
+                &[withdraw(
+                    merchant_result.0,
+                    merchant_result.3.pubkey(),
+                    order_acc_pubkey,
+                    merchant_result.1,
+                    seller_token,
+                    *merchant_token,
+                    *account_to_receive_sol_refund,
+                    pda,
+                    mint_keypair.pubkey(),
+                    Option::None,
+                    true,
+                )],
+                Some(&merchant_result.3.pubkey()),
+            );
+            transaction.sign(&[&merchant_result.3], merchant_result.4);
+            assert_eq!(
+                merchant_result
+                    .2
+                    .process_transaction(transaction)
+                    .await
+                    .unwrap_err()
+                    .unwrap(),
+                TransactionError::InstructionError(
+                    0,
+                    InstructionError::Custom(PaymentProcessorError::DuplicateAccount as u32)
+                )
+            );
+        }
+        let order_data =
+            get_account_data::<OrderAccount>(&mut merchant_result.2, order_acc_pubkey).await;
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+    }
+
+    #[tokio::test]
+    /// test that an order secret can be revealed only once
+    async fn test_reveal_secret() {
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, _seller_token) = create_order_express_checkout(
+            1000000,
+            &String::from("voucher"),
+            &String::from("redeem-me"),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let stranger = Keypair::new();
+        // the merchant operator tries again, identical transactions are only processed once
+        let operator = Keypair::new();
+        let mut transaction = Transaction::new_with_payer(
+            &[set_operator(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                merchant_result.1,
+                operator.pubkey(),
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        // someone other than the merchant and the payer, then the payer and the operator
+        let expected_results = vec![
+            (&stranger, Some(PaymentProcessorError::WrongPayer)),
+            (&merchant_result.3, Option::None),
+            (&operator, Some(PaymentProcessorError::NoSecret)),
+        ];
+        for (signer, expected_error) in expected_results {
+            let mut transaction = Transaction::new_with_payer(
+                &[reveal_secret(
+                    merchant_result.0,
+                    signer.pubkey(),
+                    order_acc_pubkey,
+                    merchant_result.1,
+                )],
+                Some(&merchant_result.3.pubkey()),
+            );
+            transaction.sign(&[&merchant_result.3, signer], merchant_result.4);
+            let result = merchant_result.2.process_transaction(transaction).await;
+            match expected_error {
+                None => assert_matches!(result, Ok(())),
+                Some(error) => assert_eq!(
+                    result.unwrap_err().unwrap(),
+                    TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+                ),
+            }
+        }
+        // the secret was cleared without resizing the order
+        let order_data =
+            get_account_data::<OrderAccount>(&mut merchant_result.2, order_acc_pubkey).await;
+        assert_eq!(Some("\0".repeat(9)), order_data.secret);
+        assert_eq!(String::from("voucher"), order_data.order_id);
+    }
+
+    async fn create_coupon_account(
+        merchant_result: &mut MerchantResult,
+        code: &str,
+        max_redemptions: u64,
+        expiry: i64,
+    ) -> Pubkey {
+        let mut transaction = Transaction::new_with_payer(
+            &[create_coupon(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                merchant_result.1,
+                String::from(code),
+                1000,
+                max_redemptions,
+                expiry,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let (coupon, _bump_seed) = get_coupon_address(&merchant_result.1, code, &merchant_result.0);
+        coupon
+    }
+
+    #[tokio::test]
+    async fn test_create_coupon() {
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let coupon = create_coupon_account(&mut merchant_result, "SAVE10", 2, 0).await;
+        let coupon_data = get_account_data::<CouponAccount>(&mut merchant_result.2, coupon).await;
+        assert_eq!(Discriminator::Coupon as u8, coupon_data.discriminator);
+        assert_eq!(merchant_result.1.to_bytes(), coupon_data.merchant);
+        assert_eq!(1000, coupon_data.bps);
+        assert_eq!(2, coupon_data.max_redemptions);
+        assert_eq!(0, coupon_data.used_count);
+        assert_eq!(0, coupon_data.expiry);
+        assert_eq!(String::from("SAVE10"), coupon_data.code);
+    }
+
+    #[tokio::test]
+    /// test that a coupon redeemed with checkouts can only be redeemed max_redemptions times,
+    /// and only with merchant signed orders
+    async fn test_express_checkout_with_coupon() {
+        let price: u64 = 1000000;
+        let amount = get_coupon_price(price, 1000);
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let coupon = create_coupon_account(&mut merchant_result, "TWICE", 2, 0).await;
+        let mint_keypair = Keypair::new();
+        let mint = mint_keypair.pubkey();
+        let buyer_token_keypair =
+            create_token_account(amount * 3, &mint_keypair, &mut merchant_result).await;
+        // (merchant signed, expected error)
+        let expected_results = vec![
+            (
+                false,
+                Some((0, PaymentProcessorError::InvalidOrderSignature)),
+            ),
+            (true, Option::None),
+            (true, Option::None),
+            (true, Some((1, PaymentProcessorError::CouponExhausted))),
+        ];
+        for (index, (signed, expected_error)) in expected_results.into_iter().enumerate() {
+            let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+                &merchant_result.0,
+                &merchant_result.1,
+                &mint,
+                &mut merchant_result.2,
+            )
+            .await;
+            let order_id = format!("coupon-{}", index);
+            let checkout = express_checkout(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint,
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                amount,
+                OrderId::try_new(&order_id).unwrap(),
+                String::from(""),
+                Option::None,
+            );
+            let instructions = if signed {
+                // the merchant signs for the discounted amount
+                let mut signature = [0; 64];
+                signature.copy_from_slice(
+                    merchant_result
+                        .3
+                        .sign_message(&get_order_signature_message(&order_id, amount, &mint))
+                        .as_ref(),
+                );
+                vec![
+                    merchant_signature(
+                        merchant_result.3.pubkey(),
+                        signature,
+                        &OrderId::try_new(&order_id).unwrap(),
+                        amount,
+                        &mint,
+                    ),
+                    with_coupon(with_merchant_signature(checkout), coupon),
+                ]
+            } else {
+                vec![with_coupon(checkout, coupon)]
+            };
+            let mut transaction =
+                Transaction::new_with_payer(&instructions, Some(&merchant_result.3.pubkey()));
+            transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+            let result = merchant_result.2.process_transaction(transaction).await;
+            match expected_error {
+                None => assert_matches!(result, Ok(())),
+                Some((instruction_index, error)) => assert_eq!(
+                    result.unwrap_err().unwrap(),
+                    TransactionError::InstructionError(
+                        instruction_index,
+                        InstructionError::Custom(error as u32)
+                    )
+                ),
+            }
+        }
+        let coupon_data = get_account_data::<CouponAccount>(&mut merchant_result.2, coupon).await;
+        assert_eq!(2, coupon_data.used_count);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_coupon() {
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        create_coupon_account(&mut merchant_result, "INSTORE", 2, 0).await;
+        create_coupon_account(&mut merchant_result, "EXPIRED", 2, 1).await;
+        let stranger = Keypair::new();
+        let expected_results = vec![
+            (
+                &stranger,
+                "INSTORE",
+                Some(PaymentProcessorError::WrongMerchant),
+            ),
+            (&merchant_result.3, "INSTORE", Option::None),
+            (
+                &merchant_result.3,
+                "EXPIRED",
+                Some(PaymentProcessorError::CouponExpired),
+            ),
+        ];
+        for (signer, code, expected_error) in expected_results {
+            let mut transaction = Transaction::new_with_payer(
+                &[redeem_coupon(
+                    merchant_result.0,
+                    signer.pubkey(),
+                    merchant_result.1,
+                    code,
+                )],
+                Some(&merchant_result.3.pubkey()),
+            );
+            transaction.sign(&[&merchant_result.3, signer], merchant_result.4);
+            let result = merchant_result.2.process_transaction(transaction).await;
+            match expected_error {
+                None => assert_matches!(result, Ok(())),
+                Some(error) => assert_eq!(
+                    result.unwrap_err().unwrap(),
+                    TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+                ),
+            }
+        }
+        let (coupon, _bump_seed) =
+            get_coupon_address(&merchant_result.1, "INSTORE", &merchant_result.0);
+        let coupon_data = get_account_data::<CouponAccount>(&mut merchant_result.2, coupon).await;
+        assert_eq!(1, coupon_data.used_count);
+    }
+
+    #[tokio::test]
+    /// test that checkouts append their payments to the merchant ledger, in order
+    async fn test_express_checkout_with_ledger() {
+        let amounts: Vec<u64> = vec![1000000, 2000000, 3000000];
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mut transaction = Transaction::new_with_payer(
+            &[create_ledger(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                merchant_result.1,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let (ledger, _bump_seed) = get_ledger_address(&merchant_result.1, &merchant_result.0);
+        let mint_keypair = Keypair::new();
+        let buyer_token_keypair =
+            create_token_account(amounts.iter().sum(), &mint_keypair, &mut merchant_result).await;
+        let mut orders = vec![];
+        for (index, amount) in amounts.iter().enumerate() {
+            let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+                &merchant_result.0,
+                &merchant_result.1,
+                &mint_keypair.pubkey(),
+                &mut merchant_result.2,
+            )
+            .await;
+            let mut transaction = Transaction::new_with_payer(
+                &[with_ledger(
+                    express_checkout(
+                        merchant_result.0,
+                        merchant_result.3.pubkey(),
+                        order_acc_keypair.pubkey(),
+                        merchant_result.1,
+                        seller_token,
+                        buyer_token_keypair.pubkey(),
+                        mint_keypair.pubkey(),
+                        Pubkey::new_from_array(merchant_data.fee_recipient),
+                        Pubkey::new_from_array(merchant_data.sponsor),
+                        pda,
+                        *amount,
+                        OrderId::try_new(&format!("ledger-{}", index)).unwrap(),
+                        String::from(""),
+                        Option::None,
+                    ),
+                    ledger,
+                )],
+                Some(&merchant_result.3.pubkey()),
+            );
+            transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+            assert_matches!(
+                merchant_result.2.process_transaction(transaction).await,
+                Ok(())
+            );
+            orders.push(order_acc_keypair.pubkey().to_bytes());
+        }
+        let ledger_data = get_account_data::<LedgerAccount>(&mut merchant_result.2, ledger).await;
+        assert_eq!(Discriminator::Ledger as u8, ledger_data.discriminator);
+        assert_eq!(merchant_result.1.to_bytes(), ledger_data.merchant);
+        assert_eq!(3, ledger_data.count);
+        // the last 2 payments, oldest first
+        let ledger_account = merchant_result
+            .2
+            .get_account(ledger)
+            .await
+            .unwrap()
+            .unwrap();
+        let entries = decode_ledger(&ledger_account.data, 2).unwrap();
+        assert_eq!(2, entries.len());
+        for (entry, (amount, order)) in entries
+            .iter()
+            .zip(amounts[1..].iter().zip(orders[1..].iter()))
+        {
+            assert_eq!(*amount, entry.amount);
+            assert_eq!(*order, entry.order);
+        }
+        assert!(entries[0].timestamp <= entries[1].timestamp);
+        // asking for more than there are returns them all
+        assert_eq!(3, decode_ledger(&ledger_account.data, 10).unwrap().len());
+    }
+
+    #[tokio::test]
+    /// test that withdrawing with a mint other than the order mint fails
+    async fn test_withdraw_wrong_mint() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, seller_token) = create_order_express_checkout(
+            amount,
+            &String::from("decimals"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let merchant_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &merchant_result.3,
+                    &mint_keypair,
+                    merchant_result.4,
+                    &merchant_token_keypair,
+                    &merchant_result.3.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        // a mint with different decimals
+        let other_mint_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_mint_transaction_with_decimals(
+                    &merchant_result.3,
+                    &other_mint_keypair,
+                    &merchant_result.3,
+                    merchant_result.4,
+                    6,
+                ))
+                .await,
+            Ok(())
+        );
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &merchant_result.0);
+        let mut transaction = Transaction::new_with_payer(
+            &[withdraw(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_pubkey,
+                merchant_result.1,
+                seller_token,
+                merchant_token_keypair.pubkey(),
+                Pubkey::from_str(PROGRAM_OWNER).unwrap(),
+                pda,
+                other_mint_keypair.pubkey(),
+                Option::None,
+                false,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongMint as u32)
+            )
+        );
+    }
+
+    async fn run_order_token_account_refund_tests(
+        order_payment_token_acc: &Option<solana_sdk::account::Account>,
+        account_to_receive_sol_refund_before: &Option<solana_sdk::account::Account>,
+        account_to_receive_sol_refund_after: &Option<solana_sdk::account::Account>,
+        previous_order_account: &Option<solana_sdk::account::Account>,
+    ) {
+        // order token account is closed
+        assert!(order_payment_token_acc.is_none());
+        let order_account_rent = match previous_order_account {
+            None => 0,
+            Some(account) => account.lamports,
+        };
+        match account_to_receive_sol_refund_before {
+            None => panic!("Oo"),
+            Some(account_before) => match account_to_receive_sol_refund_after {
+                None => panic!("Oo"),
+                Some(account_after) => {
+                    // the before balance has increased by the rent amount of both token and order account
+                    assert_eq!(
+                        account_before.lamports,
+                        account_after.lamports
+                            - (Rent::default().minimum_balance(TokenAccount::LEN)
+                                + order_account_rent)
+                    );
+                }
+            },
+        };
+    }
+
+    async fn withdraw_helper(
+        amount: u64,
+        close_order_account: bool,
+    ) -> (
+        BanksClient,
+        Option<solana_sdk::account::Account>,
+        Pubkey,
+        Pubkey,
+        Option<solana_sdk::account::Account>,
+        Option<solana_sdk::account::Account>,
+    ) {
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let merchant_token_keypair = Keypair::new();
+        let order_id = String::from("PD17CUSZ75");
+        let secret = String::from("i love oov");
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
+            amount,
+            &order_id,
+            &secret,
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let program_id = merchant_result.0;
+        let merchant_account_pubkey = merchant_result.1;
+        let mut banks_client = merchant_result.2;
+        let payer = merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+
+        // create and initialize merchant token account
+        assert_matches!(
+            banks_client
+                .process_transaction(create_token_account_transaction(
+                    &payer,
+                    &mint_keypair,
+                    recent_blockhash,
+                    &merchant_token_keypair,
+                    &payer.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (order_payment_token_acc_pubkey, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order_acc_pubkey.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint_keypair.pubkey().to_bytes(),
+            ],
+            &program_id,
+        );
+
+        let account_to_receive_sol_refund_pubkey = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let account_to_receive_sol_refund_before = banks_client
+            .get_account(account_to_receive_sol_refund_pubkey)
+            .await
+            .unwrap();
+
+        let previous_order_account = banks_client.get_account(order_acc_pubkey).await;
+        let previous_order_account = match previous_order_account {
+            Err(error) => panic!("Problem: {:?}", error),
+            Ok(value) => value,
+        };
+
+        // call withdraw ix
+        let mut transaction = Transaction::new_with_payer(
+            &[withdraw(
+                program_id,
+                payer.pubkey(),
+                order_acc_pubkey,
+                merchant_account_pubkey,
+                order_payment_token_acc_pubkey,
+                merchant_token_keypair.pubkey(),
+                account_to_receive_sol_refund_pubkey,
+                pda,
+                mint_keypair.pubkey(),
+                Option::None,
+                close_order_account,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+        // test contents of merchant token account
+        let merchant_token_account = banks_client
+            .get_account(merchant_token_keypair.pubkey())
+            .await;
+        let merchant_account_data = match merchant_token_account {
+            Ok(data) => match data {
+                None => panic!("Oo"),
+                Some(value) => match spl_token::state::Account::unpack(&value.data) {
+                    Ok(data) => data,
+                    Err(error) => panic!("Problem: {:?}", error),
+                },
+            },
+            Err(error) => panic!("Problem: {:?}", error),
+        };
+        assert_eq!(amount, merchant_account_data.amount);
+
+        let order_account = banks_client.get_account(order_acc_pubkey).await;
+        let order_account = match order_account {
+            Err(error) => panic!("Problem: {:?}", error),
+            Ok(value) => value,
+        };
+
+        (
+            banks_client,
+            order_account,
+            order_payment_token_acc_pubkey,
+            account_to_receive_sol_refund_pubkey,
+            account_to_receive_sol_refund_before,
+            previous_order_account,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_withdraw() {
+        let amount: u64 = 1234567890;
+        let (
+            mut banks_client,
+            order_account,
+            order_payment_token_acc_pubkey,
+            account_to_receive_sol_refund_pubkey,
+            account_to_receive_sol_refund_before,
+            _previous_order_account,
+        ) = withdraw_helper(amount, false).await;
+        // test contents of order account
+        let order_data = match order_account.clone() {
+            None => panic!("Oo"),
+            Some(value) => match OrderAccount::unpack(&value.data) {
+                Ok(data) => data,
+                Err(error) => panic!("Problem: {:?}", error),
+            },
+        };
+        assert_eq!(OrderStatus::Withdrawn as u8, order_data.status);
+        assert_eq!(amount, order_data.expected_amount);
+        assert_eq!(amount, order_data.paid_amount);
+        // test that token account was closed and that the refund was sent to expected account
+        let order_payment_token_acc = banks_client
+            .get_account(order_payment_token_acc_pubkey)
+            .await
+            .unwrap();
+        let account_to_receive_sol_refund_after = banks_client
+            .get_account(account_to_receive_sol_refund_pubkey)
+            .await
+            .unwrap();
+        run_order_token_account_refund_tests(
+            &order_payment_token_acc,
+            &account_to_receive_sol_refund_before,
+            &account_to_receive_sol_refund_after,
+            &Option::None,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_close_order_account() {
+        let amount: u64 = 10001;
+        let (
+            mut banks_client,
+            order_account,
+            order_payment_token_acc_pubkey,
+            account_to_receive_sol_refund_pubkey,
+            account_to_receive_sol_refund_before,
+            previous_order_account,
+        ) = withdraw_helper(amount, true).await;
+        // test closure of order account
+        assert!(order_account.is_none());
+        // test that accounts were closed and that refunds sent to expected account
+        let order_payment_token_acc = banks_client
+            .get_account(order_payment_token_acc_pubkey)
+            .await
+            .unwrap();
+        let account_to_receive_sol_refund_after = banks_client
+            .get_account(account_to_receive_sol_refund_pubkey)
+            .await
+            .unwrap();
+        run_order_token_account_refund_tests(
+            &order_payment_token_acc,
+            &account_to_receive_sol_refund_before,
+            &account_to_receive_sol_refund_after,
+            &previous_order_account,
+        )
+        .await;
+    }
+
+    async fn run_settlement_delay_tests(
+        settlement_delay: i64,
+        warp_to_slot: Option<u64>,
+    ) -> Result<(), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let mut context = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start_with_context()
+        .await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                context.banks_client.clone(),
+                Keypair::from_bytes(&context.payer.to_bytes()).unwrap(),
+                context.last_blockhash,
             ),
-            initialize_mint(
-                &spl_token::id(),
-                &mint.pubkey(),
-                &mint_authority.pubkey(),
-                None,
+            TestMerchant {
+                settlement_delay: Some(settlement_delay),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let amount: u64 = 1000000;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, seller_account_pubkey) = create_order_express_checkout(
+            amount,
+            &String::from("settlement"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let payer = &merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        let merchant_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    payer,
+                    &mint_keypair,
+                    recent_blockhash,
+                    &merchant_token_keypair,
+                    &payer.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+
+        // move the clock forward
+        if let Some(slot) = warp_to_slot {
+            context.warp_to_slot(slot).unwrap();
+        }
+
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let mut transaction = Transaction::new_with_payer(
+            &[withdraw(
+                program_id,
+                payer.pubkey(),
+                order_acc_pubkey,
+                merchant_result.1,
+                seller_account_pubkey,
+                merchant_token_keypair.pubkey(),
+                payer.pubkey(),
+                pda,
+                mint_keypair.pubkey(),
+                Option::None,
+                false,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], recent_blockhash);
+        merchant_result.2.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_no_settlement_delay() {
+        assert_matches!(run_settlement_delay_tests(0, Option::None).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_during_settlement_delay() {
+        assert_eq!(
+            run_settlement_delay_tests(86400, Option::None)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
                 0,
+                InstructionError::Custom(PaymentProcessorError::SettlementPending as u32)
             )
-            .unwrap(),
-        ];
-        let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
-        transaction.partial_sign(&[payer, mint], recent_blockhash);
-        transaction
+        );
     }
 
-    fn create_token_account_transaction(
-        payer: &Keypair,
-        mint: &Keypair,
-        recent_blockhash: Hash,
-        token_account: &Keypair,
-        token_account_owner: &Pubkey,
-        amount: u64,
-    ) -> Transaction {
-        let instructions = [
-            system_instruction::create_account(
-                &payer.pubkey(),
-                &token_account.pubkey(),
-                Rent::default().minimum_balance(TokenAccount::LEN),
-                TokenAccount::LEN as u64,
-                &spl_token::id(),
+    #[tokio::test]
+    async fn test_withdraw_after_settlement_delay() {
+        // each slot is about 400ms so this is well past a minute
+        assert_matches!(run_settlement_delay_tests(60, Some(1000)).await, Ok(()));
+    }
+
+    async fn run_verify_order_settled_test(
+        settlement_delay: i64,
+        warp_to_slot: Option<u64>,
+    ) -> Result<(), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let mut context = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start_with_context()
+        .await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                context.banks_client.clone(),
+                Keypair::from_bytes(&context.payer.to_bytes()).unwrap(),
+                context.last_blockhash,
             ),
-            initialize_account(
-                &spl_token::id(),
-                &token_account.pubkey(),
-                &mint.pubkey(),
-                token_account_owner,
+            TestMerchant {
+                settlement_delay: Some(settlement_delay),
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
+            1000000,
+            &String::from("settlement"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+
+        // move the clock forward
+        if let Some(slot) = warp_to_slot {
+            context.warp_to_slot(slot).unwrap();
+        }
+
+        let payer = &merchant_result.3;
+        let mut transaction = Transaction::new_with_payer(
+            &[verify_order_settled(
+                program_id,
+                order_acc_pubkey,
+                merchant_result.1,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    async fn test_verify_order_settled() {
+        assert_matches!(run_verify_order_settled_test(0, Option::None).await, Ok(()));
+        // each slot is about 400ms so this is well past a minute
+        assert_matches!(run_verify_order_settled_test(60, Some(1000)).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_verify_order_settled_before_settlement_delay() {
+        assert_eq!(
+            run_verify_order_settled_test(86400, Option::None)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::SettlementPending as u32)
             )
-            .unwrap(),
-            mint_to(
-                &spl_token::id(),
-                &mint.pubkey(),
-                &token_account.pubkey(),
-                token_account_owner,
-                &[&payer.pubkey()],
+        );
+    }
+
+    #[tokio::test]
+    /// test that withdrawing more than the order token account holds fails clearly
+    async fn test_withdraw_insufficient_order_balance() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let merchant_owner = Pubkey::new_unique();
+        let merchant = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let order = Pubkey::new_unique();
+        let order_token = Pubkey::new_unique();
+        let merchant_token = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        let mut add_account = |key: Pubkey, data: Vec<u8>, owner: Pubkey| {
+            program_test.add_account(
+                key,
+                solana_sdk::account::Account {
+                    lamports: Rent::default().minimum_balance(data.len()),
+                    data,
+                    owner,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+        };
+        let merchant_data = String::from("{}");
+        let mut merchant_account_data =
+            vec![0; get_merchant_account_size(&merchant_data, &[], &[])];
+        MerchantAccount {
+            discriminator: Discriminator::Merchant as u8,
+            owner: merchant_owner.to_bytes(),
+            sponsor: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            fee: DEFAULT_FEE_IN_LAMPORTS,
+            data: merchant_data,
+            fee_recipient: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            settlement_delay: 0,
+            order_count: 1,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        }
+        .pack(&mut merchant_account_data);
+        add_account(merchant, merchant_account_data, program_id);
+        let mut mint_data = vec![0; Mint::LEN];
+        Mint {
+            mint_authority: COption::None,
+            supply: 100,
+            decimals: 0,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut mint_data);
+        add_account(mint, mint_data, spl_token::id());
+        for (key, owner, amount) in
+            vec![(order_token, pda, 100), (merchant_token, merchant_owner, 0)]
+        {
+            let mut token_data = vec![0; TokenAccount::LEN];
+            TokenAccount {
+                mint,
+                owner,
+                amount,
+                delegate: COption::None,
+                state: AccountState::Initialized,
+                is_native: COption::None,
+                delegated_amount: 0,
+                close_authority: COption::None,
+            }
+            .pack_into_slice(&mut token_data);
+            add_account(key, token_data, spl_token::id());
+        }
+        // the order says more was paid than the order token account holds
+        let (order_id, secret, data) = (String::from("1"), Option::None, String::from("{}"));
+        let mut order_data = vec![0; get_order_account_size(&order_id, &secret, &data)];
+        OrderAccount {
+            discriminator: Discriminator::OrderExpressCheckout as u8,
+            status: OrderStatus::Paid as u8,
+            created: 0,
+            modified: 0,
+            merchant: merchant.to_bytes(),
+            mint: mint.to_bytes(),
+            token: order_token.to_bytes(),
+            payer: Pubkey::new_unique().to_bytes(),
+            expected_amount: 1000,
+            paid_amount: 1000,
+            order_id,
+            secret,
+            data,
+            refunded_amount: 0,
+            locked: false,
+            consumed: false,
+            quoted_amount: 0,
+        }
+        .pack(&mut order_data);
+        add_account(order, order_data, program_id);
+
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+        let mut transaction = Transaction::new_with_payer(
+            &[withdraw(
+                program_id,
+                payer.pubkey(),
+                order,
+                merchant,
+                order_token,
+                merchant_token,
+                payer.pubkey(),
+                pda,
+                mint,
+                Option::None,
+                false,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InsufficientOrderBalance as u32)
+            )
+        );
+    }
+
+    #[tokio::test]
+    /// test that unpaid orders can be cancelled to reclaim their rent
+    async fn test_cancel_pending_order() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let merchant_owner = Keypair::new();
+        let buyer = Keypair::new();
+        let stranger = Keypair::new();
+        let merchant = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let pending_order = Pubkey::new_unique();
+        let pending_order_token = Pubkey::new_unique();
+        let paid_order = Pubkey::new_unique();
+        let paid_order_token = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        let mut add_account = |key: Pubkey, data: Vec<u8>, owner: Pubkey| {
+            program_test.add_account(
+                key,
+                solana_sdk::account::Account {
+                    lamports: Rent::default().minimum_balance(data.len()),
+                    data,
+                    owner,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+        };
+        let merchant_data = String::from("{}");
+        let mut merchant_account_data =
+            vec![0; get_merchant_account_size(&merchant_data, &[], &[])];
+        MerchantAccount {
+            discriminator: Discriminator::Merchant as u8,
+            owner: merchant_owner.pubkey().to_bytes(),
+            sponsor: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            fee: DEFAULT_FEE_IN_LAMPORTS,
+            data: merchant_data,
+            fee_recipient: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            settlement_delay: 0,
+            order_count: 2,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        }
+        .pack(&mut merchant_account_data);
+        add_account(merchant, merchant_account_data, program_id);
+        let (order_id, secret, data) = (String::from("1"), Option::None, String::from("{}"));
+        let order_size = get_order_account_size(&order_id, &secret, &data);
+        for (order, order_token, status, amount) in vec![
+            (pending_order, pending_order_token, OrderStatus::Pending, 0),
+            (paid_order, paid_order_token, OrderStatus::Paid, 1000),
+        ] {
+            let mut token_data = vec![0; TokenAccount::LEN];
+            TokenAccount {
+                mint,
+                owner: pda,
                 amount,
+                delegate: COption::None,
+                state: AccountState::Initialized,
+                is_native: COption::None,
+                delegated_amount: 0,
+                close_authority: COption::None,
+            }
+            .pack_into_slice(&mut token_data);
+            add_account(order_token, token_data, spl_token::id());
+            let mut order_data = vec![0; order_size];
+            OrderAccount {
+                discriminator: Discriminator::OrderExpressCheckout as u8,
+                status: status as u8,
+                created: 0,
+                modified: 0,
+                merchant: merchant.to_bytes(),
+                mint: mint.to_bytes(),
+                token: order_token.to_bytes(),
+                payer: buyer.pubkey().to_bytes(),
+                expected_amount: 1000,
+                paid_amount: amount,
+                order_id: order_id.clone(),
+                secret: secret.clone(),
+                data: data.clone(),
+                refunded_amount: 0,
+                locked: false,
+                consumed: false,
+                quoted_amount: 0,
+            }
+            .pack(&mut order_data);
+            add_account(order, order_data, program_id);
+        }
+
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+        let cancel_transaction = |signer: &Keypair, order: Pubkey, order_token: Pubkey| {
+            let mut transaction = Transaction::new_with_payer(
+                &[cancel_pending_order(
+                    program_id,
+                    signer.pubkey(),
+                    order,
+                    merchant,
+                    order_token,
+                    destination,
+                    pda,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer, signer], recent_blockhash);
+            transaction
+        };
+        // only the merchant or the payer can cancel
+        assert_eq!(
+            banks_client
+                .process_transaction(cancel_transaction(
+                    &stranger,
+                    pending_order,
+                    pending_order_token
+                ))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongPayer as u32)
+            )
+        );
+        // paid orders are refunded instead
+        assert_eq!(
+            banks_client
+                .process_transaction(cancel_transaction(
+                    &merchant_owner,
+                    paid_order,
+                    paid_order_token
+                ))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InvalidOrder as u32)
             )
+        );
+        assert_matches!(
+            banks_client
+                .process_transaction(cancel_transaction(
+                    &buyer,
+                    pending_order,
+                    pending_order_token
+                ))
+                .await,
+            Ok(())
+        );
+        // both accounts are closed and their rent goes to the destination
+        assert!(banks_client
+            .get_account(pending_order)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(banks_client
+            .get_account(pending_order_token)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            Rent::default().minimum_balance(order_size)
+                + Rent::default().minimum_balance(TokenAccount::LEN),
+            banks_client.get_balance(destination).await.unwrap()
+        );
+        // the paid order is left as it was
+        let order_data = get_account_data::<OrderAccount>(&mut banks_client, paid_order).await;
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+    }
+
+    #[tokio::test]
+    /// test that the program owner can move escrowed tokens to a new seed version
+    async fn test_migrate_pda_token_account() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (config, _bump_seed) = get_config_address(&program_id);
+        let (old_pda, _bump_seed) = get_versioned_pda(&program_id, 0);
+        let (new_pda, _bump_seed) = get_versioned_pda(&program_id, 1);
+        let config_owner = Keypair::new();
+        let mint = Pubkey::new_unique();
+        let old_token = Pubkey::new_unique();
+        let new_token = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        let mut add_account = |key: Pubkey, data: Vec<u8>, owner: Pubkey| {
+            program_test.add_account(
+                key,
+                solana_sdk::account::Account {
+                    lamports: Rent::default().minimum_balance(data.len()),
+                    data,
+                    owner,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+        };
+        add_account(
+            config,
+            ConfigAccount {
+                discriminator: Discriminator::Config as u8,
+                owner: config_owner.pubkey().to_bytes(),
+            }
+            .try_to_vec()
             .unwrap(),
-        ];
-        let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
-        transaction.partial_sign(&[payer, token_account], recent_blockhash);
-        transaction
+            program_id,
+        );
+        let mut mint_data = vec![0; Mint::LEN];
+        Mint {
+            mint_authority: COption::None,
+            supply: 1000,
+            decimals: 0,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut mint_data);
+        add_account(mint, mint_data, spl_token::id());
+        // escrow under the old seed and an empty token account under the new one
+        for (key, owner, amount) in vec![(old_token, old_pda, 1000), (new_token, new_pda, 0)] {
+            let mut token_data = vec![0; TokenAccount::LEN];
+            TokenAccount {
+                mint,
+                owner,
+                amount,
+                delegate: COption::None,
+                state: AccountState::Initialized,
+                is_native: COption::None,
+                delegated_amount: 0,
+                close_authority: COption::None,
+            }
+            .pack_into_slice(&mut token_data);
+            add_account(key, token_data, spl_token::id());
+        }
+
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+        let migrate_transaction = |signer: &Keypair, from_version: u8, to_version: u8| {
+            let mut transaction = Transaction::new_with_payer(
+                &[migrate_pda_token_account(
+                    program_id,
+                    signer.pubkey(),
+                    old_token,
+                    new_token,
+                    destination,
+                    mint,
+                    from_version,
+                    to_version,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer, signer], recent_blockhash);
+            transaction
+        };
+        // only the program owner can migrate
+        assert_eq!(
+            banks_client
+                .process_transaction(migrate_transaction(&Keypair::new(), 0, 1))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongProgramOwner as u32)
+            )
+        );
+        // the new token account must belong to the new seed version
+        assert_eq!(
+            banks_client
+                .process_transaction(migrate_transaction(&config_owner, 0, 2))
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidAccountData)
+        );
+        assert_matches!(
+            banks_client
+                .process_transaction(migrate_transaction(&config_owner, 0, 1))
+                .await,
+            Ok(())
+        );
+        // the tokens moved and the old token account is closed
+        assert_eq!(
+            1000,
+            get_token_account_data(&mut banks_client, new_token)
+                .await
+                .amount
+        );
+        assert!(banks_client.get_account(old_token).await.unwrap().is_none());
+        assert_eq!(
+            Rent::default().minimum_balance(TokenAccount::LEN),
+            banks_client.get_balance(destination).await.unwrap()
+        );
     }
 
-    async fn create_merchant_account(
-        seed: Option<String>,
-        fee: Option<u64>,
-        sponsor: Option<&Pubkey>,
-        data: Option<String>,
-    ) -> MerchantResult {
+    #[tokio::test]
+    /// test that an account below rent exemption can be topped up by anyone
+    async fn test_top_up_rent() {
         let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let order = Pubkey::new_unique();
+        let not_owned = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        // an order account whose data grew without its rent being topped up
+        let (order_id, secret, data) = (String::from("1"), Option::None, String::from("{}"));
+        let order_size = get_order_account_size(&order_id, &secret, &data);
+        let mut order_data = vec![0; order_size];
+        OrderAccount {
+            discriminator: Discriminator::OrderExpressCheckout as u8,
+            status: OrderStatus::Paid as u8,
+            created: 0,
+            modified: 0,
+            merchant: Pubkey::new_unique().to_bytes(),
+            mint: Pubkey::new_unique().to_bytes(),
+            token: Pubkey::new_unique().to_bytes(),
+            payer: Pubkey::new_unique().to_bytes(),
+            expected_amount: 1000,
+            paid_amount: 1000,
+            order_id,
+            secret,
+            data,
+            refunded_amount: 0,
+            locked: false,
+            consumed: false,
+            quoted_amount: 0,
+        }
+        .pack(&mut order_data);
+        let rent = Rent::default();
+        for (key, owner) in vec![(order, program_id), (not_owned, Pubkey::new_unique())] {
+            program_test.add_account(
+                key,
+                solana_sdk::account::Account {
+                    lamports: rent.minimum_balance(order_size) - 1000,
+                    data: order_data.clone(),
+                    owner,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+        }
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+        // only accounts owned by this program can be topped up
+        let mut transaction = Transaction::new_with_payer(
+            &[top_up_rent(program_id, payer.pubkey(), not_owned)],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+        );
+
+        let mut transaction = Transaction::new_with_payer(
+            &[top_up_rent(program_id, payer.pubkey(), order)],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+        let order_account = banks_client.get_account(order).await.unwrap().unwrap();
+        assert!(rent.is_exempt(order_account.lamports, order_size));
+        assert_eq!(rent.minimum_balance(order_size), order_account.lamports);
+    }
 
-        let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
+    #[tokio::test]
+    async fn test_sweep_pda() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (config, _bump_seed) = get_config_address(&program_id);
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let config_owner = Keypair::new();
+        let destination = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
             "sol_payment_processor",
             program_id,
             processor!(PaymentProcessorInstruction::process),
-        )
-        .start()
-        .await;
-
-        let real_seed = match &seed {
-            None => MERCHANT,
-            Some(value) => &value,
-        };
+        );
+        // the program owner is a key we control
+        program_test.add_account(
+            config,
+            solana_sdk::account::Account {
+                lamports: Rent::default().minimum_balance(ConfigAccount::LEN),
+                data: ConfigAccount {
+                    discriminator: Discriminator::Config as u8,
+                    owner: config_owner.pubkey().to_bytes(),
+                }
+                .try_to_vec()
+                .unwrap(),
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        // someone sent SOL to the PDA by mistake
+        program_test.add_account(
+            pda,
+            solana_sdk::account::Account {
+                lamports: 1000000,
+                data: vec![],
+                owner: solana_program::system_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
 
-        // first we create a public key for the merchant account
-        let merchant_acc_pubkey =
-            Pubkey::create_with_seed(&payer.pubkey(), real_seed, &program_id).unwrap();
+        // only the program owner can sweep the PDA
+        let not_owner = Keypair::new();
+        let mut transaction = Transaction::new_with_payer(
+            &[sweep_pda(program_id, not_owner.pubkey(), destination)],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer, &not_owner], recent_blockhash);
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongProgramOwner as u32)
+            )
+        );
 
-        // then call register merchant ix
         let mut transaction = Transaction::new_with_payer(
-            &[register_merchant(
-                program_id,
-                payer.pubkey(),
-                merchant_acc_pubkey,
-                Some(real_seed.to_string()),
-                fee,
-                data,
-                sponsor,
-            )],
+            &[sweep_pda(program_id, config_owner.pubkey(), destination)],
             Some(&payer.pubkey()),
         );
-        transaction.sign(&[&payer], recent_blockhash);
+        transaction.sign(&[&payer, &config_owner], recent_blockhash);
         assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
-        return (
-            program_id,
-            merchant_acc_pubkey,
-            banks_client,
-            payer,
-            recent_blockhash,
+        assert!(banks_client.get_account(pda).await.unwrap().is_none());
+        assert_eq!(
+            1000000,
+            banks_client
+                .get_account(destination)
+                .await
+                .unwrap()
+                .unwrap()
+                .lamports
         );
     }
 
-    async fn prepare_order(
-        program_id: &Pubkey,
-        merchant: &Pubkey,
-        mint: &Pubkey,
-        banks_client: &mut BanksClient,
-    ) -> (Keypair, Pubkey, Pubkey, MerchantAccount) {
-        let order_acc_keypair = Keypair::new();
-
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+    async fn run_refund_order(
+        merchant_result: &mut MerchantResult,
+        order: Pubkey,
+        order_token: Pubkey,
+        refund_token: Pubkey,
+        amount: u64,
+    ) -> Result<(), TransportError> {
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &merchant_result.0);
+        let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+        let mut transaction = Transaction::new_with_payer(
+            &[refund_order(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order,
+                merchant_result.1,
+                order_token,
+                refund_token,
+                pda,
+                Pubkey::new_from_array(order_data.mint),
+                amount,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await
+    }
 
-        let (seller_token, _bump_seed) = Pubkey::find_program_address(
-            &[
-                &order_acc_keypair.pubkey().to_bytes(),
-                &spl_token::id().to_bytes(),
-                &mint.to_bytes(),
-            ],
-            program_id,
+    #[tokio::test]
+    /// test that partial refunds add up to at most the amount paid
+    async fn test_refund_order() {
+        let amount: u64 = 1000000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let mint_keypair = Keypair::new();
+        let (order, order_token) = create_order_express_checkout(
+            amount,
+            &String::from("refund"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let payer = &merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        let refund_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    payer,
+                    &mint_keypair,
+                    recent_blockhash,
+                    &refund_token_keypair,
+                    &payer.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let refund_token = refund_token_keypair.pubkey();
+        // two partial refunds that add up to the amount paid
+        assert_matches!(
+            run_refund_order(
+                &mut merchant_result,
+                order,
+                order_token,
+                refund_token,
+                400000
+            )
+            .await,
+            Ok(())
+        );
+        assert_matches!(
+            run_refund_order(
+                &mut merchant_result,
+                order,
+                order_token,
+                refund_token,
+                600000
+            )
+            .await,
+            Ok(())
+        );
+        // nothing is left to refund
+        assert_eq!(
+            run_refund_order(&mut merchant_result, order, order_token, refund_token, 1)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::RefundExceedsPaid as u32)
+            )
         );
+        let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+        assert_eq!(amount, order_data.paid_amount);
+        assert_eq!(amount, order_data.refunded_amount);
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+        let refund_token_data = get_token_account_data(&mut merchant_result.2, refund_token).await;
+        assert_eq!(amount, refund_token_data.amount);
+    }
 
-        let merchant_account = banks_client.get_account(*merchant).await;
-        let merchant_data = match merchant_account {
-            Ok(data) => match data {
-                None => panic!("Oo"),
-                Some(value) => match MerchantAccount::unpack(&value.data) {
-                    Ok(data) => data,
-                    Err(error) => panic!("Problem: {:?}", error),
-                },
-            },
-            Err(error) => panic!("Problem: {:?}", error),
+    async fn run_lock_order(
+        merchant_result: &mut MerchantResult,
+        config_owner: &Keypair,
+        order: Pubkey,
+        locked: bool,
+    ) -> Result<(), TransportError> {
+        let instruction = if locked {
+            lock_order(merchant_result.0, config_owner.pubkey(), order)
+        } else {
+            unlock_order(merchant_result.0, config_owner.pubkey(), order)
         };
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&merchant_result.3.pubkey()));
+        transaction.sign(&[&merchant_result.3, config_owner], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await
+    }
 
-        (order_acc_keypair, seller_token, pda, merchant_data)
+    async fn run_withdraw_order(
+        merchant_result: &mut MerchantResult,
+        order: Pubkey,
+        order_token: Pubkey,
+        merchant_token: Pubkey,
+        mint: Pubkey,
+    ) -> Result<(), TransportError> {
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &merchant_result.0);
+        let mut transaction = Transaction::new_with_payer(
+            &[withdraw(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order,
+                merchant_result.1,
+                order_token,
+                merchant_token,
+                merchant_result.3.pubkey(),
+                pda,
+                mint,
+                Option::None,
+                false,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await
     }
 
-    async fn create_token_account(
-        amount: u64,
-        mint_keypair: &Keypair,
+    /// withdraw an order and close it, signed by the merchant owner unless a signer is given
+    async fn run_close_order_withdraw(
         merchant_result: &mut MerchantResult,
-    ) -> Keypair {
-        // next create token account for test
-        let buyer_token_keypair = Keypair::new();
+        signer: Option<&Keypair>,
+        order: Pubkey,
+        order_token: Pubkey,
+        merchant_token: Pubkey,
+        mint: Pubkey,
+    ) -> Result<(), TransportError> {
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &merchant_result.0);
+        let signer_pubkey = match signer {
+            None => merchant_result.3.pubkey(),
+            Some(value) => value.pubkey(),
+        };
+        let mut transaction = Transaction::new_with_payer(
+            &[withdraw(
+                merchant_result.0,
+                signer_pubkey,
+                order,
+                merchant_result.1,
+                order_token,
+                merchant_token,
+                merchant_result.3.pubkey(),
+                pda,
+                mint,
+                Option::None,
+                true,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        match signer {
+            None => transaction.sign(&[&merchant_result.3], merchant_result.4),
+            Some(value) => transaction.sign(&[&merchant_result.3, value], merchant_result.4),
+        }
+        merchant_result.2.process_transaction(transaction).await
+    }
 
-        // create and initialize mint
-        assert_matches!(
+    #[tokio::test]
+    async fn test_set_operator() {
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+        let operator = Keypair::new();
+        let random_signer = Keypair::new();
+        let payer = &merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+
+        // only the merchant owner can set the operator
+        let mut transaction = Transaction::new_with_payer(
+            &[set_operator(
+                merchant_result.0,
+                random_signer.pubkey(),
+                merchant_result.1,
+                random_signer.pubkey(),
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer, &random_signer], recent_blockhash);
+        assert_eq!(
             merchant_result
                 .2
-                .process_transaction(create_mint_transaction(
-                    &merchant_result.3,
-                    &mint_keypair,
-                    &merchant_result.3,
-                    merchant_result.4
-                ))
-                .await,
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongMerchant as u32)
+            )
+        );
+        let mut transaction = Transaction::new_with_payer(
+            &[set_operator(
+                merchant_result.0,
+                payer.pubkey(),
+                merchant_result.1,
+                operator.pubkey(),
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
             Ok(())
         );
-        // create and initialize buyer token account
+        let merchant_data =
+            get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant_result.1).await;
+        assert_eq!(operator.pubkey().to_bytes(), merchant_data.operator);
+
+        let mint_keypair = Keypair::new();
+        let (order, order_token) = create_order_express_checkout(
+            1000000,
+            &String::from("operator-1"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let (other_order, other_order_token) = create_order_express_checkout(
+            1000000,
+            &String::from("operator-2"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let merchant_token_keypair = Keypair::new();
         assert_matches!(
             merchant_result
                 .2
@@ -635,1148 +9397,1908 @@ mod test {
                     &merchant_result.3,
                     &mint_keypair,
                     merchant_result.4,
-                    &buyer_token_keypair,
+                    &merchant_token_keypair,
                     &merchant_result.3.pubkey(),
-                    amount + 2000000,
+                    0,
                 ))
                 .await,
             Ok(())
         );
 
-        buyer_token_keypair
-    }
-
-    async fn create_order_express_checkout(
-        amount: u64,
-        order_id: &String,
-        secret: &String,
-        data: Option<String>,
-        merchant_result: &mut MerchantResult,
-        mint_keypair: &Keypair,
-    ) -> (Pubkey, Pubkey) {
-        let buyer_token_keypair = create_token_account(amount, mint_keypair, merchant_result).await;
-        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
-            &merchant_result.0,
-            &merchant_result.1,
-            &mint_keypair.pubkey(),
-            &mut merchant_result.2,
-        )
-        .await;
-
-        // call express checkout ix
-        let mut transaction = Transaction::new_with_payer(
-            &[express_checkout(
-                merchant_result.0,
-                merchant_result.3.pubkey(),
-                order_acc_keypair.pubkey(),
-                merchant_result.1,
-                seller_token,
-                buyer_token_keypair.pubkey(),
+        // a random signer can not act for the merchant
+        assert_eq!(
+            run_close_order_withdraw(
+                &mut merchant_result,
+                Some(&random_signer),
+                order,
+                order_token,
+                merchant_token_keypair.pubkey(),
                 mint_keypair.pubkey(),
-                Pubkey::from_str(PROGRAM_OWNER).unwrap(),
-                Pubkey::new_from_array(merchant_data.sponsor),
-                pda,
-                amount,
-                (&order_id).to_string(),
-                (&secret).to_string(),
-                data,
-            )],
-            Some(&merchant_result.3.pubkey()),
+            )
+            .await
+            .unwrap_err()
+            .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
+        );
+        // the operator can
+        assert_matches!(
+            run_close_order_withdraw(
+                &mut merchant_result,
+                Some(&operator),
+                order,
+                order_token,
+                merchant_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+            )
+            .await,
+            Ok(())
         );
-        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        // and so can the owner
         assert_matches!(
-            &mut merchant_result.2.process_transaction(transaction).await,
+            run_close_order_withdraw(
+                &mut merchant_result,
+                Option::None,
+                other_order,
+                other_order_token,
+                merchant_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+            )
+            .await,
             Ok(())
         );
-
-        (order_acc_keypair.pubkey(), seller_token)
     }
 
-    async fn create_chain_checkout_transaction(
-        amount: u64,
-        order_items: &OrderItems,
-        data: Option<String>,
-        merchant_result: &mut MerchantResult,
-        mint_keypair: &Keypair,
-    ) -> Result<(Pubkey, Pubkey), TransportError> {
-        let buyer_token_keypair = create_token_account(amount, mint_keypair, merchant_result).await;
-        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
-            &merchant_result.0,
-            &merchant_result.1,
-            &mint_keypair.pubkey(),
-            &mut merchant_result.2,
+    #[tokio::test]
+    /// test that locked orders can only be withdrawn once unlocked
+    async fn test_lock_order() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (config, _bump_seed) = get_config_address(&program_id);
+        let config_owner = Keypair::new();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        // the program owner is a key we control
+        program_test.add_account(
+            config,
+            solana_sdk::account::Account {
+                lamports: Rent::default().minimum_balance(ConfigAccount::LEN),
+                data: ConfigAccount {
+                    discriminator: Discriminator::Config as u8,
+                    owner: config_owner.pubkey().to_bytes(),
+                }
+                .try_to_vec()
+                .unwrap(),
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        let (banks_client, payer, recent_blockhash) = program_test.start().await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                fee_recipient: Some(Pubkey::new_unique()),
+                ..TestMerchant::default()
+            },
         )
         .await;
-        let order_items = order_items.clone();
-
-        // call chain checkout ix
-        let mut transaction = Transaction::new_with_payer(
-            &[chain_checkout(
-                merchant_result.0,
-                merchant_result.3.pubkey(),
-                order_acc_keypair.pubkey(),
-                merchant_result.1,
-                seller_token,
-                buyer_token_keypair.pubkey(),
-                mint_keypair.pubkey(),
-                Pubkey::from_str(PROGRAM_OWNER).unwrap(),
-                Pubkey::new_from_array(merchant_data.sponsor),
-                pda,
-                amount,
-                order_items,
-                data,
-            )],
-            Some(&merchant_result.3.pubkey()),
-        );
-        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
-        let _result = merchant_result.2.process_transaction(transaction).await?;
-        Ok((order_acc_keypair.pubkey(), seller_token))
-    }
-
-    async fn create_order_chain_checkout(
-        amount: u64,
-        order_items: &OrderItems,
-        data: Option<String>,
-        merchant_result: &mut MerchantResult,
-        mint_keypair: &Keypair,
-    ) -> (Pubkey, Pubkey) {
-        let transaction = create_chain_checkout_transaction(
-            amount,
-            order_items,
-            data,
-            merchant_result,
-            mint_keypair,
+        let mint_keypair = Keypair::new();
+        let (order, order_token) = create_order_express_checkout(
+            1000000,
+            &String::from("locked"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
         )
         .await;
+        let payer = &merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        let merchant_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    payer,
+                    &mint_keypair,
+                    recent_blockhash,
+                    &merchant_token_keypair,
+                    &payer.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let merchant_token = merchant_token_keypair.pubkey();
 
-        assert!(transaction.is_ok());
-        transaction.unwrap()
-    }
-
-    async fn run_merchant_tests(result: MerchantResult) -> MerchantAccount {
-        let program_id = result.0;
-        let merchant = result.1;
-        let mut banks_client = result.2;
-        let payer = result.3;
-        // test contents of merchant account
-        let merchant_account = banks_client.get_account(merchant).await;
-        let merchant_account = match merchant_account {
-            Ok(data) => match data {
-                None => panic!("Oo"),
-                Some(value) => value,
-            },
-            Err(error) => panic!("Problem: {:?}", error),
-        };
-        assert_eq!(merchant_account.owner, program_id);
-        let merchant_data = MerchantAccount::unpack(&merchant_account.data);
-        let merchant_data = match merchant_data {
-            Ok(data) => data,
-            Err(error) => panic!("Problem: {:?}", error),
-        };
-        assert_eq!(true, merchant_data.is_initialized());
-        assert_eq!(payer.pubkey(), Pubkey::new_from_array(merchant_data.owner));
-
-        merchant_data
+        // only the program owner can lock orders
+        assert_eq!(
+            run_lock_order(&mut merchant_result, &Keypair::new(), order, true)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongProgramOwner as u32)
+            )
+        );
+        // locked orders can not be withdrawn
+        assert_matches!(
+            run_lock_order(&mut merchant_result, &config_owner, order, true).await,
+            Ok(())
+        );
+        let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+        assert_eq!(true, order_data.locked);
+        assert_eq!(
+            run_withdraw_order(
+                &mut merchant_result,
+                order,
+                order_token,
+                merchant_token,
+                mint_keypair.pubkey()
+            )
+            .await
+            .unwrap_err()
+            .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::OrderLocked as u32)
+            )
+        );
+        // once unlocked the order can be withdrawn
+        assert_matches!(
+            run_lock_order(&mut merchant_result, &config_owner, order, false).await,
+            Ok(())
+        );
+        assert_matches!(
+            run_withdraw_order(
+                &mut merchant_result,
+                order,
+                order_token,
+                merchant_token,
+                mint_keypair.pubkey()
+            )
+            .await,
+            Ok(())
+        );
+        let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+        assert_eq!(false, order_data.locked);
+        assert_eq!(OrderStatus::Withdrawn as u8, order_data.status);
     }
 
+    #[cfg(feature = "debug-compute")]
     #[tokio::test]
-    async fn test_register_merchant() {
-        let result =
+    /// test that checkouts work the same with the compute units logged around them
+    ///
+    /// BanksClient does not return the transaction logs, so the logged lines themselves
+    /// are only visible with RUST_LOG=debug
+    async fn test_express_checkout_debug_compute() {
+        let amount: u64 = 2000000000;
+        let order_id = String::from("1337");
+        let secret = String::from("hunter2");
+        let mut merchant_result =
             create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
-        let merchant_data = run_merchant_tests(result).await;
-        assert_eq!(DEFAULT_FEE_IN_LAMPORTS, merchant_data.fee);
-        assert_eq!(String::from("{}"), merchant_data.data);
-    }
-
-    #[tokio::test]
-    async fn test_register_merchant_with_seed() {
-        let result = create_merchant_account(
-            Some(String::from("mosh")),
-            Option::None,
-            Option::None,
+        let mint_keypair = Keypair::new();
+        let (order_acc_pubkey, seller_account_pubkey) = create_order_express_checkout(
+            amount,
+            &order_id,
+            &secret,
             Option::None,
+            &mut merchant_result,
+            &mint_keypair,
         )
         .await;
-        let merchant = result.1;
-        let payer = result.3;
-        let program_id = result.0;
-        assert_eq!(
-            merchant,
-            Pubkey::create_with_seed(&payer.pubkey(), "mosh", &program_id).unwrap()
-        );
-    }
 
-    #[tokio::test]
-    /// assert that the minimum fee is used when custom fee too low
-    async fn test_register_merchant_fee_default() {
-        let result =
-            create_merchant_account(Option::None, Some(10), Option::None, Option::None).await;
-        let merchant_data = run_merchant_tests(result).await;
-        assert_eq!(MIN_FEE_IN_LAMPORTS, merchant_data.fee);
+        run_checkout_tests(
+            amount,
+            order_id,
+            secret,
+            Option::None,
+            &mut merchant_result,
+            &order_acc_pubkey,
+            &seller_account_pubkey,
+            &mint_keypair,
+        )
+        .await;
     }
 
+    #[cfg(feature = "admin")]
     #[tokio::test]
-    async fn test_register_merchant_with_all_stuff() {
-        let seed = String::from("mosh");
-        let sponsor_pk = Pubkey::new_unique();
-        let data = String::from(
-            r#"{"code":200,"success":true,"payload":{"features":["awesome","easyAPI","lowLearningCurve"]}}"#,
+    /// test that the program owner can correct the stored fields of an order
+    async fn test_admin_fix_order() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (config, _bump_seed) = get_config_address(&program_id);
+        let config_owner = Keypair::new();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
         );
-        let datas = data.clone();
-        let result =
-            create_merchant_account(Some(seed), Some(90000), Some(&sponsor_pk), Some(data)).await;
-        let merchant_data = run_merchant_tests(result).await;
-        assert_eq!(datas, merchant_data.data);
-        assert_eq!(90000, merchant_data.fee);
-        assert_eq!(sponsor_pk, Pubkey::new_from_array(merchant_data.sponsor));
-        // just for sanity verify that you can get some of the JSON values
-        let json_value: Value = serde_json::from_str(&merchant_data.data).unwrap();
-        assert_eq!(200, json_value["code"]);
-        assert_eq!(true, json_value["success"]);
-    }
-
-    async fn run_common_checkout_tests(
-        amount: u64,
-        merchant_result: &mut MerchantResult,
-        order_acc_pubkey: &Pubkey,
-        seller_account_pubkey: &Pubkey,
-        mint_keypair: &Keypair,
-    ) -> OrderAccount {
-        // program_id => merchant_result.0;
-        // merchant_account_pubkey => merchant_result.1;
-        // banks_client => merchant_result.2;
-        // payer => merchant_result.3;
-
-        let order_account = merchant_result.2.get_account(*order_acc_pubkey).await;
-        let order_account = match order_account {
-            Ok(data) => match data {
-                None => panic!("Oo"),
-                Some(value) => value,
+        // the program owner is a key we control
+        program_test.add_account(
+            config,
+            solana_sdk::account::Account {
+                lamports: Rent::default().minimum_balance(ConfigAccount::LEN),
+                data: ConfigAccount {
+                    discriminator: Discriminator::Config as u8,
+                    owner: config_owner.pubkey().to_bytes(),
+                }
+                .try_to_vec()
+                .unwrap(),
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
             },
-            Err(error) => panic!("Problem: {:?}", error),
-        };
-        assert_eq!(order_account.owner, merchant_result.0,);
-
-        let order_data = OrderAccount::unpack(&order_account.data);
-        let order_data = match order_data {
-            Ok(data) => data,
-            Err(error) => panic!("Problem: {:?}", error),
-        };
-        assert_eq!(true, order_data.is_initialized());
-        assert_eq!(OrderStatus::Paid as u8, order_data.status);
-        assert_eq!(merchant_result.1.to_bytes(), order_data.merchant);
-        assert_eq!(mint_keypair.pubkey().to_bytes(), order_data.mint);
-        assert_eq!(seller_account_pubkey.to_bytes(), order_data.token);
-        assert_eq!(merchant_result.3.pubkey().to_bytes(), order_data.payer);
-        assert_eq!(amount, order_data.expected_amount);
-        assert_eq!(amount, order_data.paid_amount);
+        );
+        let (banks_client, payer, recent_blockhash) = program_test.start().await;
+        let mut merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant::default(),
+        )
+        .await;
+        let mint_keypair = Keypair::new();
+        let (order, _order_token) = create_order_express_checkout(
+            1000000,
+            &String::from("misrecorded"),
+            &String::from(""),
+            Option::None,
+            &mut merchant_result,
+            &mint_keypair,
+        )
+        .await;
+        let (_, _, mut banks_client, payer, recent_blockhash) = merchant_result;
+        let fix_order_transaction =
+            |signer: &Keypair, expected_amount: Option<u64>, refunded_amount: Option<u64>| {
+                let mut transaction = Transaction::new_with_payer(
+                    &[admin_fix_order(
+                        program_id,
+                        signer.pubkey(),
+                        order,
+                        expected_amount,
+                        Option::None,
+                        refunded_amount,
+                        Option::None,
+                    )],
+                    Some(&payer.pubkey()),
+                );
+                transaction.sign(&[&payer, signer], recent_blockhash);
+                transaction
+            };
+        // only the program owner can fix orders
+        let transaction = fix_order_transaction(&Keypair::new(), Some(900000), Option::None);
         assert_eq!(
-            order_account.lamports,
-            Rent::default().minimum_balance(get_order_account_size(
-                &order_data.order_id,
-                &order_data.secret,
-                &order_data.data,
-            ))
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongProgramOwner as u32)
+            )
+        );
+        // refunds can not be more than what was paid
+        let transaction = fix_order_transaction(&config_owner, Option::None, Some(1000001));
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InvalidArgument)
         );
+        let transaction = fix_order_transaction(&config_owner, Some(900000), Option::None);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+        let order_data = get_account_data::<OrderAccount>(&mut banks_client, order).await;
+        assert_eq!(900000, order_data.expected_amount);
+        // the other fields are left as they are
+        assert_eq!(1000000, order_data.paid_amount);
+        assert_eq!(0, order_data.refunded_amount);
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+    }
 
-        // test contents of seller token account
-        let seller_token_account = merchant_result.2.get_account(*seller_account_pubkey).await;
-        let seller_token_account = match seller_token_account {
-            Ok(data) => match data {
-                None => panic!("Oo"),
-                Some(value) => value,
-            },
-            Err(error) => panic!("Problem: {:?}", error),
-        };
-        let seller_account_data = spl_token::state::Account::unpack(&seller_token_account.data);
-        let seller_account_data = match seller_account_data {
-            Ok(data) => data,
-            Err(error) => panic!("Problem: {:?}", error),
-        };
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &merchant_result.0);
-        assert_eq!(amount, seller_account_data.amount);
-        assert_eq!(pda, seller_account_data.owner);
-        assert_eq!(mint_keypair.pubkey(), seller_account_data.mint);
+    #[cfg(feature = "admin")]
+    #[tokio::test]
+    /// test withdrawing an under-paid order with and without requiring full payment
+    async fn test_withdraw_require_full_payment() {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (config, _bump_seed) = get_config_address(&program_id);
+        let config_owner = Keypair::new();
+        for require_full_payment in &[false, true] {
+            let mut program_test = ProgramTest::new(
+                "sol_payment_processor",
+                program_id,
+                processor!(PaymentProcessorInstruction::process),
+            );
+            // the program owner is a key we control, to under-pay the order
+            program_test.add_account(
+                config,
+                solana_sdk::account::Account {
+                    lamports: Rent::default().minimum_balance(ConfigAccount::LEN),
+                    data: ConfigAccount {
+                        discriminator: Discriminator::Config as u8,
+                        owner: config_owner.pubkey().to_bytes(),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                    owner: program_id,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+            let (banks_client, payer, recent_blockhash) = program_test.start().await;
+            let mut merchant_result = register_test_merchant(
+                (
+                    program_id,
+                    Pubkey::default(),
+                    banks_client,
+                    payer,
+                    recent_blockhash,
+                ),
+                TestMerchant {
+                    require_full_payment: Some(*require_full_payment),
+                    ..TestMerchant::default()
+                },
+            )
+            .await;
+            let merchant_data =
+                get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant_result.1)
+                    .await;
+            assert_eq!(*require_full_payment, merchant_data.require_full_payment);
+            let mint_keypair = Keypair::new();
+            let (order, order_token) = create_order_express_checkout(
+                1000000,
+                &String::from("under-paid"),
+                &String::from(""),
+                Option::None,
+                &mut merchant_result,
+                &mint_keypair,
+            )
+            .await;
+            let mut transaction = Transaction::new_with_payer(
+                &[admin_fix_order(
+                    program_id,
+                    config_owner.pubkey(),
+                    order,
+                    Some(1000001),
+                    Option::None,
+                    Option::None,
+                    Option::None,
+                )],
+                Some(&merchant_result.3.pubkey()),
+            );
+            transaction.sign(&[&merchant_result.3, &config_owner], merchant_result.4);
+            assert_matches!(
+                merchant_result.2.process_transaction(transaction).await,
+                Ok(())
+            );
+            let merchant_token_keypair = Keypair::new();
+            assert_matches!(
+                merchant_result
+                    .2
+                    .process_transaction(create_token_account_transaction(
+                        &merchant_result.3,
+                        &mint_keypair,
+                        merchant_result.4,
+                        &merchant_token_keypair,
+                        &merchant_result.3.pubkey(),
+                        0,
+                    ))
+                    .await,
+                Ok(())
+            );
+            let result = run_withdraw_order(
+                &mut merchant_result,
+                order,
+                order_token,
+                merchant_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+            )
+            .await;
+            let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+            if *require_full_payment {
+                assert_eq!(
+                    result.unwrap_err().unwrap(),
+                    TransactionError::InstructionError(
+                        0,
+                        InstructionError::Custom(PaymentProcessorError::NotFullyPaid as u32)
+                    )
+                );
+                assert_eq!(OrderStatus::Paid as u8, order_data.status);
+            } else {
+                assert_matches!(result, Ok(()));
+                assert_eq!(OrderStatus::Withdrawn as u8, order_data.status);
+            }
+        }
+    }
 
-        // test that sponsor was saved okay
-        let merchant_account = merchant_result.2.get_account(merchant_result.1).await;
-        let merchant_data = match merchant_account {
+    /// Fetch and unpack one of this program's accounts, panicking on failure
+    async fn get_account_data<T: Serdes>(banks_client: &mut BanksClient, key: Pubkey) -> T {
+        match banks_client.get_account(key).await {
             Ok(data) => match data {
                 None => panic!("Oo"),
-                Some(value) => match MerchantAccount::unpack(&value.data) {
+                Some(value) => match T::unpack(&value.data) {
                     Ok(data) => data,
                     Err(error) => panic!("Problem: {:?}", error),
                 },
             },
             Err(error) => panic!("Problem: {:?}", error),
-        };
-
-        let program_owner_key = Pubkey::from_str(PROGRAM_OWNER).unwrap();
-        let sponsor = Pubkey::new_from_array(merchant_data.sponsor);
+        }
+    }
 
-        let program_owner_account = merchant_result.2.get_account(program_owner_key).await;
-        let program_owner_account = match program_owner_account {
+    async fn get_token_account_data(banks_client: &mut BanksClient, key: Pubkey) -> TokenAccount {
+        match banks_client.get_account(key).await {
             Ok(data) => match data {
                 None => panic!("Oo"),
-                Some(value) => value,
+                Some(value) => match TokenAccount::unpack(&value.data) {
+                    Ok(data) => data,
+                    Err(error) => panic!("Problem: {:?}", error),
+                },
             },
             Err(error) => panic!("Problem: {:?}", error),
-        };
-
-        if sponsor == program_owner_key {
-            // test contents of program owner account
-            assert_eq!(merchant_data.fee, program_owner_account.lamports);
-        } else {
-            // test contents of program owner account and sponsor account
-            let (program_owner_fee, sponsor_fee) = get_amounts(merchant_data.fee, SPONSOR_FEE);
-            let sponsor_account = merchant_result.2.get_account(sponsor).await;
-            let sponsor_account = match sponsor_account {
-                Ok(data) => match data {
-                    None => panic!("Oo"),
-                    Some(value) => value,
-                },
-                Err(error) => panic!("Problem: {:?}", error),
-            };
-            assert_eq!(program_owner_fee, program_owner_account.lamports);
-            assert_eq!(sponsor_fee, sponsor_account.lamports);
-        }
-
-        order_data
-    }
-
-    async fn run_checkout_tests(
-        amount: u64,
-        order_id: String,
-        secret: String,
-        data: Option<String>,
-        merchant_result: &mut MerchantResult,
-        order_acc_pubkey: &Pubkey,
-        seller_account_pubkey: &Pubkey,
-        mint_keypair: &Keypair,
-    ) {
-        let order_data = run_common_checkout_tests(
-            amount,
-            merchant_result,
-            order_acc_pubkey,
-            seller_account_pubkey,
-            mint_keypair,
-        )
-        .await;
-
-        let data_string = match data {
-            None => String::from("{}"),
-            Some(value) => value,
-        };
-        assert_eq!(order_id, order_data.order_id);
-        assert_eq!(secret, order_data.secret);
-        assert_eq!(data_string, order_data.data);
-    }
-
-    async fn run_chain_checkout_tests(
-        amount: u64,
-        order_items: &OrderItems,
-        data: Option<String>,
-        merchant_result: &mut MerchantResult,
-        order_acc_pubkey: &Pubkey,
-        seller_account_pubkey: &Pubkey,
-        mint_keypair: &Keypair,
-    ) {
-        // test contents of order account
-        let order_data = run_common_checkout_tests(
-            amount,
-            merchant_result,
-            order_acc_pubkey,
-            seller_account_pubkey,
-            mint_keypair,
-        )
-        .await;
-        match data {
-            None => {
-                assert_eq!(json!({ PAID: order_items }).to_string(), order_data.data);
-            }
-            Some(value) => {
-                let json_data: Value = match serde_json::from_str(&value) {
-                    Err(error) => panic!("Problem: {:?}", error),
-                    Ok(data) => data,
-                };
-                assert_eq!(
-                    json!({ INITIAL: json_data, PAID: order_items }).to_string(),
-                    order_data.data
-                );
-            }
         }
     }
 
     #[tokio::test]
-    async fn test_chain_checkout() {
+    /// test two orders paid into one merchant token account and withdrawn independently
+    async fn test_merchant_token_account() {
+        let amount_a: u64 = 1000000;
+        let amount_b: u64 = 2500000;
+        let mut merchant_result =
+            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
         let mint_keypair = Keypair::new();
-        let amount: u64 = 2000000000;
-
-        let mut order_items: OrderItems = BTreeMap::new();
-        order_items.insert("1".to_string(), 1);
-        order_items.insert("3".to_string(), 1);
+        let buyer_token_keypair =
+            create_token_account(amount_a + amount_b, &mint_keypair, &mut merchant_result).await;
+        let program_id = merchant_result.0;
+        let merchant_account_pubkey = merchant_result.1;
+        let mut banks_client = merchant_result.2;
+        let payer = merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        let program_owner = Pubkey::from_str(PROGRAM_OWNER).unwrap();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let (merchant_token, _bump_seed) = get_merchant_token_address(
+            &merchant_account_pubkey,
+            &mint_keypair.pubkey(),
+            &program_id,
+        );
 
-        let merchant_data = format!(
-            r#"{{
-            "1": {{"price": 2000000, "mint": "{mint_key}"}},
-            "2": {{"price": 3000000, "mint": "{mint_key}"}},
-            "3": {{"price": 4000000, "mint": "{mint_key}"}},
-            "4": {{"price": 4000000, "mint": "{mint_key}"}},
-            "5": {{"price": 4000000, "mint": "{mint_key}"}}
-        }}"#,
-            mint_key = mint_keypair.pubkey()
+        // call create merchant token account ix
+        let mut transaction = Transaction::new_with_payer(
+            &[create_merchant_token_account(
+                program_id,
+                payer.pubkey(),
+                merchant_account_pubkey,
+                merchant_token,
+                mint_keypair.pubkey(),
+                pda,
+            )],
+            Some(&payer.pubkey()),
         );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
 
-        let mut merchant_result = create_merchant_account(
-            Some("chain".to_string()),
-            Option::None,
-            Option::None,
-            Some(merchant_data),
-        )
-        .await;
-        let (order_acc_pubkey, seller_account_pubkey) = create_order_chain_checkout(
-            amount,
-            &order_items,
-            Option::None,
-            &mut merchant_result,
-            &mint_keypair,
-        )
-        .await;
+        // make two orders that both pay into the merchant token account
+        let mut orders: Vec<Pubkey> = vec![];
+        for (order_id, amount) in [("A", amount_a), ("B", amount_b)].iter() {
+            let order_acc_keypair = Keypair::new();
+            let mut transaction = Transaction::new_with_payer(
+                &[express_checkout(
+                    program_id,
+                    payer.pubkey(),
+                    order_acc_keypair.pubkey(),
+                    merchant_account_pubkey,
+                    merchant_token,
+                    buyer_token_keypair.pubkey(),
+                    mint_keypair.pubkey(),
+                    program_owner,
+                    program_owner,
+                    pda,
+                    *amount,
+                    OrderId::try_new(order_id).unwrap(),
+                    String::from(""),
+                    Option::None,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer, &order_acc_keypair], recent_blockhash);
+            assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+            orders.push(order_acc_keypair.pubkey());
+        }
+        let merchant_token_data = get_token_account_data(&mut banks_client, merchant_token).await;
+        assert_eq!(amount_a + amount_b, merchant_token_data.amount);
+        assert_eq!(pda, merchant_token_data.owner);
+        // the orders together never record more than the merchant token account holds
+        let mut total_paid: u64 = 0;
+        for (order, amount) in orders.iter().zip([amount_a, amount_b].iter()) {
+            let order_data = get_account_data::<OrderAccount>(&mut banks_client, *order).await;
+            assert_eq!(*amount, order_data.paid_amount);
+            total_paid += order_data.paid_amount;
+        }
+        assert!(total_paid <= merchant_token_data.amount);
 
-        run_chain_checkout_tests(
-            amount,
-            &order_items,
-            Option::None,
-            &mut merchant_result,
-            &order_acc_pubkey,
-            &seller_account_pubkey,
-            &mint_keypair,
-        )
-        .await;
+        // create and initialize the token account that we will withdraw to
+        let withdraw_token_keypair = Keypair::new();
+        assert_matches!(
+            banks_client
+                .process_transaction(create_token_account_transaction(
+                    &payer,
+                    &mint_keypair,
+                    recent_blockhash,
+                    &withdraw_token_keypair,
+                    &payer.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+
+        // withdraw each order and check that only its paid amount is moved
+        let mut withdrawn: u64 = 0;
+        for (order, amount) in orders.iter().zip([amount_a, amount_b].iter()) {
+            let mut transaction = Transaction::new_with_payer(
+                &[withdraw(
+                    program_id,
+                    payer.pubkey(),
+                    *order,
+                    merchant_account_pubkey,
+                    merchant_token,
+                    withdraw_token_keypair.pubkey(),
+                    program_owner,
+                    pda,
+                    mint_keypair.pubkey(),
+                    Option::None,
+                    false,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer], recent_blockhash);
+            assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+            withdrawn = withdrawn + amount;
+
+            let withdraw_token_data =
+                get_token_account_data(&mut banks_client, withdraw_token_keypair.pubkey()).await;
+            assert_eq!(withdrawn, withdraw_token_data.amount);
+            // the merchant token account is left open for future orders
+            let merchant_token_data =
+                get_token_account_data(&mut banks_client, merchant_token).await;
+            assert_eq!(amount_a + amount_b - withdrawn, merchant_token_data.amount);
+        }
     }
 
-    #[tokio::test]
-    async fn test_chain_checkout_with_data() {
+    #[cfg(not(feature = "json"))]
+    #[tokio::test]
+    /// test the lean build: express checkout and withdraw work, chain checkout is rejected
+    async fn test_express_checkout_without_json() {
+        let amount: u64 = 2000000;
+        let (_banks_client, order_account, _, _, _, _) = withdraw_helper(amount, false).await;
+        let order_data = match order_account {
+            None => panic!("Oo"),
+            Some(value) => match OrderAccount::unpack(&value.data) {
+                Ok(data) => data,
+                Err(error) => panic!("Problem: {:?}", error),
+            },
+        };
+        assert_eq!(OrderStatus::Withdrawn as u8, order_data.status);
+        assert_eq!(amount, order_data.paid_amount);
+
         let mint_keypair = Keypair::new();
-        let amount: u64 = 2000000000;
-
-        let mut order_items: OrderItems = BTreeMap::new();
-        order_items.insert("1".to_string(), 1);
-
-        let merchant_data = format!(
-            r#"{{
-            "1": {{"price": 2000000, "mint": "{mint_key}"}},
-            "2": {{"price": 3000000, "mint": "{mint_key}"}}
-        }}"#,
-            mint_key = mint_keypair.pubkey()
+        assert!(
+            chain_checkout_failing_test_helper(
+                1,             // id of item being ordered
+                20,            // amount to pay
+                &mint_keypair, // mint being used for payment
+                1,             // registered item id
+                20,            // expected amount
+                &mint_keypair, // expected mint
+                InstructionError::Custom(PaymentProcessorError::InvalidInstruction as u32)
+            )
+            .await
         );
+    }
 
-        let mut merchant_result = create_merchant_account(
-            Some("chain2".to_string()),
+    async fn run_subscribe_tests(
+        amount: u64,
+        package_name: &str,
+        merchant_data: &str,
+        mint_keypair: &Keypair,
+    ) -> (
+        Result<(), TransportError>,
+        Option<(SubscriptionAccount, MerchantResult, Pubkey, Pubkey)>,
+    ) {
+        let merchant_result = create_merchant_account(
+            Some(String::from("subscription test")),
             Option::None,
             Option::None,
-            Some(merchant_data),
-        )
-        .await;
-        let (order_acc_pubkey, seller_account_pubkey) = create_order_chain_checkout(
-            amount,
-            &order_items,
-            Some(String::from(r#"{"foo": "bar"}"#)),
-            &mut merchant_result,
-            &mint_keypair,
+            Some(String::from(merchant_data)),
         )
         .await;
+        run_subscribe_tests_for_merchant(merchant_result, amount, package_name, mint_keypair).await
+    }
 
-        run_chain_checkout_tests(
+    /// subscribe to a package of an already registered merchant
+    async fn run_subscribe_tests_for_merchant(
+        mut merchant_result: MerchantResult,
+        amount: u64,
+        package_name: &str,
+        mint_keypair: &Keypair,
+    ) -> (
+        Result<(), TransportError>,
+        Option<(SubscriptionAccount, MerchantResult, Pubkey, Pubkey)>,
+    ) {
+        let (subscription, _bump_seed) = get_subscription_account_pubkey(
+            &merchant_result.3.pubkey(),
+            &merchant_result.1,
+            package_name,
+            &merchant_result.0,
+        );
+        // the helper matches the address the program derives
+        assert_eq!(
+            subscription,
+            Pubkey::find_program_address(
+                &[
+                    &merchant_result.3.pubkey().to_bytes(), // payer
+                    &merchant_result.1.to_bytes(),          // merchant
+                    &package_name.as_bytes(),
+                ],
+                &merchant_result.0, // program id
+            )
+            .0
+        );
+
+        let order_data = format!(r#"{{"subscription": "{}"}}"#, subscription.to_string());
+
+        let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
             amount,
-            &order_items,
-            Some(String::from(r#"{"foo": "bar"}"#)),
+            &String::from(package_name),
+            &String::from(""),
+            Some(order_data),
             &mut merchant_result,
-            &order_acc_pubkey,
-            &seller_account_pubkey,
             &mint_keypair,
         )
         .await;
-    }
 
-    async fn chain_checkout_failing_test_helper(
-        order_item_id: u8,
-        paid_amount: u64,
-        input_mint: &Keypair,
-        registered_item_id: u8,
-        expected_amount: u64,
-        registered_mint: &Keypair,
-        expected_error: InstructionError,
-    ) -> bool {
-        let mut order_items: OrderItems = BTreeMap::new();
-        order_items.insert(format!("{}", order_item_id), 1);
+        let program_id = merchant_result.0;
+        let merchant_account_pubkey = merchant_result.1;
+        let payer = &merchant_result.3;
+        let recent_blockhash = merchant_result.4;
 
-        let mut merchant_data = String::from("5");
+        // call subscribe ix
+        let mut transaction = Transaction::new_with_payer(
+            &[subscribe(
+                program_id,
+                payer.pubkey(),
+                subscription,
+                merchant_account_pubkey,
+                order_acc_pubkey,
+                Option::None,
+                String::from(package_name),
+                Option::None,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], recent_blockhash);
 
-        if registered_item_id != 0 {
-            merchant_data = format!(
-                r#"{{"{registered_item_id}": {{"price": {expected_amount}, "mint": "{mint_key}"}}}}"#,
-                registered_item_id = registered_item_id,
-                expected_amount = expected_amount,
-                mint_key = registered_mint.pubkey()
-            );
-        }
+        let result = merchant_result.2.process_transaction(transaction).await;
 
-        let mut merchant_result = create_merchant_account(
-            Some("test".to_string()),
-            Option::None,
-            Option::None,
-            Some(merchant_data),
-        )
-        .await;
+        if result.is_ok() {
+            // test contents of subscription token account
+            let subscription_data =
+                get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+            assert_eq!(
+                (SubscriptionStatus::Initialized as u8),
+                subscription_data.status
+            );
+            assert_eq!(String::from(package_name), subscription_data.name);
+            assert_eq!(
+                payer.pubkey(),
+                Pubkey::new_from_array(subscription_data.owner)
+            );
+            assert_eq!(
+                merchant_account_pubkey,
+                Pubkey::new_from_array(subscription_data.merchant)
+            );
+            assert_eq!(String::from("{}"), subscription_data.data);
+            assert_eq!(subscription_data.joined, subscription_data.modified);
+            // the paying order is linked for audits
+            assert_eq!(order_acc_pubkey.to_bytes(), subscription_data.current_order);
 
-        match create_chain_checkout_transaction(
-            paid_amount,
-            &order_items,
-            Option::None,
-            &mut merchant_result,
-            &input_mint,
-        )
-        .await
-        {
-            Err(error) => {
-                assert_eq!(
-                    error.unwrap(),
-                    TransactionError::InstructionError(0, expected_error)
-                );
-            }
-            Ok(_value) => panic!("Oo... we expect an error"),
-        };
+            return (
+                result,
+                Some((
+                    subscription_data,
+                    merchant_result,
+                    order_acc_pubkey,
+                    subscription,
+                )),
+            );
+        }
 
-        true
+        (result, Option::None)
     }
 
     #[tokio::test]
-    async fn test_chain_checkout_failure() {
-        let mint_a = Keypair::new();
-        let mint_b = Keypair::new();
-
-        // insufficient funds
+    async fn test_subscribe() {
+        let mint_keypair = Keypair::new();
+        let packages = format!(
+            r#"{{"packages":[{{"name":"basic","price":1000000,"duration":720,"mint":"{mint}"}},{{"name":"annual","price":11000000,"duration":262800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string()
+        );
         assert!(
-            chain_checkout_failing_test_helper(
-                1,       // id of item being ordered
-                20,      // amount to pay
-                &mint_a, // mint being used for payment
-                1,       // registered item id
-                30,      // expected amount
-                &mint_a, // expected mint
-                InstructionError::InsufficientFunds
-            )
-            .await
+            (run_subscribe_tests(1000000, "basic", &packages, &mint_keypair).await)
+                .0
+                .is_ok()
         );
+    }
 
-        // wrong item id in order
-        assert!(
-            chain_checkout_failing_test_helper(
-                7,       // id of item being ordered
-                20,      // amount to pay
-                &mint_a, // mint being used for payment
-                1,       // registered item id
-                30,      // expected amount
-                &mint_a, // expected mint
-                InstructionError::Custom(PaymentProcessorError::InvalidOrderData as u32)
-            )
-            .await
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_subscribe_with_packages_builder() {
+        let mint_keypair = Keypair::new();
+        let basic = Package {
+            name: String::from("basic"),
+            trial: Option::None,
+            trial_price: Option::None,
+            duration: 720,
+            price: 1000000,
+            mint: mint_keypair.pubkey().to_string(),
+        };
+        let annual = Package {
+            name: String::from("annual"),
+            trial: Option::None,
+            trial_price: Option::None,
+            duration: 262800,
+            price: 11000000,
+            mint: mint_keypair.pubkey().to_string(),
+        };
+        let packages = PackagesBuilder::new().add(basic).add(annual).build();
+        // no trial key is written for packages without a trial
+        assert!(!packages.contains("trial"));
+
+        // round trip
+        let parsed = parse_packages(&packages).unwrap();
+        assert_eq!(2, parsed.len());
+        assert_eq!("basic", parsed[0].name);
+        assert_eq!(262800, parsed[1].duration);
+        let rebuilt = parsed
+            .into_iter()
+            .fold(PackagesBuilder::new(), |builder, package| {
+                builder.add(package)
+            })
+            .build();
+        assert_eq!(packages, rebuilt);
+
+        let result = run_subscribe_tests(11000000, "annual", &packages, &mint_keypair).await;
+        assert!(result.0.is_ok());
+        let subscription_account = result.1.unwrap().0;
+        assert_eq!(
+            262800,
+            subscription_account.period_end - subscription_account.period_start
         );
+    }
 
-        // wrong mint in order
-        assert!(
-            chain_checkout_failing_test_helper(
-                1,       // id of item being ordered
-                20,      // amount to pay
-                &mint_a, // mint being used for payment
-                1,       // registered item id
-                20,      // expected amount
-                &mint_b, // expected mint
-                InstructionError::Custom(PaymentProcessorError::WrongMint as u32)
-            )
-            .await
+    #[tokio::test]
+    /// test paying for a subscription and subscribing in one instruction
+    async fn test_checkout_and_subscribe() {
+        let mint_keypair = Keypair::new();
+        let packages = format!(
+            r#"{{"packages":[{{"name":"basic","price":1000000,"duration":720,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string()
+        );
+        let mut merchant_result = create_merchant_account(
+            Some(String::from("subscription test")),
+            Option::None,
+            Option::None,
+            Some(packages),
+        )
+        .await;
+        let buyer_token_keypair =
+            create_token_account(1000000, &mint_keypair, &mut merchant_result).await;
+        let (subscription, _bump_seed) = get_subscription_account_pubkey(
+            &merchant_result.3.pubkey(),
+            &merchant_result.1,
+            "basic",
+            &merchant_result.0,
+        );
+        let (order_acc_keypair, seller_token, pda, merchant_data) = prepare_order(
+            &merchant_result.0,
+            &merchant_result.1,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let mut transaction = Transaction::new_with_payer(
+            &[checkout_and_subscribe(
+                merchant_result.0,
+                merchant_result.3.pubkey(),
+                order_acc_keypair.pubkey(),
+                merchant_result.1,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                Pubkey::new_from_array(merchant_data.fee_recipient),
+                Pubkey::new_from_array(merchant_data.sponsor),
+                pda,
+                subscription,
+                Option::None,
+                1000000,
+                OrderId::try_new("basic").unwrap(),
+                String::from(""),
+                String::from("basic"),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3, &order_acc_keypair], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
         );
+        let subscription_data =
+            get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+        assert_eq!(
+            SubscriptionStatus::Initialized as u8,
+            subscription_data.status
+        );
+        assert_eq!(String::from("basic"), subscription_data.name);
+        assert_eq!(
+            720,
+            subscription_data.period_end - subscription_data.period_start
+        );
+        // the order names the subscription and was used for it
+        let order_data =
+            get_account_data::<OrderAccount>(&mut merchant_result.2, order_acc_keypair.pubkey())
+                .await;
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+        assert_eq!(
+            format!(r#"{{"subscription":"{}"}}"#, subscription),
+            order_data.data
+        );
+        assert!(order_data.consumed);
+    }
 
-        // invalid merchant data
+    #[tokio::test]
+    /// test what happens when there are 0 packages
+    async fn test_subscribe_no_packages() {
+        let mint_keypair = Keypair::new();
+        let packages = r#"{"packages":[]}"#;
         assert!(
-            chain_checkout_failing_test_helper(
-                1,       // id of item being ordered
-                20,      // amount to pay
-                &mint_a, // mint being used for payment
-                0,       // registered item id
-                20,      // expected amount
-                &mint_a, // expected mint
-                InstructionError::Custom(PaymentProcessorError::InvalidMerchantData as u32)
-            )
-            .await
+            (run_subscribe_tests(1337, "basic", packages, &mint_keypair).await)
+                .0
+                .is_err()
         );
     }
 
     #[tokio::test]
-    async fn test_express_checkout() {
-        let amount: u64 = 2000000000;
-        let order_id = String::from("1337");
-        let secret = String::from("hunter2");
-        let mut merchant_result =
-            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
+    /// test what happens when there are duplicate packages
+    async fn test_subscribe_duplicate_packages() {
         let mint_keypair = Keypair::new();
-        let (order_acc_pubkey, seller_account_pubkey) = create_order_express_checkout(
-            amount,
-            &order_id,
-            &secret,
-            Option::None,
-            &mut merchant_result,
-            &mint_keypair,
-        )
-        .await;
+        let packages = format!(
+            r#"{{"packages":[{{"name":"a","price":100,"duration":720,"mint":"{mint}"}},{{"name":"a","price":222,"duration":262800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string()
+        );
 
-        run_checkout_tests(
-            amount,
-            order_id,
-            secret,
-            Option::None,
-            &mut merchant_result,
-            &order_acc_pubkey,
-            &seller_account_pubkey,
-            &mint_keypair,
-        )
-        .await;
+        let result = run_subscribe_tests(100, "a", &packages, &mint_keypair).await;
+        assert!(result.0.is_ok());
+
+        let _ = match result.1 {
+            None => (),
+            Some(value) => {
+                let subscription_account = value.0;
+                // use the duration of the first package in the array to check
+                // that the subscription was created using the first array element
+                assert_eq!(
+                    720,
+                    subscription_account.period_end - subscription_account.period_start
+                );
+                ()
+            }
+        };
     }
 
     #[tokio::test]
-    /// test checkout with all merchant options
-    async fn test_express_checkout_with_all_options() {
-        let sponsor_pk = Pubkey::new_unique();
-        let amount: u64 = 2000000000;
-        let order_id = String::from("123-SQT-MX");
-        let secret = String::from("supersecret");
-        let mut merchant_result = create_merchant_account(
-            Some(String::from("Oo")),
-            Some(123456),
-            Some(&sponsor_pk),
-            Some(String::from(r#"{"foo": "bar"}"#)),
-        )
-        .await;
+    /// test what happens when the package is not found
+    async fn test_subscribe_package_not_found() {
         let mint_keypair = Keypair::new();
-        let (order_acc_pubkey, seller_account_pubkey) = create_order_express_checkout(
-            amount,
-            &order_id,
-            &secret,
-            Some(String::from(r#"{"a": "b"}"#)),
-            &mut merchant_result,
-            &mint_keypair,
-        )
-        .await;
-        run_checkout_tests(
-            amount,
-            order_id,
-            secret,
-            Some(String::from(r#"{"a": "b"}"#)),
-            &mut merchant_result,
-            &order_acc_pubkey,
-            &seller_account_pubkey,
-            &mint_keypair,
-        )
-        .await;
+        let packages = format!(
+            r#"{{"packages":[{{"name":"a","price":100,"duration":720,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string()
+        );
+        assert!(
+            (run_subscribe_tests(100, "zz", &packages, &mint_keypair).await)
+                .0
+                .is_err()
+        );
     }
 
-    async fn run_order_token_account_refund_tests(
-        order_payment_token_acc: &Option<solana_sdk::account::Account>,
-        account_to_receive_sol_refund_before: &Option<solana_sdk::account::Account>,
-        account_to_receive_sol_refund_after: &Option<solana_sdk::account::Account>,
-        previous_order_account: &Option<solana_sdk::account::Account>,
-    ) {
-        // order token account is closed
-        assert!(order_payment_token_acc.is_none());
-        let order_account_rent = match previous_order_account {
-            None => 0,
-            Some(account) => account.lamports,
-        };
-        match account_to_receive_sol_refund_before {
-            None => panic!("Oo"),
-            Some(account_before) => match account_to_receive_sol_refund_after {
-                None => panic!("Oo"),
-                Some(account_after) => {
-                    // the before balance has increased by the rent amount of both token and order account
-                    assert_eq!(
-                        account_before.lamports,
-                        account_after.lamports
-                            - (Rent::default().minimum_balance(TokenAccount::LEN)
-                                + order_account_rent)
-                    );
-                }
-            },
-        };
+    #[tokio::test]
+    /// test what happens when there is no packages object in the JSON
+    async fn test_subscribe_no_packages_json() {
+        let mint_keypair = Keypair::new();
+        assert!(
+            (run_subscribe_tests(250, "package", r#"{}"#, &mint_keypair).await)
+                .0
+                .is_err()
+        );
     }
 
-    async fn withdraw_helper(
-        amount: u64,
-        close_order_account: bool,
-    ) -> (
-        BanksClient,
-        Option<solana_sdk::account::Account>,
-        Pubkey,
-        Pubkey,
-        Option<solana_sdk::account::Account>,
-        Option<solana_sdk::account::Account>,
-    ) {
-        let mut merchant_result =
-            create_merchant_account(Option::None, Option::None, Option::None, Option::None).await;
-        let merchant_token_keypair = Keypair::new();
-        let order_id = String::from("PD17CUSZ75");
-        let secret = String::from("i love oov");
+    #[tokio::test]
+    /// test what happens when there is no valid JSON
+    async fn test_subscribe_no_json() {
         let mint_keypair = Keypair::new();
-        let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
-            amount,
-            &order_id,
-            &secret,
-            Option::None,
-            &mut merchant_result,
-            &mint_keypair,
-        )
-        .await;
-        let program_id = merchant_result.0;
-        let merchant_account_pubkey = merchant_result.1;
-        let mut banks_client = merchant_result.2;
-        let payer = merchant_result.3;
-        let recent_blockhash = merchant_result.4;
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        assert!(
+            (run_subscribe_tests(250, "package", "what is?", &mint_keypair).await)
+                .0
+                .is_err()
+        );
+    }
 
-        // create and initialize merchant token account
-        assert_matches!(
-            banks_client
-                .process_transaction(create_token_account_transaction(
-                    &payer,
-                    &mint_keypair,
-                    recent_blockhash,
-                    &merchant_token_keypair,
-                    &payer.pubkey(),
-                    0,
-                ))
-                .await,
-            Ok(())
+    /// subscribe with an order and merchant that have the given data
+    async fn run_subscribe_bad_data_test(
+        merchant_data: &str,
+        order_data: &str,
+    ) -> TransactionError {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let merchant = Pubkey::new_unique();
+        let order = Pubkey::new_unique();
+        let buyer = Keypair::new();
+        let name = "basic";
+        let (subscription, _bump_seed) =
+            get_subscription_account_pubkey(&buyer.pubkey(), &merchant, name, &program_id);
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
         );
-        let (order_payment_token_acc_pubkey, _bump_seed) = Pubkey::find_program_address(
-            &[
-                &order_acc_pubkey.to_bytes(),
-                &spl_token::id().to_bytes(),
-                &mint_keypair.pubkey().to_bytes(),
-            ],
-            &program_id,
+        let mut merchant_account_data =
+            vec![0; get_merchant_account_size(&String::from(merchant_data), &[], &[])];
+        MerchantAccount {
+            discriminator: Discriminator::MerchantSubscription as u8,
+            owner: Pubkey::new_unique().to_bytes(),
+            sponsor: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            fee: DEFAULT_FEE_IN_LAMPORTS,
+            data: String::from(merchant_data),
+            fee_recipient: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        }
+        .pack(&mut merchant_account_data);
+        let data = order_data.replace("SUBSCRIPTION", &subscription.to_string());
+        let mut order_account_data =
+            vec![0; get_order_account_size(&String::from(name), &Option::None, &data)];
+        OrderAccount {
+            discriminator: Discriminator::OrderExpressCheckout as u8,
+            status: OrderStatus::Paid as u8,
+            created: 0,
+            modified: 0,
+            merchant: merchant.to_bytes(),
+            mint: Pubkey::new_unique().to_bytes(),
+            token: Pubkey::new_unique().to_bytes(),
+            payer: buyer.pubkey().to_bytes(),
+            expected_amount: 1000,
+            paid_amount: 1000,
+            order_id: String::from(name),
+            secret: Option::None,
+            data,
+            refunded_amount: 0,
+            locked: false,
+            consumed: false,
+            quoted_amount: 0,
+        }
+        .pack(&mut order_account_data);
+        for (key, data) in vec![
+            (merchant, merchant_account_data),
+            (order, order_account_data),
+        ] {
+            program_test.add_account(
+                key,
+                solana_sdk::account::Account {
+                    lamports: Rent::default().minimum_balance(data.len()),
+                    data,
+                    owner: program_id,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+        }
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+        let mut transaction = Transaction::new_with_payer(
+            &[subscribe(
+                program_id,
+                buyer.pubkey(),
+                subscription,
+                merchant,
+                order,
+                Option::None,
+                String::from(name),
+                Option::None,
+            )],
+            Some(&payer.pubkey()),
         );
-
-        let account_to_receive_sol_refund_pubkey = Pubkey::from_str(PROGRAM_OWNER).unwrap();
-        let account_to_receive_sol_refund_before = banks_client
-            .get_account(account_to_receive_sol_refund_pubkey)
+        transaction.sign(&[&payer, &buyer], recent_blockhash);
+        banks_client
+            .process_transaction(transaction)
             .await
-            .unwrap();
+            .unwrap_err()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    /// test that bad order data and bad merchant data give different errors
+    async fn test_subscribe_bad_data_errors() {
+        let packages = format!(
+            r#"{{"packages":[{{"name":"basic","price":1000,"duration":720,"mint":"{mint}"}}]}}"#,
+            mint = Pubkey::new_unique()
+        );
+        assert_eq!(
+            run_subscribe_bad_data_test(&packages, r#"{"foo": "bar"}"#).await,
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InvalidOrderData as u32)
+            )
+        );
+        assert_eq!(
+            run_subscribe_bad_data_test("what is?", r#"{"subscription": "SUBSCRIPTION"}"#).await,
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InvalidMerchantData as u32)
+            )
+        );
+    }
 
-        let previous_order_account = banks_client.get_account(order_acc_pubkey).await;
-        let previous_order_account = match previous_order_account {
-            Err(error) => panic!("Problem: {:?}", error),
-            Ok(value) => value,
+    /// verify access to the "basic" package with a subscription that ends at period_end
+    async fn run_verify_subscription_access_test(
+        status: SubscriptionStatus,
+        period_end: i64,
+        signer_is_owner: bool,
+        package_name: &str,
+    ) -> Result<(), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let merchant = Pubkey::new_unique();
+        let owner = Keypair::new();
+        let subscription = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        );
+        let data = SubscriptionAccount {
+            discriminator: Discriminator::Subscription as u8,
+            status: status as u8,
+            owner: owner.pubkey().to_bytes(),
+            merchant: merchant.to_bytes(),
+            name: String::from("basic"),
+            joined: 0,
+            period_start: 0,
+            period_end,
+            data: String::from("{}"),
+            modified: 0,
+            current_order: Pubkey::new_unique().to_bytes(),
+        }
+        .try_to_vec()
+        .unwrap();
+        program_test.add_account(
+            subscription,
+            solana_sdk::account::Account {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+        let signer = if signer_is_owner {
+            owner
+        } else {
+            Keypair::new()
         };
-
-        // call withdraw ix
         let mut transaction = Transaction::new_with_payer(
-            &[withdraw(
+            &[verify_subscription_access(
                 program_id,
-                payer.pubkey(),
-                order_acc_pubkey,
-                merchant_account_pubkey,
-                order_payment_token_acc_pubkey,
-                merchant_token_keypair.pubkey(),
-                account_to_receive_sol_refund_pubkey,
-                pda,
-                Option::None,
-                close_order_account,
+                signer.pubkey(),
+                subscription,
+                merchant,
+                String::from(package_name),
             )],
             Some(&payer.pubkey()),
         );
-        transaction.sign(&[&payer], recent_blockhash);
-        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
-
-        // test contents of merchant token account
-        let merchant_token_account = banks_client
-            .get_account(merchant_token_keypair.pubkey())
-            .await;
-        let merchant_account_data = match merchant_token_account {
-            Ok(data) => match data {
-                None => panic!("Oo"),
-                Some(value) => match spl_token::state::Account::unpack(&value.data) {
-                    Ok(data) => data,
-                    Err(error) => panic!("Problem: {:?}", error),
-                },
-            },
-            Err(error) => panic!("Problem: {:?}", error),
-        };
-        assert_eq!(amount, merchant_account_data.amount);
-
-        let order_account = banks_client.get_account(order_acc_pubkey).await;
-        let order_account = match order_account {
-            Err(error) => panic!("Problem: {:?}", error),
-            Ok(value) => value,
-        };
+        transaction.sign(&[&payer, &signer], recent_blockhash);
+        banks_client.process_transaction(transaction).await
+    }
 
-        (
-            banks_client,
-            order_account,
-            order_payment_token_acc_pubkey,
-            account_to_receive_sol_refund_pubkey,
-            account_to_receive_sol_refund_before,
-            previous_order_account,
-        )
+    #[tokio::test]
+    /// test that only the owner of an active subscription to the package gets access
+    async fn test_verify_subscription_access() {
+        let active = i64::MAX;
+        let expired = 1;
+        let basic = "basic";
+        assert_matches!(
+            run_verify_subscription_access_test(
+                SubscriptionStatus::Initialized,
+                active,
+                true,
+                basic
+            )
+            .await,
+            Ok(())
+        );
+        // cancelled subscriptions are active until their period ends
+        assert_matches!(
+            run_verify_subscription_access_test(SubscriptionStatus::Cancelled, active, true, basic)
+                .await,
+            Ok(())
+        );
+        for (status, period_end, signer_is_owner, package_name, error) in vec![
+            (
+                SubscriptionStatus::Initialized,
+                expired,
+                true,
+                basic,
+                PaymentProcessorError::SubscriptionNotActive,
+            ),
+            (
+                SubscriptionStatus::Paused,
+                active,
+                true,
+                basic,
+                PaymentProcessorError::SubscriptionNotActive,
+            ),
+            (
+                SubscriptionStatus::Initialized,
+                active,
+                false,
+                basic,
+                PaymentProcessorError::WrongSubscriptionOwner,
+            ),
+            (
+                SubscriptionStatus::Initialized,
+                active,
+                true,
+                "premium",
+                PaymentProcessorError::InvalidSubscriptionPackage,
+            ),
+        ] {
+            assert_eq!(
+                run_verify_subscription_access_test(
+                    status,
+                    period_end,
+                    signer_is_owner,
+                    package_name
+                )
+                .await
+                .unwrap_err()
+                .unwrap(),
+                TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+            );
+        }
     }
 
     #[tokio::test]
-    async fn test_withdraw() {
-        let amount: u64 = 1234567890;
-        let (
-            mut banks_client,
-            order_account,
-            order_payment_token_acc_pubkey,
-            account_to_receive_sol_refund_pubkey,
-            account_to_receive_sol_refund_before,
-            _previous_order_account,
-        ) = withdraw_helper(amount, false).await;
-        // test contents of order account
-        let order_data = match order_account.clone() {
-            None => panic!("Oo"),
-            Some(value) => match OrderAccount::unpack(&value.data) {
-                Ok(data) => data,
-                Err(error) => panic!("Problem: {:?}", error),
-            },
-        };
-        assert_eq!(OrderStatus::Withdrawn as u8, order_data.status);
-        assert_eq!(amount, order_data.expected_amount);
-        assert_eq!(amount, order_data.paid_amount);
-        // test that token account was closed and that the refund was sent to expected account
-        let order_payment_token_acc = banks_client
-            .get_account(order_payment_token_acc_pubkey)
-            .await
-            .unwrap();
-        let account_to_receive_sol_refund_after = banks_client
-            .get_account(account_to_receive_sol_refund_pubkey)
-            .await
-            .unwrap();
-        run_order_token_account_refund_tests(
-            &order_payment_token_acc,
-            &account_to_receive_sol_refund_before,
-            &account_to_receive_sol_refund_after,
-            &Option::None,
-        )
-        .await;
+    /// test what happens when the amount paid is insufficient
+    async fn test_subscribe_not_enough_paid() {
+        let mint_keypair = Keypair::new();
+        let packages = format!(
+            r#"{{"packages":[{{"name":"basic","price":100,"duration":720,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string()
+        );
+        assert!(
+            (run_subscribe_tests(10, "Netflix-basic", &packages, &mint_keypair).await)
+                .0
+                .is_err()
+        );
     }
 
     #[tokio::test]
-    async fn test_withdraw_close_order_account() {
-        let amount: u64 = 10001;
-        let (
-            mut banks_client,
-            order_account,
-            order_payment_token_acc_pubkey,
-            account_to_receive_sol_refund_pubkey,
-            account_to_receive_sol_refund_before,
-            previous_order_account,
-        ) = withdraw_helper(amount, true).await;
-        // test closure of order account
-        assert!(order_account.is_none());
-        // test that accounts were closed and that refunds sent to expected account
-        let order_payment_token_acc = banks_client
-            .get_account(order_payment_token_acc_pubkey)
-            .await
-            .unwrap();
-        let account_to_receive_sol_refund_after = banks_client
-            .get_account(account_to_receive_sol_refund_pubkey)
-            .await
-            .unwrap();
-        run_order_token_account_refund_tests(
-            &order_payment_token_acc,
-            &account_to_receive_sol_refund_before,
-            &account_to_receive_sol_refund_after,
-            &previous_order_account,
-        )
-        .await;
+    /// test that a package lasting nearly forever does not wrap the period end around
+    async fn test_subscribe_period_overflow() {
+        let mint_keypair = Keypair::new();
+        let packages = format!(
+            r#"{{"packages":[{{"name":"forever","price":100,"duration":{duration},"mint":"{mint}"}}]}}"#,
+            duration = i64::MAX - 1,
+            mint = mint_keypair.pubkey().to_string()
+        );
+        let result = run_subscribe_tests(100, "forever", &packages, &mint_keypair).await;
+        assert_eq!(
+            result.0.unwrap_err().unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::SubscriptionPeriodOverflow as u32)
+            )
+        );
+        assert!(result.1.is_none());
     }
 
-    async fn run_subscribe_tests(
-        amount: u64,
-        package_name: &str,
-        merchant_data: &str,
-        mint_keypair: &Keypair,
-    ) -> (
-        Result<(), TransportError>,
-        Option<(SubscriptionAccount, MerchantResult, Pubkey, Pubkey)>,
-    ) {
+    #[tokio::test]
+    async fn test_subscription_renewal() {
+        let mint_keypair = Keypair::new();
+        let name = "short";
+        // create a package that lasts only 1 second
+        let packages = format!(
+            r#"{{"packages":[{{"name":"{name}","price":999999,"duration":1,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string(),
+            name = name
+        );
+        let result = run_subscribe_tests(1000000, name, &packages, &mint_keypair).await;
+        assert!(result.0.is_ok());
+        let subscribe_result = result.1;
+        let _ = match subscribe_result {
+            None => (),
+            Some(mut subscribe_result) => {
+                let subscription_account = subscribe_result.0;
+                let subscription = subscribe_result.3; // the subscription pubkey
+
+                let order_data = format!(r#"{{"subscription": "{}"}}"#, subscription.to_string());
+
+                let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
+                    999999 * 600,
+                    &format!("{name}", name = name),
+                    &String::from(""),
+                    Some(order_data),
+                    &mut subscribe_result.1,
+                    &mint_keypair,
+                )
+                .await;
+
+                // call subscription  ix
+                let mut transaction = Transaction::new_with_payer(
+                    &[renew_subscription(
+                        subscribe_result.1 .0,          // program_id,
+                        subscribe_result.1 .3.pubkey(), // payer,
+                        subscription,
+                        Pubkey::new_from_array(subscription_account.merchant),
+                        order_acc_pubkey,
+                        Option::None,
+                        600,
+                    )],
+                    Some(&subscribe_result.1 .3.pubkey()),
+                );
+                transaction.sign(&[&subscribe_result.1 .3], subscribe_result.1 .4);
+                assert_matches!(
+                    subscribe_result.1 .2.process_transaction(transaction).await,
+                    Ok(())
+                );
+
+                // assert that period end has been updated
+                let subscription_account2 = get_account_data::<SubscriptionAccount>(
+                    &mut subscribe_result.1 .2,
+                    subscription,
+                )
+                .await;
+                assert_eq!(
+                    // the new period_end is equal to the old period_end + (1 * 600)
+                    subscription_account.period_end + 600,
+                    subscription_account2.period_end
+                );
+                // assert that modified has advanced
+                assert!(subscription_account2.modified >= subscription_account.modified);
+                // the renewal order now pays for the current period
+                assert_ne!(
+                    subscription_account.current_order,
+                    subscription_account2.current_order
+                );
+                assert_eq!(
+                    order_acc_pubkey.to_bytes(),
+                    subscription_account2.current_order
+                );
+
+                // the same order cannot be used for another renewal
+                let mut transaction = Transaction::new_with_payer(
+                    &[renew_subscription(
+                        subscribe_result.1 .0,
+                        subscribe_result.1 .3.pubkey(),
+                        subscription,
+                        Pubkey::new_from_array(subscription_account.merchant),
+                        order_acc_pubkey,
+                        Option::None,
+                        1,
+                    )],
+                    Some(&subscribe_result.1 .3.pubkey()),
+                );
+                transaction.sign(&[&subscribe_result.1 .3], subscribe_result.1 .4);
+                assert_eq!(
+                    subscribe_result
+                        .1
+                         .2
+                        .process_transaction(transaction)
+                        .await
+                        .unwrap_err()
+                        .unwrap(),
+                    TransactionError::InstructionError(
+                        0,
+                        InstructionError::Custom(PaymentProcessorError::OrderAlreadyUsed as u32)
+                    )
+                );
+
+                return ();
+            }
+        };
+    }
+
+    #[tokio::test]
+    /// test subscribing with package accounts instead of packages in the merchant data
+    async fn test_package_accounts() {
         let mut merchant_result = create_merchant_account(
-            Some(String::from("subscription test")),
+            Some(String::from("package test")),
+            Option::None,
             Option::None,
             Option::None,
-            Some(String::from(merchant_data)),
         )
         .await;
+        let program_id = merchant_result.0;
+        let merchant = merchant_result.1;
+        let basic_mint_keypair = Keypair::new();
+        let pro_mint_keypair = Keypair::new();
 
-        let (subscription, _bump_seed) = Pubkey::find_program_address(
+        // add two packages
+        let mut transaction = Transaction::new_with_payer(
             &[
-                &merchant_result.3.pubkey().to_bytes(), // payer
-                &merchant_result.1.to_bytes(),          // merchant
-                &package_name.as_bytes(),
+                add_package(
+                    program_id,
+                    merchant_result.3.pubkey(),
+                    merchant,
+                    String::from("basic"),
+                    604800,
+                    1000,
+                    basic_mint_keypair.pubkey(),
+                ),
+                add_package(
+                    program_id,
+                    merchant_result.3.pubkey(),
+                    merchant,
+                    String::from("pro"),
+                    2592000,
+                    5000,
+                    pro_mint_keypair.pubkey(),
+                ),
             ],
-            &merchant_result.0, // program id
+            Some(&merchant_result.3.pubkey()),
         );
-
-        let order_data = format!(r#"{{"subscription": "{}"}}"#, subscription.to_string());
-
-        let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
-            amount,
-            &String::from(package_name),
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let merchant_data =
+            get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant).await;
+        assert_eq!(
+            Discriminator::MerchantSubscriptionPackages as u8,
+            merchant_data.discriminator
+        );
+        let (basic_package, _bump_seed) = get_package_address(&merchant, "basic", &program_id);
+        let (pro_package, _bump_seed) = get_package_address(&merchant, "pro", &program_id);
+        let package_data =
+            get_account_data::<PackageAccount>(&mut merchant_result.2, basic_package).await;
+        assert_eq!(Discriminator::Package as u8, package_data.discriminator);
+        assert_eq!(merchant.to_bytes(), package_data.merchant);
+        assert_eq!(basic_mint_keypair.pubkey().to_bytes(), package_data.mint);
+        assert_eq!(604800, package_data.duration);
+        assert_eq!(1000, package_data.price);
+        assert_eq!(String::from("basic"), package_data.name);
+
+        // subscribe against the basic package
+        let (subscription, _bump_seed) = get_subscription_account_pubkey(
+            &merchant_result.3.pubkey(),
+            &merchant,
+            "basic",
+            &program_id,
+        );
+        let (order, _seller_token) = create_order_express_checkout(
+            1000,
+            &String::from("basic"),
             &String::from(""),
-            Some(order_data),
+            Some(format!(r#"{{"subscription": "{}"}}"#, subscription)),
             &mut merchant_result,
-            &mint_keypair,
+            &basic_mint_keypair,
         )
         .await;
-
-        let program_id = merchant_result.0;
-        let merchant_account_pubkey = merchant_result.1;
-        let payer = &merchant_result.3;
-        let recent_blockhash = merchant_result.4;
-
-        // call subscribe ix
         let mut transaction = Transaction::new_with_payer(
             &[subscribe(
                 program_id,
-                payer.pubkey(),
+                merchant_result.3.pubkey(),
                 subscription,
-                merchant_account_pubkey,
-                order_acc_pubkey,
-                String::from(package_name),
+                merchant,
+                order,
+                Some(basic_package),
+                String::from("basic"),
                 Option::None,
             )],
-            Some(&payer.pubkey()),
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let subscription_data =
+            get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+        assert_eq!(String::from("basic"), subscription_data.name);
+        assert_eq!(
+            604800,
+            subscription_data.period_end - subscription_data.period_start
         );
-        transaction.sign(&[payer], recent_blockhash);
-
-        let result = merchant_result.2.process_transaction(transaction).await;
-
-        if result.is_ok() {
-            // test contents of subscription token account
-            let subscription_account = &merchant_result.2.get_account(subscription).await;
-            let subscription_data = match subscription_account {
-                Ok(data) => match data {
-                    None => panic!("Oo"),
-                    Some(value) => match SubscriptionAccount::unpack(&value.data) {
-                        Ok(data) => data,
-                        Err(error) => panic!("Problem: {:?}", error),
-                    },
-                },
-                Err(error) => panic!("Problem: {:?}", error),
-            };
-            assert_eq!(
-                (SubscriptionStatus::Initialized as u8),
-                subscription_data.status
-            );
-            assert_eq!(String::from(package_name), subscription_data.name);
-            assert_eq!(
-                payer.pubkey(),
-                Pubkey::new_from_array(subscription_data.owner)
-            );
-            assert_eq!(
-                merchant_account_pubkey,
-                Pubkey::new_from_array(subscription_data.merchant)
-            );
-            assert_eq!(String::from("{}"), subscription_data.data);
 
-            return (
-                result,
-                Some((
-                    subscription_data,
-                    merchant_result,
-                    order_acc_pubkey,
-                    subscription,
-                )),
-            );
-        }
+        // remove the pro package
+        let mut transaction = Transaction::new_with_payer(
+            &[remove_package(
+                program_id,
+                merchant_result.3.pubkey(),
+                merchant,
+                "pro",
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        assert_eq!(
+            Option::None,
+            merchant_result.2.get_account(pro_package).await.unwrap()
+        );
 
-        (result, Option::None)
+        // one can no longer subscribe against the removed package
+        let (subscription, _bump_seed) = get_subscription_account_pubkey(
+            &merchant_result.3.pubkey(),
+            &merchant,
+            "pro",
+            &program_id,
+        );
+        let (order, _seller_token) = create_order_express_checkout(
+            5000,
+            &String::from("pro"),
+            &String::from(""),
+            Some(format!(r#"{{"subscription": "{}"}}"#, subscription)),
+            &mut merchant_result,
+            &pro_mint_keypair,
+        )
+        .await;
+        let mut transaction = Transaction::new_with_payer(
+            &[subscribe(
+                program_id,
+                merchant_result.3.pubkey(),
+                subscription,
+                merchant,
+                order,
+                Some(pro_package),
+                String::from("pro"),
+                Option::None,
+            )],
+            Some(&merchant_result.3.pubkey()),
+        );
+        transaction.sign(&[&merchant_result.3], merchant_result.4);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+        );
     }
 
     #[tokio::test]
-    async fn test_subscribe() {
+    async fn test_pause_and_resume_subscription() {
         let mint_keypair = Keypair::new();
+        let name = "pausable";
         let packages = format!(
-            r#"{{"packages":[{{"name":"basic","price":1000000,"duration":720,"mint":"{mint}"}},{{"name":"annual","price":11000000,"duration":262800,"mint":"{mint}"}}]}}"#,
-            mint = mint_keypair.pubkey().to_string()
+            r#"{{"packages":[{{"name":"{name}","price":1000,"duration":604800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string(),
+            name = name
         );
-        assert!(
-            (run_subscribe_tests(1000000, "basic", &packages, &mint_keypair).await)
-                .0
-                .is_ok()
+        let result = run_subscribe_tests(1000, name, &packages, &mint_keypair).await;
+        assert!(result.0.is_ok());
+        let (subscription_account, mut merchant_result, _order_acc_pubkey, subscription) =
+            result.1.unwrap();
+        let program_id = merchant_result.0;
+        let payer = merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+
+        // pause the subscription
+        let mut transaction = Transaction::new_with_payer(
+            &[pause_subscription(program_id, payer.pubkey(), subscription)],
+            Some(&payer.pubkey()),
         );
-    }
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let paused_account =
+            get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+        assert_eq!(SubscriptionStatus::Paused as u8, paused_account.status);
+        assert!(paused_account.modified >= subscription_account.modified);
 
-    #[tokio::test]
-    /// test what happens when there are 0 packages
-    async fn test_subscribe_no_packages() {
-        let mint_keypair = Keypair::new();
-        let packages = r#"{"packages":[]}"#;
-        assert!(
-            (run_subscribe_tests(1337, "basic", packages, &mint_keypair).await)
-                .0
-                .is_err()
+        // a paused subscription cannot be paused again
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                pause_subscription(program_id, payer.pubkey(), subscription),
+                // make this transaction different from the first pause
+                system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1),
+            ],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InvalidSubscriptionData as u32)
+            )
+        );
+
+        // resume the subscription
+        let mut transaction = Transaction::new_with_payer(
+            &[resume_subscription(
+                program_id,
+                payer.pubkey(),
+                subscription,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        let resumed_account =
+            get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+        assert_eq!(
+            SubscriptionStatus::Initialized as u8,
+            resumed_account.status
         );
+        assert!(resumed_account.modified >= paused_account.modified);
+        // the time remaining in the period is kept
+        assert!(resumed_account.period_end >= subscription_account.period_end);
     }
 
-    #[tokio::test]
-    /// test what happens when there are duplicate packages
-    async fn test_subscribe_duplicate_packages() {
+    async fn run_keeper_renew_tests(
+        duration: i64,
+        keeper_reward_funds: u64,
+    ) -> (
+        Result<(), TransportError>,
+        SubscriptionAccount,
+        MerchantResult,
+        Pubkey,
+        Pubkey,
+        Pubkey,
+    ) {
         let mint_keypair = Keypair::new();
+        let name = "keeper";
+        let price: u64 = 1000;
         let packages = format!(
-            r#"{{"packages":[{{"name":"a","price":100,"duration":720,"mint":"{mint}"}},{{"name":"a","price":222,"duration":262800,"mint":"{mint}"}}]}}"#,
-            mint = mint_keypair.pubkey().to_string()
+            r#"{{"packages":[{{"name":"{name}","price":{price},"duration":{duration},"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string(),
+            name = name,
+            price = price,
+            duration = duration
+        );
+        run_keeper_renew_tests_for_package(
+            name,
+            &packages,
+            price,
+            price * 10,
+            keeper_reward_funds,
+            &mint_keypair,
+        )
+        .await
+    }
+
+    /// subscribe to a package and have a keeper renew it from a delegated token account
+    async fn run_keeper_renew_tests_for_package(
+        name: &str,
+        packages: &str,
+        amount: u64,
+        allowance: u64,
+        keeper_reward_funds: u64,
+        mint_keypair: &Keypair,
+    ) -> (
+        Result<(), TransportError>,
+        SubscriptionAccount,
+        MerchantResult,
+        Pubkey,
+        Pubkey,
+        Pubkey,
+    ) {
+        let result = run_subscribe_tests(amount, name, packages, mint_keypair).await;
+        assert!(result.0.is_ok());
+        let (subscription_account, mut merchant_result, _order_acc_pubkey, subscription) =
+            result.1.unwrap();
+        let program_id = merchant_result.0;
+        let merchant = merchant_result.1;
+        let payer = &merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+
+        // the subscriber sets aside a token account and lets the program spend from it
+        let buyer_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    payer,
+                    mint_keypair,
+                    recent_blockhash,
+                    &buyer_token_keypair,
+                    &payer.pubkey(),
+                    allowance,
+                ))
+                .await,
+            Ok(())
+        );
+        let mut transaction = Transaction::new_with_payer(
+            &[approve(
+                &spl_token::id(),
+                &buyer_token_keypair.pubkey(),
+                &pda,
+                &payer.pubkey(),
+                &[&payer.pubkey()],
+                allowance,
+            )
+            .unwrap()],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+
+        // fund a keeper
+        let keeper = Keypair::new();
+        let mut transaction = Transaction::new_with_payer(
+            &[system_instruction::transfer(
+                &payer.pubkey(),
+                &keeper.pubkey(),
+                1000000000,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        if keeper_reward_funds > 0 {
+            // the merchant sets aside lamports to reward keepers
+            let mut transaction = Transaction::new_with_payer(
+                &[system_instruction::transfer(
+                    &payer.pubkey(),
+                    &merchant,
+                    keeper_reward_funds,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[payer], recent_blockhash);
+            assert_matches!(
+                merchant_result.2.process_transaction(transaction).await,
+                Ok(())
+            );
+        }
+
+        // the new order is derived from the merchant order count
+        let merchant_data =
+            get_account_data::<MerchantAccount>(&mut merchant_result.2, merchant).await;
+        let (order, _bump_seed) =
+            get_order_pubkey_by_index(&merchant, merchant_data.order_count, &program_id);
+        let (seller_token, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint_keypair.pubkey().to_bytes(),
+            ],
+            &program_id,
+        );
+
+        let mut transaction = Transaction::new_with_payer(
+            &[keeper_renew(
+                program_id,
+                keeper.pubkey(),
+                subscription,
+                merchant,
+                seller_token,
+                buyer_token_keypair.pubkey(),
+                mint_keypair.pubkey(),
+                pda,
+                Option::None,
+                merchant_data.order_count,
+            )],
+            Some(&keeper.pubkey()),
         );
+        transaction.sign(&[&keeper], recent_blockhash);
+        let result = merchant_result.2.process_transaction(transaction).await;
 
-        let result = run_subscribe_tests(100, "a", &packages, &mint_keypair).await;
-        assert!(result.0.is_ok());
-
-        let _ = match result.1 {
-            None => (),
-            Some(value) => {
-                let subscription_account = value.0;
-                // use the duration of the first package in the array to check
-                // that the subscription was created using the first array element
-                assert_eq!(
-                    720,
-                    subscription_account.period_end - subscription_account.period_start
-                );
-                ()
-            }
-        };
+        (
+            result,
+            subscription_account,
+            merchant_result,
+            subscription,
+            order,
+            keeper.pubkey(),
+        )
     }
 
     #[tokio::test]
-    /// test what happens when the package is not found
-    async fn test_subscribe_package_not_found() {
-        let mint_keypair = Keypair::new();
-        let packages = format!(
-            r#"{{"packages":[{{"name":"a","price":100,"duration":720,"mint":"{mint}"}}]}}"#,
-            mint = mint_keypair.pubkey().to_string()
+    async fn test_keeper_renew() {
+        // a package with no duration has lapsed as soon as it is paid for
+        let (result, subscription_account, mut merchant_result, subscription, order, _keeper) =
+            run_keeper_renew_tests(0, 0).await;
+        assert_matches!(result, Ok(()));
+
+        // a new period has started
+        let renewed_account =
+            get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+        assert_eq!(
+            SubscriptionStatus::Initialized as u8,
+            renewed_account.status
         );
-        assert!(
-            (run_subscribe_tests(100, "zz", &packages, &mint_keypair).await)
-                .0
-                .is_err()
+        assert!(renewed_account.period_start >= subscription_account.period_end);
+        assert_eq!(renewed_account.period_start, renewed_account.modified);
+        assert_eq!(order.to_bytes(), renewed_account.current_order);
+
+        // the renewal order was paid from the delegated token account
+        let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+        assert_eq!(1000, order_data.paid_amount);
+        assert_eq!(subscription_account.owner, order_data.payer);
+        assert_eq!(
+            format!(r#"{{"subscription":"{}"}}"#, subscription.to_string()),
+            order_data.data
         );
+        let seller_token_data = get_token_account_data(
+            &mut merchant_result.2,
+            Pubkey::new_from_array(order_data.token),
+        )
+        .await;
+        assert_eq!(1000, seller_token_data.amount);
     }
 
     #[tokio::test]
-    /// test what happens when there is no packages object in the JSON
-    async fn test_subscribe_no_packages_json() {
-        let mint_keypair = Keypair::new();
-        assert!(
-            (run_subscribe_tests(250, "package", r#"{}"#, &mint_keypair).await)
-                .0
-                .is_err()
-        );
+    /// test that the keeper is rewarded from the merchant account without taking its rent
+    async fn test_keeper_renew_reward() {
+        let mut unrewarded_keeper_balance = 0;
+        for (keeper_reward_funds, reward) in vec![
+            (0, 0),
+            (KEEPER_REWARD_IN_LAMPORTS / 2, KEEPER_REWARD_IN_LAMPORTS / 2),
+            (KEEPER_REWARD_IN_LAMPORTS * 3, KEEPER_REWARD_IN_LAMPORTS),
+        ] {
+            let (result, _subscription_account, mut merchant_result, _subscription, _order, keeper) =
+                run_keeper_renew_tests(0, keeper_reward_funds).await;
+            assert_matches!(result, Ok(()));
+            // the merchant account is left rent exempt
+            let merchant_account = merchant_result
+                .2
+                .get_account(merchant_result.1)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                Rent::default().minimum_balance(merchant_account.data.len()) + keeper_reward_funds
+                    - reward,
+                merchant_account.lamports
+            );
+            // the keeper pays the same for every renewal and gets the reward on top
+            let keeper_balance = merchant_result.2.get_balance(keeper).await.unwrap();
+            if keeper_reward_funds == 0 {
+                unrewarded_keeper_balance = keeper_balance;
+            }
+            assert_eq!(unrewarded_keeper_balance + reward, keeper_balance);
+        }
     }
 
     #[tokio::test]
-    /// test what happens when there is no valid JSON
-    async fn test_subscribe_no_json() {
-        let mint_keypair = Keypair::new();
-        assert!(
-            (run_subscribe_tests(250, "package", "what is?", &mint_keypair).await)
-                .0
-                .is_err()
+    /// test that an active subscription cannot be renewed by a keeper
+    async fn test_keeper_renew_not_lapsed() {
+        let (result, _subscription_account, _merchant_result, _subscription, _order, _keeper) =
+            run_keeper_renew_tests(604800, 0).await;
+        assert_eq!(
+            result.unwrap_err().unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::SubscriptionNotLapsed as u32)
+            )
         );
     }
 
     #[tokio::test]
-    /// test what happens when the amount paid is insufficient
-    async fn test_subscribe_not_enough_paid() {
+    /// test that a partial trial lapses at the trial end and a keeper converts it to a paid period
+    async fn test_keeper_renew_partial_trial() {
         let mint_keypair = Keypair::new();
+        let name = "partial";
         let packages = format!(
-            r#"{{"packages":[{{"name":"basic","price":100,"duration":720,"mint":"{mint}"}}]}}"#,
-            mint = mint_keypair.pubkey().to_string()
+            r#"{{"packages":[{{"name":"{name}","price":1000,"trial":0,"trial_price":100,"duration":604800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string(),
+            name = name
         );
-        assert!(
-            (run_subscribe_tests(10, "Netflix-basic", &packages, &mint_keypair).await)
+        // the trial price is not enough for a subscription without a partial trial
+        let full_packages = packages.replace(r#""trial_price":100,"#, "");
+        assert_eq!(
+            run_subscribe_tests(100, name, &full_packages, &mint_keypair)
+                .await
                 .0
-                .is_err()
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::NotFullyPaid as u32)
+            )
+        );
+
+        let (result, subscription_account, mut merchant_result, subscription, order, _keeper) =
+            run_keeper_renew_tests_for_package(name, &packages, 100, 10000, 0, &mint_keypair).await;
+        // only the trial was paid for when subscribing
+        assert_eq!(subscription_account.joined, subscription_account.period_end);
+        assert_matches!(result, Ok(()));
+
+        // the first full period was paid from the delegated token account
+        let renewed_account =
+            get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+        assert_eq!(
+            SubscriptionStatus::Initialized as u8,
+            renewed_account.status
+        );
+        assert_eq!(
+            renewed_account.period_start + 604800,
+            renewed_account.period_end
         );
+        assert_eq!(order.to_bytes(), renewed_account.current_order);
+        let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+        assert_eq!(OrderStatus::Paid as u8, order_data.status);
+        assert_eq!(1000, order_data.paid_amount);
     }
 
     #[tokio::test]
-    async fn test_subscription_renewal() {
+    /// test that a partial trial is left as is when the allowance does not cover the price
+    async fn test_keeper_renew_partial_trial_insufficient_allowance() {
         let mint_keypair = Keypair::new();
-        let name = "short";
-        // create a package that lasts only 1 second
+        let name = "partial";
         let packages = format!(
-            r#"{{"packages":[{{"name":"{name}","price":999999,"duration":1,"mint":"{mint}"}}]}}"#,
+            r#"{{"packages":[{{"name":"{name}","price":1000,"trial":0,"trial_price":100,"duration":604800,"mint":"{mint}"}}]}}"#,
             mint = mint_keypair.pubkey().to_string(),
             name = name
         );
-        let result = run_subscribe_tests(1000000, name, &packages, &mint_keypair).await;
-        assert!(result.0.is_ok());
-        let subscribe_result = result.1;
-        let _ = match subscribe_result {
-            None => (),
-            Some(mut subscribe_result) => {
-                let subscription_account = subscribe_result.0;
-                let subscription = subscribe_result.3; // the subscription pubkey
-
-                let order_data = format!(r#"{{"subscription": "{}"}}"#, subscription.to_string());
-
-                let (order_acc_pubkey, _seller_account_pubkey) = create_order_express_checkout(
-                    999999 * 600,
-                    &format!("{name}", name = name),
-                    &String::from(""),
-                    Some(order_data),
-                    &mut subscribe_result.1,
-                    &mint_keypair,
-                )
-                .await;
-
-                // call subscription  ix
-                let mut transaction = Transaction::new_with_payer(
-                    &[renew_subscription(
-                        subscribe_result.1 .0,          // program_id,
-                        subscribe_result.1 .3.pubkey(), // payer,
-                        subscription,
-                        Pubkey::new_from_array(subscription_account.merchant),
-                        order_acc_pubkey,
-                        600,
-                    )],
-                    Some(&subscribe_result.1 .3.pubkey()),
-                );
-                transaction.sign(&[&subscribe_result.1 .3], subscribe_result.1 .4);
-                assert_matches!(
-                    subscribe_result.1 .2.process_transaction(transaction).await,
-                    Ok(())
-                );
-
-                // assert that period end has been updated
-                let subscription_account2 = subscribe_result.1 .2.get_account(subscription).await;
-                let subscription_account2 = match subscription_account2 {
-                    Ok(data) => match data {
-                        None => panic!("Oo"),
-                        Some(value) => match SubscriptionAccount::unpack(&value.data) {
-                            Ok(data) => data,
-                            Err(error) => panic!("Problem: {:?}", error),
-                        },
-                    },
-                    Err(error) => panic!("Problem: {:?}", error),
-                };
-                assert_eq!(
-                    // the new period_end is equal to the old period_end + (1 * 600)
-                    subscription_account.period_end + 600,
-                    subscription_account2.period_end
-                );
-
-                return ();
-            }
-        };
+        let (result, subscription_account, mut merchant_result, subscription, _order, _keeper) =
+            run_keeper_renew_tests_for_package(name, &packages, 100, 999, 0, &mint_keypair).await;
+        assert_eq!(
+            result.unwrap_err().unwrap(),
+            TransactionError::InstructionError(0, InstructionError::InsufficientFunds)
+        );
+        let subscription_data =
+            get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+        assert_eq!(subscription_account, subscription_data);
     }
 
     async fn run_subscription_withdrawal_tests(
@@ -1834,6 +11356,7 @@ mod test {
                         merchant_token_keypair.pubkey(),
                         Pubkey::from_str(PROGRAM_OWNER).unwrap(),
                         pda,
+                        mint_keypair.pubkey(),
                         Some(subscription),
                         false,
                     )],
@@ -1890,6 +11413,163 @@ mod test {
         run_subscription_withdrawal_tests(name, &packages, &mint_keypair, true).await;
     }
 
+    #[tokio::test]
+    /// test that a batch withdraw skips the orders still in their trial period
+    async fn test_withdraw_subscription_batch() {
+        let amount: u64 = 1000000;
+        let mint_keypair = Keypair::new();
+        let packages = format!(
+            r#"{{"packages":[{{"name":"trialFirst","price":99,"trial":0,"duration":604800,"mint":"{mint}"}},{{"name":"try1st","price":99,"trial":604800,"duration":604800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string()
+        );
+        // the trial of the first subscription is already over
+        let (_subscription_account, mut merchant_result, order, subscription) =
+            run_subscribe_tests(amount, "trialFirst", &packages, &mint_keypair)
+                .await
+                .1
+                .unwrap();
+        let program_id = merchant_result.0;
+        let merchant = merchant_result.1;
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+
+        // the second subscription is still in its trial
+        let (trial_subscription, _bump_seed) = get_subscription_account_pubkey(
+            &merchant_result.3.pubkey(),
+            &merchant,
+            "try1st",
+            &program_id,
+        );
+        let buyer_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &merchant_result.3,
+                    &mint_keypair,
+                    merchant_result.4,
+                    &buyer_token_keypair,
+                    &merchant_result.3.pubkey(),
+                    amount,
+                ))
+                .await,
+            Ok(())
+        );
+        let (trial_order_keypair, trial_order_token, _pda, merchant_data) = prepare_order(
+            &program_id,
+            &merchant,
+            &mint_keypair.pubkey(),
+            &mut merchant_result.2,
+        )
+        .await;
+        let trial_order = trial_order_keypair.pubkey();
+        let payer = &merchant_result.3;
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                express_checkout(
+                    program_id,
+                    payer.pubkey(),
+                    trial_order,
+                    merchant,
+                    trial_order_token,
+                    buyer_token_keypair.pubkey(),
+                    mint_keypair.pubkey(),
+                    Pubkey::new_from_array(merchant_data.fee_recipient),
+                    Pubkey::new_from_array(merchant_data.sponsor),
+                    pda,
+                    amount,
+                    OrderId::try_new("try1st").unwrap(),
+                    String::from(""),
+                    Some(format!(
+                        r#"{{"subscription": "{}"}}"#,
+                        trial_subscription.to_string()
+                    )),
+                ),
+                subscribe(
+                    program_id,
+                    payer.pubkey(),
+                    trial_subscription,
+                    merchant,
+                    trial_order,
+                    Option::None,
+                    String::from("try1st"),
+                    Option::None,
+                ),
+            ],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer, &trial_order_keypair], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+
+        let merchant_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &merchant_result.3,
+                    &mint_keypair,
+                    merchant_result.4,
+                    &merchant_token_keypair,
+                    &merchant_result.3.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (order_token, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint_keypair.pubkey().to_bytes(),
+            ],
+            &program_id,
+        );
+        let payer = &merchant_result.3;
+        let mut transaction = Transaction::new_with_payer(
+            &[withdraw_subscription_batch(
+                program_id,
+                payer.pubkey(),
+                merchant,
+                merchant_token_keypair.pubkey(),
+                payer.pubkey(),
+                pda,
+                mint_keypair.pubkey(),
+                vec![
+                    (trial_order, trial_order_token, trial_subscription),
+                    (order, order_token, subscription),
+                ],
+                false,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], merchant_result.4);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+
+        // only the order past its trial was withdrawn
+        let order_data = get_account_data::<OrderAccount>(&mut merchant_result.2, order).await;
+        assert_eq!(OrderStatus::Withdrawn as u8, order_data.status);
+        let trial_order_data =
+            get_account_data::<OrderAccount>(&mut merchant_result.2, trial_order).await;
+        assert_eq!(OrderStatus::Paid as u8, trial_order_data.status);
+        assert_eq!(
+            amount,
+            get_token_account_data(&mut merchant_result.2, merchant_token_keypair.pubkey())
+                .await
+                .amount
+        );
+        assert_eq!(
+            amount,
+            get_token_account_data(&mut merchant_result.2, trial_order_token)
+                .await
+                .amount
+        );
+    }
+
     async fn run_subscription_cancel_tests(
         amount: u64,
         name: &str,
@@ -1914,18 +11594,11 @@ mod test {
             Some(mut subscribe_result) => {
                 let subscription = subscribe_result.3; // the subscription pubkey
 
-                let previous_subscription_account =
-                    subscribe_result.1 .2.get_account(subscription).await;
-                let previous_subscription_account = match previous_subscription_account {
-                    Ok(data) => match data {
-                        None => panic!("Oo"),
-                        Some(value) => match SubscriptionAccount::unpack(&value.data) {
-                            Ok(data) => data,
-                            Err(error) => panic!("Problem: {:?}", error),
-                        },
-                    },
-                    Err(error) => panic!("Problem: {:?}", error),
-                };
+                let previous_subscription_account = get_account_data::<SubscriptionAccount>(
+                    &mut subscribe_result.1 .2,
+                    subscription,
+                )
+                .await;
 
                 let order_acc_pubkey = subscribe_result.2;
                 let previous_order_account =
@@ -1984,24 +11657,19 @@ mod test {
                         refund_token_acc_keypair.pubkey(),
                         account_to_receive_sol_refund_pubkey,
                         pda,
+                        Option::None,
                     )],
                     Some(&subscribe_result.1 .3.pubkey()),
-                );
-                transaction.sign(&[&subscribe_result.1 .3], subscribe_result.1 .4);
-
-                let _cancel_result = subscribe_result.1 .2.process_transaction(transaction).await;
-
-                let subscription_account = subscribe_result.1 .2.get_account(subscription).await;
-                let subscription_account = match subscription_account {
-                    Ok(data) => match data {
-                        None => panic!("Oo"),
-                        Some(value) => match SubscriptionAccount::unpack(&value.data) {
-                            Ok(data) => data,
-                            Err(error) => panic!("Problem: {:?}", error),
-                        },
-                    },
-                    Err(error) => panic!("Problem: {:?}", error),
-                };
+                );
+                transaction.sign(&[&subscribe_result.1 .3], subscribe_result.1 .4);
+
+                let _cancel_result = subscribe_result.1 .2.process_transaction(transaction).await;
+
+                let subscription_account = get_account_data::<SubscriptionAccount>(
+                    &mut subscribe_result.1 .2,
+                    subscription,
+                )
+                .await;
                 let order_account = subscribe_result.1 .2.get_account(order_acc_pubkey).await;
                 let order_account = match order_account {
                     Ok(value) => value,
@@ -2050,6 +11718,115 @@ mod test {
         }
     }
 
+    async fn run_refund_destination_test(
+        allow_third_party_refunds: Option<bool>,
+        refund_to_payer: bool,
+    ) -> Result<(), TransportError> {
+        let program_id = Pubkey::from_str(&"mosh111111111111111111111111111111111111111").unwrap();
+        let (banks_client, payer, recent_blockhash) = ProgramTest::new(
+            "sol_payment_processor",
+            program_id,
+            processor!(PaymentProcessorInstruction::process),
+        )
+        .start()
+        .await;
+        let mint_keypair = Keypair::new();
+        let name = "trialFirst";
+        let packages = format!(
+            r#"{{"packages":[{{"name":"{name}","price":6699,"trial":604800,"duration":604800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string(),
+            name = name
+        );
+        let merchant_result = register_test_merchant(
+            (
+                program_id,
+                Pubkey::default(),
+                banks_client,
+                payer,
+                recent_blockhash,
+            ),
+            TestMerchant {
+                seed: Some(String::from("subscription test")),
+                data: Some(packages),
+                allow_third_party_refunds,
+                ..TestMerchant::default()
+            },
+        )
+        .await;
+        let (result, subscribe_result) =
+            run_subscribe_tests_for_merchant(merchant_result, 6699, name, &mint_keypair).await;
+        assert_matches!(result, Ok(()));
+        let (_subscription_data, mut merchant_result, order_acc_pubkey, subscription) =
+            subscribe_result.unwrap();
+        let payer = &merchant_result.3;
+        let refund_token_owner = if refund_to_payer {
+            payer.pubkey()
+        } else {
+            Pubkey::new_unique()
+        };
+        let refund_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    payer,
+                    &mint_keypair,
+                    merchant_result.4,
+                    &refund_token_keypair,
+                    &refund_token_owner,
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (order_token_acc_pubkey, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order_acc_pubkey.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint_keypair.pubkey().to_bytes(),
+            ],
+            &program_id,
+        );
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let mut transaction = Transaction::new_with_payer(
+            &[cancel_subscription(
+                program_id,
+                payer.pubkey(),
+                subscription,
+                merchant_result.1,
+                order_acc_pubkey,
+                order_token_acc_pubkey,
+                refund_token_keypair.pubkey(),
+                payer.pubkey(),
+                pda,
+                Option::None,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], merchant_result.4);
+        merchant_result.2.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    /// test that refunds only go to someone other than the payer if the merchant allows it
+    async fn test_cancel_subscription_refund_destination() {
+        assert_matches!(
+            run_refund_destination_test(Option::None, true).await,
+            Ok(())
+        );
+        assert_eq!(
+            run_refund_destination_test(Option::None, false)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::WrongPayer as u32)
+            )
+        );
+        assert_matches!(run_refund_destination_test(Some(true), false).await, Ok(()));
+    }
+
     #[tokio::test]
     async fn test_cancel_subscription_during_trial() {
         let mint_keypair = Keypair::new();
@@ -2085,6 +11862,8 @@ mod test {
         );
         // period end has changed to an earlier time
         assert!(previous_subscription_account.period_end > subscription_account.period_end);
+        // modified has advanced
+        assert!(subscription_account.modified >= previous_subscription_account.modified);
         // order account was closed
         assert!(order_account.is_none());
         // amount was withdrawn
@@ -2099,6 +11878,384 @@ mod test {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_close_subscription() {
+        let mint_keypair = Keypair::new();
+        let name = "closable";
+        let packages = format!(
+            r#"{{"packages":[{{"name":"{name}","price":1000,"trial":604800,"duration":604800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string(),
+            name = name
+        );
+        let result = run_subscribe_tests(1000, name, &packages, &mint_keypair).await;
+        assert!(result.0.is_ok());
+        let (_subscription_account, mut merchant_result, order_acc_pubkey, subscription) =
+            result.1.unwrap();
+        let program_id = merchant_result.0;
+        let payer = merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        let destination = Pubkey::new_unique();
+        let subscription_lamports = merchant_result
+            .2
+            .get_account(subscription)
+            .await
+            .unwrap()
+            .unwrap()
+            .lamports;
+
+        // an active subscription cannot be closed
+        let mut transaction = Transaction::new_with_payer(
+            &[close_subscription(
+                program_id,
+                payer.pubkey(),
+                subscription,
+                destination,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PaymentProcessorError::InvalidSubscriptionData as u32)
+            )
+        );
+
+        // cancel the subscription during the trial
+        let refund_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &payer,
+                    &mint_keypair,
+                    recent_blockhash,
+                    &refund_token_keypair,
+                    &payer.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let (order_token_acc_pubkey, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order_acc_pubkey.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint_keypair.pubkey().to_bytes(),
+            ],
+            &program_id,
+        );
+        let mut transaction = Transaction::new_with_payer(
+            &[cancel_subscription(
+                program_id,
+                payer.pubkey(),
+                subscription,
+                merchant_result.1,
+                order_acc_pubkey,
+                order_token_acc_pubkey,
+                refund_token_keypair.pubkey(),
+                payer.pubkey(),
+                pda,
+                Option::None,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+
+        // close the cancelled subscription
+        let mut transaction = Transaction::new_with_payer(
+            &[close_subscription(
+                program_id,
+                payer.pubkey(),
+                subscription,
+                destination,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(
+            merchant_result.2.process_transaction(transaction).await,
+            Ok(())
+        );
+        // the subscription account is gone and its rent was returned
+        assert!(merchant_result
+            .2
+            .get_account(subscription)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            subscription_lamports,
+            merchant_result
+                .2
+                .get_account(destination)
+                .await
+                .unwrap()
+                .unwrap()
+                .lamports
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_subscription_data() {
+        let mint_keypair = Keypair::new();
+        let name = "updatable";
+        let packages = format!(
+            r#"{{"packages":[{{"name":"{name}","price":1000,"trial":604800,"duration":604800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string(),
+            name = name
+        );
+        let result = run_subscribe_tests(1000, name, &packages, &mint_keypair).await;
+        assert!(result.0.is_ok());
+        let (subscription_account, mut merchant_result, order_acc_pubkey, subscription) =
+            result.1.unwrap();
+        let program_id = merchant_result.0;
+        let payer = merchant_result.3;
+        let recent_blockhash = merchant_result.4;
+        assert_eq!(String::from("{}"), subscription_account.data);
+
+        // data that does not fit in the subscription account is rejected
+        let mut transaction = Transaction::new_with_payer(
+            &[update_subscription_data(
+                program_id,
+                payer.pubkey(),
+                subscription,
+                String::from(r#"{"size":"L"}"#),
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(0, InstructionError::AccountDataTooSmall)
+        );
+
+        // data that fits replaces the old data, padded to the account size
+        for (data, expected) in &[("[]", "[]"), ("0", "0 ")] {
+            let mut transaction = Transaction::new_with_payer(
+                &[update_subscription_data(
+                    program_id,
+                    payer.pubkey(),
+                    subscription,
+                    String::from(*data),
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[&payer], recent_blockhash);
+            assert_matches!(
+                merchant_result.2.process_transaction(transaction).await,
+                Ok(())
+            );
+            let subscription_data =
+                get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+            assert_eq!(String::from(*expected), subscription_data.data);
+            assert_eq!(
+                subscription_account.period_end,
+                subscription_data.period_end
+            );
+        }
+
+        // a cancelled subscription can not be updated
+        let refund_token_keypair = Keypair::new();
+        assert_matches!(
+            merchant_result
+                .2
+                .process_transaction(create_token_account_transaction(
+                    &payer,
+                    &mint_keypair,
+                    recent_blockhash,
+                    &refund_token_keypair,
+                    &payer.pubkey(),
+                    0,
+                ))
+                .await,
+            Ok(())
+        );
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+        let (order_token_acc_pubkey, _bump_seed) = Pubkey::find_program_address(
+            &[
+                &order_acc_pubkey.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &mint_keypair.pubkey().to_bytes(),
+            ],
+            &program_id,
+        );
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                cancel_subscription(
+                    program_id,
+                    payer.pubkey(),
+                    subscription,
+                    merchant_result.1,
+                    order_acc_pubkey,
+                    order_token_acc_pubkey,
+                    refund_token_keypair.pubkey(),
+                    payer.pubkey(),
+                    pda,
+                    Option::None,
+                ),
+                update_subscription_data(
+                    program_id,
+                    payer.pubkey(),
+                    subscription,
+                    String::from("{}"),
+                ),
+            ],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            merchant_result
+                .2
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            TransactionError::InstructionError(
+                1,
+                InstructionError::Custom(PaymentProcessorError::InvalidSubscriptionData as u32)
+            )
+        );
+    }
+
+    #[tokio::test]
+    /// test that the merchant can cancel a subscriber, with and without a refund
+    async fn test_merchant_cancel_subscription() {
+        let mint_keypair = Keypair::new();
+        let packages = format!(
+            r#"{{"packages":[{{"name":"basic","price":1000000,"duration":604800,"mint":"{mint}"}}]}}"#,
+            mint = mint_keypair.pubkey().to_string()
+        );
+        for refund in &[false, true] {
+            let result = run_subscribe_tests(1000000, "basic", &packages, &mint_keypair).await;
+            assert!(result.0.is_ok());
+            let (
+                previous_subscription_account,
+                mut merchant_result,
+                order_acc_pubkey,
+                subscription,
+            ) = result.1.unwrap();
+            let program_id = merchant_result.0;
+            let payer = &merchant_result.3;
+            let recent_blockhash = merchant_result.4;
+            let order_account =
+                get_account_data::<OrderAccount>(&mut merchant_result.2, order_acc_pubkey).await;
+            let refund_token_keypair = Keypair::new();
+            assert_matches!(
+                merchant_result
+                    .2
+                    .process_transaction(create_token_account_transaction(
+                        payer,
+                        &mint_keypair,
+                        recent_blockhash,
+                        &refund_token_keypair,
+                        &payer.pubkey(),
+                        0,
+                    ))
+                    .await,
+                Ok(())
+            );
+            let (pda, _bump_seed) = Pubkey::find_program_address(&[PDA_SEED], &program_id);
+            let refund_accounts = if *refund {
+                Some((
+                    order_acc_pubkey,
+                    Pubkey::new_from_array(order_account.token),
+                    refund_token_keypair.pubkey(),
+                    payer.pubkey(),
+                    pda,
+                    mint_keypair.pubkey(),
+                ))
+            } else {
+                Option::None
+            };
+            let mut transaction = Transaction::new_with_payer(
+                &[merchant_cancel_subscription(
+                    program_id,
+                    payer.pubkey(),
+                    subscription,
+                    merchant_result.1,
+                    refund_accounts,
+                )],
+                Some(&payer.pubkey()),
+            );
+            transaction.sign(&[payer], recent_blockhash);
+            assert_matches!(
+                merchant_result.2.process_transaction(transaction).await,
+                Ok(())
+            );
+            // the subscription ended right away and records who cancelled it
+            let subscription_account =
+                get_account_data::<SubscriptionAccount>(&mut merchant_result.2, subscription).await;
+            assert_eq!(
+                SubscriptionStatus::CancelledByMerchant as u8,
+                subscription_account.status
+            );
+            assert!(previous_subscription_account.period_end > subscription_account.period_end);
+            let refund_token_data =
+                get_token_account_data(&mut merchant_result.2, refund_token_keypair.pubkey()).await;
+            if *refund {
+                // the payment went back and the order was closed
+                assert_eq!(1000000, refund_token_data.amount);
+                assert!(merchant_result
+                    .2
+                    .get_account(order_acc_pubkey)
+                    .await
+                    .unwrap()
+                    .is_none());
+            } else {
+                assert_eq!(0, refund_token_data.amount);
+                let order_data =
+                    get_account_data::<OrderAccount>(&mut merchant_result.2, order_acc_pubkey)
+                        .await;
+                assert_eq!(OrderStatus::Paid as u8, order_data.status);
+                // the subscriber can not renew a subscription the merchant cancelled
+                let mut transaction = Transaction::new_with_payer(
+                    &[renew_subscription(
+                        program_id,
+                        payer.pubkey(),
+                        subscription,
+                        merchant_result.1,
+                        order_acc_pubkey,
+                        Option::None,
+                        1,
+                    )],
+                    Some(&payer.pubkey()),
+                );
+                transaction.sign(&[payer], recent_blockhash);
+                assert_eq!(
+                    merchant_result
+                        .2
+                        .process_transaction(transaction)
+                        .await
+                        .unwrap_err()
+                        .unwrap(),
+                    TransactionError::InstructionError(
+                        0,
+                        InstructionError::Custom(
+                            PaymentProcessorError::InvalidSubscriptionData as u32
+                        )
+                    )
+                );
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_cancel_subscription_after_trial() {
         let mint_keypair = Keypair::new();
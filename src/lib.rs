@@ -5,3 +5,4 @@ pub mod processor;
 pub mod state;
 pub mod utils;
 pub mod engine;
+pub mod client;
@@ -1,10 +1,44 @@
+#[cfg(feature = "json")]
 use crate::{
     engine::cancel_subscription::process_cancel_subscription,
-    engine::pay::process_express_checkout, engine::pay::process_chain_checkout, engine::register::process_register_merchant,
-    engine::renew::process_renew_subscription, engine::subscribe::process_subscribe,
+    engine::checkout_subscribe::process_checkout_and_subscribe,
+    engine::keeper_renew::process_keeper_renew,
+    engine::package::{process_add_package, process_remove_package},
+    engine::pause_subscription::{process_pause_subscription, process_resume_subscription},
+    engine::pay::process_chain_checkout, engine::renew::process_renew_subscription,
+    engine::subscribe::process_subscribe,
+    engine::withdraw::process_withdraw_subscription_batch,
+};
+#[cfg(feature = "admin")]
+use crate::engine::admin::process_admin_fix_order;
+#[cfg(not(all(feature = "json", feature = "admin")))]
+use crate::error::PaymentProcessorError;
+use crate::{
+    engine::cancel_order::process_cancel_pending_order,
+    engine::close_subscription::process_close_subscription,
+    engine::config::{process_update_config_owner, process_update_registry},
+    engine::coupon::{process_create_coupon, process_redeem_coupon},
+    engine::ledger::process_create_ledger,
+    engine::constants::MAX_INSTRUCTION_DATA_SIZE, engine::lock::process_lock_order,
+    engine::merchant_cancel::process_merchant_cancel_subscription,
+    engine::merchant_token::process_create_merchant_token_account,
+    engine::migrate_pda::process_migrate_pda_token_account,
+    engine::operator::process_set_operator,
+    engine::order_size::process_compute_order_size,
+    engine::pay::{process_express_checkout, OrderOptions},
+    engine::refund::process_refund_order,
+    engine::register::{process_register_merchant, MerchantSettings},
+    engine::reveal_secret::process_reveal_secret,
+    engine::simulate_fees::process_simulate_checkout_fees, engine::sweep::process_sweep_pda,
+    engine::top_up::process_top_up_rent,
+    engine::update_subscription::process_update_subscription_data,
+    engine::verify_order::process_verify_order_settled,
+    engine::verify_subscription::process_verify_subscription_access,
     engine::withdraw::process_withdraw_payment, instruction::PaymentProcessorInstruction,
 };
 use borsh::BorshDeserialize;
+#[cfg(feature = "debug-compute")]
+use solana_program::log::sol_log_compute_units;
 use solana_program::{
     account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
     pubkey::Pubkey,
@@ -17,46 +51,298 @@ impl PaymentProcessorInstruction {
         accounts: &[AccountInfo],
         instruction_data: &[u8],
     ) -> ProgramResult {
+        // don't spend compute parsing data that could never come from a transaction
+        if instruction_data.len() > MAX_INSTRUCTION_DATA_SIZE {
+            msg!("Error: Instruction data is too large");
+            return Err(ProgramError::InvalidInstructionData);
+        }
         let instruction = PaymentProcessorInstruction::try_from_slice(&instruction_data)
             .map_err(|_| ProgramError::InvalidInstructionData)?;
-        match instruction {
-            PaymentProcessorInstruction::RegisterMerchant { seed, fee, data } => {
+        #[cfg(feature = "debug-compute")]
+        sol_log_compute_units();
+        let result = match instruction {
+            PaymentProcessorInstruction::RegisterMerchant {
+                seed,
+                fee,
+                data,
+                settlement_delay,
+                accepted_mints,
+                allow_third_party_refunds,
+                fee_overrides,
+                min_subscription_duration,
+                charity,
+                fee_mode,
+                amount_limits,
+                fee_free_band,
+                require_full_payment,
+            } => {
                 msg!("SolPayments: RegisterMerchant");
-                process_register_merchant(program_id, accounts, seed, fee, data)
+                process_register_merchant(
+                    program_id,
+                    accounts,
+                    seed,
+                    MerchantSettings {
+                        fee,
+                        data,
+                        settlement_delay,
+                        accepted_mints,
+                        allow_third_party_refunds,
+                        fee_overrides,
+                        min_subscription_duration,
+                        charity,
+                        fee_mode,
+                        amount_limits,
+                        fee_free_band,
+                        require_full_payment,
+                    },
+                )
             }
             PaymentProcessorInstruction::ExpressCheckout {
                 amount,
                 order_id,
                 secret,
                 data,
+                client_tag,
+                usd_amount,
+                revoke_delegate,
+                merchant_signed,
+                merchant_pays_rent,
+                strict_secret,
+                coupon,
+                ledger,
             } => {
                 msg!("SolPayments: ExpressCheckout");
-                process_express_checkout(program_id, accounts, amount, order_id, secret, data)
+                process_express_checkout(
+                    program_id,
+                    accounts,
+                    amount,
+                    order_id,
+                    secret,
+                    data,
+                    OrderOptions {
+                        checkout_items: Option::None,
+                        client_tag,
+                        usd_amount,
+                        revoke_delegate: revoke_delegate.unwrap_or(false),
+                        merchant_signed: merchant_signed.unwrap_or(false),
+                        merchant_pays_rent: merchant_pays_rent.unwrap_or(false),
+                        strict_secret: strict_secret.unwrap_or(false),
+                        coupon: coupon.unwrap_or(false),
+                        ledger: ledger.unwrap_or(false),
+                    },
+                )
             }
+            #[cfg(feature = "json")]
             PaymentProcessorInstruction::ChainCheckout {
                 amount,
                 order_items,
                 data,
+                client_tag,
             } => {
                 msg!("SolPayments: ChainCheckout");
-                process_chain_checkout(program_id, accounts, amount, order_items, data)
+                process_chain_checkout(program_id, accounts, amount, order_items, data, client_tag)
             }
             PaymentProcessorInstruction::Withdraw { close_order_account } => {
                 msg!("SolPayments: Withdraw");
                 process_withdraw_payment(program_id, accounts, close_order_account)
             }
+            #[cfg(feature = "json")]
+            PaymentProcessorInstruction::WithdrawSubscriptionBatch {
+                close_order_accounts,
+            } => {
+                msg!("SolPayments: WithdrawSubscriptionBatch");
+                process_withdraw_subscription_batch(program_id, accounts, close_order_accounts)
+            }
+            #[cfg(feature = "json")]
             PaymentProcessorInstruction::Subscribe { name, data } => {
                 msg!("SolPayments: Subscribe");
                 process_subscribe(program_id, accounts, name, data)
             }
+            #[cfg(feature = "json")]
             PaymentProcessorInstruction::RenewSubscription { quantity } => {
                 msg!("SolPayments: RenewSubscription");
                 process_renew_subscription(program_id, accounts, quantity)
             }
+            #[cfg(feature = "json")]
             PaymentProcessorInstruction::CancelSubscription => {
                 msg!("SolPayments: CancelSubscription");
                 process_cancel_subscription(program_id, accounts)
             }
-        }
+            PaymentProcessorInstruction::CreateMerchantTokenAccount => {
+                msg!("SolPayments: CreateMerchantTokenAccount");
+                process_create_merchant_token_account(program_id, accounts)
+            }
+            #[cfg(feature = "json")]
+            PaymentProcessorInstruction::PauseSubscription => {
+                msg!("SolPayments: PauseSubscription");
+                process_pause_subscription(program_id, accounts)
+            }
+            #[cfg(feature = "json")]
+            PaymentProcessorInstruction::ResumeSubscription => {
+                msg!("SolPayments: ResumeSubscription");
+                process_resume_subscription(program_id, accounts)
+            }
+            #[cfg(feature = "json")]
+            PaymentProcessorInstruction::KeeperRenew => {
+                msg!("SolPayments: KeeperRenew");
+                process_keeper_renew(program_id, accounts)
+            }
+            PaymentProcessorInstruction::UpdateConfigOwner { new_owner } => {
+                msg!("SolPayments: UpdateConfigOwner");
+                process_update_config_owner(program_id, accounts, new_owner)
+            }
+            #[cfg(feature = "json")]
+            PaymentProcessorInstruction::AddPackage {
+                name,
+                duration,
+                price,
+                mint,
+            } => {
+                msg!("SolPayments: AddPackage");
+                process_add_package(program_id, accounts, name, duration, price, mint)
+            }
+            #[cfg(feature = "json")]
+            PaymentProcessorInstruction::RemovePackage => {
+                msg!("SolPayments: RemovePackage");
+                process_remove_package(program_id, accounts)
+            }
+            PaymentProcessorInstruction::RefundOrder { amount } => {
+                msg!("SolPayments: RefundOrder");
+                process_refund_order(program_id, accounts, amount)
+            }
+            PaymentProcessorInstruction::LockOrder => {
+                msg!("SolPayments: LockOrder");
+                process_lock_order(program_id, accounts, true)
+            }
+            PaymentProcessorInstruction::UnlockOrder => {
+                msg!("SolPayments: UnlockOrder");
+                process_lock_order(program_id, accounts, false)
+            }
+            PaymentProcessorInstruction::CancelPendingOrder => {
+                msg!("SolPayments: CancelPendingOrder");
+                process_cancel_pending_order(program_id, accounts)
+            }
+            PaymentProcessorInstruction::TopUpRent => {
+                msg!("SolPayments: TopUpRent");
+                process_top_up_rent(program_id, accounts)
+            }
+            PaymentProcessorInstruction::CloseSubscription => {
+                msg!("SolPayments: CloseSubscription");
+                process_close_subscription(program_id, accounts)
+            }
+            PaymentProcessorInstruction::SweepPda => {
+                msg!("SolPayments: SweepPda");
+                process_sweep_pda(program_id, accounts)
+            }
+            PaymentProcessorInstruction::SetOperator { operator } => {
+                msg!("SolPayments: SetOperator");
+                process_set_operator(program_id, accounts, operator)
+            }
+            PaymentProcessorInstruction::VerifyOrderSettled => {
+                msg!("SolPayments: VerifyOrderSettled");
+                process_verify_order_settled(program_id, accounts)
+            }
+            PaymentProcessorInstruction::MigratePdaTokenAccount {
+                from_version,
+                to_version,
+            } => {
+                msg!("SolPayments: MigratePdaTokenAccount");
+                process_migrate_pda_token_account(program_id, accounts, from_version, to_version)
+            }
+            PaymentProcessorInstruction::UpdateSubscriptionData { data } => {
+                msg!("SolPayments: UpdateSubscriptionData");
+                process_update_subscription_data(program_id, accounts, data)
+            }
+            PaymentProcessorInstruction::ComputeOrderSize {
+                order_id,
+                secret,
+                data,
+            } => {
+                msg!("SolPayments: ComputeOrderSize");
+                process_compute_order_size(order_id, secret, data)
+            }
+            PaymentProcessorInstruction::SimulateCheckoutFees { amount } => {
+                msg!("SolPayments: SimulateCheckoutFees");
+                process_simulate_checkout_fees(program_id, accounts, amount)
+            }
+            PaymentProcessorInstruction::MerchantCancelSubscription { refund } => {
+                msg!("SolPayments: MerchantCancelSubscription");
+                process_merchant_cancel_subscription(program_id, accounts, refund)
+            }
+            PaymentProcessorInstruction::RevealSecret => {
+                msg!("SolPayments: RevealSecret");
+                process_reveal_secret(program_id, accounts)
+            }
+            PaymentProcessorInstruction::CreateCoupon {
+                code,
+                bps,
+                max_redemptions,
+                expiry,
+            } => {
+                msg!("SolPayments: CreateCoupon");
+                process_create_coupon(program_id, accounts, code, bps, max_redemptions, expiry)
+            }
+            PaymentProcessorInstruction::RedeemCoupon => {
+                msg!("SolPayments: RedeemCoupon");
+                process_redeem_coupon(program_id, accounts)
+            }
+            PaymentProcessorInstruction::UpdateRegistry { registrants } => {
+                msg!("SolPayments: UpdateRegistry");
+                process_update_registry(program_id, accounts, registrants)
+            }
+            PaymentProcessorInstruction::CreateLedger => {
+                msg!("SolPayments: CreateLedger");
+                process_create_ledger(program_id, accounts)
+            }
+            PaymentProcessorInstruction::VerifySubscriptionAccess { package_name } => {
+                msg!("SolPayments: VerifySubscriptionAccess");
+                process_verify_subscription_access(program_id, accounts, package_name)
+            }
+            #[cfg(feature = "json")]
+            PaymentProcessorInstruction::CheckoutAndSubscribe {
+                amount,
+                order_id,
+                secret,
+                name,
+                data,
+            } => {
+                msg!("SolPayments: CheckoutAndSubscribe");
+                process_checkout_and_subscribe(
+                    program_id, accounts, amount, order_id, secret, name, data,
+                )
+            }
+            #[cfg(feature = "admin")]
+            PaymentProcessorInstruction::AdminFixOrder {
+                expected_amount,
+                paid_amount,
+                refunded_amount,
+                status,
+            } => {
+                msg!("SolPayments: AdminFixOrder");
+                process_admin_fix_order(
+                    program_id,
+                    accounts,
+                    expected_amount,
+                    paid_amount,
+                    refunded_amount,
+                    status,
+                )
+            }
+            // production builds leave out the admin instructions
+            #[cfg(not(feature = "admin"))]
+            PaymentProcessorInstruction::AdminFixOrder { .. } => {
+                msg!("Error: Instruction requires the admin feature");
+                Err(PaymentProcessorError::InvalidInstruction.into())
+            }
+            // subscriptions and chain checkout are not compiled in
+            #[cfg(not(feature = "json"))]
+            _ => {
+                msg!("Error: Instruction requires the json feature");
+                Err(PaymentProcessorError::InvalidInstruction.into())
+            }
+        };
+        #[cfg(feature = "debug-compute")]
+        sol_log_compute_units();
+        result
     }
 }
@@ -1,9 +1,19 @@
+use crate::engine::constants::{LEDGER_CAPACITY, MAX_REGISTRANTS, PROGRAM_OWNER};
+use crate::error::PaymentProcessorError;
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use solana_program::{
     clock::UnixTimestamp,
+    entrypoint::ProgramResult,
+    hash::hashv,
+    msg,
     program_pack::{IsInitialized, Sealed},
+    pubkey::Pubkey,
+};
+use std::{
+    io::{Error, ErrorKind, Write},
+    mem::size_of,
+    str::FromStr,
 };
-use std::mem::size_of;
 
 pub type PublicKey = [u8; 32];
 
@@ -23,23 +33,211 @@ pub enum Discriminator {
     Merchant = 10,
     MerchantSubscription = 11,
     MerchantSubscriptionWithTrial = 12,
+    MerchantSubscriptionPackages = 13,
     MerchantChainCheckout = 15,
     OrderExpressCheckout = 20,
     OrderChainCheckout = 21,
     Subscription = 30,
+    Package = 31,
+    Coupon = 32,
+    Ledger = 33,
+    Config = 40,
+    Registry = 41,
     Closed = 255,
 }
 
-#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+/// How the merchant fee is charged
+#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum FeeMode {
+    /// the fee is a flat amount of SOL lamports
+    FlatSol = 0,
+    /// the fee is basis points of the amount, paid in SOL lamports.  Meant for
+    /// mints whose smallest unit is worth about a lamport e.g. wrapped SOL
+    BpsSol = 1,
+    /// the fee is basis points of the amount, paid in the order mint out of the payment
+    BpsToken = 2,
+}
+
+#[derive(BorshSchema, Debug, PartialEq)]
 pub struct MerchantAccount {
     pub discriminator: u8,
     pub owner: PublicKey,
     pub sponsor: PublicKey,
-    /// represents the fee (in SOL lamports) that will be charged for transactions
+    /// represents the fee that will be charged for transactions, see fee_mode
     pub fee: u64,
     /// this is represented as a string but really is meant to hold JSON
     /// found this to be a convenient hack to allow flexible data
     pub data: String,
+    // The fields below were added after data.  Merchant accounts from before them
+    // end here, and are read with each missing field at its default.
+    /// receives the program owner portion of the fee, the program owner by default
+    pub fee_recipient: PublicKey,
+    /// the number of seconds after an order is created before its payment can be withdrawn
+    pub settlement_delay: i64,
+    /// the number of orders made to this merchant
+    /// also the index of the next order (see utils::get_order_pubkey_by_index)
+    pub order_count: u64,
+    /// the mints (currencies) accepted for payment, any mint is accepted when empty
+    pub accepted_mints: Vec<PublicKey>,
+    /// whether subscription refunds may go to token accounts not owned by the payer
+    pub allow_third_party_refunds: bool,
+    /// fees charged to specific buyers instead of the merchant fee
+    pub fee_overrides: Vec<FeeOverride>,
+    /// the shortest duration (in seconds) of the subscription packages this merchant offers
+    pub min_subscription_duration: i64,
+    /// can act for the owner e.g. to withdraw, no one when all zeros (see SetOperator)
+    pub operator: PublicKey,
+    /// receives charity_bps of every payment, no one when all zeros
+    pub charity: PublicKey,
+    /// the share of every payment (in basis points) that goes to the charity
+    pub charity_bps: u16,
+    /// how the fee (and fee overrides) are charged, a flat SOL fee by default
+    pub fee_mode: FeeMode,
+    /// the smallest amount a checkout can be for, no minimum when zero
+    pub min_amount: u64,
+    /// the largest amount a checkout can be for, no maximum when zero
+    pub max_amount: u64,
+    /// checkouts for less than this amount are free of fees, unused when zero
+    pub fee_free_below: u64,
+    /// checkouts for more than this amount are free of fees, unused when zero
+    pub fee_free_above: u64,
+    /// whether orders paid less than the merchant's share of their expected amount
+    /// can not be withdrawn (see utils::is_fully_paid).  They can by default
+    pub require_full_payment: bool,
+}
+
+/// The default fee recipient of a merchant account, the program owner
+fn default_fee_recipient() -> PublicKey {
+    Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes()
+}
+
+impl BorshSerialize for MerchantAccount {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.discriminator.serialize(writer)?;
+        self.owner.serialize(writer)?;
+        self.sponsor.serialize(writer)?;
+        self.fee.serialize(writer)?;
+        self.data.serialize(writer)?;
+        // the fields after data are left out from the last one that is not at its
+        // default, so that older, shorter, merchant accounts can be packed
+        let mut extension = vec![];
+        let mut extension_len = 0;
+        self.fee_recipient.serialize(&mut extension)?;
+        if self.fee_recipient != default_fee_recipient() {
+            extension_len = extension.len();
+        }
+        self.settlement_delay.serialize(&mut extension)?;
+        if self.settlement_delay != 0 {
+            extension_len = extension.len();
+        }
+        self.order_count.serialize(&mut extension)?;
+        if self.order_count != 0 {
+            extension_len = extension.len();
+        }
+        self.accepted_mints.serialize(&mut extension)?;
+        if !self.accepted_mints.is_empty() {
+            extension_len = extension.len();
+        }
+        self.allow_third_party_refunds.serialize(&mut extension)?;
+        if self.allow_third_party_refunds {
+            extension_len = extension.len();
+        }
+        self.fee_overrides.serialize(&mut extension)?;
+        if !self.fee_overrides.is_empty() {
+            extension_len = extension.len();
+        }
+        self.min_subscription_duration.serialize(&mut extension)?;
+        if self.min_subscription_duration != 0 {
+            extension_len = extension.len();
+        }
+        self.operator.serialize(&mut extension)?;
+        if self.operator != [0; 32] {
+            extension_len = extension.len();
+        }
+        self.charity.serialize(&mut extension)?;
+        if self.charity != [0; 32] {
+            extension_len = extension.len();
+        }
+        self.charity_bps.serialize(&mut extension)?;
+        if self.charity_bps != 0 {
+            extension_len = extension.len();
+        }
+        self.fee_mode.serialize(&mut extension)?;
+        if self.fee_mode != FeeMode::FlatSol {
+            extension_len = extension.len();
+        }
+        self.min_amount.serialize(&mut extension)?;
+        if self.min_amount != 0 {
+            extension_len = extension.len();
+        }
+        self.max_amount.serialize(&mut extension)?;
+        if self.max_amount != 0 {
+            extension_len = extension.len();
+        }
+        self.fee_free_below.serialize(&mut extension)?;
+        if self.fee_free_below != 0 {
+            extension_len = extension.len();
+        }
+        self.fee_free_above.serialize(&mut extension)?;
+        if self.fee_free_above != 0 {
+            extension_len = extension.len();
+        }
+        self.require_full_payment.serialize(&mut extension)?;
+        if self.require_full_payment {
+            extension_len = extension.len();
+        }
+        writer.write_all(&extension[..extension_len])
+    }
+}
+
+/// Whether only zero padding is left in the buffer
+fn is_blank(buf: &[u8]) -> bool {
+    buf.iter().all(|byte| *byte == 0)
+}
+
+/// Read a field that older accounts end before, the default when only zero
+/// padding is left in the buffer
+fn deserialize_or<T: BorshDeserialize>(buf: &mut &[u8], default: T) -> Result<T, Error> {
+    if is_blank(buf) {
+        return Ok(default);
+    }
+    T::deserialize(buf)
+}
+
+impl BorshDeserialize for MerchantAccount {
+    fn deserialize(buf: &mut &[u8]) -> Result<Self, Error> {
+        Ok(MerchantAccount {
+            discriminator: BorshDeserialize::deserialize(buf)?,
+            owner: BorshDeserialize::deserialize(buf)?,
+            sponsor: BorshDeserialize::deserialize(buf)?,
+            fee: BorshDeserialize::deserialize(buf)?,
+            data: BorshDeserialize::deserialize(buf)?,
+            fee_recipient: deserialize_or(buf, default_fee_recipient())?,
+            settlement_delay: deserialize_or(buf, 0)?,
+            order_count: deserialize_or(buf, 0)?,
+            accepted_mints: deserialize_or(buf, vec![])?,
+            allow_third_party_refunds: deserialize_or(buf, false)?,
+            fee_overrides: deserialize_or(buf, vec![])?,
+            min_subscription_duration: deserialize_or(buf, 0)?,
+            operator: deserialize_or(buf, [0; 32])?,
+            charity: deserialize_or(buf, [0; 32])?,
+            charity_bps: deserialize_or(buf, 0)?,
+            // merchant accounts from before fee modes charge a flat SOL fee
+            fee_mode: deserialize_or(buf, FeeMode::FlatSol)?,
+            min_amount: deserialize_or(buf, 0)?,
+            max_amount: deserialize_or(buf, 0)?,
+            fee_free_below: deserialize_or(buf, 0)?,
+            fee_free_above: deserialize_or(buf, 0)?,
+            require_full_payment: deserialize_or(buf, false)?,
+        })
+    }
+}
+
+#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+pub struct FeeOverride {
+    pub buyer: PublicKey,
+    /// the fee this buyer is charged (see FeeMode), at most the merchant fee
+    pub fee: u64,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
@@ -51,7 +249,7 @@ pub enum OrderStatus {
     Cancelled = 4,
 }
 
-#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+#[derive(BorshSchema, Debug, PartialEq)]
 pub struct OrderAccount {
     pub discriminator: u8,
     pub status: u8,
@@ -64,10 +262,119 @@ pub struct OrderAccount {
     pub expected_amount: u64,
     pub paid_amount: u64,
     pub order_id: String,
-    pub secret: String,
+    /// None when the order was paid without a secret, which is then not stored at
+    /// all (see NO_SECRET_FLAG)
+    pub secret: Option<String>,
     /// this is represented as a string but really is meant to hold JSON
     /// found this to be a convenient hack to allow flexible data
     pub data: String,
+    // The fields below were added after data.  Orders from before them end here,
+    // and are read with each missing field at its default.
+    /// the total amount refunded to the payer, at most the paid amount
+    pub refunded_amount: u64,
+    /// locked orders can not be withdrawn, refunded or cancelled
+    pub locked: bool,
+    /// whether the order was used to start or renew a subscription
+    pub consumed: bool,
+    /// for orders quoted in USD, whose expected amount is in USD, the amount in
+    /// token units it was worth at the oracle price.  Zero for other orders
+    pub quoted_amount: u64,
+}
+
+/// Set on the stored status of orders without a secret, which leave the secret
+/// out instead of storing an empty one
+const NO_SECRET_FLAG: u8 = 0x80;
+
+impl BorshSerialize for OrderAccount {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.discriminator.serialize(writer)?;
+        match &self.secret {
+            None => (self.status | NO_SECRET_FLAG).serialize(writer)?,
+            Some(_secret) => self.status.serialize(writer)?,
+        }
+        self.created.serialize(writer)?;
+        self.modified.serialize(writer)?;
+        self.merchant.serialize(writer)?;
+        self.mint.serialize(writer)?;
+        self.token.serialize(writer)?;
+        self.payer.serialize(writer)?;
+        self.expected_amount.serialize(writer)?;
+        self.paid_amount.serialize(writer)?;
+        self.order_id.serialize(writer)?;
+        if let Some(secret) = &self.secret {
+            secret.serialize(writer)?;
+        }
+        self.data.serialize(writer)?;
+        // the fields after data are left out from the last one that is not at its
+        // default, so that older, shorter, orders can be packed
+        let mut extension = vec![];
+        let mut extension_len = 0;
+        self.refunded_amount.serialize(&mut extension)?;
+        if self.refunded_amount != 0 {
+            extension_len = extension.len();
+        }
+        self.locked.serialize(&mut extension)?;
+        if self.locked {
+            extension_len = extension.len();
+        }
+        self.consumed.serialize(&mut extension)?;
+        if self.consumed {
+            extension_len = extension.len();
+        }
+        self.quoted_amount.serialize(&mut extension)?;
+        if self.quoted_amount != 0 {
+            extension_len = extension.len();
+        }
+        writer.write_all(&extension[..extension_len])
+    }
+}
+
+impl BorshDeserialize for OrderAccount {
+    fn deserialize(buf: &mut &[u8]) -> Result<Self, Error> {
+        let discriminator = BorshDeserialize::deserialize(buf)?;
+        let status: u8 = BorshDeserialize::deserialize(buf)?;
+        let created = BorshDeserialize::deserialize(buf)?;
+        let modified = BorshDeserialize::deserialize(buf)?;
+        let merchant = BorshDeserialize::deserialize(buf)?;
+        let mint = BorshDeserialize::deserialize(buf)?;
+        let token = BorshDeserialize::deserialize(buf)?;
+        let payer = BorshDeserialize::deserialize(buf)?;
+        let expected_amount = BorshDeserialize::deserialize(buf)?;
+        let paid_amount = BorshDeserialize::deserialize(buf)?;
+        let order_id = BorshDeserialize::deserialize(buf)?;
+        // older orders without a secret store an empty one
+        let secret = if status & NO_SECRET_FLAG != 0 {
+            Option::None
+        } else {
+            let secret: String = BorshDeserialize::deserialize(buf)?;
+            if secret.is_empty() {
+                Option::None
+            } else {
+                Some(secret)
+            }
+        };
+        Ok(OrderAccount {
+            discriminator,
+            status: status & !NO_SECRET_FLAG,
+            created,
+            modified,
+            merchant,
+            mint,
+            token,
+            payer,
+            expected_amount,
+            paid_amount,
+            order_id,
+            secret,
+            data: BorshDeserialize::deserialize(buf)?,
+            // orders from before refunds, locks and consumption have none of them
+            refunded_amount: deserialize_or(buf, 0)?,
+            locked: deserialize_or(buf, false)?,
+            consumed: deserialize_or(buf, false)?,
+            // nor were they quoted in USD
+            quoted_amount: deserialize_or(buf, 0)?,
+        })
+    }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
@@ -75,9 +382,12 @@ pub enum SubscriptionStatus {
     Uninitialized = 0,
     Initialized = 1,
     Cancelled = 2,
+    Paused = 3,
+    /// cancelled by the merchant rather than by the subscriber
+    CancelledByMerchant = 4,
 }
 
-#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+#[derive(BorshSchema, Debug, PartialEq)]
 pub struct SubscriptionAccount {
     pub discriminator: u8,
     pub status: u8,
@@ -90,22 +400,233 @@ pub struct SubscriptionAccount {
     /// this is represented as a string but really is meant to hold JSON
     /// found this to be a convenient hack to allow flexible data
     pub data: String,
+    // The fields below were added after data.  Subscriptions from before them end
+    // here, and are read with each missing field at its default (see is_legacy).
+    /// when the subscription last changed, when it was joined by default
+    pub modified: UnixTimestamp,
+    /// the order that paid for the current period, all zeros when unknown
+    pub current_order: PublicKey,
+}
+
+impl BorshSerialize for SubscriptionAccount {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.discriminator.serialize(writer)?;
+        self.status.serialize(writer)?;
+        self.owner.serialize(writer)?;
+        self.merchant.serialize(writer)?;
+        self.name.serialize(writer)?;
+        self.joined.serialize(writer)?;
+        self.period_start.serialize(writer)?;
+        self.period_end.serialize(writer)?;
+        self.data.serialize(writer)?;
+        self.modified.serialize(writer)?;
+        self.current_order.serialize(writer)
+    }
+}
+
+impl BorshDeserialize for SubscriptionAccount {
+    fn deserialize(buf: &mut &[u8]) -> Result<Self, Error> {
+        let discriminator = BorshDeserialize::deserialize(buf)?;
+        let status = BorshDeserialize::deserialize(buf)?;
+        let owner = BorshDeserialize::deserialize(buf)?;
+        let merchant = BorshDeserialize::deserialize(buf)?;
+        let name = BorshDeserialize::deserialize(buf)?;
+        let joined = BorshDeserialize::deserialize(buf)?;
+        Ok(SubscriptionAccount {
+            discriminator,
+            status,
+            owner,
+            merchant,
+            name,
+            joined,
+            period_start: BorshDeserialize::deserialize(buf)?,
+            period_end: BorshDeserialize::deserialize(buf)?,
+            data: BorshDeserialize::deserialize(buf)?,
+            // subscriptions from before modified were not modified since joined
+            modified: deserialize_or(buf, joined)?,
+            current_order: deserialize_or(buf, [0; 32])?,
+        })
+    }
+}
+
+#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+pub struct PackageAccount {
+    pub discriminator: u8,
+    pub merchant: PublicKey,
+    /// the mint (currency) used for this package
+    pub mint: PublicKey,
+    /// duration of the subscription in seconds
+    pub duration: i64,
+    /// the price in full for this subscription option
+    pub price: u64,
+    pub name: String,
+}
+
+#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+pub struct CouponAccount {
+    pub discriminator: u8,
+    pub merchant: PublicKey,
+    /// the discount in basis points of the checkout amount
+    pub bps: u16,
+    /// how many times the coupon can be redeemed
+    pub max_redemptions: u64,
+    /// how many times the coupon was redeemed
+    pub used_count: u64,
+    /// the coupon can not be redeemed after this time, zero means it never expires
+    pub expiry: UnixTimestamp,
+    pub code: String,
+}
+
+#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+pub struct ConfigAccount {
+    pub discriminator: u8,
+    /// the key that collects the program owner fees
+    pub owner: PublicKey,
+}
+
+#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+pub struct RegistryAccount {
+    pub discriminator: u8,
+    /// the keys approved to register merchants
+    pub registrants: Vec<PublicKey>,
+}
+
+/// A payment appended to a merchant ledger, see LedgerAccount
+#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct LedgerEntry {
+    /// the amount paid to the order (see OrderAccount::paid_amount)
+    pub amount: u64,
+    pub timestamp: UnixTimestamp,
+    /// the order account
+    pub order: PublicKey,
+}
+
+#[derive(BorshSerialize, BorshSchema, BorshDeserialize, Debug, PartialEq)]
+pub struct LedgerAccount {
+    pub discriminator: u8,
+    pub merchant: PublicKey,
+    /// how many payments were ever appended to the ledger
+    pub count: u64,
+    /// the last LEDGER_CAPACITY payments, payment i is at i % LEDGER_CAPACITY so the
+    /// oldest entry is overwritten once the ledger is full
+    pub entries: Vec<LedgerEntry>,
 }
 
 // impl for MerchantAccount
 impl Sealed for MerchantAccount {}
 
-impl Serdes for MerchantAccount {}
+impl Serdes for MerchantAccount {
+    /// The fields after data are left out at their defaults (see serialize), so
+    /// the space after the content is zeroed to clear any that were set before
+    fn pack(&self, dst: &mut [u8]) {
+        let encoded = self.try_to_vec().unwrap();
+        dst[..encoded.len()].copy_from_slice(&encoded);
+        for byte in dst[encoded.len()..].iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    /// Merchant accounts can be larger than their content, the unused (reserved)
+    /// space must be left zeroed
+    fn unpack(src: &[u8]) -> Result<Self, Error> {
+        let mut buf = src;
+        let merchant = Self::deserialize(&mut buf)?;
+        if !is_blank(buf) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Not all bytes read: the merchant account has trailing data",
+            ));
+        }
+        Ok(merchant)
+    }
+}
 
 impl MerchantAccount {
-    pub const MIN_LEN: usize =
+    /// The length of a merchant account from before the fields after data, not
+    /// counting the data string
+    pub const BASE_LEN: usize =
         size_of::<u8>() + size_of::<PublicKey>() + size_of::<PublicKey>() + size_of::<u64>();
+
+    pub const MIN_LEN: usize = Self::BASE_LEN
+        + size_of::<PublicKey>()
+        + size_of::<i64>()
+        + size_of::<u64>()
+        + size_of::<u32>() // accepted_mints length, each mint adds a PublicKey
+        + size_of::<bool>()
+        + size_of::<u32>() // fee_overrides length, each override adds a FeeOverride
+        + size_of::<i64>()
+        + size_of::<PublicKey>()
+        + size_of::<PublicKey>()
+        + size_of::<u16>()
+        + size_of::<u8>() // the fee mode
+        + size_of::<u64>()
+        + size_of::<u64>()
+        + size_of::<u64>()
+        + size_of::<u64>()
+        + size_of::<bool>();
+
+    /// Whether the merchant can be packed into an account of this length
+    ///
+    /// Merchant accounts from before the fields after data have no room for them,
+    /// so they can only be packed while those fields are at their defaults.
+    pub fn fits(&self, len: usize) -> bool {
+        self.try_to_vec().unwrap().len() <= len
+    }
+
+    /// Whether the key can act for the merchant i.e. it is the owner or the operator
+    pub fn can_operate(&self, key: &PublicKey) -> bool {
+        self.owner == *key || (self.operator != [0; 32] && self.operator == *key)
+    }
+
+    /// The charity and its share (in basis points) of every payment, if any
+    pub fn get_charity(&self) -> Option<(PublicKey, u16)> {
+        if self.charity == [0; 32] || self.charity_bps == 0 {
+            return None;
+        }
+        Some((self.charity, self.charity_bps))
+    }
+
+    /// Whether a checkout of the amount is free of fees, see fee_free_below and
+    /// fee_free_above
+    pub fn is_fee_free(&self, amount: u64) -> bool {
+        (self.fee_free_below != 0 && amount < self.fee_free_below)
+            || (self.fee_free_above != 0 && amount > self.fee_free_above)
+    }
+}
+
+// impl for FeeOverride
+impl FeeOverride {
+    pub const LEN: usize = size_of::<PublicKey>() + size_of::<u64>();
 }
 
 // impl for OrderAccount
 impl Sealed for OrderAccount {}
 
-impl Serdes for OrderAccount {}
+impl Serdes for OrderAccount {
+    /// The fields after data are left out at their defaults (see serialize), so
+    /// the space after the content is zeroed to clear any that were set before
+    fn pack(&self, dst: &mut [u8]) {
+        let encoded = self.try_to_vec().unwrap();
+        dst[..encoded.len()].copy_from_slice(&encoded);
+        for byte in dst[encoded.len()..].iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    /// Order accounts are sized for the fields after data, which are left zeroed
+    /// at their defaults
+    fn unpack(src: &[u8]) -> Result<Self, Error> {
+        let mut buf = src;
+        let order = Self::deserialize(&mut buf)?;
+        if !is_blank(buf) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Not all bytes read: the order account has trailing data",
+            ));
+        }
+        Ok(order)
+    }
+}
 
 impl OrderAccount {
     pub const MIN_LEN: usize = size_of::<u8>()
@@ -117,13 +638,142 @@ impl OrderAccount {
         + size_of::<PublicKey>()
         + size_of::<PublicKey>()
         + size_of::<u64>()
+        + size_of::<u64>()
+        + size_of::<u64>()
+        + size_of::<bool>()
+        + size_of::<bool>()
         + size_of::<u64>();
+
+    /// the length of the fields after data
+    const EXTENSION_LEN: usize =
+        size_of::<u64>() + size_of::<bool>() + size_of::<bool>() + size_of::<u64>();
+
+    /// Whether the order was created before refunds, locks and consumption, i.e.
+    /// its account ends after data and has no room for the fields after it
+    pub fn is_legacy(src: &[u8]) -> bool {
+        match Self::unpack(src) {
+            Err(_error) => false,
+            Ok(order) => src.len() < order.base_len() + Self::EXTENSION_LEN,
+        }
+    }
+
+    /// Convert an order created before the fields after data to the current layout
+    ///
+    /// The order gets (zeroed) room for the fields after data, orders in the current
+    /// layout are returned unchanged.  Accounts can not be resized so this is for
+    /// tools that copy orders, on chain the old layout is read and written as it is
+    /// (see unpack and fits).
+    pub fn migrate(src: &[u8]) -> Result<Vec<u8>, Error> {
+        let order = Self::unpack(src)?;
+        let mut dst = src.to_vec();
+        if Self::is_legacy(src) {
+            dst.resize(order.base_len() + Self::EXTENSION_LEN, 0);
+        }
+        Ok(dst)
+    }
+
+    /// The length of the order up to and including data
+    fn base_len(&self) -> usize {
+        size_of::<u8>()
+            + size_of::<u8>()
+            + size_of::<UnixTimestamp>()
+            + size_of::<UnixTimestamp>()
+            + size_of::<PublicKey>() * 4
+            + size_of::<u64>()
+            + size_of::<u64>()
+            + size_of::<u32>()
+            + self.order_id.len()
+            + self
+                .secret
+                .as_ref()
+                .map_or(0, |secret| size_of::<u32>() + secret.len())
+            + size_of::<u32>()
+            + self.data.len()
+    }
+
+    /// Whether the order can be packed into an account of this length
+    ///
+    /// Orders from before the fields after data have no room for them, so they
+    /// can only be packed while those fields are at their defaults.
+    pub fn fits(&self, len: usize) -> bool {
+        self.try_to_vec().unwrap().len() <= len
+    }
+
+    /// Move the order to another status
+    ///
+    /// Orders are created paid (or pending), paid orders are either withdrawn or
+    /// cancelled and pending orders are either paid or cancelled.  Withdrawn and
+    /// cancelled orders are final.
+    pub fn transition(&mut self, to: OrderStatus) -> ProgramResult {
+        let from = self.status;
+        let is_legal = match &to {
+            OrderStatus::Uninitialized => false,
+            OrderStatus::Pending => from == OrderStatus::Uninitialized as u8,
+            OrderStatus::Paid => {
+                from == OrderStatus::Uninitialized as u8 || from == OrderStatus::Pending as u8
+            }
+            OrderStatus::Withdrawn => from == OrderStatus::Paid as u8,
+            OrderStatus::Cancelled => {
+                from == OrderStatus::Pending as u8 || from == OrderStatus::Paid as u8
+            }
+        };
+        let to = to as u8;
+        if !is_legal {
+            msg!(
+                "Error: An order cannot go from status {:?} to {:?}",
+                from,
+                to
+            );
+            return Err(PaymentProcessorError::InvalidOrderTransition.into());
+        }
+        self.status = to;
+        Ok(())
+    }
+
+    /// A deterministic 32 byte commitment to the order, e.g. for Merkle trees
+    ///
+    /// The SHA-256 hash of the Borsh encoding of the discriminator, status, created,
+    /// merchant, mint, token, payer, expected_amount, paid_amount, refunded_amount
+    /// and order_id, in that order.  The modified time, flags, secret and data are
+    /// left out.
+    pub fn commitment(&self) -> [u8; 32] {
+        hashv(&[
+            &[self.discriminator, self.status],
+            &self.created.to_le_bytes(),
+            &self.merchant,
+            &self.mint,
+            &self.token,
+            &self.payer,
+            &self.expected_amount.to_le_bytes(),
+            &self.paid_amount.to_le_bytes(),
+            &self.refunded_amount.to_le_bytes(),
+            &(self.order_id.len() as u32).to_le_bytes(),
+            self.order_id.as_bytes(),
+        ])
+        .to_bytes()
+    }
+
+    /// Whether the order is paid and its settlement delay (see MerchantAccount) has passed
+    pub fn is_settled(&self, now: UnixTimestamp, settlement_delay: i64) -> bool {
+        self.status == OrderStatus::Paid as u8
+            && now >= self.created.saturating_add(settlement_delay)
+    }
 }
 
 // impl for SubscriptionAccount
 impl Sealed for SubscriptionAccount {}
 
-impl Serdes for SubscriptionAccount {}
+impl Serdes for SubscriptionAccount {
+    /// Subscriptions from before the fields after data have no room for them and
+    /// are written without them, see is_legacy
+    fn pack(&self, dst: &mut [u8]) {
+        let mut encoded = self.try_to_vec().unwrap();
+        if encoded.len() == dst.len() + Self::EXTENSION_LEN {
+            encoded.truncate(dst.len());
+        }
+        dst[..encoded.len()].copy_from_slice(&encoded);
+    }
+}
 
 impl SubscriptionAccount {
     pub const MIN_LEN: usize = size_of::<u8>()
@@ -132,7 +782,178 @@ impl SubscriptionAccount {
         + size_of::<PublicKey>()
         + size_of::<UnixTimestamp>()
         + size_of::<UnixTimestamp>()
+        + size_of::<UnixTimestamp>()
+        + Self::EXTENSION_LEN;
+
+    /// the length of the fields after data
+    pub const EXTENSION_LEN: usize = size_of::<UnixTimestamp>() + size_of::<PublicKey>();
+
+    /// Whether the subscription was created before modified and current_order, i.e.
+    /// its account ends after data and has no room for them
+    ///
+    /// Such subscriptions are read with their defaults, and written without them, so
+    /// nothing that depends on them being stored can be done to them.
+    pub fn is_legacy(src: &[u8]) -> bool {
+        match Self::unpack(src) {
+            Err(_error) => false,
+            Ok(subscription) => {
+                subscription.try_to_vec().unwrap().len() == src.len() + Self::EXTENSION_LEN
+            }
+        }
+    }
+
+    /// Whether the subscription was cancelled, by the subscriber or the merchant
+    pub fn is_cancelled(&self) -> bool {
+        self.status == SubscriptionStatus::Cancelled as u8
+            || self.status == SubscriptionStatus::CancelledByMerchant as u8
+    }
+}
+
+// impl for PackageAccount
+impl Sealed for PackageAccount {}
+
+impl Serdes for PackageAccount {}
+
+impl PackageAccount {
+    pub const MIN_LEN: usize = size_of::<u8>()
+        + size_of::<PublicKey>()
+        + size_of::<PublicKey>()
+        + size_of::<i64>()
+        + size_of::<u64>();
+}
+
+// impl for CouponAccount
+impl Sealed for CouponAccount {}
+
+impl Serdes for CouponAccount {}
+
+impl CouponAccount {
+    pub const MIN_LEN: usize = size_of::<u8>()
+        + size_of::<PublicKey>()
+        + size_of::<u16>()
+        + size_of::<u64>()
+        + size_of::<u64>()
         + size_of::<UnixTimestamp>();
+
+    /// Whether the coupon can no longer be redeemed because it expired
+    pub fn is_expired(&self, timestamp: UnixTimestamp) -> bool {
+        self.expiry != 0 && timestamp > self.expiry
+    }
+
+    /// Whether the coupon was redeemed as many times as it can be
+    pub fn is_exhausted(&self) -> bool {
+        self.used_count >= self.max_redemptions
+    }
+}
+
+// impl for ConfigAccount
+impl Sealed for ConfigAccount {}
+
+impl Serdes for ConfigAccount {}
+
+impl ConfigAccount {
+    pub const LEN: usize = size_of::<u8>() + size_of::<PublicKey>();
+}
+
+// impl for RegistryAccount
+impl Sealed for RegistryAccount {}
+
+impl Serdes for RegistryAccount {
+    /// The registry account has room for MAX_REGISTRANTS keys, the unused space is
+    /// zeroed so that fewer keys can replace more
+    fn pack(&self, dst: &mut [u8]) {
+        let encoded = self.try_to_vec().unwrap();
+        dst[..encoded.len()].copy_from_slice(&encoded);
+        for byte in dst[encoded.len()..].iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    fn unpack(src: &[u8]) -> Result<Self, Error> {
+        let mut buf = src;
+        let registry = Self::deserialize(&mut buf)?;
+        if !is_blank(buf) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Not all bytes read: the registry account has trailing data",
+            ));
+        }
+        Ok(registry)
+    }
+}
+
+impl RegistryAccount {
+    pub const LEN: usize =
+        size_of::<u8>() + size_of::<u32>() + MAX_REGISTRANTS * size_of::<PublicKey>();
+
+    /// Whether the key is approved to register merchants
+    pub fn is_approved(&self, key: &PublicKey) -> bool {
+        self.registrants.contains(key)
+    }
+}
+
+// impl for LedgerEntry
+impl LedgerEntry {
+    pub const LEN: usize = size_of::<u64>() + size_of::<UnixTimestamp>() + size_of::<PublicKey>();
+}
+
+// impl for LedgerAccount
+impl Sealed for LedgerAccount {}
+
+impl Serdes for LedgerAccount {
+    /// The ledger account has room for LEDGER_CAPACITY entries, the unused space is
+    /// zeroed until the ledger is full
+    fn pack(&self, dst: &mut [u8]) {
+        let encoded = self.try_to_vec().unwrap();
+        dst[..encoded.len()].copy_from_slice(&encoded);
+        for byte in dst[encoded.len()..].iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    fn unpack(src: &[u8]) -> Result<Self, Error> {
+        let mut buf = src;
+        let ledger = Self::deserialize(&mut buf)?;
+        if !is_blank(buf) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Not all bytes read: the ledger account has trailing data",
+            ));
+        }
+        Ok(ledger)
+    }
+}
+
+impl LedgerAccount {
+    pub const LEN: usize = size_of::<u8>()
+        + size_of::<PublicKey>()
+        + size_of::<u64>()
+        + size_of::<u32>()
+        + LEDGER_CAPACITY * LedgerEntry::LEN;
+
+    /// Append a payment, overwriting the oldest entry when the ledger is full
+    pub fn append(&mut self, entry: LedgerEntry) {
+        if self.entries.len() < LEDGER_CAPACITY {
+            self.entries.push(entry);
+        } else {
+            let index = (self.count % LEDGER_CAPACITY as u64) as usize;
+            self.entries[index] = entry;
+        }
+        self.count = self.count.saturating_add(1);
+    }
+
+    /// The last n entries (all of them when there are fewer), oldest first
+    pub fn last_entries(&self, n: usize) -> Vec<LedgerEntry> {
+        let len = self.entries.len();
+        let oldest = if len < LEDGER_CAPACITY {
+            0
+        } else {
+            (self.count % LEDGER_CAPACITY as u64) as usize
+        };
+        (len.saturating_sub(n)..len)
+            .map(|position| self.entries[(oldest + position) % len].clone())
+            .collect()
+    }
 }
 
 /// Check if a program account state is closed
@@ -161,5 +982,579 @@ macro_rules! impl_IsClosed {
     }
 }
 
-impl_IsInitialized!(for MerchantAccount, OrderAccount, SubscriptionAccount);
-impl_IsClosed!(for MerchantAccount, OrderAccount, SubscriptionAccount);
+impl_IsInitialized!(for ConfigAccount, CouponAccount, LedgerAccount, MerchantAccount, OrderAccount, PackageAccount, RegistryAccount, SubscriptionAccount);
+impl_IsClosed!(for CouponAccount, LedgerAccount, MerchantAccount, OrderAccount, PackageAccount, RegistryAccount, SubscriptionAccount);
+
+#[cfg(test)]
+mod test {
+    use {super::*, solana_program::program_error::ProgramError, solana_program_test::*};
+
+    fn get_order(status: OrderStatus) -> OrderAccount {
+        OrderAccount {
+            discriminator: Discriminator::OrderExpressCheckout as u8,
+            status: status as u8,
+            created: 0,
+            modified: 0,
+            merchant: [0; 32],
+            mint: [0; 32],
+            token: [0; 32],
+            payer: [0; 32],
+            expected_amount: 1000,
+            paid_amount: 1000,
+            order_id: String::from("1"),
+            secret: Option::None,
+            data: String::from("{}"),
+            refunded_amount: 0,
+            locked: false,
+            consumed: false,
+            quoted_amount: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merchant_fee_mode_defaults_to_flat_sol() {
+        let mut merchant = MerchantAccount {
+            discriminator: Discriminator::Merchant as u8,
+            owner: [1; 32],
+            sponsor: [2; 32],
+            fee: 300,
+            data: String::from("{}"),
+            fee_recipient: [3; 32],
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        // merchant accounts from before fee modes have no fee mode byte
+        let legacy = merchant.try_to_vec().unwrap();
+        assert_eq!(merchant, MerchantAccount::unpack(&legacy).unwrap());
+        // the fee mode round trips
+        let len = MerchantAccount::MIN_LEN + 4 + merchant.data.len();
+        merchant.fee_mode = FeeMode::BpsToken;
+        let mut data = vec![0; len];
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        // and so do the amount limits
+        merchant.min_amount = 100;
+        merchant.max_amount = 5000;
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        // unused amount limits unpack as zero
+        merchant.min_amount = 0;
+        merchant.max_amount = 0;
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        // the fee free band round trips, with or without amount limits
+        merchant.fee_free_below = 1000;
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        merchant.min_amount = 100;
+        merchant.fee_free_above = 1000000;
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        // and so does the payment policy, with or without the fields before it
+        merchant.require_full_payment = true;
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        merchant.fee_mode = FeeMode::FlatSol;
+        merchant.min_amount = 0;
+        merchant.max_amount = 0;
+        merchant.fee_free_below = 0;
+        merchant.fee_free_above = 0;
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        // fields set back to their defaults are cleared
+        merchant.require_full_payment = false;
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_merchant_unpack_baseline_layout() {
+        // a merchant account registered before the fields after data existed
+        let mut baseline = vec![Discriminator::Merchant as u8];
+        baseline.extend_from_slice(&[1; 32]); // owner
+        baseline.extend_from_slice(&[2; 32]); // sponsor
+        baseline.extend_from_slice(&300u64.to_le_bytes()); // fee
+        baseline.extend_from_slice(&2u32.to_le_bytes());
+        baseline.extend_from_slice(b"{}"); // data
+
+        let mut merchant = MerchantAccount::unpack(&baseline).unwrap();
+        assert_eq!(Discriminator::Merchant as u8, merchant.discriminator);
+        assert_eq!([1; 32], merchant.owner);
+        assert_eq!([2; 32], merchant.sponsor);
+        assert_eq!(300, merchant.fee);
+        assert_eq!(String::from("{}"), merchant.data);
+        assert_eq!(default_fee_recipient(), merchant.fee_recipient);
+        assert_eq!(0, merchant.order_count);
+        assert_eq!(FeeMode::FlatSol, merchant.fee_mode);
+        assert!(merchant.accepted_mints.is_empty());
+        assert_eq!(None, merchant.get_charity());
+
+        // it is written back in the same layout
+        assert!(merchant.fits(baseline.len()));
+        let mut dst = baseline.clone();
+        merchant.pack(&mut dst);
+        assert_eq!(baseline, dst);
+        // but the fields after data do not fit
+        merchant.order_count = 1;
+        assert!(!merchant.fits(baseline.len()));
+        merchant.order_count = 0;
+        merchant.operator = [3; 32];
+        assert!(!merchant.fits(baseline.len()));
+    }
+
+    /// OrderAccount as it was before the fields after data, in the first state.rs
+    #[derive(BorshSerialize)]
+    struct BaselineOrderAccount {
+        discriminator: u8,
+        status: u8,
+        created: UnixTimestamp,
+        modified: UnixTimestamp,
+        merchant: PublicKey,
+        mint: PublicKey,
+        token: PublicKey,
+        payer: PublicKey,
+        expected_amount: u64,
+        paid_amount: u64,
+        order_id: String,
+        secret: String,
+        data: String,
+    }
+
+    fn get_baseline_order(secret: &str) -> Vec<u8> {
+        BaselineOrderAccount {
+            discriminator: Discriminator::OrderExpressCheckout as u8,
+            status: OrderStatus::Paid as u8,
+            created: 0,
+            modified: 0,
+            merchant: [0; 32],
+            mint: [0; 32],
+            token: [0; 32],
+            payer: [0; 32],
+            expected_amount: 1000,
+            paid_amount: 1000,
+            order_id: String::from("1"),
+            secret: String::from(secret),
+            data: String::from("{}"),
+        }
+        .try_to_vec()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_order_unpack_baseline_layout() {
+        // an order paid before refunds, locks and consumption existed
+        let baseline = get_baseline_order("");
+        let mut order = get_order(OrderStatus::Paid);
+        assert_eq!(order, OrderAccount::unpack(&baseline).unwrap());
+        // its secret is read as is
+        order.secret = Some(String::from("hunter2"));
+        assert_eq!(
+            order,
+            OrderAccount::unpack(&get_baseline_order("hunter2")).unwrap()
+        );
+        order.secret = Option::None;
+
+        // it can be withdrawn, and is written back in the same layout
+        let mut dst = baseline.clone();
+        order.transition(OrderStatus::Withdrawn).unwrap();
+        assert!(order.fits(baseline.len()));
+        order.pack(&mut dst);
+        assert_eq!(baseline.len(), dst.len());
+        assert_eq!(order, OrderAccount::unpack(&dst).unwrap());
+        // but it has no room for the fields after data
+        order.locked = true;
+        assert!(!order.fits(baseline.len()));
+        order.locked = false;
+        order.refunded_amount = 1;
+        assert!(!order.fits(baseline.len()));
+        order.refunded_amount = 0;
+
+        // migrating it makes room for them
+        assert!(OrderAccount::is_legacy(&baseline));
+        let migrated = OrderAccount::migrate(&baseline).unwrap();
+        // its empty secret is left out when it is written in the current layout
+        assert_eq!(baseline.len() - 4 + 18, migrated.len());
+        assert!(!OrderAccount::is_legacy(&migrated));
+        assert_eq!(
+            OrderAccount::unpack(&baseline).unwrap(),
+            OrderAccount::unpack(&migrated).unwrap()
+        );
+        order.status = OrderStatus::Paid as u8;
+        order.locked = true;
+        order.refunded_amount = 1;
+        let mut dst = migrated.clone();
+        assert!(order.fits(migrated.len()));
+        order.pack(&mut dst);
+        assert_eq!(order, OrderAccount::unpack(&dst).unwrap());
+        // orders in the current layout are left as they are
+        assert_eq!(migrated, OrderAccount::migrate(&migrated).unwrap());
+        assert!(OrderAccount::migrate(&baseline[..10]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_order_pack_fields_after_data() {
+        let mut order = get_order(OrderStatus::Paid);
+        // room for the fields after data and some padding
+        let len = order.try_to_vec().unwrap().len() + 18 + 8;
+        let mut data = vec![0; len];
+        order.pack(&mut data);
+        assert_eq!(order, OrderAccount::unpack(&data).unwrap());
+        order.locked = true;
+        order.pack(&mut data);
+        assert_eq!(order, OrderAccount::unpack(&data).unwrap());
+        order.refunded_amount = 500;
+        order.consumed = true;
+        order.pack(&mut data);
+        assert_eq!(order, OrderAccount::unpack(&data).unwrap());
+        // fields set back to their defaults are cleared
+        order.refunded_amount = 0;
+        order.locked = false;
+        order.consumed = false;
+        order.pack(&mut data);
+        assert_eq!(order, OrderAccount::unpack(&data).unwrap());
+        // anything else after the content is rejected
+        data[len - 1] = 1;
+        assert!(OrderAccount::unpack(&data).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_order_without_secret() {
+        let mut order = get_order(OrderStatus::Paid);
+        let without_secret = order.try_to_vec().unwrap();
+        // the secret is left out and the status says so
+        assert_eq!(OrderStatus::Paid as u8 | NO_SECRET_FLAG, without_secret[1]);
+        assert_eq!(order, OrderAccount::unpack(&without_secret).unwrap());
+        order.secret = Some(String::from(""));
+        let empty_secret = order.try_to_vec().unwrap();
+        assert_eq!(without_secret.len() + 4, empty_secret.len());
+        assert_eq!(OrderStatus::Paid as u8, empty_secret[1]);
+        // an empty secret is read as no secret, as older orders store it
+        order.secret = Option::None;
+        assert_eq!(order, OrderAccount::unpack(&empty_secret).unwrap());
+        order.secret = Some(String::from("hunter2"));
+        assert_eq!(
+            order,
+            OrderAccount::unpack(&order.try_to_vec().unwrap()).unwrap()
+        );
+    }
+
+    /// SubscriptionAccount as it was before the fields after data, in the first state.rs
+    #[derive(BorshSerialize)]
+    struct BaselineSubscriptionAccount {
+        discriminator: u8,
+        status: u8,
+        owner: PublicKey,
+        merchant: PublicKey,
+        name: String,
+        joined: UnixTimestamp,
+        period_start: UnixTimestamp,
+        period_end: UnixTimestamp,
+        data: String,
+    }
+
+    #[tokio::test]
+    async fn test_subscription_unpack_baseline_layout() {
+        let baseline = BaselineSubscriptionAccount {
+            discriminator: Discriminator::Subscription as u8,
+            status: SubscriptionStatus::Initialized as u8,
+            owner: [1; 32],
+            merchant: [2; 32],
+            name: String::from("basic"),
+            joined: 100,
+            period_start: 100,
+            period_end: 200,
+            data: String::from("{}"),
+        }
+        .try_to_vec()
+        .unwrap();
+        let mut subscription = SubscriptionAccount {
+            discriminator: Discriminator::Subscription as u8,
+            status: SubscriptionStatus::Initialized as u8,
+            owner: [1; 32],
+            merchant: [2; 32],
+            name: String::from("basic"),
+            joined: 100,
+            period_start: 100,
+            period_end: 200,
+            data: String::from("{}"),
+            // not modified since joined, and the order is unknown
+            modified: 100,
+            current_order: [0; 32],
+        };
+        assert_eq!(
+            subscription,
+            SubscriptionAccount::unpack(&baseline).unwrap()
+        );
+        assert!(SubscriptionAccount::is_legacy(&baseline));
+
+        // it is written back in the same layout, without the fields after data
+        let mut dst = baseline.clone();
+        subscription.period_end = 300;
+        subscription.modified = 150;
+        subscription.current_order = [3; 32];
+        subscription.pack(&mut dst);
+        let unpacked = SubscriptionAccount::unpack(&dst).unwrap();
+        assert_eq!(300, unpacked.period_end);
+        assert_eq!(100, unpacked.modified);
+        assert_eq!([0; 32], unpacked.current_order);
+
+        // new subscriptions have room for them
+        let mut data = vec![0; baseline.len() + SubscriptionAccount::EXTENSION_LEN];
+        subscription.pack(&mut data);
+        assert_eq!(subscription, SubscriptionAccount::unpack(&data).unwrap());
+        assert!(!SubscriptionAccount::is_legacy(&data));
+    }
+
+    #[tokio::test]
+    async fn test_registry_pack_fewer_registrants() {
+        let mut data = vec![0; RegistryAccount::LEN];
+        let mut registry = RegistryAccount {
+            discriminator: Discriminator::Registry as u8,
+            registrants: vec![[1; 32], [2; 32], [3; 32]],
+        };
+        registry.pack(&mut data);
+        assert_eq!(registry, RegistryAccount::unpack(&data).unwrap());
+        assert!(registry.is_approved(&[2; 32]));
+        // the keys left behind are zeroed
+        registry.registrants = vec![[3; 32]];
+        registry.pack(&mut data);
+        assert_eq!(registry, RegistryAccount::unpack(&data).unwrap());
+        assert!(!registry.is_approved(&[2; 32]));
+        data[RegistryAccount::LEN - 1] = 1;
+        assert!(RegistryAccount::unpack(&data).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_merchant_unpack_with_padding() {
+        let mut merchant = MerchantAccount {
+            discriminator: Discriminator::Merchant as u8,
+            owner: [1; 32],
+            sponsor: [2; 32],
+            fee: 300,
+            data: String::from(r#"{"a": "b"}"#),
+            fee_recipient: [3; 32],
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        let len = merchant.try_to_vec().unwrap().len();
+        // any amount of zero padding, even less than a whole field, is ignored
+        for padding in &[0, 1, 3, 8, 100] {
+            let mut data = vec![0; len + padding];
+            merchant.pack(&mut data);
+            assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        }
+        // including after the trailing fields
+        merchant.fee_mode = FeeMode::BpsToken;
+        merchant.max_amount = 5000;
+        merchant.fee_free_above = 256;
+        let len = merchant.try_to_vec().unwrap().len();
+        let mut data = vec![0; len + 100];
+        merchant.pack(&mut data);
+        assert_eq!(merchant, MerchantAccount::unpack(&data).unwrap());
+        // but anything else after the content is rejected
+        data[len + 50] = 1;
+        assert!(MerchantAccount::unpack(&data).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_merchant_is_fee_free() {
+        let mut merchant = MerchantAccount {
+            discriminator: Discriminator::Merchant as u8,
+            owner: [1; 32],
+            sponsor: [2; 32],
+            fee: 300,
+            data: String::from("{}"),
+            fee_recipient: [3; 32],
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        // no band, no free checkouts
+        assert!(!merchant.is_fee_free(0));
+        assert!(!merchant.is_fee_free(u64::MAX));
+        merchant.fee_free_below = 1000;
+        assert!(merchant.is_fee_free(999));
+        assert!(!merchant.is_fee_free(1000));
+        assert!(!merchant.is_fee_free(u64::MAX));
+        merchant.fee_free_above = 1000000;
+        assert!(merchant.is_fee_free(999));
+        assert!(!merchant.is_fee_free(1000));
+        assert!(!merchant.is_fee_free(1000000));
+        assert!(merchant.is_fee_free(1000001));
+        merchant.fee_free_below = 0;
+        assert!(!merchant.is_fee_free(999));
+        assert!(merchant.is_fee_free(1000001));
+    }
+
+    #[tokio::test]
+    async fn test_order_transition() {
+        // OrderStatus is not Copy, so the expected status comes with each transition
+        let legal_transitions = vec![
+            (
+                OrderStatus::Uninitialized,
+                OrderStatus::Pending,
+                OrderStatus::Pending as u8,
+            ),
+            (
+                OrderStatus::Uninitialized,
+                OrderStatus::Paid,
+                OrderStatus::Paid as u8,
+            ),
+            (
+                OrderStatus::Pending,
+                OrderStatus::Paid,
+                OrderStatus::Paid as u8,
+            ),
+            (
+                OrderStatus::Pending,
+                OrderStatus::Cancelled,
+                OrderStatus::Cancelled as u8,
+            ),
+            (
+                OrderStatus::Paid,
+                OrderStatus::Withdrawn,
+                OrderStatus::Withdrawn as u8,
+            ),
+            (
+                OrderStatus::Paid,
+                OrderStatus::Cancelled,
+                OrderStatus::Cancelled as u8,
+            ),
+        ];
+        for (from, to, expected_status) in legal_transitions {
+            let mut order = get_order(from);
+            assert_eq!(Ok(()), order.transition(to));
+            assert_eq!(expected_status, order.status);
+        }
+        let illegal_transitions = vec![
+            (OrderStatus::Uninitialized, OrderStatus::Withdrawn),
+            (OrderStatus::Pending, OrderStatus::Withdrawn),
+            (OrderStatus::Paid, OrderStatus::Paid),
+            (OrderStatus::Paid, OrderStatus::Uninitialized),
+            (OrderStatus::Withdrawn, OrderStatus::Withdrawn),
+            (OrderStatus::Withdrawn, OrderStatus::Cancelled),
+            (OrderStatus::Cancelled, OrderStatus::Paid),
+            (OrderStatus::Cancelled, OrderStatus::Withdrawn),
+        ];
+        for (from, to) in illegal_transitions {
+            let mut order = get_order(from);
+            let from_status = order.status;
+            assert_eq!(
+                Err(ProgramError::Custom(
+                    PaymentProcessorError::InvalidOrderTransition as u32
+                )),
+                order.transition(to)
+            );
+            // the status is left as it was
+            assert_eq!(from_status, order.status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_order_commitment() {
+        let order = get_order(OrderStatus::Paid);
+        // identical orders have the same commitment
+        assert_eq!(
+            order.commitment(),
+            get_order(OrderStatus::Paid).commitment()
+        );
+        // which is the hash of the key fields
+        let mut encoded = vec![order.discriminator, order.status];
+        encoded.extend_from_slice(&order.created.to_le_bytes());
+        encoded.extend_from_slice(&order.merchant);
+        encoded.extend_from_slice(&order.mint);
+        encoded.extend_from_slice(&order.token);
+        encoded.extend_from_slice(&order.payer);
+        encoded.extend_from_slice(&order.expected_amount.to_le_bytes());
+        encoded.extend_from_slice(&order.paid_amount.to_le_bytes());
+        encoded.extend_from_slice(&order.refunded_amount.to_le_bytes());
+        encoded.extend_from_slice(&order.order_id.try_to_vec().unwrap());
+        assert_eq!(
+            solana_program::hash::hash(&encoded).to_bytes(),
+            order.commitment()
+        );
+        // changing any key field changes the commitment
+        let changes: Vec<fn(&mut OrderAccount)> = vec![
+            |order| order.discriminator = Discriminator::OrderChainCheckout as u8,
+            |order| order.status = OrderStatus::Withdrawn as u8,
+            |order| order.created = 1,
+            |order| order.merchant = [1; 32],
+            |order| order.mint = [1; 32],
+            |order| order.token = [1; 32],
+            |order| order.payer = [1; 32],
+            |order| order.expected_amount = 1001,
+            |order| order.paid_amount = 1001,
+            |order| order.refunded_amount = 1,
+            |order| order.order_id = String::from("2"),
+        ];
+        for change in changes {
+            let mut changed = get_order(OrderStatus::Paid);
+            change(&mut changed);
+            assert_ne!(order.commitment(), changed.commitment());
+        }
+        // the other fields do not
+        let mut changed = get_order(OrderStatus::Paid);
+        changed.modified = 1;
+        changed.data = String::from(r#"{"a": 1}"#);
+        assert_eq!(order.commitment(), changed.commitment());
+    }
+
+    #[tokio::test]
+    async fn test_order_is_settled() {
+        let mut order = get_order(OrderStatus::Paid);
+        order.created = 1000;
+        // paid orders settle once the settlement delay has passed
+        assert!(order.is_settled(1000, 0));
+        assert!(!order.is_settled(1059, 60));
+        assert!(order.is_settled(1060, 60));
+        assert!(!order.is_settled(i64::MAX - 1, i64::MAX));
+        // other orders never do
+        for status in vec![
+            OrderStatus::Uninitialized,
+            OrderStatus::Pending,
+            OrderStatus::Withdrawn,
+            OrderStatus::Cancelled,
+        ] {
+            order.status = status as u8;
+            assert!(!order.is_settled(1060, 60));
+        }
+    }
+}
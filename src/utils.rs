@@ -1,9 +1,25 @@
-use crate::engine::constants::STRING_SIZE;
-use crate::state::{MerchantAccount, OrderAccount, SubscriptionAccount};
+use crate::engine::constants::{
+    ASSOCIATED_TOKEN_PROGRAM_ID, COMPACT_ID_LEN, CONFIG_SEED, COUPON_SEED, LEDGER_SEED, MAX_BPS,
+    MAX_ORDER_ID_LEN, ORDER_SEED, PACKAGE_SEED, PDA_SEED, PROGRAM_OWNER, REGISTRY_SEED,
+    SPONSOR_FEE, STRING_SIZE, USD_DECIMALS,
+};
+use crate::error::PaymentProcessorError;
+use crate::state::{
+    CouponAccount, FeeMode, FeeOverride, MerchantAccount, OrderAccount, PackageAccount, PublicKey,
+    SubscriptionAccount,
+};
+use borsh::BorshSerialize;
+use solana_program::{program_pack::Pack, pubkey::Pubkey, rent::Rent};
+use spl_token::state::Account as TokenAccount;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::str::FromStr;
 
-/// Given the expected amount, calculate the fee and take home amount
-/// Currently fee is 0.3% with a minimum fee of 1 lamport
-/// If the amount is less than 100 lamports the fee is 0
+/// Given an amount and a share in tenths of a percent, calculate the (rest, share)
+///
+/// The share is at least 1 from an amount of 100 and 0 below that.  Only used to
+/// split the SOL fee between the program owner and the sponsor (see distribute_fee),
+/// checkouts pay the merchant fee of get_checkout_fees whatever the amount.
 pub fn get_amounts(amount: u64, fee_percentage: u128) -> (u64, u64) {
     let mut fee_amount: u64 = 0;
     let mut take_home_amount: u64 = amount;
@@ -20,6 +36,125 @@ pub fn get_amounts(amount: u64, fee_percentage: u128) -> (u64, u64) {
     (take_home_amount, fee_amount)
 }
 
+/// Given the merchant fee, calculate the (program owner, sponsor) shares
+///
+/// For a merchant without a sponsor (see has_sponsor) the sponsor share is 0 and
+/// the checkout only needs one fee transfer.
+/// Otherwise the sponsor share is rounded down (see get_amounts) and the program
+/// owner keeps the rest, rounding dust included, so the two shares always add up
+/// to the total fee.
+pub fn distribute_fee(total_fee: u64, sponsor_is_owner: bool, sponsor_fee_pct: u128) -> (u64, u64) {
+    if sponsor_is_owner {
+        return (total_fee, 0);
+    }
+    get_amounts(total_fee, sponsor_fee_pct)
+}
+
+/// Whether the merchant has a sponsor that takes a share of the SOL fee
+///
+/// Merchants registered without a sponsor have the program owner as their sponsor,
+/// and a sponsor that is the fee recipient (e.g. the config owner, see
+/// config::get_program_owner) would only pay itself, so neither takes a share.
+pub fn has_sponsor(merchant_account: &MerchantAccount, fee_recipient: &Pubkey) -> bool {
+    let sponsor = Pubkey::new_from_array(merchant_account.sponsor);
+    sponsor != *fee_recipient && sponsor != Pubkey::from_str(PROGRAM_OWNER).unwrap()
+}
+
+/// Given the SOL fee of a checkout to the merchant, calculate the (fee recipient,
+/// sponsor) shares, see distribute_fee and has_sponsor
+pub fn get_fee_shares(
+    merchant_account: &MerchantAccount,
+    fee_recipient: &Pubkey,
+    sol_fee: u64,
+) -> (u64, u64) {
+    distribute_fee(
+        sol_fee,
+        !has_sponsor(merchant_account, fee_recipient),
+        SPONSOR_FEE,
+    )
+}
+
+/// Given the SOL fee of a checkout to the merchant, the (recipient, lamports) fee
+/// transfers the checkout makes
+///
+/// Each transfer is one system program CPI.  Without a sponsor (the common case)
+/// the whole fee goes to the fee recipient in a single transfer, and shares of 0
+/// are not transferred at all.
+pub fn get_fee_transfers(
+    merchant_account: &MerchantAccount,
+    fee_recipient: &Pubkey,
+    sol_fee: u64,
+) -> Vec<(Pubkey, u64)> {
+    let (fee_recipient_share, sponsor_share) =
+        get_fee_shares(merchant_account, fee_recipient, sol_fee);
+    vec![
+        (*fee_recipient, fee_recipient_share),
+        (
+            Pubkey::new_from_array(merchant_account.sponsor),
+            sponsor_share,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_recipient, lamports)| *lamports > 0)
+    .collect()
+}
+
+/// Given the payment amount and the charity share in basis points, calculate the
+/// (seller, charity) amounts
+///
+/// The charity amount is rounded down and the two amounts always add up to the
+/// payment amount.
+pub fn split_charity_amount(amount: u64, charity_bps: u16) -> (u64, u64) {
+    let charity_amount = get_bps_amount(amount, charity_bps as u64);
+    (amount - charity_amount, charity_amount)
+}
+
+/// Given an amount, calculate the given basis points of it, rounded down
+///
+/// Never more than the amount, even for more than MAX_BPS basis points.
+pub fn get_bps_amount(amount: u64, bps: u64) -> u64 {
+    (amount as u128 * bps.min(MAX_BPS as u64) as u128 / MAX_BPS as u128) as u64
+}
+
+/// Given a price and the basis points of a coupon, calculate the price after the
+/// coupon discount
+///
+/// The discount is rounded down, so the buyer never pays less than the coupon allows.
+pub fn get_coupon_price(amount: u64, coupon_bps: u16) -> u64 {
+    amount - get_bps_amount(amount, coupon_bps as u64)
+}
+
+/// Given the balances of the seller token account before and after the payment
+/// transfer, calculate the amount actually paid
+///
+/// This is never more than the amount the buyer meant to pay
+pub fn get_paid_amount(amount: u64, balance_before: u64, balance_after: u64) -> u64 {
+    amount.min(balance_after.saturating_sub(balance_before))
+}
+
+/// Convert a USD amount to token units given the oracle price of one token
+///
+/// The oracle price is price * 10^expo USD.  The result is rounded up so that
+/// paying it is never less than the USD amount.  None if the price is not
+/// positive or the conversion overflows.
+pub fn get_token_amount(usd_amount: u64, price: i64, expo: i32, decimals: u8) -> Option<u64> {
+    if price <= 0 {
+        return None;
+    }
+    let mut numerator = (usd_amount as u128).checked_mul(10u128.checked_pow(decimals as u32)?)?;
+    let mut denominator = (price as u128).checked_mul(10u128.pow(USD_DECIMALS))?;
+    if expo < 0 {
+        numerator = numerator.checked_mul(10u128.checked_pow(expo.checked_neg()? as u32)?)?;
+    } else {
+        denominator = denominator.checked_mul(10u128.checked_pow(expo as u32)?)?;
+    }
+    let token_amount = numerator.checked_add(denominator - 1)? / denominator;
+    if token_amount > u64::MAX as u128 {
+        return None;
+    }
+    Some(token_amount as u64)
+}
+
 pub fn get_account_size(min_len: usize, strings: &Vec<&String>) -> usize {
     let mut size = min_len;
     for item in strings {
@@ -29,14 +164,141 @@ pub fn get_account_size(min_len: usize, strings: &Vec<&String>) -> usize {
     size
 }
 
+/// get the secret stored in an order account, None for an empty secret
+pub fn get_order_secret(secret: String) -> Option<String> {
+    if secret.is_empty() {
+        return None;
+    }
+    Some(secret)
+}
+
+/// whether an order secret was cleared (see RevealSecret), i.e. is all NUL characters
+pub fn is_cleared_secret(secret: &str) -> bool {
+    secret.chars().all(|c| c == '\0')
+}
+
+/// whether a secret is safe to hand to other tooling i.e. has no control characters
+///
+/// Secrets are always valid UTF-8, this rejects e.g. newlines and NUL.
+pub fn is_printable_secret(secret: &str) -> bool {
+    !secret.chars().any(char::is_control)
+}
+
+/// get the message a merchant signs for a merchant signed order
+///
+/// This is the Borsh serialization of (order_id, amount, mint), see
+/// engine::signature::verify_order_signature
+pub fn get_order_signature_message(order_id: &str, amount: u64, mint: &Pubkey) -> Vec<u8> {
+    (order_id, amount, mint.to_bytes()).try_to_vec().unwrap()
+}
+
+/// An order id that is valid to send in a checkout
+///
+/// Order ids are 1 to MAX_ORDER_ID_LEN printable ASCII characters (spaces included).
+/// The program itself accepts any string, this is checked when building instructions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderId(String);
+
+impl OrderId {
+    pub fn try_new(order_id: &str) -> Result<OrderId, PaymentProcessorError> {
+        if order_id.is_empty()
+            || order_id.len() > MAX_ORDER_ID_LEN
+            || !order_id
+                .chars()
+                .all(|character| character.is_ascii_graphic() || character == ' ')
+        {
+            return Err(PaymentProcessorError::InvalidOrderId);
+        }
+        Ok(OrderId(String::from(order_id)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<OrderId> for String {
+    fn from(order_id: OrderId) -> String {
+        order_id.0
+    }
+}
+
+/// get the string of an order id or secret sent in a compact express checkout
+///
+/// This is the lowercase hex of the bytes, or an empty string for all zero bytes
+/// (e.g. no secret).
+pub fn get_compact_id_string(id: &[u8; COMPACT_ID_LEN]) -> String {
+    if id.iter().all(|byte| *byte == 0) {
+        return String::from("");
+    }
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
 /// get order account size
-pub fn get_order_account_size(order_id: &String, secret: &String, data: &String) -> usize {
-    get_account_size(OrderAccount::MIN_LEN, &vec![order_id, secret, data])
+///
+/// Orders without a secret leave it out, length included.
+pub fn get_order_account_size(order_id: &String, secret: &Option<String>, data: &String) -> usize {
+    match secret {
+        None => get_account_size(OrderAccount::MIN_LEN, &vec![order_id, data]),
+        Some(secret) => get_account_size(OrderAccount::MIN_LEN, &vec![order_id, secret, data]),
+    }
 }
 
 /// get merchant account size
-pub fn get_merchant_account_size(data: &String) -> usize {
-    get_account_size(MerchantAccount::MIN_LEN, &vec![data])
+pub fn get_merchant_account_size(
+    data: &String,
+    accepted_mints: &[Pubkey],
+    fee_overrides: &[(Pubkey, u64)],
+) -> usize {
+    get_account_size(
+        MerchantAccount::MIN_LEN
+            + accepted_mints.len() * size_of::<PublicKey>()
+            + fee_overrides.len() * FeeOverride::LEN,
+        &vec![data],
+    )
+}
+
+/// Get the fee that the merchant charges this buyer, see FeeMode
+pub fn get_buyer_fee(merchant_account: &MerchantAccount, buyer: &Pubkey) -> u64 {
+    match merchant_account
+        .fee_overrides
+        .iter()
+        .find(|fee_override| fee_override.buyer == buyer.to_bytes())
+    {
+        None => merchant_account.fee,
+        Some(fee_override) => fee_override.fee,
+    }
+}
+
+/// Get the (SOL lamports, token) fees that the merchant charges this buyer for
+/// paying the amount, none if the amount is in the merchant's fee free band
+///
+/// A flat fee is charged in full however small the amount, basis point fees are
+/// rounded down and so are 0 for small enough amounts.  These are the only fees
+/// of a checkout, the SOL fee is then shared out by get_fee_shares.
+pub fn get_checkout_fees(
+    merchant_account: &MerchantAccount,
+    buyer: &Pubkey,
+    amount: u64,
+) -> (u64, u64) {
+    if merchant_account.is_fee_free(amount) {
+        return (0, 0);
+    }
+    get_fees(
+        &merchant_account.fee_mode,
+        get_buyer_fee(merchant_account, buyer),
+        amount,
+    )
+}
+
+/// Get the (SOL lamports, token) fees of paying the amount given the fee mode
+/// and fee of the merchant
+fn get_fees(fee_mode: &FeeMode, fee: u64, amount: u64) -> (u64, u64) {
+    match fee_mode {
+        FeeMode::FlatSol => (fee, 0),
+        FeeMode::BpsSol => (get_bps_amount(amount, fee), 0),
+        FeeMode::BpsToken => (0, get_bps_amount(amount, fee)),
+    }
 }
 
 /// get subscription account size
@@ -44,9 +306,295 @@ pub fn get_subscription_account_size(name: &String, data: &String) -> usize {
     get_account_size(SubscriptionAccount::MIN_LEN, &vec![name, data])
 }
 
+/// get package account size
+pub fn get_package_account_size(name: &String) -> usize {
+    get_account_size(PackageAccount::MIN_LEN, &vec![name])
+}
+
+/// get coupon account size
+pub fn get_coupon_account_size(code: &String) -> usize {
+    get_account_size(CouponAccount::MIN_LEN, &vec![code])
+}
+
+/// get the rent (in SOL lamports) of a token account
+///
+/// Token accounts of the SPL token program are TokenAccount::LEN long.  The
+/// length is passed in because token accounts with Token-2022 extensions are
+/// longer.
+pub fn token_account_rent(rent: &Rent, account_len: usize) -> u64 {
+    rent.minimum_balance(account_len)
+}
+
+/// estimate the total cost (in SOL lamports) of a checkout for the buyer
+///
+/// This is the rent for the order account plus the rent for the seller token
+/// account plus the merchant's processing fee.  When paying into a merchant
+/// token account no seller token account is created so the cost is lower.
+pub fn estimate_checkout_cost(order_id: &str, secret: &str, data: &str, merchant_fee: u64) -> u64 {
+    let order_account_size = get_order_account_size(
+        &String::from(order_id),
+        &get_order_secret(String::from(secret)),
+        &String::from(data),
+    );
+    let rent = Rent::default();
+    rent.minimum_balance(order_account_size)
+        + token_account_rent(&rent, TokenAccount::LEN)
+        + merchant_fee
+}
+
+/// The price of a cart of items, see quote_cart
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CartQuote {
+    /// the amount (in token units) of each line, keyed by item
+    pub lines: BTreeMap<String, u64>,
+    /// the amount (in token units) to pay for the whole cart
+    pub total: u64,
+    /// the fees of paying the total to the merchant
+    pub fee: FeeQuote,
+}
+
+/// Quote a cart of items given the unit price of each item
+///
+/// The total is the sum of price * quantity, the least a chain checkout of the
+/// same items accepts, and the fee is that of paying the total (see quote).
+/// None if an item has no price or the amounts overflow.
+pub fn quote_cart(
+    items: &BTreeMap<String, u64>,
+    unit_prices: &BTreeMap<String, u64>,
+    merchant_account: &MerchantAccount,
+) -> Option<CartQuote> {
+    let mut lines = BTreeMap::new();
+    let mut total: u64 = 0;
+    for (key, quantity) in items.iter() {
+        let line_amount = unit_prices.get(key)?.checked_mul(*quantity)?;
+        total = total.checked_add(line_amount)?;
+        lines.insert(key.clone(), line_amount);
+    }
+    Some(CartQuote {
+        lines,
+        total,
+        fee: quote(total, merchant_account),
+    })
+}
+
+/// The fees of a checkout to a merchant, see quote
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeeQuote {
+    /// the amount (in token units) the buyer pays
+    pub amount: u64,
+    /// the fee (in SOL lamports) the buyer pays on top of the amount
+    pub sol_fee: u64,
+    /// the fee (in token units) taken out of the amount
+    pub token_fee: u64,
+    /// the share of the amount (in token units) that goes to the merchant's charity
+    pub charity_amount: u64,
+    /// what is left of the amount (in token units) for the merchant
+    pub seller_amount: u64,
+}
+
+/// Quote the fees of paying the amount to the merchant
+///
+/// This is the split a checkout makes for buyers without a fee override.  The
+/// seller amount is 0 if the fee and charity share are more than the amount, a
+/// checkout of that amount fails.
+pub fn quote(amount: u64, merchant_account: &MerchantAccount) -> FeeQuote {
+    let (sol_fee, token_fee) = if merchant_account.is_fee_free(amount) {
+        (0, 0)
+    } else {
+        get_fees(&merchant_account.fee_mode, merchant_account.fee, amount)
+    };
+    let (seller_amount, charity_amount) = match merchant_account.get_charity() {
+        None => (amount, 0),
+        Some((_charity, charity_bps)) => split_charity_amount(amount, charity_bps),
+    };
+    FeeQuote {
+        amount,
+        sol_fee,
+        token_fee,
+        charity_amount,
+        seller_amount: seller_amount.saturating_sub(token_fee),
+    }
+}
+
+/// Whether the order was paid at least the merchant's share of its expected amount
+///
+/// The charity share and the token fee (see quote) never reach the order token
+/// account so they are not counted as missing.  Orders quoted in USD expect the
+/// USD amount, so their quoted amount in token units is used instead.
+pub fn is_fully_paid(order_account: &OrderAccount, merchant_account: &MerchantAccount) -> bool {
+    let expected_amount = if order_account.quoted_amount != 0 {
+        order_account.quoted_amount
+    } else {
+        order_account.expected_amount
+    };
+    order_account.paid_amount >= quote(expected_amount, merchant_account).seller_amount
+}
+
+/// Quote the fees of paying each of the amounts to its merchant, see quote
+///
+/// Meant for clients that show the fees of a checkout to several merchants at
+/// once, the quotes are in the same order as the orders.
+pub fn quote_many(orders: &[(u64, MerchantAccount)]) -> Vec<FeeQuote> {
+    orders
+        .iter()
+        .map(|(amount, merchant_account)| quote(*amount, merchant_account))
+        .collect()
+}
+
+/// get the address of the merchant token account for a mint
+///
+/// This token account is owned by the program and can be shared by all the
+/// orders made to the merchant that are paid using this mint
+pub fn get_merchant_token_address(
+    merchant: &Pubkey,
+    mint: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &merchant.to_bytes(),
+            &spl_token::id().to_bytes(),
+            &mint.to_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// get the address of the seller token account of an order
+///
+/// This token account is owned by the program and holds the payment of a single
+/// order until it is withdrawn, see process_express_checkout
+pub fn get_seller_token_address(
+    order: &Pubkey,
+    mint: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &order.to_bytes(),
+            &spl_token::id().to_bytes(),
+            &mint.to_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// get the seller token account addresses of many (order, mint) pairs, in order
+pub fn get_seller_token_addresses(orders: &[(Pubkey, Pubkey)], program_id: &Pubkey) -> Vec<Pubkey> {
+    orders
+        .iter()
+        .map(|(order, mint)| get_seller_token_address(order, mint, program_id).0)
+        .collect()
+}
+
+/// get the address of the associated token account of a wallet for a mint
+///
+/// This token account is owned by the wallet and created by the associated
+/// token account program, e.g. the fee recipient token account of token fees
+pub fn get_associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            &wallet.to_bytes(),
+            &spl_token::id().to_bytes(),
+            &mint.to_bytes(),
+        ],
+        &Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID).unwrap(),
+    )
+    .0
+}
+
+/// get the address of the program config account
+pub fn get_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
+
+/// get the address of the registry account of the keys approved to register merchants
+pub fn get_registry_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REGISTRY_SEED], program_id)
+}
+
+/// get the address of a merchant's subscription package account
+///
+/// The package name is used as a seed so it can be at most MAX_SEED_LEN bytes
+pub fn get_package_address(merchant: &Pubkey, name: &str, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[&merchant.to_bytes(), PACKAGE_SEED, name.as_bytes()],
+        program_id,
+    )
+}
+
+/// get the address of a merchant's coupon account
+///
+/// The coupon code is used as a seed so it can be at most MAX_SEED_LEN bytes
+pub fn get_coupon_address(merchant: &Pubkey, code: &str, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[&merchant.to_bytes(), COUPON_SEED, code.as_bytes()],
+        program_id,
+    )
+}
+
+/// get the address of a merchant's ledger account, see LedgerAccount
+pub fn get_ledger_address(merchant: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[&merchant.to_bytes(), LEDGER_SEED], program_id)
+}
+
+/// get the address of a subscription account
+///
+/// The address is derived from the subscriber (base), the merchant and the
+/// package name, so there is one subscription per subscriber + merchant + name
+pub fn get_subscription_account_pubkey(
+    base: &Pubkey,
+    merchant: &Pubkey,
+    package_name: &str,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &base.to_bytes(),
+            &merchant.to_bytes(),
+            package_name.as_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// get the address of a merchant's order by its index
+///
+/// The index is the merchant's order count at the time of the order, so a
+/// merchant's orders can be enumerated by deriving the addresses for the
+/// indexes 0 to order_count - 1.  The merchant is part of the seeds, so orders
+/// with the same order id at different merchants never collide.
+pub fn get_order_pubkey_by_index(
+    merchant: &Pubkey,
+    index: u64,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[&merchant.to_bytes(), ORDER_SEED, &index.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// get the address of this program's derived address for a seed version
+///
+/// Version 0 is derived from PDA_SEED alone and owns the order and merchant token
+/// accounts, later versions add the version to the seeds so that token accounts can
+/// be moved to a new escrow authority (see MigratePdaTokenAccount).
+pub fn get_versioned_pda(program_id: &Pubkey, version: u8) -> (Pubkey, u8) {
+    if version == 0 {
+        Pubkey::find_program_address(&[PDA_SEED], program_id)
+    } else {
+        Pubkey::find_program_address(&[PDA_SEED, &[version]], program_id)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use {super::*, solana_program_test::*};
+    use {
+        super::*,
+        crate::state::{Discriminator, OrderStatus},
+        solana_program_test::*,
+    };
 
     #[tokio::test]
     async fn test_get_amounts() {
@@ -62,50 +610,737 @@ mod test {
         assert_eq!((996, 4), get_amounts(1000, 4));
     }
 
+    #[tokio::test]
+    async fn test_distribute_fee() {
+        // the fast path: no sponsor means a single fee transfer
+        let (program_owner_fee, sponsor_fee) = distribute_fee(500000, true, SPONSOR_FEE);
+        assert_eq!((500000, 0), (program_owner_fee, sponsor_fee));
+        assert_eq!(
+            1,
+            [program_owner_fee, sponsor_fee]
+                .iter()
+                .filter(|amount| **amount > 0)
+                .count()
+        );
+        // with a sponsor the fee is split over two transfers
+        assert_eq!((498500, 1500), distribute_fee(500000, false, SPONSOR_FEE));
+        // the split always adds up to the total fee
+        for total_fee in [0, 1, 99, 100, 50000, 500000, 1000000007, u64::MAX].iter() {
+            for sponsor_is_owner in [true, false].iter() {
+                let (program_owner_fee, sponsor_fee) =
+                    distribute_fee(*total_fee, *sponsor_is_owner, SPONSOR_FEE);
+                assert_eq!(*total_fee, program_owner_fee + sponsor_fee);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_distribute_fee_rounding() {
+        // 1999 * 0.3% is 5.997, the program owner keeps the 0.997 lamports of dust
+        assert_eq!((1994, 5), distribute_fee(1999, false, SPONSOR_FEE));
+        for total_fee in (0..5000).chain(u64::MAX - 5000..=u64::MAX) {
+            let (program_owner_fee, sponsor_fee) = distribute_fee(total_fee, false, SPONSOR_FEE);
+            assert_eq!(total_fee, program_owner_fee + sponsor_fee);
+            // the sponsor never gets more than its exact share, but at least 1 lamport
+            // of fees of 100 lamports or more
+            let exact_sponsor_fee = total_fee as u128 * SPONSOR_FEE / 1000;
+            assert!(sponsor_fee as u128 <= exact_sponsor_fee.max(1));
+            assert_eq!(total_fee >= 100, sponsor_fee > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_split_charity_amount() {
+        assert_eq!((1000, 0), split_charity_amount(1000, 0));
+        // 2.5% goes to the charity
+        assert_eq!((975, 25), split_charity_amount(1000, 250));
+        // the charity amount is rounded down
+        assert_eq!((999, 0), split_charity_amount(999, 1));
+        assert_eq!((0, 1000), split_charity_amount(1000, MAX_BPS));
+        // no overflow with the largest amounts
+        assert_eq!(
+            (u64::MAX - u64::MAX / 2, u64::MAX / 2),
+            split_charity_amount(u64::MAX, 5000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_paid_amount() {
+        assert_eq!(1000, get_paid_amount(1000, 0, 1000));
+        assert_eq!(1000, get_paid_amount(1000, 5000, 6000));
+        // less was received than the instruction amount
+        assert_eq!(990, get_paid_amount(1000, 0, 990));
+        // more was received than the instruction amount
+        assert_eq!(1000, get_paid_amount(1000, 0, 2000));
+        // the balance went down
+        assert_eq!(0, get_paid_amount(1000, 1000, 500));
+    }
+
+    #[tokio::test]
+    async fn test_get_token_amount() {
+        // $5 at $20 (2000000000 * 10^-8) a token with 6 decimals
+        assert_eq!(Some(250000), get_token_amount(5000000, 2000000000, -8, 6));
+        // rounded up so that the USD amount is always covered
+        assert_eq!(Some(1), get_token_amount(5000000, 2000000000, -8, 0));
+        // positive exponents
+        assert_eq!(Some(5000000), get_token_amount(100000000, 2, 1, 6));
+        // prices must be positive
+        assert_eq!(None, get_token_amount(5000000, 0, -8, 6));
+        assert_eq!(None, get_token_amount(5000000, -1, -8, 6));
+        // overflows
+        assert_eq!(None, get_token_amount(u64::MAX, 1, -8, 18));
+    }
+
+    #[tokio::test]
+    async fn test_quote_cart() {
+        let mut merchant_account = MerchantAccount {
+            discriminator: Discriminator::MerchantChainCheckout as u8,
+            owner: Pubkey::new_unique().to_bytes(),
+            sponsor: Pubkey::new_unique().to_bytes(),
+            fee: 50000,
+            data: String::from("{}"),
+            fee_recipient: Pubkey::new_unique().to_bytes(),
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        let mut unit_prices: BTreeMap<String, u64> = BTreeMap::new();
+        unit_prices.insert("1".to_string(), 2000000);
+        unit_prices.insert("2".to_string(), 3000000);
+        unit_prices.insert("3".to_string(), 4000000);
+        let mut items: BTreeMap<String, u64> = BTreeMap::new();
+        items.insert("1".to_string(), 2);
+        items.insert("3".to_string(), 3);
+
+        let cart_quote = quote_cart(&items, &unit_prices, &merchant_account).unwrap();
+        assert_eq!(Some(&4000000), cart_quote.lines.get("1"));
+        assert_eq!(Some(&12000000), cart_quote.lines.get("3"));
+        assert_eq!(None, cart_quote.lines.get("2"));
+        assert_eq!(16000000, cart_quote.total);
+        assert_eq!(quote(16000000, &merchant_account), cart_quote.fee);
+        assert_eq!(50000, cart_quote.fee.sol_fee);
+        // an empty cart only costs the fee
+        assert_eq!(
+            CartQuote {
+                fee: quote(0, &merchant_account),
+                ..CartQuote::default()
+            },
+            quote_cart(&BTreeMap::new(), &unit_prices, &merchant_account).unwrap()
+        );
+        // the fee follows the merchant fee mode
+        merchant_account.fee_mode = FeeMode::BpsToken;
+        merchant_account.fee = 300;
+        let cart_quote = quote_cart(&items, &unit_prices, &merchant_account).unwrap();
+        assert_eq!(0, cart_quote.fee.sol_fee);
+        assert_eq!(480000, cart_quote.fee.token_fee);
+        assert_eq!(16000000 - 480000, cart_quote.fee.seller_amount);
+        // items must have a price
+        items.insert("4".to_string(), 1);
+        assert_eq!(None, quote_cart(&items, &unit_prices, &merchant_account));
+        // overflows
+        items.remove("4");
+        items.insert("2".to_string(), u64::MAX);
+        assert_eq!(None, quote_cart(&items, &unit_prices, &merchant_account));
+    }
+
     #[tokio::test]
     async fn test_get_order_account_size() {
         assert_eq!(
-            198,
+            216,
             get_order_account_size(
                 &String::from("123456"),
-                &String::from("password"),
+                &Some(String::from("password")),
+                &String::from(r#"{"a": "b"}"#)
+            )
+        );
+        assert_eq!(
+            208,
+            get_order_account_size(
+                &String::from("test-6"),
+                &Some(String::from("")),
                 &String::from(r#"{"a": "b"}"#)
             )
         );
+        // without a secret not even the length of an empty one is stored
         assert_eq!(
-            190,
+            208 - STRING_SIZE,
             get_order_account_size(
                 &String::from("test-6"),
-                &String::from(""),
+                &Option::None,
                 &String::from(r#"{"a": "b"}"#)
             )
         );
-        assert_eq!(423, get_order_account_size(&String::from("WSUDUBDG2"), &String::from("Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type"), &String::from(r#"{"a": "b"}"#)));
+        assert_eq!(441, get_order_account_size(&String::from("WSUDUBDG2"), &Some(String::from("Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type")), &String::from(r#"{"a": "b"}"#)));
+    }
+
+    #[tokio::test]
+    async fn test_token_account_rent() {
+        let rent = Rent::default();
+        assert_eq!(
+            rent.minimum_balance(165),
+            token_account_rent(&rent, TokenAccount::LEN)
+        );
+        // token accounts with extensions are longer and cost more
+        assert_eq!(
+            rent.minimum_balance(TokenAccount::LEN + 15),
+            token_account_rent(&rent, TokenAccount::LEN + 15)
+        );
+        assert!(
+            token_account_rent(&rent, TokenAccount::LEN + 15)
+                > token_account_rent(&rent, TokenAccount::LEN)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_estimate_checkout_cost() {
+        let rent = Rent::default();
+        let token_account_rent = rent.minimum_balance(TokenAccount::LEN);
+        assert_eq!(
+            rent.minimum_balance(216) + token_account_rent + 500000,
+            estimate_checkout_cost("123456", "password", r#"{"a": "b"}"#, 500000)
+        );
+        assert_eq!(
+            rent.minimum_balance(get_order_account_size(
+                &String::from("test-6"),
+                &Option::None,
+                &String::from(r#"{"a": "b"}"#)
+            )) + token_account_rent,
+            estimate_checkout_cost("test-6", "", r#"{"a": "b"}"#, 0)
+        );
+        // a bigger order account costs more
+        assert!(
+            estimate_checkout_cost("test-6", "a much longer secret", r#"{"a": "b"}"#, 50000)
+                > estimate_checkout_cost("test-6", "", r#"{"a": "b"}"#, 50000)
+        );
     }
 
     #[tokio::test]
     async fn test_get_merchant_account_size() {
-        assert_eq!(79, get_merchant_account_size(&String::from("{}")));
         assert_eq!(
-            168,
-            get_merchant_account_size(&String::from(
-                r#"{"code":200,"success":true,"payload":{"features":["awesome","easyAPI","lowLearningCurve"]}}"#
-            ))
+            244,
+            get_merchant_account_size(&String::from("{}"), &[], &[])
+        );
+        assert_eq!(
+            333,
+            get_merchant_account_size(
+                &String::from(
+                    r#"{"code":200,"success":true,"payload":{"features":["awesome","easyAPI","lowLearningCurve"]}}"#
+                ),
+                &[],
+                &[]
+            )
+        );
+        assert_eq!(
+            308,
+            get_merchant_account_size(
+                &String::from("{}"),
+                &[Pubkey::new_unique(), Pubkey::new_unique()],
+                &[]
+            )
+        );
+        assert_eq!(
+            284,
+            get_merchant_account_size(&String::from("{}"), &[], &[(Pubkey::new_unique(), 0)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_buyer_fee() {
+        let vip = Pubkey::new_unique();
+        let merchant_account = MerchantAccount {
+            discriminator: 10,
+            owner: Pubkey::new_unique().to_bytes(),
+            sponsor: Pubkey::new_unique().to_bytes(),
+            fee: 500000,
+            data: String::from("{}"),
+            fee_recipient: Pubkey::new_unique().to_bytes(),
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![FeeOverride {
+                buyer: vip.to_bytes(),
+                fee: 1000,
+            }],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        assert_eq!(1000, get_buyer_fee(&merchant_account, &vip));
+        assert_eq!(
+            500000,
+            get_buyer_fee(&merchant_account, &Pubkey::new_unique())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_order_signature_message() {
+        let mint = Pubkey::new_unique();
+        let message = get_order_signature_message(&String::from("123"), 1000, &mint);
+        // the order id with its length, the amount and the mint
+        assert_eq!(4 + 3 + 8 + 32, message.len());
+        assert_eq!(&mint.to_bytes()[..], &message[15..]);
+        assert_ne!(
+            message,
+            get_order_signature_message(&String::from("123"), 1001, &mint)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_compact_id_string() {
+        assert_eq!("", get_compact_id_string(&[0; COMPACT_ID_LEN]));
+        let mut id = [0; COMPACT_ID_LEN];
+        id[0] = 0xab;
+        id[COMPACT_ID_LEN - 1] = 1;
+        assert_eq!(
+            "ab000000000000000000000000000001",
+            get_compact_id_string(&id)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_printable_secret() {
+        assert!(is_printable_secret(""));
+        assert!(is_printable_secret("hunter2"));
+        assert!(is_printable_secret("pässwört 🔑"));
+        assert!(!is_printable_secret("hunter2\n"));
+        assert!(!is_printable_secret("hun\0ter2"));
+        assert!(!is_printable_secret("\u{1b}[31mhunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_get_checkout_fees() {
+        let vip = Pubkey::new_unique();
+        let mut merchant_account = MerchantAccount {
+            discriminator: 10,
+            owner: Pubkey::new_unique().to_bytes(),
+            sponsor: Pubkey::new_unique().to_bytes(),
+            fee: 300,
+            data: String::from("{}"),
+            fee_recipient: Pubkey::new_unique().to_bytes(),
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![FeeOverride {
+                buyer: vip.to_bytes(),
+                fee: 100,
+            }],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        let buyer = Pubkey::new_unique();
+        // the fee is in lamports whatever the amount
+        assert_eq!(
+            (300, 0),
+            get_checkout_fees(&merchant_account, &buyer, 1000000)
+        );
+        // 3% of the amount, paid in lamports
+        merchant_account.fee_mode = FeeMode::BpsSol;
+        assert_eq!(
+            (30000, 0),
+            get_checkout_fees(&merchant_account, &buyer, 1000000)
+        );
+        assert_eq!(
+            (10000, 0),
+            get_checkout_fees(&merchant_account, &vip, 1000000)
+        );
+        // 3% of the amount, paid in the payment token
+        merchant_account.fee_mode = FeeMode::BpsToken;
+        assert_eq!(
+            (0, 30000),
+            get_checkout_fees(&merchant_account, &buyer, 1000000)
+        );
+        assert_eq!((0, 0), get_checkout_fees(&merchant_account, &buyer, 33));
+        // small amounts, including those below the get_amounts threshold of 100
+        for amount in 0..200 {
+            merchant_account.fee_mode = FeeMode::FlatSol;
+            assert_eq!(
+                (300, 0),
+                get_checkout_fees(&merchant_account, &buyer, amount)
+            );
+            let fee_recipient = Pubkey::new_from_array(merchant_account.fee_recipient);
+            let (owner_share, sponsor_share) =
+                get_fee_shares(&merchant_account, &fee_recipient, 300);
+            assert_eq!(300, owner_share + sponsor_share);
+            merchant_account.fee_mode = FeeMode::BpsSol;
+            assert_eq!(
+                (amount * 300 / 10000, 0),
+                get_checkout_fees(&merchant_account, &buyer, amount)
+            );
+            merchant_account.fee_mode = FeeMode::BpsToken;
+            let (sol_fee, token_fee) = get_checkout_fees(&merchant_account, &buyer, amount);
+            assert_eq!((0, amount * 300 / 10000), (sol_fee, token_fee));
+            assert!(token_fee <= amount);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quote_many() {
+        let charity = Pubkey::new_unique().to_bytes();
+        let merchant = |fee_mode: FeeMode, charity_bps: u16| MerchantAccount {
+            discriminator: 10,
+            owner: Pubkey::new_unique().to_bytes(),
+            sponsor: Pubkey::new_unique().to_bytes(),
+            fee: 300,
+            data: String::from("{}"),
+            fee_recipient: Pubkey::new_unique().to_bytes(),
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity,
+            charity_bps,
+            fee_mode,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        assert_eq!(
+            FeeQuote {
+                amount: 1000000,
+                sol_fee: 0,
+                token_fee: 30000,
+                charity_amount: 100000,
+                seller_amount: 870000,
+            },
+            quote(1000000, &merchant(FeeMode::BpsToken, 1000))
+        );
+        let orders = vec![
+            (1000000, merchant(FeeMode::FlatSol, 0)),
+            (1000000, merchant(FeeMode::BpsToken, 1000)),
+            (2000000, merchant(FeeMode::BpsSol, 0)),
+            (1000, merchant(FeeMode::BpsToken, MAX_BPS)),
+        ];
+        let quotes = quote_many(&orders);
+        assert_eq!(orders.len(), quotes.len());
+        for ((amount, merchant_account), fee_quote) in orders.iter().zip(quotes.iter()) {
+            assert_eq!(quote(*amount, merchant_account), *fee_quote);
+        }
+        assert_eq!(0, quotes[3].seller_amount);
+        assert_eq!(Vec::<FeeQuote>::new(), quote_many(&[]));
+    }
+
+    #[tokio::test]
+    async fn test_get_fee_shares() {
+        let mut merchant = MerchantAccount {
+            discriminator: 10,
+            owner: Pubkey::new_unique().to_bytes(),
+            sponsor: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            fee: 300,
+            data: String::from("{}"),
+            fee_recipient: Pubkey::new_unique().to_bytes(),
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        let fee_recipient = Pubkey::new_unique();
+        // without a sponsor the fee recipient gets the whole fee
+        assert_eq!(
+            (500000, 0),
+            get_fee_shares(&merchant, &fee_recipient, 500000)
+        );
+        // as it does when it is the sponsor, e.g. the config owner
+        merchant.sponsor = fee_recipient.to_bytes();
+        assert_eq!(
+            (500000, 0),
+            get_fee_shares(&merchant, &fee_recipient, 500000)
+        );
+        merchant.sponsor = Pubkey::new_unique().to_bytes();
+        assert_eq!(
+            (498500, 1500),
+            get_fee_shares(&merchant, &fee_recipient, 500000)
+        );
+        assert_eq!((0, 0), get_fee_shares(&merchant, &fee_recipient, 0));
+    }
+
+    #[tokio::test]
+    async fn test_get_fee_transfers() {
+        let mut merchant = MerchantAccount {
+            discriminator: 10,
+            owner: Pubkey::new_unique().to_bytes(),
+            sponsor: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            fee: 300,
+            data: String::from("{}"),
+            fee_recipient: Pubkey::from_str(PROGRAM_OWNER).unwrap().to_bytes(),
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: false,
+        };
+        let config_owner = Pubkey::new_unique();
+        // the fast path: one transfer CPI, even after the config owner took over
+        assert_eq!(
+            vec![(config_owner, 500000)],
+            get_fee_transfers(&merchant, &config_owner, 500000)
+        );
+        // a sponsor takes its share in a second transfer CPI
+        let sponsor = Pubkey::new_unique();
+        merchant.sponsor = sponsor.to_bytes();
+        assert_eq!(
+            vec![(config_owner, 498500), (sponsor, 1500)],
+            get_fee_transfers(&merchant, &config_owner, 500000)
+        );
+        // fees below the sponsor share threshold only go to the fee recipient
+        assert_eq!(
+            vec![(config_owner, 99)],
+            get_fee_transfers(&merchant, &config_owner, 99)
         );
+        // and fee-free checkouts make no fee CPI at all
+        assert!(get_fee_transfers(&merchant, &config_owner, 0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_bps_amount() {
+        assert_eq!(0, get_bps_amount(1000, 0));
+        assert_eq!(30, get_bps_amount(1000, 300));
+        assert_eq!(1000, get_bps_amount(1000, MAX_BPS as u64));
+        // never more than the amount
+        assert_eq!(1000, get_bps_amount(1000, u64::MAX));
+        assert_eq!(u64::MAX / 2, get_bps_amount(u64::MAX, 5000));
     }
 
     #[tokio::test]
     async fn test_get_subscription_account_size() {
         assert_eq!(
-            100,
+            140,
             get_subscription_account_size(&String::from("a"), &String::from("b"))
         );
         assert_eq!(
-            132,
+            172,
             get_subscription_account_size(
                 &String::from("Annual"),
                 &String::from(r#"{"foo": "bar", "price": 200}"#)
             )
         );
     }
+
+    #[tokio::test]
+    async fn test_get_package_account_size() {
+        assert_eq!(90, get_package_account_size(&String::from("basic")));
+        assert_eq!(91, get_package_account_size(&String::from("Annual")));
+    }
+
+    #[tokio::test]
+    async fn test_get_coupon_account_size() {
+        assert_eq!(69, get_coupon_account_size(&String::from("SAVE10")));
+        assert_eq!(71, get_coupon_account_size(&String::from("BLACKFRI")));
+    }
+
+    #[tokio::test]
+    async fn test_get_coupon_price() {
+        assert_eq!(900, get_coupon_price(1000, 1000));
+        assert_eq!(1000, get_coupon_price(1000, 0));
+        assert_eq!(0, get_coupon_price(1000, MAX_BPS));
+        // the discount is rounded down
+        assert_eq!(67, get_coupon_price(99, 3333));
+    }
+
+    #[tokio::test]
+    async fn test_get_versioned_pda() {
+        let program_id = Pubkey::new_unique();
+        // version 0 is the derived address the program has always used
+        assert_eq!(
+            Pubkey::find_program_address(&[PDA_SEED], &program_id),
+            get_versioned_pda(&program_id, 0)
+        );
+        assert_eq!(
+            Pubkey::find_program_address(&[PDA_SEED, &[1]], &program_id),
+            get_versioned_pda(&program_id, 1)
+        );
+        assert_ne!(
+            get_versioned_pda(&program_id, 1).0,
+            get_versioned_pda(&program_id, 2).0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_fully_paid() {
+        let mut merchant_account = MerchantAccount {
+            discriminator: 10,
+            owner: [1; 32],
+            sponsor: [2; 32],
+            fee: 300,
+            data: String::from("{}"),
+            fee_recipient: [3; 32],
+            settlement_delay: 0,
+            order_count: 0,
+            accepted_mints: vec![],
+            allow_third_party_refunds: false,
+            fee_overrides: vec![],
+            min_subscription_duration: 0,
+            operator: [0; 32],
+            charity: [0; 32],
+            charity_bps: 0,
+            fee_mode: FeeMode::FlatSol,
+            min_amount: 0,
+            max_amount: 0,
+            fee_free_below: 0,
+            fee_free_above: 0,
+            require_full_payment: true,
+        };
+        let mut order_account = OrderAccount {
+            discriminator: Discriminator::OrderExpressCheckout as u8,
+            status: OrderStatus::Paid as u8,
+            created: 0,
+            modified: 0,
+            merchant: [0; 32],
+            mint: [0; 32],
+            token: [0; 32],
+            payer: [0; 32],
+            expected_amount: 10000,
+            paid_amount: 10000,
+            order_id: String::from("1"),
+            secret: Option::None,
+            data: String::from("{}"),
+            refunded_amount: 0,
+            locked: false,
+            consumed: false,
+            quoted_amount: 0,
+        };
+        assert!(is_fully_paid(&order_account, &merchant_account));
+        order_account.paid_amount = 9999;
+        assert!(!is_fully_paid(&order_account, &merchant_account));
+        // the charity share is not missing
+        merchant_account.charity = [4; 32];
+        merchant_account.charity_bps = 250;
+        order_account.paid_amount = 9750;
+        assert!(is_fully_paid(&order_account, &merchant_account));
+        order_account.paid_amount = 9749;
+        assert!(!is_fully_paid(&order_account, &merchant_account));
+        // nor is the token fee, 1% of the amount
+        merchant_account.fee_mode = FeeMode::BpsToken;
+        merchant_account.fee = 100;
+        order_account.paid_amount = 9650;
+        assert!(is_fully_paid(&order_account, &merchant_account));
+        order_account.paid_amount = 9649;
+        assert!(!is_fully_paid(&order_account, &merchant_account));
+        // orders quoted in USD expect their quoted amount of tokens, here $2 at
+        // $0.50 a token, less the charity share and the token fee
+        merchant_account.charity_bps = 0;
+        merchant_account.fee = 0;
+        order_account.expected_amount = 2000000;
+        order_account.quoted_amount = 4000000;
+        order_account.paid_amount = 4000000;
+        assert!(is_fully_paid(&order_account, &merchant_account));
+        order_account.paid_amount = 3999999;
+        assert!(!is_fully_paid(&order_account, &merchant_account));
+        // more tokens than the USD amount is not enough
+        order_account.paid_amount = 2000000;
+        assert!(!is_fully_paid(&order_account, &merchant_account));
+    }
+
+    #[tokio::test]
+    async fn test_is_cleared_secret() {
+        assert!(is_cleared_secret("\0\0\0"));
+        assert!(!is_cleared_secret("\0a\0"));
+        assert!(!is_cleared_secret("redeem-me"));
+    }
+
+    #[tokio::test]
+    async fn test_get_seller_token_addresses() {
+        let program_id = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let orders = vec![
+            (Pubkey::new_unique(), mint),
+            (Pubkey::new_unique(), mint),
+            (Pubkey::new_unique(), Pubkey::new_unique()),
+        ];
+        let addresses = get_seller_token_addresses(&orders, &program_id);
+        assert_eq!(orders.len(), addresses.len());
+        for ((order, mint), address) in orders.iter().zip(addresses.iter()) {
+            assert_eq!(
+                get_seller_token_address(order, mint, &program_id).0,
+                *address
+            );
+        }
+        // the seeds are the same as the merchant token account's
+        assert_eq!(
+            get_merchant_token_address(&orders[0].0, &mint, &program_id),
+            get_seller_token_address(&orders[0].0, &mint, &program_id)
+        );
+        assert!(get_seller_token_addresses(&[], &program_id).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_order_id() {
+        let longest = "x".repeat(MAX_ORDER_ID_LEN);
+        let too_long = "x".repeat(MAX_ORDER_ID_LEN + 1);
+        for valid in &["1337", "fee mode", "order-42#A/b", longest.as_str()] {
+            assert_eq!(*valid, OrderId::try_new(valid).unwrap().as_str());
+        }
+        // too short or too long
+        for invalid in &["", too_long.as_str()] {
+            assert_eq!(
+                Err(PaymentProcessorError::InvalidOrderId),
+                OrderId::try_new(invalid)
+            );
+        }
+        // invalid characters
+        for invalid in &["13\n37", "13\t37", "13\037", "bestellüng", "🔑"] {
+            assert_eq!(
+                Err(PaymentProcessorError::InvalidOrderId),
+                OrderId::try_new(invalid)
+            );
+        }
+        assert_eq!(
+            String::from("1337"),
+            String::from(OrderId::try_new("1337").unwrap())
+        );
+    }
 }